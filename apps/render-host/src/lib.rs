@@ -1,22 +1,29 @@
 use anyhow::Result;
+use appearance::appearance_asset_database::asset_paths::resolve_asset_path;
 use appearance::appearance_camera::CameraController;
 use appearance::appearance_distributed_renderer::DistributedRenderer;
 use appearance::appearance_input::InputHandler;
+use appearance::appearance_path_tracer::PathTracer;
+use appearance::appearance_render_loop::auth::PreSharedKey;
 use appearance::appearance_render_loop::block_to_linear_pass::BlockToLinearPassParameters;
 use appearance::appearance_render_loop::node::NodeRenderer;
+use appearance::appearance_render_loop::tile_codec::TileEncoding;
 use appearance::appearance_render_loop::winit::keyboard::KeyCode;
-use appearance::appearance_transform::{Transform, RIGHT, UP};
+use appearance::appearance_transform::{RIGHT, UP};
 use appearance::appearance_wgpu::pipeline_database::PipelineDatabase;
 use appearance::appearance_wgpu::Context;
-use appearance::appearance_world::components::{ModelComponent, TransformComponent};
+use appearance::appearance_world::components::TransformComponent;
 use appearance::appearance_world::visible_world_action::VisibleWorldActionType;
 use appearance::appearance_world::{specs, World};
-use clap::Parser;
-use glam::{Quat, UVec2, Vec3};
+use clap::{Parser, ValueEnum};
+use glam::{Quat, UVec2};
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
 
-use appearance::appearance_render_loop::host::{Host, RENDER_BLOCK_SIZE};
+use appearance::appearance_render_loop::host::{
+    padded_resolution, Host, NodeCapabilities, RenderScheduling, DEFAULT_FRAME_DEADLINE,
+};
 use appearance::appearance_render_loop::winit::window::Window;
 use appearance::appearance_render_loop::{
     block_to_linear_pass, winit, RenderLoop, RenderLoopHandler, RenderLoopWindowDesc,
@@ -26,9 +33,92 @@ use appearance::appearance_wgpu::helper_passes::blit_pass;
 use appearance::appearance_wgpu::wgpu::{self, Extent3d, Origin3d};
 use appearance::Appearance;
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Spectral path tracer running on the cpu
+    Cpu,
+    /// Hardware accelerated path tracer running on the gpu
+    Gpu,
+}
+
+enum LocalRenderer {
+    Cpu(PathTracer),
+    Gpu(DistributedRenderer),
+}
+
+impl NodeRenderer for LocalRenderer {
+    fn visible_world_action(&mut self, action: &VisibleWorldActionType) {
+        match self {
+            Self::Cpu(path_tracer) => path_tracer.visible_world_action(action),
+            Self::Gpu(distributed_renderer) => distributed_renderer.visible_world_action(action),
+        }
+    }
+
+    fn provide_asset(&mut self, path: &str, bytes: &[u8]) {
+        match self {
+            Self::Cpu(path_tracer) => path_tracer.provide_asset(path, bytes),
+            Self::Gpu(distributed_renderer) => distributed_renderer.provide_asset(path, bytes),
+        }
+    }
+
+    fn capabilities(&self) -> NodeCapabilities {
+        match self {
+            Self::Cpu(path_tracer) => path_tracer.capabilities(),
+            Self::Gpu(distributed_renderer) => distributed_renderer.capabilities(),
+        }
+    }
+
+    fn set_hdr_output(&mut self, hdr_output: bool) -> bool {
+        match self {
+            Self::Cpu(path_tracer) => path_tracer.set_hdr_output(hdr_output),
+            Self::Gpu(distributed_renderer) => distributed_renderer.set_hdr_output(hdr_output),
+        }
+    }
+
+    fn set_sample_index(&mut self, sample_index: Option<u32>) {
+        match self {
+            Self::Cpu(path_tracer) => path_tracer.set_sample_index(sample_index),
+            Self::Gpu(distributed_renderer) => distributed_renderer.set_sample_index(sample_index),
+        }
+    }
+
+    fn render<F: FnMut(&[u8])>(
+        &mut self,
+        resolution: UVec2,
+        start_row: u32,
+        end_row: u32,
+        result_callback: F,
+    ) {
+        match self {
+            Self::Cpu(path_tracer) => {
+                path_tracer.render(resolution, start_row, end_row, result_callback)
+            }
+            Self::Gpu(distributed_renderer) => {
+                distributed_renderer.render(resolution, start_row, end_row, result_callback)
+            }
+        }
+    }
+
+    fn render_blocks<F: FnMut(UVec2, &[u8])>(
+        &mut self,
+        resolution: UVec2,
+        blocks: &[UVec2],
+        result_callback: F,
+    ) {
+        match self {
+            Self::Cpu(path_tracer) => {
+                path_tracer.render_blocks(resolution, blocks, result_callback)
+            }
+            Self::Gpu(distributed_renderer) => {
+                distributed_renderer.render_blocks(resolution, blocks, result_callback)
+            }
+        }
+    }
+}
+
 enum RenderingStrategy {
     Distributed(Host),
-    Local(DistributedRenderer),
+    Local(LocalRenderer),
 }
 
 #[derive(Parser, Debug)]
@@ -49,6 +139,38 @@ struct Args {
     /// Forcefully disable gpu validation
     #[arg(long, default_value_t = false)]
     no_gpu_validation: bool,
+
+    /// Scene file to load on startup
+    #[arg(long, default_value_t = String::from("::scenes/sponza.json"))]
+    scene: String,
+
+    /// Renderer used when rendering locally
+    #[arg(long, value_enum, default_value_t = Backend::Gpu)]
+    backend: Backend,
+
+    /// Let nodes send linear radiance tiles losslessly, which the host tonemaps, instead of tonemapped jpeg tiles
+    #[arg(long, default_value_t = false)]
+    hdr_tiles: bool,
+
+    /// Let nodes pull this many blocks at a time from a shared queue, instead of assigning each node a range of rows
+    #[arg(long)]
+    tile_blocks: Option<u32>,
+
+    /// Milliseconds nodes get to deliver a frame, blocks arriving later show the previous frame instead
+    #[arg(long, default_value_t = DEFAULT_FRAME_DEADLINE.as_millis() as u64)]
+    frame_deadline_ms: u64,
+
+    /// Once the camera has been static for this many frames, let every node render the whole frame and average their samples, requires --hdr-tiles
+    #[arg(long)]
+    accumulate_after: Option<u32>,
+
+    /// Log the network stats of every node each frame
+    #[arg(long, default_value_t = false)]
+    network_stats: bool,
+
+    /// Only accept nodes started with the same key and drop all unauthenticated traffic
+    #[arg(long)]
+    pre_shared_key: Option<String>,
 }
 
 pub struct HostRenderLoop {
@@ -63,7 +185,8 @@ pub struct HostRenderLoop {
     camera_controller: CameraController,
     world: World,
     // duck_entity: Option<specs::Entity>,
-    toy_car_entity: specs::Entity,
+    toy_car_entity: Option<specs::Entity>,
+    log_network_stats: bool,
 }
 
 impl RenderLoop for HostRenderLoop {
@@ -95,106 +218,51 @@ impl RenderLoop for HostRenderLoop {
         let args = Args::parse();
 
         let rendering_strategy = if args.render_local {
-            let distributed_renderer = DistributedRenderer::new_with_context(ctx.clone());
-            RenderingStrategy::Local(distributed_renderer)
+            let local_renderer = match args.backend {
+                Backend::Cpu => LocalRenderer::Cpu(PathTracer::new()),
+                Backend::Gpu => {
+                    LocalRenderer::Gpu(DistributedRenderer::new_with_context(ctx.clone()))
+                }
+            };
+            RenderingStrategy::Local(local_renderer)
         } else {
-            let host =
-                Host::new(args.host_port, args.node_port, config.width, config.height).unwrap();
+            let pre_shared_key = args
+                .pre_shared_key
+                .as_ref()
+                .map(|key| PreSharedKey::new(key).unwrap());
+            let mut host = Host::new(
+                args.host_port,
+                args.node_port,
+                config.width,
+                config.height,
+                pre_shared_key,
+            )
+            .unwrap();
+            if let Some(blocks_per_request) = args.tile_blocks {
+                host.set_scheduling(RenderScheduling::Tiles { blocks_per_request });
+            }
+            if args.hdr_tiles {
+                host.set_tile_encoding(TileEncoding::Rgb9e5Lz4);
+            }
+            host.set_frame_deadline(Duration::from_millis(args.frame_deadline_ms));
+            host.set_accumulation_delay(args.accumulate_after);
             RenderingStrategy::Distributed(host)
         };
 
-        let texture = std::array::from_fn(|_| {
-            ctx.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("texture"),
-                size: wgpu::Extent3d {
-                    width: config.width,
-                    height: config.height,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &[],
-            })
-        });
+        let texture = create_textures(UVec2::new(config.width, config.height), ctx);
 
         let mut world = World::new();
-        // let duck_entity =
-        //     world.create_entity("Duck", Transform::from_scale(Vec3::splat(1.0)), |builder| {
-        //         builder.with(ModelComponent::new("::Duck.glb"))
-        //     });
-        let _ = world.create_entity(
-            "Sponza",
-            Transform::new(Vec3::new(3.0, 0.0, 0.0), Quat::IDENTITY, Vec3::splat(1.0)),
-            |builder| builder.with(ModelComponent::new("::Sponza.glb")),
-        );
-        // let _ = world.create_entity(
-        //     "CornellBox",
-        //     Transform::new(Vec3::new(0.0, 0.0, 0.0), Quat::IDENTITY, Vec3::splat(1.0)),
-        //     |builder| builder.with(ModelComponent::new("::CornellBoxConor.glb")),
-        // );
-        let toy_car_entity = world.create_entity(
-            "Orbs",
-            Transform::new(Vec3::new(3.0, 0.0, 0.0), Quat::IDENTITY, Vec3::splat(1.0)),
-            |builder| builder.with(ModelComponent::new("::SponzaOrbs.glb")),
-        );
-        let _ = world.create_entity(
-            "NeonSigns",
-            Transform::new(Vec3::new(3.0, 0.0, 0.0), Quat::IDENTITY, Vec3::splat(1.0)),
-            |builder| builder.with(ModelComponent::new("::SponzaNeon.glb")),
-        );
-        // let toy_car_entity = world.create_entity(
-        //     "ToyCar",
-        //     Transform::new(Vec3::new(3.0, 0.5, 0.0), Quat::IDENTITY, Vec3::splat(45.0)),
-        //     |builder| builder.with(ModelComponent::new("::ToyCarNonEmissive.glb")),
-        // );
-        // let _ = world.create_entity(
-        //     "Chess",
-        //     Transform::new(Vec3::new(-2.0, 0.5, 0.0), Quat::IDENTITY, Vec3::splat(3.0)),
-        //     |builder| builder.with(ModelComponent::new("::ABeautifulGame.glb")),
-        // );
-        // let _ = world.create_entity(
-        //     "Glass",
-        //     Transform::new(
-        //         Vec3::new(0.0, 1.0, 0.0),
-        //         Quat::from_axis_angle(UP, 90.0f32.to_radians()),
-        //         Vec3::splat(1.0),
-        //     ),
-        //     |builder| builder.with(ModelComponent::new("::GlassPanel.glb")),
-        // );
-        // let _ = world.create_entity(
-        //     "Dragon",
-        //     Transform::new(
-        //         Vec3::new(-4.0, 0.5, 0.0),
-        //         Quat::from_axis_angle(UP, 90.0f32.to_radians()),
-        //         Vec3::splat(0.5),
-        //     ),
-        //     |builder| builder.with(ModelComponent::new("::DragonAttenuation.glb")),
-        // );
-
-        // let _ = world.create_entity(
-        //     "ClearCoatTest",
-        //     Transform::new(
-        //         Vec3::new(0.0, 0.0, 15.0),
-        //         Quat::from_axis_angle(UP, 90.0f32.to_radians()),
-        //         Vec3::splat(1.0),
-        //     ),
-        //     |builder| builder.with(ModelComponent::new("::test_models/ClearCoatTest.glb")),
-        // );
-
-        // let _ = world.create_entity(
-        //     "AttenuationTest",
-        //     Transform::new(
-        //         Vec3::new(0.0, 0.0, 35.0),
-        //         Quat::from_axis_angle(UP, 90.0f32.to_radians()),
-        //         Vec3::splat(1.0),
-        //     ),
-        //     |builder| builder.with(ModelComponent::new("::test_models/AttenuationTest.glb")),
-        // );
+        if let Err(err) = world.load_scene(resolve_asset_path(&args.scene, "")) {
+            log::error!(
+                "Failed to load scene {}, starting with an empty world: {}",
+                args.scene,
+                err
+            );
+        }
+        let toy_car_entity = world.find_entity("Orbs");
+
+        let mut camera_controller = CameraController::new();
+        camera_controller.sync_rotation(world.camera());
 
         Self {
             pipeline_database: PipelineDatabase::new(),
@@ -205,34 +273,23 @@ impl RenderLoop for HostRenderLoop {
             fps_history: VecDeque::new(),
 
             input_handler: InputHandler::new(),
-            camera_controller: CameraController::new(),
+            camera_controller,
             world,
             // duck_entity: Some(duck_entity),
             toy_car_entity,
+            log_network_stats: args.network_stats,
         }
     }
 
     fn resize(&mut self, config: &wgpu::SurfaceConfiguration, ctx: &Context) {
-        let width = config.width.div_ceil(RENDER_BLOCK_SIZE) * RENDER_BLOCK_SIZE;
-        let height = config.height.div_ceil(RENDER_BLOCK_SIZE) * RENDER_BLOCK_SIZE;
-
-        self.texture = std::array::from_fn(|_| {
-            ctx.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("texture"),
-                size: wgpu::Extent3d {
-                    width,
-                    height,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &[],
-            })
+        let width = config.width;
+        let height = config.height;
+
+        self.texture = create_textures(UVec2::new(width, height), ctx);
+
+        // Keep the host camera in sync with the nodes, picking depends on it
+        self.world.camera_mut(|camera| {
+            camera.set_aspect_ratio(width as f32 / height as f32);
         });
 
         if let RenderingStrategy::Distributed(host) = &mut self.rendering_strategy {
@@ -272,9 +329,9 @@ impl RenderLoop for HostRenderLoop {
         //     duck_transform.transform.translate(RIGHT * delta_time * 0.5);
         // }
 
-        {
+        if let Some(toy_car_entity) = self.toy_car_entity {
             let mut transforms_mut = self.world.entities_mut::<TransformComponent>();
-            let transform = transforms_mut.get_mut(self.toy_car_entity).unwrap();
+            let transform = transforms_mut.get_mut(toy_car_entity).unwrap();
             transform
                 .transform
                 .rotate(Quat::from_axis_angle(UP, delta_time * 0.3));
@@ -295,18 +352,19 @@ impl RenderLoop for HostRenderLoop {
                 self.camera_controller
                     .update(camera, &self.input_handler, delta_time);
         });
+        self.world.advance_animations(delta_time);
 
         match &mut self.rendering_strategy {
             RenderingStrategy::Distributed(host) => {
-                if host.handle_new_connections() {
-                    self.world.resync_all_visible_world_actions();
-                } else {
-                    self.world.finalize_visible_world_actions();
-                }
-
+                self.world.finalize_visible_world_actions();
                 host.send_visible_world_actions(self.world.get_visible_world_actions());
 
-                host.render(|pixels| {
+                // Nodes which just connected receive the entire world instead, including this frame's changes
+                if host.has_unsynced_nodes() {
+                    host.send_snapshot(self.world.snapshot_visible_world_actions());
+                }
+
+                let frame_completeness = host.render(|pixels| {
                     ctx.queue.write_texture(
                         wgpu::TexelCopyTextureInfo {
                             texture: &self.texture[0],
@@ -327,21 +385,41 @@ impl RenderLoop for HostRenderLoop {
                         },
                     );
                 });
+
+                // Nodes take several frames per sample while accumulating, blocks keep their previous average meanwhile
+                if !frame_completeness.is_complete() && !host.is_accumulating() {
+                    log::warn!(
+                        "Frame {} is missing {} of {} blocks, late nodes: {:?}",
+                        frame_completeness.frame_idx,
+                        frame_completeness.missing_blocks,
+                        frame_completeness.total_blocks,
+                        frame_completeness.late_nodes
+                    );
+                }
+
+                if self.log_network_stats {
+                    for node in host.nodes() {
+                        log::info!("Node {:?}: {:?}", node.addr, node.stats);
+                    }
+                }
             }
-            RenderingStrategy::Local(distributed_renderer) => {
+            RenderingStrategy::Local(local_renderer) => {
                 self.world.finalize_visible_world_actions();
                 let visible_world_actions = self.world.get_visible_world_actions();
                 for action in visible_world_actions {
-                    let visible_world_action =
-                        VisibleWorldActionType::from_ty_and_bytes(action.ty, action.data.as_ref());
-
-                    distributed_renderer.visible_world_action(&visible_world_action);
+                    match VisibleWorldActionType::from_ty_and_bytes(action.ty, action.data.as_ref())
+                    {
+                        Ok(visible_world_action) => {
+                            local_renderer.visible_world_action(&visible_world_action)
+                        }
+                        Err(err) => log::warn!("Failed to read visible world action: {}", err),
+                    }
                 }
 
-                distributed_renderer.render(
-                    UVec2::new(self.texture[0].width(), self.texture[0].height()),
+                local_renderer.render(
+                    UVec2::new(self.texture[1].width(), self.texture[1].height()),
                     0,
-                    self.texture[0].height(),
+                    self.texture[1].height(),
                     |pixels| {
                         ctx.queue.write_texture(
                             wgpu::TexelCopyTextureInfo {
@@ -378,7 +456,7 @@ impl RenderLoop for HostRenderLoop {
 
         block_to_linear_pass::encode(
             &BlockToLinearPassParameters {
-                resolution: UVec2::new(self.texture[0].width(), self.texture[0].height()),
+                resolution: UVec2::new(self.texture[1].width(), self.texture[1].height()),
                 target_view: &unresolved_texture_view,
                 resolve_target_view: &resolved_texture_view,
             },
@@ -406,6 +484,28 @@ impl RenderLoop for HostRenderLoop {
     }
 }
 
+/// Texture receiving the pixels laid out in blocks, padded to whole blocks, and the texture they're resolved into at `resolution`
+fn create_textures(resolution: UVec2, ctx: &Context) -> [wgpu::Texture; 2] {
+    [padded_resolution(resolution), resolution].map(|size| {
+        ctx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("texture"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        })
+    })
+}
+
 pub fn internal_main() -> Result<()> {
     let _ = Appearance::new("Render Host");
     RenderLoopHandler::<HostRenderLoop>::new(&RenderLoopWindowDesc {
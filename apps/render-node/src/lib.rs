@@ -1,11 +1,26 @@
 use core::net::SocketAddr;
 use core::str::FromStr;
+use core::time::Duration;
+use std::thread;
+use std::time::Instant;
 
 use anyhow::Result;
 use appearance::appearance_distributed_renderer::DistributedRenderer;
-use appearance::appearance_render_loop::node::Node;
+use appearance::appearance_path_tracer::PathTracer;
+use appearance::appearance_render_loop::asset_stream::{AssetCache, DEFAULT_ASSET_CACHE_DIR};
+use appearance::appearance_render_loop::auth::PreSharedKey;
+use appearance::appearance_render_loop::node::{Node, NodeRenderer};
+use appearance::appearance_render_loop::recording::{Recorder, Replay};
 use appearance::Appearance;
-use clap::{arg, command, Parser};
+use clap::{arg, command, Parser, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Spectral path tracer running on the cpu
+    Cpu,
+    /// Hardware accelerated path tracer running on the gpu
+    Gpu,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -25,20 +40,92 @@ struct Args {
     /// Forcefully disable gpu validation
     #[arg(long, default_value_t = false)]
     no_gpu_validation: bool,
+
+    /// Renderer used to render the rows assigned by the host
+    #[arg(long, value_enum, default_value_t = Backend::Gpu)]
+    backend: Backend,
+
+    /// Record all messages received from the host to this file
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Replay a recording on the selected backend instead of connecting to a host
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<String>,
+
+    /// Directory in which assets streamed from the host are cached
+    #[arg(long, default_value_t = String::from(DEFAULT_ASSET_CACHE_DIR))]
+    asset_cache: String,
+
+    /// Log the stats of the connection to the host every second
+    #[arg(long, default_value_t = false)]
+    network_stats: bool,
+
+    /// Key the host was started with, required to join a host using one
+    #[arg(long)]
+    pre_shared_key: Option<String>,
 }
 
-pub fn internal_main() -> Result<()> {
-    let _appearance = Appearance::new("Render Node");
+fn replay<T: NodeRenderer>(mut renderer: T, path: &str) -> Result<()> {
+    let mut replay = Replay::new(path)?;
+    log::info!("Replaying {} frames from {}.", replay.frame_count(), path);
+
+    loop {
+        let timer = Instant::now();
+        let Some(data) = replay.replay_frame(&mut renderer, |_| {}) else {
+            break;
+        };
+
+        log::info!(
+            "Replayed frame {} in {:.2}ms",
+            data.frame_idx,
+            timer.elapsed().as_secs_f32() * 1000.0
+        );
+    }
+
+    Ok(())
+}
+
+fn run<T: NodeRenderer + 'static>(renderer: T, args: &Args) -> Result<()> {
+    if let Some(path) = &args.replay {
+        return replay(renderer, path);
+    }
 
-    let args = Args::parse();
     let addr = SocketAddr::from_str(&format!("{}:{}", args.host_ip, args.host_port)).unwrap();
 
-    let node = Node::new(
-        DistributedRenderer::new(args.no_gpu_validation),
-        addr,
-        args.node_port,
-    )?;
+    let pre_shared_key = args
+        .pre_shared_key
+        .as_ref()
+        .map(PreSharedKey::new)
+        .transpose()?;
+    let mut node = Node::new(renderer, addr, args.node_port, pre_shared_key)?
+        .with_asset_cache(AssetCache::new(&args.asset_cache)?);
+    if let Some(path) = &args.record {
+        node = node.with_recorder(Recorder::new(path)?);
+    }
+
+    if args.network_stats {
+        let network_stats = node.network_stats();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            if let Ok(network_stats) = network_stats.lock() {
+                log::info!("{:?}", *network_stats);
+            }
+        });
+    }
+
     node.run();
 
     Ok(())
 }
+
+pub fn internal_main() -> Result<()> {
+    let _appearance = Appearance::new("Render Node");
+
+    let args = Args::parse();
+
+    match args.backend {
+        Backend::Cpu => run(PathTracer::new(), &args),
+        Backend::Gpu => run(DistributedRenderer::new(args.no_gpu_validation), &args),
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Mutex;
 use appearance_input::InputHandler;
 use appearance_transform::{Transform, RIGHT, UP};
 use frustum::Frustum;
-use glam::{Mat4, Quat, Vec3};
+use glam::{EulerRot, Mat4, Quat, Vec3};
 use winit::keyboard::KeyCode;
 
 pub mod frustum;
@@ -15,6 +15,10 @@ pub struct Camera {
     fov: f32,
     near: f32,
     far: f32,
+    aperture: f32,
+    focus_distance: f32,
+    sensor_size: f32,
+    blade_count: u32,
     matrix: Mutex<(Mat4, bool)>,
     prev_matrix: Mat4,
 }
@@ -38,6 +42,10 @@ impl Clone for Camera {
             fov: self.fov,
             near: self.near,
             far: self.far,
+            aperture: self.aperture,
+            focus_distance: self.focus_distance,
+            sensor_size: self.sensor_size,
+            blade_count: self.blade_count,
             matrix: Mutex::new(*matrix),
             prev_matrix: self.prev_matrix,
         }
@@ -52,6 +60,10 @@ impl Default for Camera {
             fov: 60.0,
             near: 0.1,
             far: 300.0,
+            aperture: 0.0,
+            focus_distance: 10.0,
+            sensor_size: 24.0,
+            blade_count: 0,
             matrix: Mutex::new((Mat4::IDENTITY, true)),
             prev_matrix: Mat4::IDENTITY,
         }
@@ -116,6 +128,56 @@ impl Camera {
         self.matrix.lock().unwrap().1 = true;
     }
 
+    /// Aperture as f-number, zero renders as a pinhole camera without depth of field
+    pub fn get_aperture(&self) -> f32 {
+        self.aperture
+    }
+
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.aperture = aperture.max(0.0);
+    }
+
+    /// Distance in meters of the plane that is in perfect focus
+    pub fn get_focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
+    pub fn set_focus_distance(&mut self, focus_distance: f32) {
+        self.focus_distance = focus_distance.max(f32::EPSILON);
+    }
+
+    /// Vertical sensor size in millimeters, together with the fov this determines the focal length
+    pub fn get_sensor_size(&self) -> f32 {
+        self.sensor_size
+    }
+
+    pub fn set_sensor_size(&mut self, sensor_size: f32) {
+        self.sensor_size = sensor_size.max(f32::EPSILON);
+    }
+
+    /// Number of aperture blades, less than three gives a circular aperture
+    pub fn get_blade_count(&self) -> u32 {
+        self.blade_count
+    }
+
+    pub fn set_blade_count(&mut self, blade_count: u32) {
+        self.blade_count = blade_count;
+    }
+
+    /// Focal length in meters matching the vertical fov on the sensor
+    pub fn focal_length(&self) -> f32 {
+        (self.sensor_size * 0.001 * 0.5) / (self.fov.to_radians() * 0.5).tan()
+    }
+
+    /// Radius in meters of the thin lens, zero when depth of field is disabled
+    pub fn lens_radius(&self) -> f32 {
+        if self.aperture > 0.0 {
+            self.focal_length() / (2.0 * self.aperture)
+        } else {
+            0.0
+        }
+    }
+
     pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
         self.aspect_ratio = aspect_ratio;
         self.matrix.lock().unwrap().1 = true;
@@ -174,6 +236,13 @@ impl CameraController {
         Self::default()
     }
 
+    /// Match the controller orientation with the camera, required after the camera was moved without the controller
+    pub fn sync_rotation(&mut self, camera: &Camera) {
+        let (yaw, pitch, _) = camera.transform.get_rotation().to_euler(EulerRot::YXZ);
+        self.vertical_rotation = Quat::from_axis_angle(UP, yaw);
+        self.horizontal_rotation = Quat::from_axis_angle(RIGHT, pitch);
+    }
+
     pub fn update(&mut self, camera: &Camera, input: &InputHandler, delta_time: f32) -> Transform {
         let mut transform = camera.transform.clone();
 
@@ -0,0 +1,312 @@
+// Generates the rgb to spectrum coefficient tables used by the spectral path tracer
+// Source: https://github.com/mmp/pbrt-v4/blob/779d1a78b74aab393853544198189729434121b5/src/pbrt/cmd/rgb2spec_opt.cpp
+
+use std::{fs::File, io::Write, path::Path};
+
+use glam::{DMat3, DVec2, DVec3};
+use rayon::prelude::*;
+
+#[allow(clippy::approx_constant)]
+pub mod cie;
+
+pub const TABLE_RESOLUTION: usize = 64;
+
+const CIE_LAMBDA_MIN: f64 = 360.0;
+const CIE_LAMBDA_MAX: f64 = 830.0;
+const CIE_FINE_SAMPLES: usize = (cie::CIE_LAMBDA.len() - 1) * 3 / 5 + 1;
+
+const GAUSS_NEWTON_ITERATIONS: usize = 15;
+const MAX_COEFFICIENT: f64 = 200.0;
+
+struct ColorSpaceTables {
+    lambda: Vec<f64>,
+    rgb: Vec<DVec3>,
+    rgb_to_xyz: DMat3,
+    xyz_whitepoint: DVec3,
+}
+
+impl ColorSpaceTables {
+    fn new(r_xy: DVec2, g_xy: DVec2, b_xy: DVec2, illuminant: &[f32]) -> Self {
+        let h = (CIE_LAMBDA_MAX - CIE_LAMBDA_MIN) / (CIE_FINE_SAMPLES - 1) as f64;
+
+        // Simpson's 3/8 rule quadrature of the color matching functions weighted by the illuminant
+        let mut lambda = Vec::with_capacity(CIE_FINE_SAMPLES);
+        let mut xyz_weighted = Vec::with_capacity(CIE_FINE_SAMPLES);
+        let mut luminance = 0.0;
+        for i in 0..CIE_FINE_SAMPLES {
+            let l = CIE_LAMBDA_MIN + i as f64 * h;
+
+            let weight = 3.0 / 8.0 * h;
+            let weight = if i == 0 || i == CIE_FINE_SAMPLES - 1 {
+                weight
+            } else if (i - 1) % 3 == 2 {
+                weight * 2.0
+            } else {
+                weight * 3.0
+            };
+
+            let xyz = DVec3::new(
+                cie_interp(cie::CIE_X, l),
+                cie_interp(cie::CIE_Y, l),
+                cie_interp(cie::CIE_Z, l),
+            );
+            let xyz = xyz * interleaved_interp(illuminant, l) * weight;
+            luminance += xyz.y;
+
+            lambda.push(l);
+            xyz_weighted.push(xyz);
+        }
+
+        for xyz in &mut xyz_weighted {
+            *xyz /= luminance;
+        }
+        let xyz_whitepoint: DVec3 = xyz_weighted.iter().sum();
+
+        let primaries = DMat3::from_cols(xy_to_xyz(r_xy), xy_to_xyz(g_xy), xy_to_xyz(b_xy));
+        let scale = primaries.inverse() * xyz_whitepoint;
+        let rgb_to_xyz = primaries * DMat3::from_diagonal(scale);
+        let xyz_to_rgb = rgb_to_xyz.inverse();
+
+        let rgb = xyz_weighted.iter().map(|xyz| xyz_to_rgb * *xyz).collect();
+
+        Self {
+            lambda,
+            rgb,
+            rgb_to_xyz,
+            xyz_whitepoint,
+        }
+    }
+
+    fn cie_lab(&self, rgb: DVec3) -> DVec3 {
+        let xyz = self.rgb_to_xyz * rgb / self.xyz_whitepoint;
+
+        let f = |t: f64| {
+            let delta = 6.0 / 29.0;
+            if t > delta * delta * delta {
+                t.cbrt()
+            } else {
+                t / (delta * delta * 3.0) + 4.0 / 29.0
+            }
+        };
+
+        DVec3::new(
+            116.0 * f(xyz.y) - 16.0,
+            500.0 * (f(xyz.x) - f(xyz.y)),
+            200.0 * (f(xyz.y) - f(xyz.z)),
+        )
+    }
+
+    fn eval_residual(&self, coeffs: DVec3, rgb: DVec3) -> DVec3 {
+        let mut out = DVec3::ZERO;
+        for (lambda, rgb_weight) in self.lambda.iter().zip(&self.rgb) {
+            let lambda = (lambda - CIE_LAMBDA_MIN) / (CIE_LAMBDA_MAX - CIE_LAMBDA_MIN);
+            let x = (coeffs.x * lambda + coeffs.y) * lambda + coeffs.z;
+            out += *rgb_weight * sigmoid(x);
+        }
+
+        self.cie_lab(rgb) - self.cie_lab(out)
+    }
+
+    fn eval_jacobian(&self, coeffs: DVec3, rgb: DVec3) -> DMat3 {
+        const EPSILON: f64 = 1e-4;
+
+        let mut cols = [DVec3::ZERO; 3];
+        for (i, col) in cols.iter_mut().enumerate() {
+            let mut tmp = coeffs;
+            tmp[i] -= EPSILON;
+            let r0 = self.eval_residual(tmp, rgb);
+
+            let mut tmp = coeffs;
+            tmp[i] += EPSILON;
+            let r1 = self.eval_residual(tmp, rgb);
+
+            *col = (r1 - r0) / (2.0 * EPSILON);
+        }
+
+        DMat3::from_cols(cols[0], cols[1], cols[2])
+    }
+
+    fn gauss_newton(&self, rgb: DVec3, coeffs: &mut DVec3) {
+        for _ in 0..GAUSS_NEWTON_ITERATIONS {
+            let residual = self.eval_residual(*coeffs, rgb);
+            let jacobian = self.eval_jacobian(*coeffs, rgb);
+
+            if jacobian.determinant().abs() < 1e-15 {
+                break;
+            }
+
+            *coeffs -= jacobian.inverse() * residual;
+
+            let max = coeffs.max_element();
+            if max > MAX_COEFFICIENT {
+                *coeffs *= MAX_COEFFICIENT / max;
+            }
+
+            if residual.length_squared() < 1e-6 {
+                break;
+            }
+        }
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    0.5 * x / (1.0 + x * x).sqrt() + 0.5
+}
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+fn xy_to_xyz(xy: DVec2) -> DVec3 {
+    DVec3::new(xy.x / xy.y, 1.0, (1.0 - xy.x - xy.y) / xy.y)
+}
+
+fn cie_interp(data: &[f32], lambda: f64) -> f64 {
+    let x = (lambda - CIE_LAMBDA_MIN) * (data.len() - 1) as f64 / (CIE_LAMBDA_MAX - CIE_LAMBDA_MIN);
+    let offset = (x.max(0.0) as usize).min(data.len() - 2);
+    let weight = x - offset as f64;
+
+    (1.0 - weight) * data[offset] as f64 + weight * data[offset + 1] as f64
+}
+
+fn interleaved_interp(data: &[f32], lambda: f64) -> f64 {
+    let sample_count = data.len() / 2;
+    let sample = |i: usize| (data[i * 2] as f64, data[i * 2 + 1] as f64);
+
+    let offset = (0..sample_count - 1)
+        .find(|i| sample(i + 1).0 >= lambda)
+        .unwrap_or(sample_count - 2);
+    let (lambda0, value0) = sample(offset);
+    let (lambda1, value1) = sample(offset + 1);
+    let weight = ((lambda - lambda0) / (lambda1 - lambda0)).clamp(0.0, 1.0);
+
+    (1.0 - weight) * value0 + weight * value1
+}
+
+fn optimize_tables(tables: &ColorSpaceTables) -> (Vec<f32>, Vec<f32>) {
+    let res = TABLE_RESOLUTION;
+
+    let scale: Vec<f64> = (0..res)
+        .map(|k| smoothstep(smoothstep(k as f64 / (res - 1) as f64)))
+        .collect();
+
+    let mut out = vec![0.0f32; 3 * 3 * res * res * res];
+    for l in 0..3 {
+        let slice = &mut out[l * 3 * res * res * res..(l + 1) * 3 * res * res * res];
+
+        let rows: Vec<Vec<(usize, [f32; 3])>> = (0..res)
+            .into_par_iter()
+            .map(|j| {
+                let y = j as f64 / (res - 1) as f64;
+                let mut row = Vec::with_capacity(res * res);
+
+                for i in 0..res {
+                    let x = i as f64 / (res - 1) as f64;
+                    let start = res / 5;
+
+                    let mut solve = |k: usize, coeffs: &mut DVec3| {
+                        let b = scale[k];
+                        let mut rgb = DVec3::ZERO;
+                        rgb[l] = b;
+                        rgb[(l + 1) % 3] = x * b;
+                        rgb[(l + 2) % 3] = y * b;
+
+                        tables.gauss_newton(rgb, coeffs);
+                        row.push(((k * res + j) * res + i, remap_coefficients(*coeffs)));
+                    };
+
+                    let mut coeffs = DVec3::ZERO;
+                    for k in start..res {
+                        solve(k, &mut coeffs);
+                    }
+
+                    let mut coeffs = DVec3::ZERO;
+                    for k in (0..=start).rev() {
+                        solve(k, &mut coeffs);
+                    }
+                }
+
+                row
+            })
+            .collect();
+
+        for (idx, coeffs) in rows.into_iter().flatten() {
+            slice[idx * 3..idx * 3 + 3].copy_from_slice(&coeffs);
+        }
+    }
+
+    (scale.iter().map(|s| *s as f32).collect(), out)
+}
+
+/// Convert coefficients from the normalized [0, 1] wavelength domain to nanometers
+fn remap_coefficients(coeffs: DVec3) -> [f32; 3] {
+    let c0 = CIE_LAMBDA_MIN;
+    let c1 = 1.0 / (CIE_LAMBDA_MAX - CIE_LAMBDA_MIN);
+    let (a, b, c) = (coeffs.x, coeffs.y, coeffs.z);
+
+    [
+        (a * c1 * c1) as f32,
+        (b * c1 - 2.0 * a * c0 * c1 * c1) as f32,
+        (c - b * c0 * c1 + a * (c0 * c1) * (c0 * c1)) as f32,
+    ]
+}
+
+fn write_f32s(path: &Path, data: &[f32]) -> std::io::Result<()> {
+    let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_ne_bytes()).collect();
+    File::create(path)?.write_all(&bytes)
+}
+
+/// Writes `<path>.acss` containing the brightness scales and `<path>.acsc` containing the coefficients
+fn write_tables(
+    path: &Path,
+    r_xy: DVec2,
+    g_xy: DVec2,
+    b_xy: DVec2,
+    illuminant: &[f32],
+) -> std::io::Result<()> {
+    let tables = ColorSpaceTables::new(r_xy, g_xy, b_xy, illuminant);
+    let (scales, coeffs) = optimize_tables(&tables);
+
+    write_f32s(&path.with_extension("acss"), &scales)?;
+    write_f32s(&path.with_extension("acsc"), &coeffs)
+}
+
+pub fn write_aces_tables<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    write_tables(
+        path.as_ref(),
+        DVec2::new(0.7347, 0.2653),
+        DVec2::new(0.0, 1.0),
+        DVec2::new(0.0001, -0.077),
+        cie::ACES_ILLUM_D60,
+    )
+}
+
+pub fn write_dci_p3_tables<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    write_tables(
+        path.as_ref(),
+        DVec2::new(0.68, 0.32),
+        DVec2::new(0.265, 0.690),
+        DVec2::new(0.15, 0.06),
+        cie::CIE_ILLUM_D6500,
+    )
+}
+
+pub fn write_rec2020_tables<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    write_tables(
+        path.as_ref(),
+        DVec2::new(0.708, 0.292),
+        DVec2::new(0.170, 0.797),
+        DVec2::new(0.131, 0.046),
+        cie::CIE_ILLUM_D6500,
+    )
+}
+
+pub fn write_srgb_tables<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    write_tables(
+        path.as_ref(),
+        DVec2::new(0.64, 0.33),
+        DVec2::new(0.3, 0.6),
+        DVec2::new(0.15, 0.06),
+        cie::CIE_ILLUM_D6500,
+    )
+}
@@ -2,7 +2,10 @@ use core::ops::FnMut;
 use std::sync::Arc;
 
 use appearance_path_tracer_gpu::{PathTracerGpu, PathTracerGpuConfig};
-use appearance_render_loop::node::NodeRenderer;
+use appearance_render_loop::{
+    host::{NodeCapabilities, RendererKind, NODE_BYTES_PER_PIXEL, RENDER_BLOCK_SIZE},
+    node::NodeRenderer,
+};
 use appearance_wgpu::{pipeline_database::PipelineDatabase, wgpu, Context};
 use appearance_world::visible_world_action::VisibleWorldActionType;
 use futures::executor::block_on;
@@ -63,6 +66,30 @@ impl NodeRenderer for DistributedRenderer {
             .handle_visible_world_action(action, &self.ctx);
     }
 
+    fn provide_asset(&mut self, path: &str, bytes: &[u8]) {
+        self.path_tracer.provide_asset(path, bytes);
+    }
+
+    fn capabilities(&self) -> NodeCapabilities {
+        let adapter_info = self.ctx.adapter.get_info();
+
+        NodeCapabilities {
+            adapter_name: adapter_info.name,
+            backend: format!("{:?}", adapter_info.backend),
+            features: self.ctx.device.features(),
+            ..NodeCapabilities::new(RendererKind::Gpu)
+        }
+    }
+
+    fn set_hdr_output(&mut self, hdr_output: bool) -> bool {
+        self.path_tracer.set_hdr_output(hdr_output);
+        true
+    }
+
+    fn set_sample_index(&mut self, sample_index: Option<u32>) {
+        self.path_tracer.set_sample_index(sample_index);
+    }
+
     fn render<F: FnMut(&[u8])>(
         &mut self,
         resolution: UVec2,
@@ -79,4 +106,40 @@ impl NodeRenderer for DistributedRenderer {
             &mut self.pipeline_database,
         );
     }
+
+    /// Renders the rectangle enclosing the blocks once, rather than the full rows the default does
+    fn render_blocks<F: FnMut(UVec2, &[u8])>(
+        &mut self,
+        resolution: UVec2,
+        blocks: &[UVec2],
+        mut result_callback: F,
+    ) {
+        let (Some(min_block), Some(max_block)) = (
+            blocks.iter().copied().reduce(UVec2::min),
+            blocks.iter().copied().reduce(UVec2::max),
+        ) else {
+            return;
+        };
+
+        let num_blocks_x = max_block.x - min_block.x + 1;
+        let block_bytes = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize * NODE_BYTES_PER_PIXEL;
+
+        self.path_tracer.render_region(
+            resolution,
+            min_block * RENDER_BLOCK_SIZE,
+            ((max_block + 1) * RENDER_BLOCK_SIZE).min(resolution),
+            |pixels| {
+                for block in blocks {
+                    let local_block = *block - min_block;
+                    let block_idx = (local_block.y * num_blocks_x + local_block.x) as usize;
+                    result_callback(
+                        *block,
+                        &pixels[(block_idx * block_bytes)..((block_idx + 1) * block_bytes)],
+                    );
+                }
+            },
+            &self.ctx,
+            &mut self.pipeline_database,
+        );
+    }
 }
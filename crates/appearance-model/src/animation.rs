@@ -0,0 +1,153 @@
+use std::ops::{Add, Mul};
+
+use glam::{Mat4, Quat, Vec3};
+
+use crate::ModelNode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+#[derive(Debug, Clone)]
+pub enum AnimationOutputs {
+    Translations(Vec<Vec3>),
+    Rotations(Vec<Quat>),
+    Scales(Vec<Vec3>),
+}
+
+/// Keyframes of a single node property. Cubic spline outputs hold an in-tangent, value and out-tangent per keyframe.
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    pub node: u32,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub outputs: AnimationOutputs,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<AnimationChannel>,
+    /// Time of the last keyframe in seconds
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    /// Local transforms of all nodes at `time` in seconds, nodes without channels keep their rest transform.
+    /// A looping clip wraps around its duration, otherwise the time is clamped.
+    pub fn sample(&self, time: f32, looping: bool, nodes: &[ModelNode]) -> Vec<Mat4> {
+        let time = if looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time.clamp(0.0, self.duration)
+        };
+
+        let mut node_transforms: Vec<(Vec3, Quat, Vec3)> = nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.transform.get_translation(),
+                    node.transform.get_rotation(),
+                    node.transform.get_scale(),
+                )
+            })
+            .collect();
+
+        for channel in &self.channels {
+            let Some((translation, rotation, scale)) =
+                node_transforms.get_mut(channel.node as usize)
+            else {
+                continue;
+            };
+
+            match &channel.outputs {
+                AnimationOutputs::Translations(values) => {
+                    if let Some(value) = channel.sample(values, time, Vec3::lerp) {
+                        *translation = value;
+                    }
+                }
+                AnimationOutputs::Rotations(values) => {
+                    if let Some(value) = channel.sample(values, time, Quat::slerp) {
+                        *rotation = value.normalize();
+                    }
+                }
+                AnimationOutputs::Scales(values) => {
+                    if let Some(value) = channel.sample(values, time, Vec3::lerp) {
+                        *scale = value;
+                    }
+                }
+            }
+        }
+
+        node_transforms
+            .into_iter()
+            .map(|(translation, rotation, scale)| {
+                Mat4::from_scale_rotation_translation(scale, rotation, translation)
+            })
+            .collect()
+    }
+}
+
+impl AnimationChannel {
+    /// Number of output values stored per keyframe
+    pub fn values_per_keyframe(&self) -> usize {
+        match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        }
+    }
+
+    fn sample<T>(&self, values: &[T], time: f32, lerp: fn(T, T, f32) -> T) -> Option<T>
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+    {
+        let stride = self.values_per_keyframe();
+        let value =
+            |keyframe: usize, offset: usize| values.get(keyframe * stride + offset).copied();
+        // Cubic spline keyframes store the value in between its tangents
+        let value_offset = stride / 2;
+
+        let last = self.times.len().checked_sub(1)?;
+        let next = self
+            .times
+            .partition_point(|keyframe_time| *keyframe_time <= time);
+        if next == 0 {
+            return value(0, value_offset);
+        }
+        if next > last {
+            return value(last, value_offset);
+        }
+
+        let prev = next - 1;
+        let delta_time = self.times[next] - self.times[prev];
+        let t = if delta_time > 0.0 {
+            (time - self.times[prev]) / delta_time
+        } else {
+            0.0
+        };
+
+        match self.interpolation {
+            Interpolation::Step => value(prev, 0),
+            Interpolation::Linear => Some(lerp(value(prev, 0)?, value(next, 0)?, t)),
+            Interpolation::CubicSpline => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+
+                let p0 = value(prev, 1)?;
+                let m0 = value(prev, 2)? * delta_time;
+                let p1 = value(next, 1)?;
+                let m1 = value(next, 0)? * delta_time;
+
+                Some(
+                    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+                        + m0 * (t3 - 2.0 * t2 + t)
+                        + p1 * (-2.0 * t3 + 3.0 * t2)
+                        + m1 * (t3 - t2),
+                )
+            }
+        }
+    }
+}
@@ -10,6 +10,7 @@ use image::DynamicImage;
 use uuid::Uuid;
 
 use crate::{
+    animation::{AnimationChannel, AnimationClip, AnimationOutputs, Interpolation},
     material::Material,
     mesh::{generate_normals, generate_tangents, Mesh},
     Model, ModelNode,
@@ -31,6 +32,7 @@ impl Asset for Model {
         let mut nodes = Vec::new();
         let mut meshes = Vec::new();
         meshes.resize_with(document.meshes().len(), Default::default);
+        let mut node_mapping = vec![None; document.nodes().len()];
 
         if let Some(scene) = document.default_scene() {
             for root_node in scene.nodes() {
@@ -41,6 +43,7 @@ impl Asset for Model {
                     &buffers,
                     &images,
                     &mut nodes,
+                    &mut node_mapping,
                     &mut internal_images,
                     &mut materials,
                     &mut meshes,
@@ -50,11 +53,17 @@ impl Asset for Model {
 
         let meshes = meshes.into_iter().map(|mesh| mesh.unwrap()).collect();
 
+        let animations = document
+            .animations()
+            .map(|animation| process_animation(&animation, &buffers, &node_mapping))
+            .collect();
+
         Ok(Model {
             root_nodes,
             materials,
             meshes,
             nodes,
+            animations,
             uuid: Uuid::new_v4(),
         })
     }
@@ -71,10 +80,12 @@ fn process_nodes_recursive(
     buffers: &[gltf::buffer::Data],
     images: &[gltf::image::Data],
     nodes: &mut Vec<ModelNode>,
+    node_mapping: &mut [Option<u32>],
     internal_images: &mut Vec<Option<Arc<Texture>>>,
     materials: &mut Vec<Material>,
     meshes: &mut Vec<Option<Mesh>>,
 ) {
+    node_mapping[node.index()] = Some(nodes.len() as u32);
     nodes.push(process_node(
         document,
         node,
@@ -95,6 +106,7 @@ fn process_nodes_recursive(
             buffers,
             images,
             nodes,
+            node_mapping,
             internal_images,
             materials,
             meshes,
@@ -102,6 +114,85 @@ fn process_nodes_recursive(
     }
 }
 
+/// Load all translation, rotation and scale channels of an animation, `node_mapping` maps gltf node indices to model node indices
+fn process_animation(
+    animation: &gltf::Animation,
+    buffers: &[gltf::buffer::Data],
+    node_mapping: &[Option<u32>],
+) -> AnimationClip {
+    appearance_profiling::profile_function!();
+
+    let name = animation.name().unwrap_or("Unnamed").to_owned();
+
+    let mut channels = vec![];
+    let mut duration: f32 = 0.0;
+    for channel in animation.channels() {
+        // Nodes outside of the default scene aren't part of the model
+        let Some(node) = node_mapping[channel.target().node().index()] else {
+            continue;
+        };
+
+        let interpolation = match channel.sampler().interpolation() {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        };
+
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(times) = reader
+            .read_inputs()
+            .map(|inputs| inputs.collect::<Vec<f32>>())
+        else {
+            continue;
+        };
+
+        let outputs = match reader.read_outputs() {
+            Some(gltf::animation::util::ReadOutputs::Translations(translations)) => {
+                AnimationOutputs::Translations(translations.map(Vec3::from).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::Rotations(rotations)) => {
+                AnimationOutputs::Rotations(rotations.into_f32().map(Quat::from_array).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::Scales(scales)) => {
+                AnimationOutputs::Scales(scales.map(Vec3::from).collect())
+            }
+            // Morph targets aren't supported
+            _ => continue,
+        };
+
+        let channel = AnimationChannel {
+            node,
+            interpolation,
+            times,
+            outputs,
+        };
+
+        let output_count = match &channel.outputs {
+            AnimationOutputs::Translations(values) => values.len(),
+            AnimationOutputs::Rotations(values) => values.len(),
+            AnimationOutputs::Scales(values) => values.len(),
+        };
+        if output_count != channel.times.len() * channel.values_per_keyframe() {
+            log::warn!(
+                "Skipping animation channel of {} with mismatching keyframe count.",
+                name
+            );
+            continue;
+        }
+
+        if let Some(last_time) = channel.times.last() {
+            duration = duration.max(*last_time);
+        }
+        channels.push(channel);
+    }
+
+    AnimationClip {
+        name,
+        channels,
+        duration,
+    }
+}
+
 fn process_node(
     document: &gltf::Document,
     node: &gltf::Node,
@@ -1,8 +1,10 @@
+use animation::AnimationClip;
 use appearance_transform::Transform;
 use material::Material;
 use mesh::Mesh;
 use uuid::Uuid;
 
+pub mod animation;
 pub mod asset;
 pub mod material;
 pub mod mesh;
@@ -23,5 +25,25 @@ pub struct Model {
     pub materials: Vec<Material>,
     pub meshes: Vec<Mesh>,
     pub nodes: Vec<ModelNode>,
+    pub animations: Vec<AnimationClip>,
     uuid: Uuid,
 }
+
+impl Model {
+    /// Model with a single root node holding `mesh`, used for geometry which isn't loaded from an asset
+    pub fn from_mesh(mesh: Mesh, material: Material) -> Self {
+        Self {
+            root_nodes: vec![0],
+            materials: vec![material],
+            meshes: vec![mesh],
+            nodes: vec![ModelNode {
+                name: "Mesh".to_owned(),
+                transform: Transform::default(),
+                children: vec![],
+                mesh: Some(0),
+            }],
+            animations: vec![],
+            uuid: Uuid::new_v4(),
+        }
+    }
+}
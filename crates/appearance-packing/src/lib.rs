@@ -41,6 +41,11 @@ impl PackedRgb9e5 {
             data: e | (rgb_as_u32.z << 18) | (rgb_as_u32.y << 9) | (rgb_as_u32.x & 0x1FFu32),
         }
     }
+
+    pub fn unpack(&self) -> Vec3 {
+        let rgb = UVec3::new(self.data, self.data >> 9, self.data >> 18) & UVec3::splat(0x1FF);
+        rgb.as_vec3() * 2.0f32.powi((self.data >> 27) as i32 - 24)
+    }
 }
 
 impl Default for PackedNormalizedXyz10 {
@@ -57,6 +62,14 @@ impl PackedNormalizedXyz10 {
 
         Self { data }
     }
+
+    pub fn unpack(&self) -> Vec3 {
+        let oct_encoded_dir = Vec2::new(
+            (self.data & 0x7fff) as f32 / (0x7fff as f32),
+            ((self.data >> 15) & 0x7fff) as f32 / (0x7fff as f32),
+        );
+        dir_oct_quad_decode(oct_encoded_dir)
+    }
 }
 
 // Inspired by https://knarkowicz.wordpress.com/2014/04/16/octahedron-normal-vector-encoding/
@@ -79,3 +92,17 @@ fn dir_oct_quad_encode(dir: Vec3) -> Vec2 {
     }
     ret_val * 0.5 + 0.5
 }
+
+// Inspired by https://knarkowicz.wordpress.com/2014/04/16/octahedron-normal-vector-encoding/
+fn dir_oct_quad_decode(encoded: Vec2) -> Vec3 {
+    let encoded = encoded * 2.0 - 1.0;
+    let mut n = Vec3::new(
+        encoded.x,
+        encoded.y,
+        1.0 - encoded.x.abs() - encoded.y.abs(),
+    );
+    let t = (-n.z).clamp(0.0, 1.0);
+    n.x += if n.x >= 0.0 { -t } else { t };
+    n.y += if n.y >= 0.0 { -t } else { t };
+    n.normalize()
+}
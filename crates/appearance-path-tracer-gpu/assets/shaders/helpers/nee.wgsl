@@ -13,6 +13,34 @@
 /// appearance-path-tracer-gpu::shared/sky_bindings
 ///
 
+fn Light::load_eval_data(_self: Light, uv: vec2<f32>, hit_point_ws: vec3<f32>) -> LightSampleEvalData {
+    switch (_self.ty) {
+        case LIGHT_TYPE_DIRECTIONAL: {
+            return LightSampleEvalData::new(_self.emission, hit_point_ws - _self.direction * SUN_DISTANCE);
+        }
+        case LIGHT_TYPE_RECT: {
+            let point_ws: vec3<f32> = _self.position + _self.right * (uv.x * 2.0 - 1.0) + _self.up * (uv.y * 2.0 - 1.0);
+            let direction: vec3<f32> = normalize(point_ws - hit_point_ws);
+            let distance: f32 = distance(point_ws, hit_point_ws);
+
+            let emission: vec3<f32> = _self.emission * Triangle::solid_angle(_self.direction, direction, distance);
+
+            return LightSampleEvalData::new(emission, point_ws);
+        }
+        default: {
+            let direction: vec3<f32> = normalize(_self.position - hit_point_ws);
+            let distance: f32 = distance(_self.position, hit_point_ws);
+
+            var emission: vec3<f32> = _self.emission / max(sqr(distance), 1e-4);
+            if (_self.ty == LIGHT_TYPE_SPOT) {
+                emission *= smoothstep(_self.cos_outer, _self.cos_inner, dot(_self.direction, -direction));
+            }
+
+            return LightSampleEvalData::new(emission, _self.position);
+        }
+    }
+}
+
 fn LightSample::load_eval_data(_self: LightSample, hit_point_ws: vec3<f32>) -> LightSampleEvalData {
     if (LightSample::is_sun(_self)) {
         let direction: vec3<f32> =  Sky::direction_to_sun(_self.uv);
@@ -21,6 +49,8 @@ fn LightSample::load_eval_data(_self: LightSample, hit_point_ws: vec3<f32>) -> L
         let emission: vec3<f32> =  Sky::sun_intensity(direction) * sky_constants.sun_color;
 
         return LightSampleEvalData::new(emission, point_ws);
+    } else if (LightSample::is_light(_self)) {
+        return Light::load_eval_data(lights[_self.local_triangle_idx], _self.uv, hit_point_ws);
     } else {
         let emissive_triangle_instance: EmissiveTriangleInstance = emissive_triangle_instances[_self.emissive_triangle_instance_idx]; // TODO: speedup
         let vertex_pool_slice: VertexPoolSlice = vertex_pool_slices[emissive_triangle_instance.vertex_pool_slice_idx];
@@ -56,7 +86,7 @@ fn LightSample::load_eval_data(_self: LightSample, hit_point_ws: vec3<f32>) -> L
     }
 }
 
-fn Nee::sample_emissive_triangle(r0: f32, r1: f32, r23: vec2<f32>, sample_point: vec3<f32>, sun_pick_probability: f32, pdf: ptr<function, f32>) -> LightSample {
+fn Nee::sample_emissive_triangle(r0: f32, r1: f32, r23: vec2<f32>, sample_point: vec3<f32>, pick_probability: f32, pdf: ptr<function, f32>) -> LightSample {
     for (var i: u32 = 0; i < vertex_pool_constants.num_emissive_triangle_instances; i += 1) {
         if (r0 <= emissive_triangle_instance_cdf[i]) {
             let emissive_triangle_instance: EmissiveTriangleInstance = emissive_triangle_instances[i];
@@ -82,7 +112,7 @@ fn Nee::sample_emissive_triangle(r0: f32, r1: f32, r23: vec2<f32>, sample_point:
 
             *pdf = 1.0 / f32(vertex_pool_constants.num_emissive_triangles);
             *pdf /= triangle_area;
-            *pdf = max(1e-6, (*pdf) * (1.0 - sun_pick_probability));
+            *pdf = max(1e-6, (*pdf) * pick_probability);
 
             return LightSample::new_triangle_sample(r23, i, local_triangle_idx);
         }
@@ -99,18 +129,32 @@ fn Nee::sample_sun(r01: vec2<f32>, sun_pick_probability: f32, pdf: ptr<function,
     return LightSample::new_sun_sample(r01);
 }
 
-fn Nee::sample_uniform(r0: f32, r1: f32, r2: f32, r34: vec2<f32>, sample_point: vec3<f32>, pdf: ptr<function, f32>) -> LightSample {
-    var sun_pick_probability: f32;
-    if (vertex_pool_constants.num_emissive_triangles > 0) {
-        sun_pick_probability = 0.5;
-    } else {
-        sun_pick_probability = 1.0;
+fn Nee::sample_light(r0: f32, r12: vec2<f32>, pick_probability: f32, pdf: ptr<function, f32>) -> LightSample {
+    let light_idx: u32 = min(u32(r0 * f32(sky_constants.num_lights)), sky_constants.num_lights - 1);
+    let light: Light = lights[light_idx];
+
+    *pdf = pick_probability / f32(sky_constants.num_lights);
+    if (light.ty == LIGHT_TYPE_RECT) {
+        let area: f32 = 4.0 * length(light.right) * length(light.up);
+        *pdf = max(1e-6, (*pdf) / area);
     }
 
-    if (r0 < sun_pick_probability) {
-        return Nee::sample_sun(r34, sun_pick_probability, pdf);
+    return LightSample::new_light_sample(r12, light_idx);
+}
+
+fn Nee::sample_uniform(r0: f32, r1: f32, r2: f32, r34: vec2<f32>, sample_point: vec3<f32>, pdf: ptr<function, f32>) -> LightSample {
+    let has_emissive_triangles: bool = vertex_pool_constants.num_emissive_triangles > 0;
+    let has_lights: bool = sky_constants.num_lights > 0;
+
+    // Pick uniformly between the sun, emissive triangles and lights placed in the world
+    let pick_probability: f32 = 1.0 / (1.0 + select(0.0, 1.0, has_emissive_triangles) + select(0.0, 1.0, has_lights));
+
+    if (r0 < pick_probability) {
+        return Nee::sample_sun(r34, pick_probability, pdf);
+    } else if (has_lights && (!has_emissive_triangles || r0 < pick_probability * 2.0)) {
+        return Nee::sample_light(r1, r34, pick_probability, pdf);
     } else {
-        return Nee::sample_emissive_triangle(r1, r2, r34, sample_point, sun_pick_probability, pdf);
+        return Nee::sample_emissive_triangle(r1, r2, r34, sample_point, pick_probability, pdf);
     }
 }
 
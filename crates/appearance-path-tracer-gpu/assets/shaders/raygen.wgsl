@@ -1,3 +1,4 @@
+@include ::math
 @include ::random
 @include appearance-path-tracer-gpu::shared/ray
 
@@ -8,6 +9,10 @@ struct Constants {
     height: u32,
     seed: u32,
     _padding0: u32,
+    lens_radius: f32,
+    focus_distance: f32,
+    blade_count: u32,
+    _padding1: u32,
 }
 
 @group(0)
@@ -18,6 +23,27 @@ var<uniform> constants: Constants;
 @binding(1)
 var<storage, read_write> rays: array<Ray>;
 
+// Point on the unit aperture, a regular polygon with a blade per side or a circle with fewer than three blades
+fn sample_aperture(u: vec2<f32>) -> vec2<f32> {
+    if (constants.blade_count < 3u) {
+        let r: f32 = sqrt(u.x);
+        let theta: f32 = TWO_PI * u.y;
+        return vec2<f32>(cos(theta), sin(theta)) * r;
+    }
+
+    let sides: f32 = f32(constants.blade_count);
+    let corner_u: f32 = u.x * sides;
+    let corner: f32 = min(floor(corner_u), sides - 1.0);
+    let u_edge: f32 = corner_u - corner;
+
+    let angle_a: f32 = TWO_PI * corner / sides;
+    let angle_b: f32 = TWO_PI * (corner + 1.0) / sides;
+    let a = vec2<f32>(cos(angle_a), sin(angle_a));
+    let b = vec2<f32>(cos(angle_b), sin(angle_b));
+
+    return sqrt(u.y) * mix(a, b, u_edge);
+}
+
 @compute
 @workgroup_size(16, 16)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
@@ -32,9 +58,20 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
     let pixel_center = vec2<f32>(f32(id.x) + 0.5, f32(id.y) + 0.5);
     var uv: vec2<f32> = (pixel_center / vec2<f32>(f32(constants.width), f32(constants.height))) * 2.0 - 1.0;
     uv.y = -uv.y;
-    let origin: vec4<f32> = constants.inv_view * vec4<f32>(0.0, 0.0, 0.0, 1.0);
     let targt: vec4<f32> = constants.inv_proj * vec4<f32>(uv, 1.0, 1.0);
-    let direction: vec4<f32> = constants.inv_view * vec4<f32>(normalize(targt.xyz), 0.0);
+    var local_origin = vec3<f32>(0.0);
+    var local_direction: vec3<f32> = normalize(targt.xyz);
+
+    if (constants.lens_radius > 0.0) {
+        // Camera space looks down -z, refocus through the point where the pinhole ray crosses the plane of focus
+        let focus_point: vec3<f32> = local_direction * (constants.focus_distance / -local_direction.z);
+        let lens_point: vec2<f32> = constants.lens_radius * sample_aperture(random_uniform_float2(&rng));
+        local_origin = vec3<f32>(lens_point, 0.0);
+        local_direction = normalize(focus_point - local_origin);
+    }
+
+    let origin: vec4<f32> = constants.inv_view * vec4<f32>(local_origin, 1.0);
+    let direction: vec4<f32> = constants.inv_view * vec4<f32>(local_direction, 0.0);
 
-    rays[id.y * constants.width + id.x] = Ray::new(origin.xyz, direction.xyz);
+    rays[id.y * constants.width + id.x] = Ray::new(origin.xyz, normalize(direction.xyz));
 }
\ No newline at end of file
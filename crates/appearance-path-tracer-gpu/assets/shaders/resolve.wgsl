@@ -8,6 +8,10 @@ struct Constants {
     height: u32,
     sample_count: u32,
     accum_frame_count: u32,
+    hdr_output: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
 }
 
 @group(0)
@@ -47,13 +51,21 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
 
     var radiance: vec3<f32> = PackedRgb9e5::unpack(radiance[i]);
     radiance /= f32(constants.sample_count);
-    radiance = hdr_to_sdr(radiance);
+    if (constants.hdr_output == 0) {
+        radiance = hdr_to_sdr(radiance);
+    }
 
     var accumulated_radiance: vec3<f32> = accum_radiance[i].rgb;
     accumulated_radiance += radiance;
     accum_radiance[i] = vec4<f32>(accumulated_radiance, 0.0);
 
     let block_id: vec2<u32> = linear_to_block_pixel_idx(id, constants.width);
-    textureStore(texture, vec2(i32(block_id.x), i32(block_id.y)), vec4(accumulated_radiance / f32(constants.accum_frame_count + 1), 1.0));
+    var color: vec4<f32> = vec4(accumulated_radiance / f32(constants.accum_frame_count + 1), 1.0);
+    if (constants.hdr_output != 0) {
+        // Store the packed bits through the rgba8 target byte by byte, n / 255 survives unorm quantization exactly
+        let packed: u32 = PackedRgb9e5::new(color.rgb).data;
+        color = vec4<f32>(vec4<u32>(packed, packed >> 8u, packed >> 16u, packed >> 24u) & vec4<u32>(0xFFu)) / 255.0;
+    }
+    textureStore(texture, vec2(i32(block_id.x), i32(block_id.y)), color);
     //textureStore(texture, vec2(i32(block_id.x), i32(block_id.y)), vec4(radiance, 1.0));
 }
\ No newline at end of file
@@ -6,7 +6,7 @@ struct LightSampleEvalData {
     point_ws: vec3<f32>,
 }
 
-// Used for sampling lights in the world, can both sample emissive triangles or the sun
+// Used for sampling lights in the world, can sample emissive triangles, the sun or lights placed in the world
 struct LightSample {
     uv: vec2<f32>,
     emissive_triangle_instance_idx: u32,
@@ -44,6 +44,10 @@ fn LightSample::new_sun_sample(uv: vec2<f32>) -> LightSample {
     return LightSample(uv, U32_MAX - 1, 0);
 }
 
+fn LightSample::new_light_sample(uv: vec2<f32>, light_idx: u32) -> LightSample {
+    return LightSample(uv, U32_MAX - 2, light_idx);
+}
+
 fn LightSample::empty() -> LightSample {
     return LightSample(vec2<f32>(0.0), U32_MAX, 0);
 }
@@ -56,6 +60,10 @@ fn LightSample::is_sun(_self: LightSample) -> bool {
     return _self.emissive_triangle_instance_idx == U32_MAX - 1;
 }
 
+fn LightSample::is_light(_self: LightSample) -> bool {
+    return _self.emissive_triangle_instance_idx == U32_MAX - 2;
+}
+
 fn PackedLightSample::new(light_sample: LightSample) -> PackedLightSample {
     return PackedLightSample(
         light_sample.uv,
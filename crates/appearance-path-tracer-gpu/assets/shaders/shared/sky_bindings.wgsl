@@ -3,11 +3,34 @@
 
 const SUN_DISTANCE: f32 = 1e+6;
 
+const LIGHT_TYPE_POINT: u32 = 0;
+const LIGHT_TYPE_SPOT: u32 = 1;
+const LIGHT_TYPE_DIRECTIONAL: u32 = 2;
+const LIGHT_TYPE_RECT: u32 = 3;
+
 struct SkyConstants {
     sun_direction: vec3<f32>,
     sun_size: f32,
     sun_color: vec3<f32>,   
     sun_intensity: f32,
+    num_lights: u32,
+    sky_intensity: f32,
+    sky_rotation: f32,
+    _padding0: u32,
+}
+
+// Light placed in the world, rect lights are one sided and emit along their direction
+struct Light {
+    position: vec3<f32>,
+    ty: u32,
+    direction: vec3<f32>,
+    cos_inner: f32,
+    emission: vec3<f32>,
+    cos_outer: f32,
+    right: vec3<f32>,
+    _padding0: u32,
+    up: vec3<f32>,
+    _padding1: u32,
 }
 
 @group(3)
@@ -22,6 +45,10 @@ var sky_texture: texture_2d<f32>;
 @binding(2)
 var sky_texture_sampler: sampler;
 
+@group(3)
+@binding(3)
+var<storage, read> lights: array<Light>;
+
 fn Sky::sun_intensity(direction: vec3<f32>) -> f32 {
     const CUTOFF_ANGLE: f32 = PI / 1.95;
 
@@ -65,8 +92,16 @@ fn Sky::sun_solid_angle() -> f32 {
     return TWO_PI * (1.0 - cos(sky_constants.sun_size * 0.1));
 }
 
+// Rotate a world space direction into the space of the sky texture
+fn Sky::to_sky_texture_space(direction: vec3<f32>) -> vec3<f32> {
+    let s: f32 = sin(-sky_constants.sky_rotation);
+    let c: f32 = cos(-sky_constants.sky_rotation);
+    return vec3<f32>(c * direction.x + s * direction.z, direction.y, -s * direction.x + c * direction.z);
+}
+
 fn Sky::sky(direction: vec3<f32>, skip_sun: bool) -> vec3<f32> {
-    var sky_color = textureSampleLevel(sky_texture, sky_texture_sampler, unit_vector_to_panorama_coords(direction), 0.0).rgb;
+    let sky_direction: vec3<f32> = Sky::to_sky_texture_space(direction);
+    var sky_color = textureSampleLevel(sky_texture, sky_texture_sampler, unit_vector_to_panorama_coords(sky_direction), 0.0).rgb * sky_constants.sky_intensity;
 
     if (!skip_sun) {
         var intensity = Sky::sun_intensity(direction);
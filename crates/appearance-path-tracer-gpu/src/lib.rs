@@ -2,6 +2,7 @@
 
 use appearance_camera::Camera;
 use appearance_packing::PackedRgb9e5;
+use appearance_render_loop::host::padded_resolution;
 use appearance_wgpu::{pipeline_database::PipelineDatabase, wgpu, Context};
 use appearance_world::visible_world_action::VisibleWorldActionType;
 use apply_di_pass::ApplyDiPassParameters;
@@ -11,7 +12,7 @@ use film::Film;
 use firefly_filter_pass::FireflyFilterPassParameters;
 use gbuffer::GBuffer;
 use gbuffer_pass::GbufferPassParameters;
-use glam::{UVec2, Vec3, Vec4};
+use glam::{Mat4, UVec2, Vec3, Vec4};
 use raygen_pass::RaygenPassParameters;
 use resolve_pass::ResolvePassParameters;
 use restir_di_pass::{LightSampleCtx, PackedDiReservoir, RestirDiPass, RestirDiPassParameters};
@@ -73,7 +74,8 @@ struct SizedResources {
 
 impl SizedResources {
     fn new(resolution: UVec2, device: &wgpu::Device) -> Self {
-        let film = Film::new(resolution, device);
+        // Pixels are resolved block after block, edge blocks are padded to whole blocks
+        let film = Film::new(padded_resolution(resolution), device);
 
         let rays = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("appearance-path-tracer-gpu rays"),
@@ -247,11 +249,18 @@ impl Default for PathTracerGpuConfig {
 pub struct PathTracerGpu {
     config: PathTracerGpuConfig,
     resolution: UVec2,
+    /// Top left pixel of the region of the resolution being rendered
+    local_origin: UVec2,
     local_resolution: UVec2,
     sized_resources: SizedResources,
     camera: Camera,
     scene_resources: SceneResources,
     frame_idx: u32,
+    /// Overrides the sample index derived from `frame_idx`, set while the host accumulates samples of multiple nodes
+    sample_index: Option<u32>,
+    hdr_output: bool,
+    /// Accumulated radiance can't be mixed between ldr and hdr output
+    hdr_output_changed: bool,
 
     upload_command_encoder: Option<wgpu::CommandEncoder>,
 }
@@ -271,15 +280,37 @@ impl PathTracerGpu {
         Self {
             config,
             resolution,
+            local_origin: UVec2::ZERO,
             local_resolution: resolution,
             sized_resources,
             camera: Camera::default(),
             scene_resources,
             upload_command_encoder,
             frame_idx: 0,
+            sample_index: None,
+            hdr_output: false,
+            hdr_output_changed: false,
         }
     }
 
+    /// Output linear radiance packed as `PackedRgb9e5` instead of tonemapped 8-bit RGBX
+    pub fn set_hdr_output(&mut self, hdr_output: bool) {
+        if self.hdr_output != hdr_output {
+            self.hdr_output = hdr_output;
+            self.hdr_output_changed = true;
+        }
+    }
+
+    /// Render with the seeds of the given sample index instead of the current frame, every sample is independent of earlier frames
+    pub fn set_sample_index(&mut self, sample_index: Option<u32>) {
+        self.sample_index = sample_index;
+    }
+
+    /// Load a model asset streamed from the host, see `SceneResources::provide_model_asset`
+    pub fn provide_asset(&mut self, path: &str, bytes: &[u8]) {
+        self.scene_resources.provide_model_asset(path, bytes);
+    }
+
     pub fn handle_visible_world_action(&mut self, action: &VisibleWorldActionType, ctx: &Context) {
         match action {
             VisibleWorldActionType::CameraUpdate(data) => {
@@ -289,6 +320,10 @@ impl PathTracerGpu {
                 self.camera.set_near(data.near);
                 self.camera.set_far(data.far);
                 self.camera.set_fov(data.fov);
+                self.camera.set_aperture(data.aperture);
+                self.camera.set_focus_distance(data.focus_distance);
+                self.camera.set_sensor_size(data.sensor_size);
+                self.camera.set_blade_count(data.blade_count);
                 self.camera.transform.set_rotation(rotation);
                 self.camera.transform.set_translation(translation);
             }
@@ -301,21 +336,67 @@ impl PathTracerGpu {
         }
     }
 
-    fn resize(&mut self, resolution: UVec2, start_row: u32, end_row: u32, ctx: &Context) {
-        let local_resolution = UVec2::new(resolution.x, end_row - start_row);
+    /// Temporal history only carries over while the same region is rendered, any other region starts over
+    fn resize(&mut self, resolution: UVec2, local_origin: UVec2, local_end: UVec2, ctx: &Context) {
+        let local_resolution = local_end - local_origin;
 
-        if self.resolution != resolution || self.local_resolution != local_resolution {
+        if self.resolution != resolution
+            || self.local_origin != local_origin
+            || self.local_resolution != local_resolution
+        {
             self.resolution = resolution;
+            self.local_origin = local_origin;
             self.local_resolution = local_resolution;
             self.sized_resources = SizedResources::new(self.local_resolution, &ctx.device);
         }
     }
 
+    /// Narrow a projection down to the region being rendered, so every pass can work in the pixels of the region alone
+    fn region_projection(&self, projection: Mat4) -> Mat4 {
+        let resolution = self.resolution.as_vec2();
+        let origin = self.local_origin.as_vec2();
+        let end = origin + self.local_resolution.as_vec2();
+
+        // Ndc of the center of the region, y points up while pixel rows go down
+        let scale = resolution / self.local_resolution.as_vec2();
+        let center_x = (origin.x + end.x) / resolution.x - 1.0;
+        let center_y = 1.0 - (origin.y + end.y) / resolution.y;
+
+        Mat4::from_cols(
+            Vec4::new(scale.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, scale.y, 0.0, 0.0),
+            Vec4::Z,
+            Vec4::new(-scale.x * center_x, -scale.y * center_y, 0.0, 1.0),
+        ) * projection
+    }
+
+    /// Render the rows `start_row..end_row` of `resolution`
     pub fn render<F: FnMut(&[u8])>(
         &mut self,
         resolution: UVec2,
         start_row: u32,
         end_row: u32,
+        result_callback: F,
+        ctx: &Context,
+        pipeline_database: &mut PipelineDatabase,
+    ) {
+        self.render_region(
+            resolution,
+            UVec2::new(0, start_row),
+            UVec2::new(resolution.x, end_row),
+            result_callback,
+            ctx,
+            pipeline_database,
+        );
+    }
+
+    /// Render the pixels `region_start..region_end` of `resolution`, `region_start` has to be aligned to blocks.
+    /// The pixels are passed block after block, for the blocks covering the region only.
+    pub fn render_region<F: FnMut(&[u8])>(
+        &mut self,
+        resolution: UVec2,
+        region_start: UVec2,
+        region_end: UVec2,
         mut result_callback: F,
         ctx: &Context,
         pipeline_database: &mut PipelineDatabase,
@@ -328,15 +409,16 @@ impl PathTracerGpu {
             );
         }
 
-        self.resize(resolution, start_row, end_row, ctx);
+        self.resize(resolution, region_start, region_end, ctx);
 
         self.camera
             .set_aspect_ratio(resolution.x as f32 / resolution.y as f32);
+        let proj = self.region_projection(self.camera.get_matrix());
+        let prev_proj = self.region_projection(self.camera.get_prev_matrix());
         let inv_view = self.camera.transform.get_view_matrix().inverse();
-        let inv_proj = self.camera.get_matrix().inverse();
-        let view_proj = self.camera.get_matrix() * self.camera.transform.get_view_matrix();
-        let prev_view_proj =
-            self.camera.get_prev_matrix() * self.camera.transform.get_prev_matrix();
+        let inv_proj = proj.inverse();
+        let view_proj = proj * self.camera.transform.get_view_matrix();
+        let prev_view_proj = prev_proj * self.camera.transform.get_prev_matrix();
 
         let mut command_encoder = ctx
             .device
@@ -346,9 +428,15 @@ impl PathTracerGpu {
             .rebuild_tlas(&mut command_encoder, &ctx.queue);
 
         command_encoder.clear_buffer(&self.sized_resources.radiance, 0, None);
-        if view_proj != prev_view_proj || !self.config.accum_frames {
+        // The host averages the samples itself, accumulating them here as well would correlate them
+        if view_proj != prev_view_proj
+            || !self.config.accum_frames
+            || self.hdr_output_changed
+            || self.sample_index.is_some()
+        {
             self.sized_resources
                 .invalidate_accum_radiance(&mut command_encoder);
+            self.hdr_output_changed = false;
         }
 
         let demodulated_radiance =
@@ -371,7 +459,8 @@ impl PathTracerGpu {
 
         for sample in 0..self.config.sample_count {
             //let seed = 1337 * self.config.sample_count + sample;
-            let seed = self.frame_idx * self.config.sample_count + sample;
+            let seed =
+                self.sample_index.unwrap_or(self.frame_idx) * self.config.sample_count + sample;
 
             raygen_pass::encode(
                 &RaygenPassParameters {
@@ -379,6 +468,9 @@ impl PathTracerGpu {
                     inv_proj,
                     resolution: self.local_resolution,
                     seed,
+                    lens_radius: self.camera.lens_radius(),
+                    focus_distance: self.camera.get_focus_distance(),
+                    blade_count: self.camera.get_blade_count(),
                     rays: &self.sized_resources.rays,
                 },
                 &ctx.device,
@@ -565,6 +657,7 @@ impl PathTracerGpu {
                 resolution: self.local_resolution,
                 sample_count: self.config.sample_count,
                 accum_frame_count: self.sized_resources.accum_frame_count,
+                hdr_output: self.hdr_output,
                 radiance: &self.sized_resources.radiance,
                 accum_radiance: &self.sized_resources.accum_radiance,
                 gbuffer: &self.sized_resources.gbuffer,
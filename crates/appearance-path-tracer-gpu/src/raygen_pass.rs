@@ -16,6 +16,10 @@ struct Constants {
     height: u32,
     seed: u32,
     _padding0: u32,
+    lens_radius: f32,
+    focus_distance: f32,
+    blade_count: u32,
+    _padding1: u32,
 }
 
 pub struct RaygenPassParameters<'a> {
@@ -23,6 +27,10 @@ pub struct RaygenPassParameters<'a> {
     pub inv_proj: Mat4,
     pub resolution: UVec2,
     pub seed: u32,
+    /// Thin lens radius in world units, zero for a pinhole camera
+    pub lens_radius: f32,
+    pub focus_distance: f32,
+    pub blade_count: u32,
     pub rays: &'a wgpu::Buffer,
 }
 
@@ -86,6 +94,10 @@ pub fn encode(
             height: parameters.resolution.y,
             seed: parameters.seed,
             _padding0: 0,
+            lens_radius: parameters.lens_radius,
+            focus_distance: parameters.focus_distance,
+            blade_count: parameters.blade_count,
+            _padding1: 0,
         }),
         usage: wgpu::BufferUsages::UNIFORM,
     });
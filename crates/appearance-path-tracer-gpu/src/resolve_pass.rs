@@ -16,12 +16,18 @@ struct Constants {
     height: u32,
     sample_count: u32,
     accum_frame_count: u32,
+    hdr_output: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
 }
 
 pub struct ResolvePassParameters<'a> {
     pub resolution: UVec2,
     pub sample_count: u32,
     pub accum_frame_count: u32,
+    /// Write linear radiance packed as `PackedRgb9e5` to the target instead of tonemapped colors
+    pub hdr_output: bool,
     pub radiance: &'a wgpu::Buffer,
     pub accum_radiance: &'a wgpu::Buffer,
     pub gbuffer: &'a GBuffer,
@@ -110,6 +116,10 @@ pub fn encode(
             height: parameters.resolution.y,
             sample_count: parameters.sample_count,
             accum_frame_count: parameters.accum_frame_count,
+            hdr_output: parameters.hdr_output as u32,
+            _padding0: 0,
+            _padding1: 0,
+            _padding2: 0,
         }),
         usage: wgpu::BufferUsages::UNIFORM,
     });
@@ -3,6 +3,7 @@ use std::{collections::HashMap, num::NonZeroU32, sync::Arc};
 use appearance_asset_database::Asset;
 use appearance_model::material::Material;
 use appearance_wgpu::{empty_texture_view, wgpu};
+use appearance_world::components::MaterialOverrideComponent;
 use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
 use uuid::Uuid;
@@ -277,6 +278,56 @@ impl MaterialPool {
         self.material_descriptors.len() as u32 - 1
     }
 
+    /// Allocate overridden copies of `material_count` materials starting at `material_idx`, returns the index of the first copy
+    pub fn alloc_material_override(
+        &mut self,
+        material_idx: u32,
+        material_count: u32,
+        material_override: &MaterialOverrideComponent,
+    ) -> u32 {
+        let override_idx = self.material_descriptors.len() as u32;
+        self.material_descriptors
+            .extend_from_within(material_idx as usize..(material_idx + material_count) as usize);
+        self.update_material_override(
+            override_idx,
+            material_idx,
+            material_count,
+            material_override,
+        );
+        override_idx
+    }
+
+    /// Rewrite the overridden copies at `override_idx` of `material_count` materials starting at `material_idx`
+    pub fn update_material_override(
+        &mut self,
+        override_idx: u32,
+        material_idx: u32,
+        material_count: u32,
+        material_override: &MaterialOverrideComponent,
+    ) {
+        for i in 0..material_count as usize {
+            let mut material_descriptor = self.material_descriptors[material_idx as usize + i];
+
+            if let Some(color) = material_override.color {
+                material_descriptor.color = color;
+            }
+            if let Some(roughness) = material_override.roughness {
+                material_descriptor.roughness = roughness;
+            }
+            if let Some(metallic) = material_override.metallic {
+                material_descriptor.metallic = metallic;
+            }
+            if let Some(emission) = material_override.emission {
+                material_descriptor.emission = emission;
+            }
+            if let Some(transmission) = material_override.transmission {
+                material_descriptor.transmission = transmission;
+            }
+
+            self.material_descriptors[override_idx as usize + i] = material_descriptor;
+        }
+    }
+
     pub fn write_materials(&self, queue: &wgpu::Queue) {
         queue.write_buffer(
             &self.material_descriptor_buffer,
@@ -4,11 +4,16 @@ use appearance_asset_database::{asset_paths::resolve_asset_path, AssetDatabase};
 use appearance_model::Model;
 use appearance_texture::Texture;
 use appearance_wgpu::wgpu::{self, TlasPackage};
-use appearance_world::visible_world_action::VisibleWorldActionType;
+use appearance_world::{
+    components::MaterialOverrideComponent,
+    environment::Environment,
+    mesh_stream::{mesh_model_key, MeshStreamReceiver},
+    visible_world_action::{AnimationData, LightData, VisibleWorldActionType},
+};
 use glam::{Mat4, Vec3};
 use material_pool::MaterialPool;
 use scene_model::SceneModel;
-use sky::Sky;
+use sky::{GpuLight, Sky};
 use uuid::Uuid;
 use vertex_pool::{VertexPool, VertexPoolAlloc};
 
@@ -38,13 +43,102 @@ impl TransformWithHistory {
     }
 }
 
+struct AnimationWithHistory {
+    pub animation: AnimationData,
+    pub prev_animation: AnimationData,
+}
+
+impl AnimationWithHistory {
+    fn new(animation: AnimationData) -> Self {
+        Self {
+            animation,
+            prev_animation: animation,
+        }
+    }
+
+    fn update(&mut self, animation: AnimationData) {
+        self.prev_animation = self.animation;
+        self.animation = animation;
+    }
+}
+
+struct MaterialOverride {
+    material_override: MaterialOverrideComponent,
+    // Index and size of the block of overridden material descriptors, allocated on the next tlas rebuild
+    material_block: Option<(u32, u32)>,
+    dirty: bool,
+}
+
+impl MaterialOverride {
+    fn new(material_override: MaterialOverrideComponent) -> Self {
+        Self {
+            material_override,
+            material_block: None,
+            dirty: true,
+        }
+    }
+
+    /// Make sure the material block of this override is up to date, returns the index of its first material
+    fn update_material_block(
+        &mut self,
+        model: &SceneModel,
+        material_pool: &mut MaterialPool,
+        free_material_blocks: &mut Vec<(u32, u32)>,
+    ) -> u32 {
+        if self.material_block.is_none() {
+            if let Some(i) = free_material_blocks
+                .iter()
+                .position(|(_, material_count)| *material_count == model.material_count)
+            {
+                self.material_block = Some(free_material_blocks.swap_remove(i));
+                self.dirty = true;
+            } else {
+                let override_idx = material_pool.alloc_material_override(
+                    model.material_idx,
+                    model.material_count,
+                    &self.material_override,
+                );
+                self.material_block = Some((override_idx, model.material_count));
+                self.dirty = false;
+            }
+        }
+
+        let (override_idx, _) = self.material_block.unwrap();
+        if self.dirty {
+            material_pool.update_material_override(
+                override_idx,
+                model.material_idx,
+                model.material_count,
+                &self.material_override,
+            );
+            self.dirty = false;
+        }
+
+        override_idx
+    }
+
+    fn is_emissive(&self, model_is_emissive: bool) -> bool {
+        self.material_override
+            .emission
+            .map_or(model_is_emissive, |emission| emission != Vec3::ZERO)
+    }
+}
+
 pub struct SceneResources {
     model_assets: AssetDatabase<Model>,
     models: HashMap<String, (SceneModel, Vec<Uuid>)>,
     model_instances: HashMap<Uuid, TransformWithHistory>,
+    model_animations: HashMap<Uuid, AnimationWithHistory>,
+    mesh_streams: MeshStreamReceiver,
+    material_overrides: HashMap<Uuid, MaterialOverride>,
+    // Material blocks of removed overrides, reused by overrides of models with the same material count
+    free_material_blocks: Vec<(u32, u32)>,
     vertex_pool: VertexPool,
     material_pool: MaterialPool,
+    texture_assets: AssetDatabase<Texture>,
     sky: Sky,
+    sky_texture_path: String,
+    lights: Vec<(Uuid, LightData)>,
     frame_idx: u32,
 
     tlas_package: wgpu::TlasPackage,
@@ -67,10 +161,9 @@ impl SceneResources {
         let material_pool = MaterialPool::new(device);
         let mut sky = Sky::new(device);
 
+        let sky_texture_path = resolve_asset_path(&Environment::default().sky_texture, "");
         sky.set_sky_texture(
-            &texture_assets
-                .get(&resolve_asset_path("::evening_road_01_puresky_4k.hdr", ""))
-                .unwrap(),
+            &texture_assets.get(&sky_texture_path).unwrap(),
             device,
             queue,
         );
@@ -79,9 +172,16 @@ impl SceneResources {
             model_assets,
             models: HashMap::new(),
             model_instances: HashMap::new(),
+            model_animations: HashMap::new(),
+            mesh_streams: MeshStreamReceiver::new(),
+            material_overrides: HashMap::new(),
+            free_material_blocks: Vec::new(),
             vertex_pool,
             material_pool,
+            texture_assets,
             sky,
+            sky_texture_path,
+            lights: Vec::new(),
             frame_idx: 0,
             tlas_package: TlasPackage::new(tlas),
             blas_idx_to_mesh_mapping: HashMap::new(),
@@ -104,6 +204,13 @@ impl SceneResources {
         &self.sky
     }
 
+    /// Load a model from bytes streamed by the host, models spawned with the same resolved path use it instead of reading from disk
+    pub fn provide_model_asset(&mut self, path: &str, bytes: &[u8]) {
+        if let Err(err) = self.model_assets.get_from_bytes(path, bytes) {
+            log::warn!("Failed to load streamed model {}: {}", path, err);
+        }
+    }
+
     pub fn handle_visible_world_action(
         &mut self,
         action: &VisibleWorldActionType,
@@ -118,10 +225,43 @@ impl SceneResources {
                 if let Some(model) = self.models.get_mut(&resolved_asset_path) {
                     model.1.push(data.entity_uuid);
                 } else {
-                    let model_asset = self.model_assets.get(&resolved_asset_path).unwrap();
+                    match self.model_assets.get(&resolved_asset_path) {
+                        Ok(model_asset) => {
+                            let scene_model = SceneModel::new(
+                                (*model_asset).clone(),
+                                &mut self.vertex_pool,
+                                &mut self.material_pool,
+                                command_encoder,
+                                device,
+                                queue,
+                            );
+
+                            self.models
+                                .insert(resolved_asset_path, (scene_model, vec![data.entity_uuid]));
+                        }
+                        // The instance is still tracked, so updates to it don't fail as well
+                        Err(err) => {
+                            log::warn!("Failed to load model {}: {}", resolved_asset_path, err)
+                        }
+                    }
+                }
 
+                self.model_instances.insert(
+                    data.entity_uuid,
+                    TransformWithHistory::new(data.transform_matrix),
+                );
+            }
+            VisibleWorldActionType::SpawnMesh(data) => {
+                self.model_instances.insert(
+                    data.entity_uuid,
+                    TransformWithHistory::new(data.transform_matrix),
+                );
+            }
+            VisibleWorldActionType::MeshChunk(data) => match self.mesh_streams.receive(data) {
+                Some(Ok(mesh)) => {
+                    // Vertex pool allocations can't be freed yet, so replaced geometry stays allocated
                     let scene_model = SceneModel::new(
-                        (*model_asset).clone(),
+                        mesh.to_model(),
                         &mut self.vertex_pool,
                         &mut self.material_pool,
                         command_encoder,
@@ -129,15 +269,14 @@ impl SceneResources {
                         queue,
                     );
 
-                    self.models
-                        .insert(resolved_asset_path, (scene_model, vec![data.entity_uuid]));
+                    self.models.insert(
+                        mesh_model_key(&data.entity_uuid),
+                        (scene_model, vec![data.entity_uuid]),
+                    );
                 }
-
-                self.model_instances.insert(
-                    data.entity_uuid,
-                    TransformWithHistory::new(data.transform_matrix),
-                );
-            }
+                Some(Err(err)) => log::warn!("{}", err),
+                None => {}
+            },
             VisibleWorldActionType::TransformModel(data) => {
                 if let Some(instance_transform) = self.model_instances.get_mut(&data.entity_uuid) {
                     instance_transform.update(data.transform_matrix);
@@ -147,18 +286,116 @@ impl SceneResources {
             }
             VisibleWorldActionType::DestroyModel(data) => {
                 self.model_instances.remove(&data.entity_uuid);
+                self.model_animations.remove(&data.entity_uuid);
+                self.remove_material_override(&data.entity_uuid);
+                self.models.remove(&mesh_model_key(&data.entity_uuid));
+                self.mesh_streams.remove(&data.entity_uuid);
+            }
+            VisibleWorldActionType::AnimateModel(data) => {
+                if let Some(animation) = self.model_animations.get_mut(&data.entity_uuid) {
+                    animation.update(*data);
+                } else {
+                    self.model_animations
+                        .insert(data.entity_uuid, AnimationWithHistory::new(*data));
+                }
+            }
+            VisibleWorldActionType::SpawnLight(data) => {
+                self.lights.push((data.entity_uuid, *data));
+                self.update_sky_lights();
+            }
+            VisibleWorldActionType::UpdateLight(data) => {
+                if let Some(light) = self
+                    .lights
+                    .iter_mut()
+                    .find(|(entity_uuid, _)| *entity_uuid == data.entity_uuid)
+                {
+                    light.1 = *data;
+                    self.update_sky_lights();
+                } else {
+                    log::warn!("Failed to update light.");
+                }
+            }
+            VisibleWorldActionType::DestroyLight(data) => {
+                self.lights
+                    .retain(|(entity_uuid, _)| *entity_uuid != data.entity_uuid);
+                self.update_sky_lights();
+            }
+            VisibleWorldActionType::MaterialOverride(data) => {
+                if data.is_cleared() {
+                    self.remove_material_override(&data.entity_uuid);
+                } else if let Some(material_override) =
+                    self.material_overrides.get_mut(&data.entity_uuid)
+                {
+                    material_override.material_override = data.material_override();
+                    material_override.dirty = true;
+                } else {
+                    self.material_overrides.insert(
+                        data.entity_uuid,
+                        MaterialOverride::new(data.material_override()),
+                    );
+                }
+            }
+            VisibleWorldActionType::SetEnvironment(data) => {
+                let sky_texture_path = resolve_asset_path(data.sky_texture(), "");
+                if sky_texture_path != self.sky_texture_path {
+                    match self.texture_assets.get(&sky_texture_path) {
+                        Ok(texture) => {
+                            self.sky.set_sky_texture(&texture, device, queue);
+                            self.sky_texture_path = sky_texture_path;
+                        }
+                        Err(err) => {
+                            log::warn!("Failed to load sky texture {}: {}.", sky_texture_path, err)
+                        }
+                    }
+                }
+
+                self.sky.set_environment(data);
             }
             VisibleWorldActionType::Clear(_) => {
                 self.models.clear();
+                self.model_animations.clear();
+                self.mesh_streams.clear();
+                let entity_uuids: Vec<Uuid> = self.material_overrides.keys().copied().collect();
+                for entity_uuid in entity_uuids {
+                    self.remove_material_override(&entity_uuid);
+                }
+                self.lights.clear();
+                self.update_sky_lights();
             }
             _ => log::warn!("Unable to process world action: {:?}.", action),
         }
     }
 
+    fn remove_material_override(&mut self, entity_uuid: &Uuid) {
+        if let Some(material_block) = self
+            .material_overrides
+            .remove(entity_uuid)
+            .and_then(|material_override| material_override.material_block)
+        {
+            self.free_material_blocks.push(material_block);
+        }
+    }
+
+    fn update_sky_lights(&mut self) {
+        self.sky.lights = self
+            .lights
+            .iter()
+            .filter_map(|(entity_uuid, light)| match light.light_type() {
+                Ok(light_type) => Some(GpuLight::new(light, light_type)),
+                Err(err) => {
+                    log::warn!("Skipping light {}: {}", entity_uuid, err);
+                    None
+                }
+            })
+            .collect();
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn rebuild_tlas_rec(
         model_asset_path: String,
         model: &SceneModel,
+        material_override: Option<(&MaterialOverride, u32)>,
+        animated_node_transforms: Option<&[Mat4]>,
         node: u32,
         parent_transform: Mat4,
         mut blas_idx: u32,
@@ -166,7 +403,7 @@ impl SceneResources {
         blas_idx_to_mesh_mapping: &mut HashMap<u32, (String, u32, Mat4)>,
         vertex_pool: &mut VertexPool,
     ) -> u32 {
-        let transform = parent_transform * model.nodes[node as usize].transform.get_matrix();
+        let transform = parent_transform * model.node_transform(node, animated_node_transforms);
 
         if let Some(mesh_idx) = &model.nodes[node as usize].mesh {
             let inv_trans_transform = transform.inverse().transpose();
@@ -181,7 +418,14 @@ impl SceneResources {
                 .unwrap();
 
             let blas = &model.blases[*mesh_idx as usize];
-            let vertex_slice_index = model.vertex_pool_allocs[*mesh_idx as usize].index;
+            let mut vertex_slice_index = model.vertex_pool_allocs[*mesh_idx as usize].index;
+            let mut is_emissive = model.is_emissive[*mesh_idx as usize];
+
+            // Point the instance at its own materials by giving it a slice with the same geometry
+            if let Some((material_override, override_idx)) = material_override {
+                vertex_slice_index = vertex_pool.alias_slice(vertex_slice_index, override_idx);
+                is_emissive = material_override.is_emissive(is_emissive);
+            }
 
             blas_instances.push(wgpu::TlasInstance::new(
                 blas,
@@ -190,11 +434,7 @@ impl SceneResources {
                 0xff,
             ));
 
-            vertex_pool.submit_slice_instance(
-                vertex_slice_index,
-                transform,
-                model.is_emissive[*mesh_idx as usize],
-            );
+            vertex_pool.submit_slice_instance(vertex_slice_index, transform, is_emissive);
 
             blas_idx += 1;
         }
@@ -203,6 +443,8 @@ impl SceneResources {
             blas_idx = Self::rebuild_tlas_rec(
                 model_asset_path.clone(),
                 model,
+                material_override,
+                animated_node_transforms,
                 *child_node,
                 transform,
                 blas_idx,
@@ -231,9 +473,30 @@ impl SceneResources {
                 for (i, entity_uuid) in entity_uuids.iter().enumerate() {
                     // If this instance doesn't have a transform anymore, it has been destroyed
                     if let Some(instance_transform) = self.model_instances.get(entity_uuid) {
+                        let material_override =
+                            self.material_overrides
+                                .get_mut(entity_uuid)
+                                .map(|material_override| {
+                                    let override_idx = material_override.update_material_block(
+                                        model,
+                                        &mut self.material_pool,
+                                        &mut self.free_material_blocks,
+                                    );
+                                    (&*material_override, override_idx)
+                                });
+
+                        let animated_node_transforms = self
+                            .model_animations
+                            .get(entity_uuid)
+                            .and_then(|animation| {
+                                model.animated_node_transforms(&animation.animation)
+                            });
+
                         Self::rebuild_tlas_rec(
                             asset_path.clone(),
                             model,
+                            material_override,
+                            animated_node_transforms.as_deref(),
                             *root_node,
                             instance_transform.transform,
                             0,
@@ -278,27 +541,22 @@ impl SceneResources {
     pub fn end_frame(&mut self) {
         self.frame_idx += 1;
         self.vertex_pool.end_frame();
-
-        // TODO: this kind of logic shouldn't be here, just for testing
-        self.sky.sun_info.direction = Vec3::new(
-            (self.frame_idx as f32 / 100.0).cos() * -0.2,
-            -1.0,
-            (self.frame_idx as f32 / 100.0).sin() * 0.3,
-        )
-        .normalize();
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn model_instance_iter_rec<F: FnMut(&VertexPoolAlloc, Mat4, Mat4)>(
         f: &mut F,
         model_asset_path: String,
         model: &SceneModel,
+        animated_node_transforms: Option<&[Mat4]>,
+        prev_animated_node_transforms: Option<&[Mat4]>,
         node: u32,
         parent_transform: Mat4,
         prev_parent_transform: Mat4,
     ) {
-        let transform = parent_transform * model.nodes[node as usize].transform.get_matrix();
+        let transform = parent_transform * model.node_transform(node, animated_node_transforms);
         let prev_transform =
-            prev_parent_transform * model.nodes[node as usize].transform.get_matrix();
+            prev_parent_transform * model.node_transform(node, prev_animated_node_transforms);
 
         if let Some(mesh_idx) = &model.nodes[node as usize].mesh {
             let vertex_slice = &model.vertex_pool_allocs[*mesh_idx as usize];
@@ -311,6 +569,8 @@ impl SceneResources {
                 f,
                 model_asset_path.clone(),
                 model,
+                animated_node_transforms,
+                prev_animated_node_transforms,
                 *child_node,
                 transform,
                 prev_transform,
@@ -325,10 +585,20 @@ impl SceneResources {
                 for entity_uuid in entity_uuids {
                     // If this instance doesn't have a transform anymore, it has been destroyed
                     if let Some(instance_transform) = self.model_instances.get(entity_uuid) {
+                        let animation = self.model_animations.get(entity_uuid);
+                        let animated_node_transforms = animation.and_then(|animation| {
+                            model.animated_node_transforms(&animation.animation)
+                        });
+                        let prev_animated_node_transforms = animation.and_then(|animation| {
+                            model.animated_node_transforms(&animation.prev_animation)
+                        });
+
                         Self::model_instance_iter_rec(
                             &mut f,
                             asset_path.clone(),
                             model,
+                            animated_node_transforms.as_deref(),
+                            prev_animated_node_transforms.as_deref(),
                             *root_node,
                             instance_transform.transform,
                             instance_transform.prev_transform,
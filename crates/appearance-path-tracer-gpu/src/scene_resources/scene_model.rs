@@ -1,7 +1,9 @@
 use std::iter;
 
-use appearance_model::{mesh::PackedVertex, Model, ModelNode};
+use appearance_model::{animation::AnimationClip, mesh::PackedVertex, Model, ModelNode};
 use appearance_wgpu::wgpu;
+use appearance_world::visible_world_action::AnimationData;
+use glam::Mat4;
 
 use super::{
     material_pool::MaterialPool,
@@ -14,6 +16,9 @@ pub struct SceneModel {
     pub is_emissive: Vec<bool>,
     pub vertex_pool_allocs: Vec<VertexPoolAlloc>,
     pub nodes: Vec<ModelNode>,
+    pub animations: Vec<AnimationClip>,
+    pub material_idx: u32,
+    pub material_count: u32,
 }
 
 impl SceneModel {
@@ -98,6 +103,24 @@ impl SceneModel {
             is_emissive,
             vertex_pool_allocs,
             nodes: model.nodes,
+            animations: model.animations,
+            material_idx: material_idx as u32,
+            material_count: model.materials.len() as u32,
         }
     }
+
+    /// Local transforms of all nodes posed by an animation, `None` when the model doesn't have the clip
+    pub fn animated_node_transforms(&self, animation: &AnimationData) -> Option<Vec<Mat4>> {
+        self.animations
+            .get(animation.clip as usize)
+            .map(|clip| clip.sample(animation.time, animation.looping(), &self.nodes))
+    }
+
+    /// Local transform of a node, taken from the animated transforms when available
+    pub fn node_transform(&self, node: u32, animated_node_transforms: Option<&[Mat4]>) -> Mat4 {
+        animated_node_transforms.map_or_else(
+            || self.nodes[node as usize].transform.get_matrix(),
+            |node_transforms| node_transforms[node as usize],
+        )
+    }
 }
@@ -5,6 +5,10 @@ use appearance_wgpu::{
     empty_texture_view,
     wgpu::{self, util::DeviceExt},
 };
+use appearance_world::{
+    components::LightType,
+    visible_world_action::{EnvironmentData, LightData},
+};
 use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
 
@@ -21,12 +25,83 @@ pub struct SunInfo {
     pub intensity: f32,
 }
 
+#[derive(Debug, Pod, Clone, Copy, Zeroable)]
+#[repr(C)]
+struct SkyConstants {
+    sun_info: SunInfo,
+    num_lights: u32,
+    sky_intensity: f32,
+    sky_rotation: f32,
+    _padding: u32,
+}
+
+/// Light placed in the world, sampled alongside the sun and emissive triangles
+#[derive(Debug, Pod, Clone, Copy, Zeroable, Default)]
+#[repr(C)]
+pub struct GpuLight {
+    position: Vec3,
+    ty: u32,
+    // Normalized direction the light is emitting towards
+    direction: Vec3,
+    // Cosine of the spot inner angle
+    cos_inner: f32,
+    // Color scaled by intensity
+    emission: Vec3,
+    // Cosine of the spot outer angle
+    cos_outer: f32,
+    // Half extent of rect lights
+    right: Vec3,
+    _padding0: u32,
+    // Half extent of rect lights
+    up: Vec3,
+    _padding1: u32,
+}
+
+impl GpuLight {
+    pub fn new(light: &LightData, light_type: LightType) -> Self {
+        let mut gpu_light = GpuLight {
+            position: light.position(),
+            direction: light.direction(),
+            emission: light.color * light.intensity,
+            ..Default::default()
+        };
+
+        match light_type {
+            LightType::Point => {
+                gpu_light.ty = 0;
+            }
+            LightType::Spot {
+                inner_angle,
+                outer_angle,
+            } => {
+                gpu_light.ty = 1;
+                gpu_light.cos_inner = inner_angle.cos();
+                gpu_light.cos_outer = outer_angle.cos();
+            }
+            LightType::Directional => {
+                gpu_light.ty = 2;
+            }
+            LightType::Rect { width, height } => {
+                gpu_light.ty = 3;
+                gpu_light.right = light.right() * width * 0.5;
+                gpu_light.up = light.up() * height * 0.5;
+            }
+        }
+
+        gpu_light
+    }
+}
+
 pub struct Sky {
     texture_view: Option<wgpu::TextureView>,
     sampler: wgpu::Sampler,
     bind_group_layout: wgpu::BindGroupLayout,
 
     pub sun_info: SunInfo,
+    pub sky_intensity: f32,
+    // Rotation of the sky texture around the up axis, in radians
+    pub sky_rotation: f32,
+    pub lights: Vec<GpuLight>,
 }
 
 impl Default for SunInfo {
@@ -79,6 +154,16 @@ impl Sky {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -87,9 +172,24 @@ impl Sky {
             sampler,
             bind_group_layout,
             sun_info: SunInfo::default(),
+            sky_intensity: 1.0,
+            sky_rotation: 0.0,
+            lights: Vec::new(),
         }
     }
 
+    /// Apply everything but the sky texture of an environment, which has to be loaded separately through `set_sky_texture`
+    pub fn set_environment(&mut self, environment: &EnvironmentData) {
+        self.sun_info = SunInfo {
+            direction: environment.sun_direction.normalize(),
+            size: environment.sun_size,
+            color: environment.sun_color,
+            intensity: environment.sun_intensity,
+        };
+        self.sky_intensity = environment.sky_intensity;
+        self.sky_rotation = environment.sky_rotation;
+    }
+
     pub fn set_sky_texture(
         &mut self,
         texture: &Arc<Texture>,
@@ -110,10 +210,28 @@ impl Sky {
     pub fn bind_group(&self, device: &wgpu::Device) -> wgpu::BindGroup {
         let constants = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("appearance-path-tracer-gpu::sky constants"),
-            contents: bytemuck::bytes_of(&self.sun_info),
+            contents: bytemuck::bytes_of(&SkyConstants {
+                sun_info: self.sun_info,
+                num_lights: self.lights.len() as u32,
+                sky_intensity: self.sky_intensity,
+                sky_rotation: self.sky_rotation,
+                _padding: 0,
+            }),
             usage: wgpu::BufferUsages::UNIFORM,
         });
 
+        // Storage buffers can't be empty, always upload at least a single light
+        let lights = if self.lights.is_empty() {
+            vec![GpuLight::default()]
+        } else {
+            self.lights.clone()
+        };
+        let lights = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("appearance-path-tracer-gpu::sky lights"),
+            contents: bytemuck::cast_slice(&lights),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
         let sky_texture_view = self
             .texture_view
             .as_ref()
@@ -135,6 +253,10 @@ impl Sky {
                     binding: 2,
                     resource: wgpu::BindingResource::Sampler(&self.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: lights.as_entire_binding(),
+                },
             ],
         })
     }
@@ -88,6 +88,8 @@ pub struct VertexPool {
     emissive_triangle_count: u32,
     blas_instances: Vec<BlasInstance>,
     slices: Vec<VertexPoolSlice>,
+    // Copies of slices pointing at different materials, stored behind the slices and rebuilt every frame
+    slice_aliases: Vec<VertexPoolSlice>,
 
     bind_group_layout: wgpu::BindGroupLayout,
 }
@@ -249,6 +251,7 @@ impl VertexPool {
             emissive_triangle_count: 0,
             blas_instances: Vec::new(),
             slices: Vec::new(),
+            slice_aliases: Vec::new(),
             bind_group_layout,
         }
     }
@@ -285,6 +288,12 @@ impl VertexPool {
             bytemuck::cast_slice(self.slices.as_slice()),
         );
 
+        queue.write_buffer(
+            &self.slices_buffer,
+            (self.slices.len() * std::mem::size_of::<VertexPoolSlice>()) as u64,
+            bytemuck::cast_slice(self.slice_aliases.as_slice()),
+        );
+
         queue.write_buffer(
             &self.emissive_triangle_instance_buffer,
             0,
@@ -304,11 +313,32 @@ impl VertexPool {
         );
     }
 
+    fn slice(&self, index: u32) -> &VertexPoolSlice {
+        let index = index as usize;
+        if index < self.slices.len() {
+            &self.slices[index]
+        } else {
+            &self.slice_aliases[index - self.slices.len()]
+        }
+    }
+
+    /// Create a slice sharing the geometry of the slice at `index`, but using the materials starting at `material_idx`.
+    /// Aliases only live until the end of the frame.
+    pub fn alias_slice(&mut self, index: u32, material_idx: u32) -> u32 {
+        let slice = VertexPoolSlice {
+            material_idx,
+            ..*self.slice(index)
+        };
+        self.slice_aliases.push(slice);
+
+        (self.slices.len() + self.slice_aliases.len()) as u32 - 1
+    }
+
     pub fn submit_slice_instance(&mut self, index: u32, transform: Mat4, is_emissive: bool) {
         let mut emissive_blas_instance_idx = u32::MAX;
 
         if is_emissive {
-            let num_triangles = self.slices[index as usize].num_indices / 3;
+            let num_triangles = self.slice(index).num_indices / 3;
             let transform4x3 = transform.transpose().to_cols_array()[..12]
                 .try_into()
                 .unwrap();
@@ -505,5 +535,6 @@ impl VertexPool {
         self.emissive_triangle_instances.clear();
         self.emissive_triangle_count = 0;
         self.blas_instances.clear();
+        self.slice_aliases.clear();
     }
 }
@@ -1,15 +1,17 @@
 use std::path::Path;
 
 fn main() {
-    let color_space_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("acs");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let color_space_dir = Path::new(&std::env::var("OUT_DIR").unwrap()).join("acs");
     std::fs::create_dir_all(&color_space_dir).unwrap();
 
     appearance_color_spaces::write_aces_tables(color_space_dir.join("aces"))
         .expect("Failed to write aces color space.");
     appearance_color_spaces::write_dci_p3_tables(color_space_dir.join("dci_p3"))
-        .expect("Failed to write aces color space.");
+        .expect("Failed to write dci p3 color space.");
     appearance_color_spaces::write_rec2020_tables(color_space_dir.join("rec2020"))
-        .expect("Failed to write aces color space.");
+        .expect("Failed to write rec2020 color space.");
     appearance_color_spaces::write_srgb_tables(color_space_dir.join("srgb"))
         .expect("Failed to write srgb color space.");
 }
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use appearance_packing::PackedRgb9e5;
 use appearance_render_loop::host::NODE_BYTES_PER_PIXEL;
 use glam::{Mat3, UVec2, Vec3};
 
@@ -106,7 +107,8 @@ impl Film {
         &self.pixels
     }
 
-    pub fn get_pixels_out(&mut self, samples_per_pixel: u32) -> &[u8] {
+    /// Resolve the film to 8-bit RGBX, or to linear radiance packed as `PackedRgb9e5` when `hdr` is set
+    pub fn get_pixels_out(&mut self, samples_per_pixel: u32, hdr: bool) -> &[u8] {
         for y in 0..self.resolution.y {
             for x in 0..self.resolution.x {
                 let i = (y * self.resolution.x + x) as usize;
@@ -118,9 +120,14 @@ impl Film {
 
                 let rgb = Rgb::new(self.output_rgb_from_sensor_rgb * rgb.0);
 
-                self.pixels_out[i * NODE_BYTES_PER_PIXEL] = (rgb.0.x * 255.0) as u8;
-                self.pixels_out[i * NODE_BYTES_PER_PIXEL + 1] = (rgb.0.y * 255.0) as u8;
-                self.pixels_out[i * NODE_BYTES_PER_PIXEL + 2] = (rgb.0.z * 255.0) as u8;
+                if hdr {
+                    self.pixels_out[(i * NODE_BYTES_PER_PIXEL)..((i + 1) * NODE_BYTES_PER_PIXEL)]
+                        .copy_from_slice(bytemuck::bytes_of(&PackedRgb9e5::new(rgb.0)));
+                } else {
+                    self.pixels_out[i * NODE_BYTES_PER_PIXEL] = (rgb.0.x * 255.0) as u8;
+                    self.pixels_out[i * NODE_BYTES_PER_PIXEL + 1] = (rgb.0.y * 255.0) as u8;
+                    self.pixels_out[i * NODE_BYTES_PER_PIXEL + 2] = (rgb.0.z * 255.0) as u8;
+                }
             }
         }
 
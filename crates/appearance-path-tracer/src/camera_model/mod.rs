@@ -4,6 +4,7 @@ use tinybvh::Ray;
 pub mod film;
 pub mod perspective;
 pub mod pixel_sensor;
+pub mod thin_lens;
 
 use crate::radiometry::{SampledSpectrum, SampledWavelengths};
 
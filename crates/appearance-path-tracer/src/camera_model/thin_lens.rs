@@ -0,0 +1,76 @@
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use tinybvh::Ray;
+
+use crate::{
+    radiometry::{SampledSpectrum, SampledWavelengths},
+    sampling::{sample_uniform_disk_polar, sample_uniform_regular_polygon},
+};
+
+use super::{CameraModel, CameraRay, CameraSample};
+
+/// Perspective camera with a thin lens, degrades to a pinhole camera when the lens radius is zero
+pub struct ThinLensCamera {
+    inv_view: Mat4,
+    inv_proj: Mat4,
+    lens_radius: f32,
+    focus_distance: f32,
+    blade_count: u32,
+}
+
+impl ThinLensCamera {
+    pub fn new(
+        inv_view: Mat4,
+        inv_proj: Mat4,
+        lens_radius: f32,
+        focus_distance: f32,
+        blade_count: u32,
+    ) -> Self {
+        Self {
+            inv_view,
+            inv_proj,
+            lens_radius,
+            focus_distance,
+            blade_count,
+        }
+    }
+
+    /// Point on the unit aperture, circular unless there are enough blades to form a polygon
+    fn sample_aperture(&self, u: Vec2) -> Vec2 {
+        if self.blade_count >= 3 {
+            sample_uniform_regular_polygon(u, self.blade_count)
+        } else {
+            sample_uniform_disk_polar(u)
+        }
+    }
+}
+
+impl CameraModel for ThinLensCamera {
+    fn generate_ray(
+        &self,
+        sample: &CameraSample,
+        _wavelengths: &mut SampledWavelengths,
+    ) -> Option<CameraRay> {
+        let corrected_uv = Vec2::new(sample.film_uv.x, -sample.film_uv.y);
+        let target = self.inv_proj * Vec4::from((corrected_uv, 1.0, 1.0));
+        let mut direction = target.xyz().normalize();
+        let mut origin = Vec3::ZERO;
+
+        if self.lens_radius > 0.0 {
+            // Camera space looks down -z, find where the pinhole ray crosses the plane of focus
+            let focus_t = self.focus_distance / -direction.z;
+            let focus_point = direction * focus_t;
+
+            let lens_point = self.lens_radius * self.sample_aperture(sample.lens_uv);
+            origin = Vec3::from((lens_point, 0.0));
+            direction = (focus_point - origin).normalize();
+        }
+
+        let origin = self.inv_view * Vec4::from((origin, 1.0));
+        let direction = self.inv_view * Vec4::from((direction, 0.0));
+
+        Some(CameraRay {
+            ray: Ray::new(origin.xyz(), direction.xyz().normalize()),
+            weight: SampledSpectrum::new(Vec4::ONE),
+        })
+    }
+}
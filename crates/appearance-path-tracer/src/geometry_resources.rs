@@ -1,17 +1,23 @@
 use std::{collections::HashMap, sync::Arc};
 
-use appearance_asset_database::AssetDatabase;
-use appearance_model::{material::Material, Model};
+use appearance_asset_database::{asset_paths::resolve_asset_path, AssetDatabase};
+use appearance_model::{material::Material, mesh::Mesh, Model};
 use appearance_texture::Texture;
-use appearance_world::visible_world_action::VisibleWorldActionType;
-use glam::{swizzles::Vec4Swizzles, Mat4, Vec2, Vec3, Vec4};
+use appearance_world::{
+    components::{LightType, MaterialOverrideComponent},
+    environment::Environment,
+    mesh_stream::{mesh_model_key, MeshStreamReceiver},
+    visible_world_action::{AnimationData, EnvironmentData, LightData, VisibleWorldActionType},
+};
+use glam::{swizzles::Vec4Swizzles, Mat4, Quat, Vec2, Vec3, Vec4};
 use tinybvh::{BlasInstance, Bvh, BvhBase, Intersection};
 use uuid::Uuid;
 
 use crate::{
     light_sources::{
-        distant_light::DistantLight, infinite_light::InfiniteLight,
-        uniform_light_sampler::UniformLightSourceSampler, LightSourceSampler,
+        distant_light::DistantLight, infinite_light::InfiniteLight, point_light::PointLight,
+        rect_light::RectLight, spot_light::SpotLight,
+        uniform_light_sampler::UniformLightSourceSampler, LightSource, LightSourceSampler,
     },
     radiometry::{
         DenselySampledSpectrum, Rgb, RgbColorSpace, RgbIlluminantSpectrum, LAMBDA_MAX, LAMBDA_MIN,
@@ -25,14 +31,105 @@ pub struct GeometryHitData<'a> {
     pub material: &'a Material,
 }
 
+struct SceneModel {
+    model: Arc<Model>,
+    blasses: Vec<Arc<Bvh>>,
+    // Triangle soup referenced by the blasses, must outlive them
+    _blas_vertices: Vec<Vec<Vec4>>,
+}
+
+impl SceneModel {
+    fn new(model: Arc<Model>) -> Self {
+        let mut blasses = Vec::with_capacity(model.meshes.len());
+        let mut blas_vertices = Vec::with_capacity(model.meshes.len());
+
+        for mesh in &model.meshes {
+            let vertices = Self::triangle_soup(mesh);
+
+            let mut blas = Bvh::new();
+            blas.build(&vertices);
+
+            blasses.push(Arc::new(blas));
+            blas_vertices.push(vertices);
+        }
+
+        Self {
+            model,
+            blasses,
+            _blas_vertices: blas_vertices,
+        }
+    }
+
+    /// Local transforms of all nodes posed by an animation, `None` when the model doesn't have the clip
+    fn animated_node_transforms(&self, animation: &AnimationData) -> Option<Vec<Mat4>> {
+        self.model
+            .animations
+            .get(animation.clip as usize)
+            .map(|clip| clip.sample(animation.time, animation.looping(), &self.model.nodes))
+    }
+
+    /// Local transform of a node, taken from the animated transforms when available
+    fn node_transform(&self, node: u32, animated_node_transforms: Option<&[Mat4]>) -> Mat4 {
+        animated_node_transforms.map_or_else(
+            || self.model.nodes[node as usize].transform.get_matrix(),
+            |node_transforms| node_transforms[node as usize],
+        )
+    }
+
+    fn triangle_soup(mesh: &Mesh) -> Vec<Vec4> {
+        mesh.indices
+            .iter()
+            .map(|i| Vec4::from((mesh.packed_vertices[*i as usize].position, 0.0)))
+            .collect()
+    }
+}
+
+struct MaterialOverride {
+    material_override: MaterialOverrideComponent,
+    // Overridden copies of the materials of the entity's model, built on the next tlas rebuild
+    materials: Vec<Material>,
+}
+
+impl MaterialOverride {
+    fn apply(&self, material: &Material) -> Material {
+        let mut material = material.clone();
+
+        if let Some(color) = self.material_override.color {
+            material.color = color;
+        }
+        if let Some(roughness) = self.material_override.roughness {
+            material.roughness = roughness;
+        }
+        if let Some(metallic) = self.material_override.metallic {
+            material.metallic = metallic;
+        }
+        if let Some(emission) = self.material_override.emission {
+            material.emission = emission;
+        }
+        if let Some(transmission) = self.material_override.transmission {
+            material.transmission = transmission;
+        }
+
+        material
+    }
+}
+
 pub struct GeometryResources {
     model_assets: AssetDatabase<Model>,
-    models: HashMap<String, (Arc<Model>, Vec<Uuid>)>,
+    models: HashMap<String, (SceneModel, Vec<Uuid>)>,
     model_instances: HashMap<Uuid, Mat4>,
+    model_animations: HashMap<Uuid, AnimationData>,
+    mesh_streams: MeshStreamReceiver,
+    material_overrides: HashMap<Uuid, MaterialOverride>,
 
     tlas: Bvh,
-    blas_idx_to_mesh_mapping: HashMap<u32, (String, u32, Mat4)>,
+    blas_idx_to_mesh_mapping: HashMap<u32, (String, u32, Mat4, Uuid)>,
 
+    texture_assets: AssetDatabase<Texture>,
+    environment: EnvironmentData,
+    sky_texture: Arc<Texture>,
+
+    lights: Vec<(Uuid, LightData)>,
     pub light_sampler: Box<dyn LightSourceSampler>,
     pub infinite_light: InfiniteLight,
 }
@@ -49,34 +146,29 @@ impl GeometryResources {
         let model_assets = AssetDatabase::<Model>::new();
         let mut texture_assets = AssetDatabase::<Texture>::new();
 
-        let light_spectrum =
-            RgbIlluminantSpectrum::new(Rgb(Vec3::new(1.0, 1.0, 0.95)), &RgbColorSpace::srgb());
-        let light_spectrum = DenselySampledSpectrum::new_from_spectrum(
-            &light_spectrum,
-            LAMBDA_MIN as u32,
-            LAMBDA_MAX as u32,
-        );
-        let distant_light = Box::new(DistantLight::new(
-            Vec3::new(0.0, -1.0, 0.2).normalize(),
-            light_spectrum,
-            100.0,
-            1000.0,
-        ));
-
-        let light_sampler = Box::new(UniformLightSourceSampler::new(vec![distant_light]));
+        let environment = EnvironmentData::new(&Environment::default());
+        let light_sampler = Box::new(UniformLightSourceSampler::new(vec![Self::sun(
+            &environment,
+        )]));
 
-        let infinite_light_texture = texture_assets
-            .get("assets/evening_road_01_puresky_4k.png")
+        let sky_texture = texture_assets
+            .get(&resolve_asset_path(environment.sky_texture(), ""))
             .unwrap();
-        let infinite_light =
-            InfiniteLight::new(infinite_light_texture, RgbColorSpace::srgb(), 5.0, 1000.0);
+        let infinite_light = Self::infinite_light(&environment, sky_texture.clone());
 
         Self {
             models: HashMap::new(),
             model_instances: HashMap::new(),
+            model_animations: HashMap::new(),
+            mesh_streams: MeshStreamReceiver::new(),
+            material_overrides: HashMap::new(),
             model_assets,
             tlas: Bvh::new(),
             blas_idx_to_mesh_mapping: HashMap::new(),
+            texture_assets,
+            environment,
+            sky_texture,
+            lights: Vec::new(),
             light_sampler,
             infinite_light,
         }
@@ -86,22 +178,131 @@ impl GeometryResources {
         &self.tlas
     }
 
+    fn spectrum(color: Vec3) -> DenselySampledSpectrum {
+        let spectrum = RgbIlluminantSpectrum::new(Rgb(color), &RgbColorSpace::srgb());
+        DenselySampledSpectrum::new_from_spectrum(&spectrum, LAMBDA_MIN as u32, LAMBDA_MAX as u32)
+    }
+
+    fn sun(environment: &EnvironmentData) -> Box<dyn LightSource> {
+        Box::new(DistantLight::new(
+            environment.sun_direction.normalize(),
+            Self::spectrum(environment.sun_color),
+            environment.sun_intensity,
+            1000.0,
+        ))
+    }
+
+    fn infinite_light(environment: &EnvironmentData, sky_texture: Arc<Texture>) -> InfiniteLight {
+        InfiniteLight::new(
+            sky_texture,
+            RgbColorSpace::srgb(),
+            5.0 * environment.sky_intensity,
+            1000.0,
+            Quat::from_rotation_y(environment.sky_rotation),
+        )
+    }
+
+    fn light_source(light: &LightData, light_type: LightType) -> Box<dyn LightSource> {
+        let spectrum = Self::spectrum(light.color);
+
+        match light_type {
+            LightType::Point => {
+                Box::new(PointLight::new(light.position(), spectrum, light.intensity))
+            }
+            LightType::Spot {
+                inner_angle,
+                outer_angle,
+            } => Box::new(SpotLight::new(
+                light.position(),
+                light.direction(),
+                spectrum,
+                light.intensity,
+                inner_angle,
+                outer_angle,
+            )),
+            LightType::Directional => Box::new(DistantLight::new(
+                light.direction(),
+                spectrum,
+                light.intensity,
+                1000.0,
+            )),
+            LightType::Rect { width, height } => Box::new(RectLight::new(
+                light.position(),
+                light.right(),
+                light.up(),
+                light.direction(),
+                width,
+                height,
+                spectrum,
+                light.intensity,
+            )),
+        }
+    }
+
+    fn rebuild_light_sampler(&mut self) {
+        let mut light_sources = vec![Self::sun(&self.environment)];
+        light_sources.extend(self.lights.iter().filter_map(|(entity_uuid, light)| {
+            match light.light_type() {
+                Ok(light_type) => Some(Self::light_source(light, light_type)),
+                Err(err) => {
+                    log::warn!("Skipping light {}: {}", entity_uuid, err);
+                    None
+                }
+            }
+        }));
+
+        self.light_sampler = Box::new(UniformLightSourceSampler::new(light_sources));
+    }
+
+    /// Load a model from bytes streamed by the host, models spawned with the same resolved path use it instead of reading from disk
+    pub fn provide_model_asset(&mut self, path: &str, bytes: &[u8]) {
+        if let Err(err) = self.model_assets.get_from_bytes(path, bytes) {
+            log::warn!("Failed to load streamed model {}: {}", path, err);
+        }
+    }
+
     pub fn handle_visible_world_action(&mut self, action: &VisibleWorldActionType) {
         match action {
             VisibleWorldActionType::SpawnModel(data) => {
-                if let Some(model) = self.models.get_mut(data.asset_path()) {
+                let resolved_asset_path = resolve_asset_path(data.asset_path(), "");
+
+                if let Some(model) = self.models.get_mut(&resolved_asset_path) {
                     model.1.push(data.entity_uuid);
                 } else {
-                    let model_asset = self.model_assets.get(data.asset_path()).unwrap();
-                    self.models.insert(
-                        data.asset_path().to_owned(),
-                        (model_asset, vec![data.entity_uuid]),
-                    );
+                    match self.model_assets.get(&resolved_asset_path) {
+                        Ok(model_asset) => {
+                            self.models.insert(
+                                resolved_asset_path,
+                                (SceneModel::new(model_asset), vec![data.entity_uuid]),
+                            );
+                        }
+                        // The instance is still tracked, so updates to it don't fail as well
+                        Err(err) => {
+                            log::warn!("Failed to load model {}: {}", resolved_asset_path, err)
+                        }
+                    }
                 }
 
                 self.model_instances
                     .insert(data.entity_uuid, data.transform_matrix);
             }
+            VisibleWorldActionType::SpawnMesh(data) => {
+                self.model_instances
+                    .insert(data.entity_uuid, data.transform_matrix);
+            }
+            VisibleWorldActionType::MeshChunk(data) => match self.mesh_streams.receive(data) {
+                Some(Ok(mesh)) => {
+                    self.models.insert(
+                        mesh_model_key(&data.entity_uuid),
+                        (
+                            SceneModel::new(Arc::new(mesh.to_model())),
+                            vec![data.entity_uuid],
+                        ),
+                    );
+                }
+                Some(Err(err)) => log::warn!("{}", err),
+                None => {}
+            },
             VisibleWorldActionType::TransformModel(data) => {
                 if let Some(instance_transform) = self.model_instances.get_mut(&data.entity_uuid) {
                     *instance_transform = data.transform_matrix;
@@ -111,9 +312,75 @@ impl GeometryResources {
             }
             VisibleWorldActionType::DestroyModel(data) => {
                 self.model_instances.remove(&data.entity_uuid);
+                self.model_animations.remove(&data.entity_uuid);
+                self.material_overrides.remove(&data.entity_uuid);
+                self.models.remove(&mesh_model_key(&data.entity_uuid));
+                self.mesh_streams.remove(&data.entity_uuid);
+            }
+            VisibleWorldActionType::AnimateModel(data) => {
+                self.model_animations.insert(data.entity_uuid, *data);
+            }
+            VisibleWorldActionType::SpawnLight(data) => {
+                self.lights.push((data.entity_uuid, *data));
+                self.rebuild_light_sampler();
+            }
+            VisibleWorldActionType::UpdateLight(data) => {
+                if let Some(light) = self
+                    .lights
+                    .iter_mut()
+                    .find(|(entity_uuid, _)| *entity_uuid == data.entity_uuid)
+                {
+                    light.1 = *data;
+                    self.rebuild_light_sampler();
+                } else {
+                    log::warn!("Failed to update light.");
+                }
+            }
+            VisibleWorldActionType::DestroyLight(data) => {
+                self.lights
+                    .retain(|(entity_uuid, _)| *entity_uuid != data.entity_uuid);
+                self.rebuild_light_sampler();
+            }
+            VisibleWorldActionType::MaterialOverride(data) => {
+                if data.is_cleared() {
+                    self.material_overrides.remove(&data.entity_uuid);
+                } else {
+                    self.material_overrides.insert(
+                        data.entity_uuid,
+                        MaterialOverride {
+                            material_override: data.material_override(),
+                            materials: Vec::new(),
+                        },
+                    );
+                }
+            }
+            VisibleWorldActionType::SetEnvironment(data) => {
+                if data.sky_texture() != self.environment.sky_texture() {
+                    match self
+                        .texture_assets
+                        .get(&resolve_asset_path(data.sky_texture(), ""))
+                    {
+                        Ok(texture) => self.sky_texture = texture,
+                        Err(err) => log::warn!(
+                            "Failed to load sky texture {}: {}.",
+                            data.sky_texture(),
+                            err
+                        ),
+                    }
+                }
+
+                self.environment = data.clone();
+                self.infinite_light =
+                    Self::infinite_light(&self.environment, self.sky_texture.clone());
+                self.rebuild_light_sampler();
             }
             VisibleWorldActionType::Clear(_) => {
                 self.models.clear();
+                self.model_animations.clear();
+                self.mesh_streams.clear();
+                self.material_overrides.clear();
+                self.lights.clear();
+                self.rebuild_light_sampler();
             }
             _ => log::warn!("Unable to process world action: {:?}.", action),
         }
@@ -122,28 +389,34 @@ impl GeometryResources {
     #[allow(clippy::too_many_arguments)]
     fn rebuild_tlas_rec(
         model_asset_path: String,
-        model: &Model,
+        entity_uuid: Uuid,
+        model: &SceneModel,
+        animated_node_transforms: Option<&[Mat4]>,
         node: u32,
         parent_transform: Mat4,
         mut blas_idx: u32,
         blas_idx_offset: u32,
         blas_instances: &mut Vec<BlasInstance>,
         blasses: &mut Option<&mut Vec<Arc<dyn BvhBase>>>,
-        blas_idx_to_mesh_mapping: &mut HashMap<u32, (String, u32, Mat4)>,
+        blas_idx_to_mesh_mapping: &mut HashMap<u32, (String, u32, Mat4, Uuid)>,
     ) -> u32 {
-        let transform = parent_transform * model.nodes[node as usize].transform.get_matrix();
+        let transform = parent_transform * model.node_transform(node, animated_node_transforms);
 
-        if let Some(mesh_idx) = &model.nodes[node as usize].mesh {
+        if let Some(mesh_idx) = &model.model.nodes[node as usize].mesh {
             if let Some(blasses) = blasses {
-                let mesh = &model.meshes[*mesh_idx as usize];
-                blasses.push(mesh.blas.clone() as Arc<dyn BvhBase>);
+                blasses.push(model.blasses[*mesh_idx as usize].clone() as Arc<dyn BvhBase>);
             }
 
             let inv_trans_transform = transform.inverse().transpose();
 
             blas_idx_to_mesh_mapping.insert(
                 blas_instances.len() as u32,
-                (model_asset_path.clone(), node, inv_trans_transform),
+                (
+                    model_asset_path.clone(),
+                    node,
+                    inv_trans_transform,
+                    entity_uuid,
+                ),
             );
 
             blas_instances.push(BlasInstance::new(transform, blas_idx_offset + blas_idx));
@@ -151,10 +424,12 @@ impl GeometryResources {
             blas_idx += 1;
         }
 
-        for child_node in &model.nodes[node as usize].children {
+        for child_node in &model.model.nodes[node as usize].children {
             blas_idx = Self::rebuild_tlas_rec(
                 model_asset_path.clone(),
+                entity_uuid,
                 model,
+                animated_node_transforms,
                 *child_node,
                 transform,
                 blas_idx,
@@ -176,18 +451,38 @@ impl GeometryResources {
 
         let mut blas_idx_offset = 0;
         for (asset_path, (model, entity_uuids)) in &mut self.models {
-            for root_node in &model.root_nodes {
+            for root_node in &model.model.root_nodes {
                 let mut entity_uuids_indices_to_remove = vec![];
 
                 // Loop over all world instances of the model
                 for (i, entity_uuid) in entity_uuids.iter().enumerate() {
                     // If this instance doesn't have a transform anymore, it has been destroyed
                     if let Some(instance_transform) = self.model_instances.get(entity_uuid) {
+                        if let Some(material_override) =
+                            self.material_overrides.get_mut(entity_uuid)
+                        {
+                            if material_override.materials.is_empty() {
+                                material_override.materials = model
+                                    .model
+                                    .materials
+                                    .iter()
+                                    .map(|material| material_override.apply(material))
+                                    .collect();
+                            }
+                        }
+
+                        let animated_node_transforms = self
+                            .model_animations
+                            .get(entity_uuid)
+                            .and_then(|animation| model.animated_node_transforms(animation));
+
                         // Assign blasses when on the last instance, also increment the blas idx offset
                         if i == entity_uuids.len() - 1 {
                             blas_idx_offset += Self::rebuild_tlas_rec(
                                 asset_path.clone(),
+                                *entity_uuid,
                                 model,
+                                animated_node_transforms.as_deref(),
                                 *root_node,
                                 *instance_transform,
                                 0,
@@ -199,7 +494,9 @@ impl GeometryResources {
                         } else {
                             Self::rebuild_tlas_rec(
                                 asset_path.clone(),
+                                *entity_uuid,
                                 model,
+                                animated_node_transforms.as_deref(),
                                 *root_node,
                                 *instance_transform,
                                 0,
@@ -229,7 +526,7 @@ impl GeometryResources {
     pub fn get_hit_data(&self, intersection: &Intersection) -> GeometryHitData {
         let blas_instance = intersection.inst;
         let instance_mapping = self.blas_idx_to_mesh_mapping.get(&blas_instance).unwrap();
-        let model = &self.models.get(&instance_mapping.0).unwrap().0;
+        let model = &self.models.get(&instance_mapping.0).unwrap().0.model;
         let mesh_idx = model.nodes[instance_mapping.1 as usize]
             .mesh
             .as_ref()
@@ -246,32 +543,35 @@ impl GeometryResources {
         let i1 = mesh.indices[(intersection.prim * 3 + 1) as usize] as usize;
         let i2 = mesh.indices[(intersection.prim * 3 + 2) as usize] as usize;
 
-        let p0 = mesh.vertex_positions[i0];
-        let p1 = mesh.vertex_positions[i1];
-        let p2 = mesh.vertex_positions[i2];
-        let position = (p0 * barycentrics.x + p1 * barycentrics.y + p2 * barycentrics.z).xyz();
+        let v0 = &mesh.packed_vertices[i0];
+        let v1 = &mesh.packed_vertices[i1];
+        let v2 = &mesh.packed_vertices[i2];
+        let position = v0.position * barycentrics.x
+            + v1.position * barycentrics.y
+            + v2.position * barycentrics.z;
 
-        let n0 = mesh.vertex_normals[i0];
-        let n1 = mesh.vertex_normals[i1];
-        let n2 = mesh.vertex_normals[i2];
-        let normal = n0 * barycentrics.x + n1 * barycentrics.y + n2 * barycentrics.z;
+        let normal = v0.normal.unpack() * barycentrics.x
+            + v1.normal.unpack() * barycentrics.y
+            + v2.normal.unpack() * barycentrics.z;
         let inv_trans_transform = instance_mapping.2;
-        let normal = (inv_trans_transform * Vec4::from((normal, 1.0)))
+        let normal = (inv_trans_transform * Vec4::from((normal, 0.0)))
             .xyz()
             .normalize();
 
-        let tex_coord = if !mesh.vertex_tex_coords.is_empty() {
-            let t0 = mesh.vertex_tex_coords[i0];
-            let t1 = mesh.vertex_tex_coords[i1];
-            let t2 = mesh.vertex_tex_coords[i2];
-            Some(t0 * barycentrics.x + t1 * barycentrics.y + t2 * barycentrics.z)
-        } else {
-            None
-        };
+        let tex_coord = Some(
+            v0.tex_coord * barycentrics.x
+                + v1.tex_coord * barycentrics.y
+                + v2.tex_coord * barycentrics.z,
+        );
 
         let material_idx = mesh.triangle_material_indices[intersection.prim as usize] as usize;
 
-        let material = &model.materials[material_idx];
+        let material = match self.material_overrides.get(&instance_mapping.3) {
+            Some(material_override) if !material_override.materials.is_empty() => {
+                &material_override.materials[material_idx]
+            }
+            _ => &model.materials[material_idx],
+        };
 
         GeometryHitData {
             position,
@@ -11,14 +11,17 @@ mod path_tracer;
 mod radiometry;
 mod reflectance;
 mod sampling;
-use camera_model::{film::Film, pixel_sensor::PixelSensor};
+use camera_model::{film::Film, pixel_sensor::PixelSensor, thin_lens::ThinLensCamera};
 use glam::{UVec2, Vec2};
 mod math;
 
-use appearance_render_loop::host::RENDER_BLOCK_SIZE;
+use appearance_render_loop::{
+    host::{NodeCapabilities, RendererKind, NODE_BYTES_PER_PIXEL, RENDER_BLOCK_SIZE},
+    node::NodeRenderer,
+};
 use appearance_world::visible_world_action::VisibleWorldActionType;
 use geometry_resources::*;
-use path_tracer::{CameraMatrices, PATH_TRACER_RAY_PACKET_SIZE, RAYS_PER_PACKET};
+use path_tracer::{PATH_TRACER_RAY_PACKET_SIZE, RAYS_PER_PACKET};
 use radiometry::{DenselySampledSpectrum, PiecewiseLinearSpectrum, RgbColorSpace};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
@@ -26,7 +29,10 @@ pub struct PathTracer {
     film: Film,
 
     frame_idx: u32,
+    /// Overrides the seed derived from `frame_idx` while the host accumulates samples of multiple nodes
+    sample_index: Option<u32>,
     camera: Camera,
+    hdr_output: bool,
 
     geometry_resources: GeometryResources,
 }
@@ -57,17 +63,25 @@ impl PathTracer {
         Self {
             film,
             frame_idx: 0,
+            sample_index: None,
             camera: Camera::default(),
+            hdr_output: false,
             geometry_resources: GeometryResources::new(),
         }
     }
+}
 
-    pub fn handle_visible_world_action(&mut self, action: &VisibleWorldActionType) {
+impl NodeRenderer for PathTracer {
+    fn visible_world_action(&mut self, action: &VisibleWorldActionType) {
         match action {
             VisibleWorldActionType::CameraUpdate(data) => {
                 self.camera.set_near(data.near);
                 self.camera.set_far(data.far);
                 self.camera.set_fov(data.fov);
+                self.camera.set_aperture(data.aperture);
+                self.camera.set_focus_distance(data.focus_distance);
+                self.camera.set_sensor_size(data.sensor_size);
+                self.camera.set_blade_count(data.blade_count);
                 self.camera
                     .transform
                     .set_matrix(data.transform_matrix_bytes);
@@ -76,40 +90,93 @@ impl PathTracer {
         }
     }
 
-    pub fn render<F: FnMut(&[u8])>(
+    fn provide_asset(&mut self, path: &str, bytes: &[u8]) {
+        self.geometry_resources.provide_model_asset(path, bytes);
+    }
+
+    fn capabilities(&self) -> NodeCapabilities {
+        NodeCapabilities {
+            adapter_name: format!(
+                "{} cpu with {} threads",
+                std::env::consts::ARCH,
+                rayon::current_num_threads()
+            ),
+            backend: String::from("Cpu"),
+            ..NodeCapabilities::new(RendererKind::Cpu)
+        }
+    }
+
+    fn set_hdr_output(&mut self, hdr_output: bool) -> bool {
+        self.hdr_output = hdr_output;
+        hdr_output
+    }
+
+    fn set_sample_index(&mut self, sample_index: Option<u32>) {
+        self.sample_index = sample_index;
+    }
+
+    fn render<F: FnMut(&[u8])>(
         &mut self,
         resolution: UVec2,
         start_row: u32,
         end_row: u32,
         mut result_callback: F,
     ) {
+        // Blocks crossing the edge are traced whole, only their pixels within the resolution are sent
+        let num_blocks_x = resolution.x.div_ceil(RENDER_BLOCK_SIZE);
+        let blocks: Vec<UVec2> = (start_row / RENDER_BLOCK_SIZE
+            ..end_row.div_ceil(RENDER_BLOCK_SIZE))
+            .flat_map(|y| (0..num_blocks_x).map(move |x| UVec2::new(x, y)))
+            .collect();
+
+        result_callback(self.trace_blocks(resolution, &blocks));
+    }
+
+    fn render_blocks<F: FnMut(UVec2, &[u8])>(
+        &mut self,
+        resolution: UVec2,
+        blocks: &[UVec2],
+        mut result_callback: F,
+    ) {
+        let block_bytes = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize * NODE_BYTES_PER_PIXEL;
+
+        let pixels = self.trace_blocks(resolution, blocks);
+        for (i, block) in blocks.iter().enumerate() {
+            result_callback(*block, &pixels[(i * block_bytes)..((i + 1) * block_bytes)]);
+        }
+    }
+}
+
+impl PathTracer {
+    /// Trace the blocks in parallel, returns the pixels of all blocks one after the other
+    fn trace_blocks(&mut self, resolution: UVec2, blocks: &[UVec2]) -> &[u8] {
         let width = resolution.x;
         let height = resolution.y;
 
-        let num_rows = end_row - start_row;
-        let num_blocks_x = width / RENDER_BLOCK_SIZE;
-        let num_blocks_y = num_rows / RENDER_BLOCK_SIZE;
-
-        self.film.resize(UVec2::new(width, num_rows));
+        self.film.resize(UVec2::new(
+            RENDER_BLOCK_SIZE,
+            RENDER_BLOCK_SIZE * blocks.len() as u32,
+        ));
 
         self.camera.set_aspect_ratio(width as f32 / height as f32);
 
-        let camera_matrices = CameraMatrices {
-            inv_view: self.camera.transform.get_matrix(),
-            inv_proj: self.camera.get_matrix().inverse(),
-        };
+        let camera_model = ThinLensCamera::new(
+            self.camera.transform.get_matrix(),
+            self.camera.get_matrix().inverse(),
+            self.camera.lens_radius(),
+            self.camera.get_focus_distance(),
+            self.camera.get_blade_count(),
+        );
 
         self.geometry_resources.rebuild_tlas();
 
         let samples_per_pixel = 1;
 
-        // Loop over the number of blocks, flattened to allow for better multithreading utilization
-        let flat_block_indices = (0..(num_blocks_y * num_blocks_x)).collect::<Vec<u32>>();
-        flat_block_indices
+        // Loop over the blocks in parallel, each block is stored contiguously in the film
+        (0..blocks.len() as u32)
             .into_par_iter()
-            .for_each(|flat_block_idx| {
-                let local_block_y = flat_block_idx / num_blocks_x;
-                let local_block_x = flat_block_idx % num_blocks_x;
+            .for_each(|block_idx| {
+                let block = blocks[block_idx as usize];
 
                 for sample in 0..samples_per_pixel {
                     // Loop over the block size, divided by the number of rays per packet
@@ -125,11 +192,8 @@ impl PathTracer {
                                     let block_x =
                                         block_x * PATH_TRACER_RAY_PACKET_SIZE + ray_block_x;
 
-                                    let local_x = (local_block_x * RENDER_BLOCK_SIZE) + block_x;
-                                    let local_y = (local_block_y * RENDER_BLOCK_SIZE) + block_y;
-
-                                    let x = local_x;
-                                    let y = local_y + start_row;
+                                    let x = (block.x * RENDER_BLOCK_SIZE) + block_x;
+                                    let y = (block.y * RENDER_BLOCK_SIZE) + block_y;
 
                                     let uv = Vec2::new(
                                         (x as f32 + 0.5) / width as f32,
@@ -146,8 +210,8 @@ impl PathTracer {
 
                             let result = path_tracer::render_pixels(
                                 ray_uvs,
-                                self.frame_idx as u64,
-                                &camera_matrices,
+                                self.sample_index.unwrap_or(self.frame_idx) as u64,
+                                &camera_model,
                                 &self.geometry_resources,
                                 width,
                                 height,
@@ -163,8 +227,7 @@ impl PathTracer {
                                         block_x * PATH_TRACER_RAY_PACKET_SIZE + ray_block_x;
 
                                     let block_size = RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE;
-                                    let start_pixel = (local_block_y * block_size * num_blocks_x)
-                                        + local_block_x * block_size;
+                                    let start_pixel = block_idx * block_size;
 
                                     let local_block_id = block_y * RENDER_BLOCK_SIZE + block_x;
                                     let local_id = (start_pixel + local_block_id) as usize;
@@ -191,6 +254,6 @@ impl PathTracer {
 
         self.frame_idx += 1;
 
-        result_callback(self.film.get_pixels_out(samples_per_pixel));
+        self.film.get_pixels_out(samples_per_pixel, self.hdr_output)
     }
 }
@@ -2,7 +2,7 @@ use core::f32::consts::PI;
 use std::sync::Arc;
 
 use appearance_texture::{Texture, TextureSampleInterpolation, TextureSampleRepeat};
-use glam::{UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+use glam::{Quat, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
 use tinybvh::Ray;
 
 use crate::{
@@ -23,6 +23,8 @@ pub struct InfiniteLight {
     color_space: Arc<RgbColorSpace>,
     scale: f32,
     scene_radius: f32,
+    // Rotation from texture space to world space
+    rotation: Quat,
     distribution: PiecewiseConstant2D,
     compensated_distribution: PiecewiseConstant2D,
 }
@@ -33,6 +35,7 @@ impl InfiniteLight {
         color_space: Arc<RgbColorSpace>,
         scale: f32,
         scene_radius: f32,
+        rotation: Quat,
     ) -> Self {
         let texture_distrbution = texture.get_sampling_distribution();
         let distribution =
@@ -61,6 +64,7 @@ impl InfiniteLight {
             color_space,
             scale,
             scene_radius,
+            rotation,
             distribution,
             compensated_distribution,
         }
@@ -119,7 +123,7 @@ impl LightSource for InfiniteLight {
         if sample.pdf == 0.0 {
             None
         } else {
-            let wi = panorama_coords_to_unit_vector(sample.value);
+            let wi = self.rotation * panorama_coords_to_unit_vector(sample.value);
             let pdf = sample.pdf / (4.0 * PI);
 
             Some(LightSourceLiSample {
@@ -132,7 +136,7 @@ impl LightSource for InfiniteLight {
     }
 
     fn pdf_li(&self, _ctx: LightSourceSampleCtx, wi: Vec3, allow_incomplete_pdf: bool) -> f32 {
-        let uv = unit_vector_to_panorama_coords(wi);
+        let uv = unit_vector_to_panorama_coords(self.rotation.inverse() * wi);
         let pdf = if allow_incomplete_pdf {
             self.compensated_distribution.pdf(uv)
         } else {
@@ -143,7 +147,7 @@ impl LightSource for InfiniteLight {
     }
 
     fn le(&self, ray: &Ray, wavelengths: &SampledWavelengths) -> SampledSpectrum {
-        let uv = unit_vector_to_panorama_coords(ray.D.into());
+        let uv = unit_vector_to_panorama_coords(self.rotation.inverse() * ray.D);
         self.image_le(uv, wavelengths)
     }
 }
@@ -14,6 +14,8 @@ use crate::{
 pub mod distant_light;
 pub mod infinite_light;
 pub mod point_light;
+pub mod rect_light;
+pub mod spot_light;
 
 pub mod uniform_light_sampler;
 
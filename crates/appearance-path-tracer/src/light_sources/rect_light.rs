@@ -0,0 +1,110 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    math::interaction::Interaction,
+    radiometry::{DenselySampledSpectrum, SampledSpectrum, SampledWavelengths, Spectrum},
+};
+
+use super::{LightSource, LightSourceLiSample, LightSourceSampleCtx, LightSourceType};
+
+/// One sided diffuse emitter, emitting along the normal of the rectangle
+pub struct RectLight {
+    center: Vec3,
+    // Half extents of the rectangle
+    right: Vec3,
+    up: Vec3,
+    normal: Vec3,
+    area: f32,
+    radiance: DenselySampledSpectrum,
+    scale: f32,
+}
+
+impl RectLight {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        center: Vec3,
+        right: Vec3,
+        up: Vec3,
+        normal: Vec3,
+        width: f32,
+        height: f32,
+        radiance: DenselySampledSpectrum,
+        scale: f32,
+    ) -> Self {
+        Self {
+            center,
+            right: right * width * 0.5,
+            up: up * height * 0.5,
+            normal,
+            area: width * height,
+            radiance,
+            scale,
+        }
+    }
+}
+
+impl LightSource for RectLight {
+    fn phi(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::new(
+            self.scale * self.radiance.sample(wavelengths).0 * self.area * core::f32::consts::PI,
+        )
+    }
+
+    fn ty(&self) -> LightSourceType {
+        LightSourceType::Area
+    }
+
+    fn sample_li(
+        &self,
+        ctx: LightSourceSampleCtx,
+        u: Vec2,
+        wavelengths: &SampledWavelengths,
+        _allow_incomplete_pdf: bool,
+    ) -> Option<LightSourceLiSample> {
+        let u = u * 2.0 - 1.0;
+        let point = self.center + self.right * u.x + self.up * u.y;
+
+        let to_light = point - ctx.point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let wi = to_light / distance_squared.sqrt();
+
+        let cos_light = -self.normal.dot(wi);
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        // Convert the uniform area density to solid angle
+        let pdf = distance_squared / (cos_light * self.area);
+
+        Some(LightSourceLiSample {
+            l: SampledSpectrum::new(self.scale * self.radiance.sample(wavelengths).0),
+            wi,
+            pdf,
+            light_interaction: Interaction::new_from_point(point),
+        })
+    }
+
+    fn pdf_li(&self, ctx: LightSourceSampleCtx, wi: Vec3, _allow_incomplete_pdf: bool) -> f32 {
+        let cos_light = -self.normal.dot(wi);
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+
+        let t = (self.center - ctx.point).dot(self.normal) / self.normal.dot(wi);
+        if t <= 0.0 {
+            return 0.0;
+        }
+
+        let local = ctx.point + wi * t - self.center;
+        if local.dot(self.right).abs() > self.right.length_squared()
+            || local.dot(self.up).abs() > self.up.length_squared()
+        {
+            return 0.0;
+        }
+
+        t * t / (cos_light * self.area)
+    }
+}
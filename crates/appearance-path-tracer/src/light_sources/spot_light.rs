@@ -0,0 +1,102 @@
+use core::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+
+use crate::{
+    math::interaction::Interaction,
+    radiometry::{DenselySampledSpectrum, SampledSpectrum, SampledWavelengths, Spectrum},
+};
+
+use super::{LightSource, LightSourceLiSample, LightSourceSampleCtx, LightSourceType};
+
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: DenselySampledSpectrum,
+    scale: f32,
+    cos_falloff_start: f32,
+    cos_falloff_end: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: DenselySampledSpectrum,
+        scale: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            position,
+            direction,
+            intensity,
+            scale,
+            cos_falloff_start: inner_angle.cos(),
+            cos_falloff_end: outer_angle.cos(),
+        }
+    }
+
+    fn falloff(&self, w: Vec3) -> f32 {
+        let cos_theta = self.direction.dot(w);
+        if self.cos_falloff_start <= self.cos_falloff_end {
+            return if cos_theta >= self.cos_falloff_end {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        let t = ((cos_theta - self.cos_falloff_end)
+            / (self.cos_falloff_start - self.cos_falloff_end))
+            .clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl LightSource for SpotLight {
+    fn phi(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::new(
+            self.scale
+                * self.intensity.sample(wavelengths).0
+                * 2.0
+                * PI
+                * ((1.0 - self.cos_falloff_start)
+                    + (self.cos_falloff_start - self.cos_falloff_end) / 2.0),
+        )
+    }
+
+    fn ty(&self) -> LightSourceType {
+        LightSourceType::DeltaPosition
+    }
+
+    fn sample_li(
+        &self,
+        ctx: LightSourceSampleCtx,
+        _u: Vec2,
+        wavelengths: &SampledWavelengths,
+        _allow_incomplete_pdf: bool,
+    ) -> Option<LightSourceLiSample> {
+        let wi = (self.position - ctx.point).normalize();
+        let falloff = self.falloff(-wi);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        let li = SampledSpectrum::new(
+            self.scale * falloff * self.intensity.sample(wavelengths).0
+                / self.position.distance_squared(ctx.point),
+        );
+
+        Some(LightSourceLiSample {
+            l: li,
+            wi,
+            pdf: 1.0,
+            light_interaction: Interaction::new_from_point(self.position),
+        })
+    }
+
+    fn pdf_li(&self, _ctx: LightSourceSampleCtx, _wi: Vec3, _allow_incomplete_pdf: bool) -> f32 {
+        0.0
+    }
+}
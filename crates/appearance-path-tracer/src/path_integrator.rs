@@ -4,7 +4,7 @@ use tinybvh::Ray;
 
 use crate::{
     geometry_resources::GeometryResources,
-    light_sources::{LightSource, LightSourceSampleCtx},
+    light_sources::{LightSource, LightSourceSampleCtx, LightSourceType},
     math::{
         interaction::{Interaction, SurfaceInteraction},
         normal::Normal,
@@ -79,7 +79,11 @@ impl PathIntegrator {
                         if !geometry_resources.tlas().is_occluded(&shadow_ray) {
                             let p_l = light_source_sample.pdf * light_sample.pdf;
 
-                            if light_source_sample.light_source.ty().is_delta() {
+                            // Area lights aren't part of the scene geometry, so they can only be reached through light sampling
+                            let light_source_ty = light_source_sample.light_source.ty();
+                            if light_source_ty.is_delta()
+                                || matches!(light_source_ty, LightSourceType::Area)
+                            {
                                 return SampledSpectrum(light_sample.l.0 * f.0 / p_l);
                             } else {
                                 let p_b = bsdf.pdf(
@@ -119,15 +123,15 @@ impl PathIntegrator {
             let hit_data = loop {
                 geometry_resources.tlas().intersect(&mut ray);
 
-                let hit_data = geometry_resources.get_hit_data(&ray.hit);
-
                 if ray.hit.t == 1e30 {
-                    break hit_data;
+                    break None;
                 }
 
+                let hit_data = geometry_resources.get_hit_data(&ray.hit);
+
                 if let Some(tex_coord) = hit_data.tex_coord {
-                    if let Some(base_color_texture) = &hit_data.material.base_color_texture {
-                        let alpha = base_color_texture
+                    if let Some(color_texture) = &hit_data.material.color_texture {
+                        let alpha = color_texture
                             .sample(
                                 tex_coord,
                                 TextureSampleRepeat::Repeat,
@@ -144,27 +148,10 @@ impl PathIntegrator {
                     }
                 }
 
-                break hit_data;
-            };
-
-            let hit_point = Vec3::from(ray.O) + Vec3::from(ray.D) * ray.hit.t;
-
-            let interaction = Interaction {
-                point: hit_point,
-                wo: -Vec3::from(ray.D),
-                normal: Normal(hit_data.normal),
-                uv: hit_data.tex_coord.unwrap_or_default(), // TODO: is this not supposed to be the bary coords?
-            };
-            let surface_interaction = SurfaceInteraction {
-                interaction: interaction.clone(),
-                dpdu: Vec3::ZERO, // TODO: derivates
-                dpdv: Vec3::ZERO,
-                dndu: Normal(Vec3::ZERO),
-                dndv: Normal(Vec3::ZERO),
-                shading_normal: interaction.normal, // TODO: optional normal mapping
+                break Some(hit_data);
             };
 
-            if ray.hit.t == 1e30 {
+            let Some(hit_data) = hit_data else {
                 let light_source = &geometry_resources.infinite_light;
                 let le = light_source.le(&ray, wavelengths);
 
@@ -182,7 +169,24 @@ impl PathIntegrator {
                 }
 
                 break;
-            }
+            };
+
+            let hit_point = Vec3::from(ray.O) + Vec3::from(ray.D) * ray.hit.t;
+
+            let interaction = Interaction {
+                point: hit_point,
+                wo: -Vec3::from(ray.D),
+                normal: Normal(hit_data.normal),
+                uv: hit_data.tex_coord.unwrap_or_default(), // TODO: is this not supposed to be the bary coords?
+            };
+            let surface_interaction = SurfaceInteraction {
+                interaction: interaction.clone(),
+                dpdu: Vec3::ZERO, // TODO: derivates
+                dpdv: Vec3::ZERO,
+                dndu: Normal(Vec3::ZERO),
+                dndv: Normal(Vec3::ZERO),
+                shading_normal: interaction.normal, // TODO: optional normal mapping
+            };
 
             // let normal_f = RgbAlbedoSpectrum::new(
             //     Rgb(hit_data.normal * 0.5 + 0.5),
@@ -199,12 +203,12 @@ impl PathIntegrator {
                 break;
             }
 
-            let mut base_color_factor = hit_data.material.base_color_factor.xyz();
-            let mut metallic_factor = hit_data.material.metallic_factor;
+            let mut color = hit_data.material.color;
+            let mut metallic = hit_data.material.metallic;
 
             if let Some(tex_coord) = hit_data.tex_coord {
-                if let Some(base_color_texture) = &hit_data.material.base_color_texture {
-                    base_color_factor *= base_color_texture
+                if let Some(color_texture) = &hit_data.material.color_texture {
+                    color *= color_texture
                         .sample(
                             tex_coord,
                             TextureSampleRepeat::Repeat,
@@ -223,23 +227,22 @@ impl PathIntegrator {
                             TextureSampleInterpolation::Linear,
                         )
                         .xyz();
-                    metallic_factor *= metallic_roughness.z;
+                    metallic *= metallic_roughness.z;
                 }
             }
 
-            let bsdf = if metallic_factor > 0.9 {
+            let bsdf = if metallic > 0.9 {
                 let eta = PiecewiseLinearSpectrum::au_eta().sample(wavelengths);
                 let k = PiecewiseLinearSpectrum::au_k().sample(wavelengths);
                 let microfacet = ThrowbridgeReitzDistribution::new(0.04, 0.04);
                 let conductor_bxdf = Box::new(ConductorBxdf::new(microfacet, eta, k));
                 Bsdf::new(conductor_bxdf, Normal(hit_data.normal), Vec3::ZERO)
-            } else if hit_data.material.transmission_factor > 0.0 {
+            } else if hit_data.material.transmission > 0.0 {
                 let microfacet = ThrowbridgeReitzDistribution::new(0.0, 0.0);
                 let dielectric_bxdf = Box::new(DielectricBxdf::new(microfacet, 1.5));
                 Bsdf::new(dielectric_bxdf, Normal(hit_data.normal), Vec3::ZERO)
             } else {
-                let spectrum =
-                    RgbAlbedoSpectrum::new(Rgb(base_color_factor), &RgbColorSpace::srgb());
+                let spectrum = RgbAlbedoSpectrum::new(Rgb(color), &RgbColorSpace::srgb());
                 let diffuse_bxdf = Box::new(DiffuseBxdf::new(spectrum.sample(wavelengths)));
                 Bsdf::new(diffuse_bxdf, Normal(hit_data.normal), Vec3::ZERO)
             };
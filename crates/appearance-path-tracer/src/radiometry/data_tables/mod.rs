@@ -1,9 +1,11 @@
 pub mod camera;
-pub mod cie;
+#[allow(clippy::approx_constant)]
 pub mod materials;
 pub mod rgb_color_space;
 pub mod swatch_reflectances;
 
+pub use appearance_color_spaces::cie;
+
 // Source: https://users.rust-lang.org/t/can-i-conveniently-compile-bytes-into-a-rust-program-with-a-specific-alignment/24049/2
 #[macro_use]
 pub mod macros {
@@ -15,7 +17,7 @@ pub mod macros {
 
     #[macro_export]
     macro_rules! include_bytes_align_as {
-        ($align_ty:ty, $path:literal) => {{
+        ($align_ty:ty, $path:expr) => {{
             // const block expression to encapsulate the static
             use $crate::radiometry::data_tables::macros::AlignedAs;
 
@@ -3,18 +3,22 @@ use std::sync::{Arc, OnceLock};
 
 pub type RgbSpectrumCoefficientArray = [[[[[f32; 3]; 64]; 64]; 64]; 3];
 
-const ACES_TO_SPECTRUM_SCALE_BYTES: &[u8] = include_bytes_align_as!(f32, "../../../acs/aces.acss");
-const ACES_TO_SPECTRUM_COEFF_BYTES: &[u8] = include_bytes_align_as!(f32, "../../../acs/aces.acsc");
+const ACES_TO_SPECTRUM_SCALE_BYTES: &[u8] =
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/aces.acss"));
+const ACES_TO_SPECTRUM_COEFF_BYTES: &[u8] =
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/aces.acsc"));
 const DCI_P3_TO_SPECTRUM_SCALE_BYTES: &[u8] =
-    include_bytes_align_as!(f32, "../../../acs/dci_p3.acss");
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/dci_p3.acss"));
 const DCI_P3_TO_SPECTRUM_COEFF_BYTES: &[u8] =
-    include_bytes_align_as!(f32, "../../../acs/dci_p3.acsc");
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/dci_p3.acsc"));
 const REC2020_TO_SPECTRUM_SCALE_BYTES: &[u8] =
-    include_bytes_align_as!(f32, "../../../acs/rec2020.acss");
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/rec2020.acss"));
 const REC2020_TO_SPECTRUM_COEFF_BYTES: &[u8] =
-    include_bytes_align_as!(f32, "../../../acs/rec2020.acsc");
-const SRGB_TO_SPECTRUM_SCALE_BYTES: &[u8] = include_bytes_align_as!(f32, "../../../acs/srgb.acss");
-const SRGB_TO_SPECTRUM_COEFF_BYTES: &[u8] = include_bytes_align_as!(f32, "../../../acs/srgb.acsc");
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/rec2020.acsc"));
+const SRGB_TO_SPECTRUM_SCALE_BYTES: &[u8] =
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/srgb.acss"));
+const SRGB_TO_SPECTRUM_COEFF_BYTES: &[u8] =
+    include_bytes_align_as!(f32, concat!(env!("OUT_DIR"), "/acs/srgb.acsc"));
 
 static ACES_TO_SPECTRUM_SCALE: OnceLock<Arc<Box<[f32]>>> = OnceLock::new();
 static ACES_TO_SPECTRUM_COEFF: OnceLock<Arc<RgbSpectrumCoefficientArray>> = OnceLock::new();
@@ -52,6 +52,22 @@ pub fn sample_uniform_disk_polar(u: Vec2) -> Vec2 {
     Vec2::new(r * theta.cos(), r * theta.sin())
 }
 
+/// Uniformly sample a regular polygon inscribed in the unit circle, with its first corner on the positive x axis
+pub fn sample_uniform_regular_polygon(u: Vec2, sides: u32) -> Vec2 {
+    let sides_f = sides as f32;
+    let corner_u = u.x * sides_f;
+    let corner = corner_u.floor().min(sides_f - 1.0);
+    let u_edge = corner_u - corner;
+
+    let corner_angle = |corner: f32| 2.0 * PI * corner / sides_f;
+    let a = Vec2::from_angle(corner_angle(corner));
+    let b = Vec2::from_angle(corner_angle(corner + 1.0));
+
+    // Uniform point in the triangle spanned by the center and the polygon edge
+    let s = u.y.sqrt();
+    s * (a * (1.0 - u_edge) + b * u_edge)
+}
+
 pub fn sample_cosine_hemisphere(u: Vec2) -> Vec3 {
     let d = sample_uniform_disk_concentric(u);
     let z = safe_sqrt(1.0 - sqr(d.x) - sqr(d.y));
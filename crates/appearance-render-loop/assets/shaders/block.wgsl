@@ -12,5 +12,7 @@ fn linear_to_block_pixel_idx(id: vec2<u32>, width: u32) -> vec2<u32> {
     let pixel_index: u32 = block_index * (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) +
                       block_offset.y * RENDER_BLOCK_SIZE + block_offset.x;
 
-    return vec2<u32>(pixel_index % width, pixel_index / width);
+    // Blocks are stored whole, so the texture holding them is padded to whole blocks as well
+    let padded_width: u32 = blocks_per_row * RENDER_BLOCK_SIZE;
+    return vec2<u32>(pixel_index % padded_width, pixel_index / padded_width);
 }
\ No newline at end of file
@@ -0,0 +1,29 @@
+#![no_main]
+
+use appearance_render_loop::host::{HostToNodeMessage, NodeToHostMessage};
+use appearance_world::{
+    components::MeshComponent,
+    visible_world_action::{VisibleWorldAction, VisibleWorldActionType},
+};
+use libfuzzer_sys::fuzz_target;
+
+// Decoding arbitrary packets must never panic, run with `cargo fuzz run decode_messages`
+fuzz_target!(|data: &[u8]| {
+    if let Ok(HostToNodeMessage::VisibleWorldActions(actions)) = HostToNodeMessage::from_bytes(data)
+    {
+        for action in &actions {
+            let _ = VisibleWorldActionType::from_ty_and_bytes(action.ty, &action.data);
+        }
+        let _ = VisibleWorldAction::coalesce(actions);
+    }
+
+    let _ = NodeToHostMessage::from_bytes(data);
+
+    // Also feed the raw bytes as an action payload, so the fuzzer doesn't need to find a valid header first
+    if let Some((ty, payload)) = data.split_first() {
+        let _ = VisibleWorldActionType::from_ty_and_bytes(*ty as u32 % 16, payload);
+    }
+
+    // Reassembled procedural mesh geometry is decoded separately from the chunks carrying it
+    let _ = MeshComponent::from_bytes(data, 0);
+});
@@ -0,0 +1,585 @@
+use anyhow::{anyhow, Result};
+use core::net::SocketAddr;
+use murmurhash3::murmurhash3_x64_128;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::host::{AssetChunkData, AssetInfoData, AssetRequestData, HostToNodeMessage};
+
+/// Bytes of an asset per chunk, small enough for a chunk to fit a single message of `MAX_BATCH_SIZE`
+pub const ASSET_CHUNK_SIZE: usize = 1024;
+/// Largest number of chunks an asset may be split into, limits streamed assets to 1GiB
+pub const MAX_ASSET_CHUNKS: u32 = 1024 * 1024;
+/// Chunks the host sends per frame across all transfers, so streaming large assets doesn't stall rendering
+pub const ASSET_CHUNKS_PER_FRAME: usize = 2048;
+/// Assets a node reassembles at once, the host streams them one after another so this is only reached by misbehaving peers
+pub const MAX_PENDING_ASSETS: usize = 16;
+/// Bytes of chunks a node holds for incomplete assets at once, as large as the largest asset which can be streamed
+pub const MAX_PENDING_ASSET_BYTES: usize = MAX_ASSET_CHUNKS as usize * ASSET_CHUNK_SIZE;
+/// Directory render nodes keep streamed assets in by default
+pub const DEFAULT_ASSET_CACHE_DIR: &str = "asset_cache";
+
+const ASSET_CACHE_INDEX: &str = "index";
+
+/// Hash identifying the content of an asset, 0 is reserved for assets which aren't available
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    murmurhash3_x64_128(bytes, 0).0.max(1)
+}
+
+/// Only relative paths inside the asset directory can be streamed, so nodes can't read arbitrary files from the host
+pub fn validate_asset_path(path: &str) -> Result<()> {
+    let asset_path = Path::new(path);
+    if !asset_path.starts_with("assets")
+        || !asset_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "Asset path {} is outside of the asset directory.",
+            path
+        ));
+    }
+
+    Ok(())
+}
+
+struct ServedAsset {
+    content_hash: u64,
+    modified: Option<SystemTime>,
+    bytes: Arc<Vec<u8>>,
+}
+
+struct AssetTransfer {
+    addr: SocketAddr,
+    content_hash: u64,
+    bytes: Arc<Vec<u8>>,
+    next_chunk: u32,
+}
+
+impl AssetTransfer {
+    fn chunk_count(&self) -> u32 {
+        self.bytes.len().div_ceil(ASSET_CHUNK_SIZE).max(1) as u32
+    }
+}
+
+/// Serves assets requested by render nodes from the asset directory of the host.
+/// Assets are kept in memory until they change on disk, transfers are sent in order a limited number of chunks at a time.
+#[derive(Default)]
+pub struct AssetServer {
+    assets: HashMap<String, ServedAsset>,
+    transfers: VecDeque<AssetTransfer>,
+}
+
+impl AssetServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn load(&mut self, path: &str) -> Result<(u64, Arc<Vec<u8>>)> {
+        validate_asset_path(path)?;
+
+        let modified = fs::metadata(path)?.modified().ok();
+        let is_current = self
+            .assets
+            .get(path)
+            .is_some_and(|asset| asset.modified.is_some() && asset.modified == modified);
+        if !is_current {
+            let bytes = fs::read(path)?;
+            if bytes.len().div_ceil(ASSET_CHUNK_SIZE) > MAX_ASSET_CHUNKS as usize {
+                return Err(anyhow!("Asset {} is too large to stream.", path));
+            }
+
+            self.assets.insert(
+                path.to_owned(),
+                ServedAsset {
+                    content_hash: content_hash(&bytes),
+                    modified,
+                    bytes: Arc::new(bytes),
+                },
+            );
+        }
+
+        let asset = &self.assets[path];
+        Ok((asset.content_hash, asset.bytes.clone()))
+    }
+
+    /// Answer the request of a node, the content of the asset is queued for transfer unless the node already has the same content
+    pub fn request(&mut self, addr: SocketAddr, request: AssetRequestData) -> HostToNodeMessage {
+        let content_hash = match self.load(&request.path) {
+            Ok((content_hash, bytes)) => {
+                let is_transferring = self
+                    .transfers
+                    .iter()
+                    .any(|transfer| transfer.addr == addr && transfer.content_hash == content_hash);
+                if content_hash != request.cached_hash && !is_transferring {
+                    log::info!(
+                        "Streaming asset {} ({} KiB) to {:?}.",
+                        request.path,
+                        bytes.len() >> 10,
+                        addr
+                    );
+
+                    self.transfers.push_back(AssetTransfer {
+                        addr,
+                        content_hash,
+                        bytes,
+                        next_chunk: 0,
+                    });
+                }
+
+                content_hash
+            }
+            Err(err) => {
+                log::warn!(
+                    "Node {:?} requested asset {} which can't be served: {}",
+                    addr,
+                    request.path,
+                    err
+                );
+                0
+            }
+        };
+
+        HostToNodeMessage::AssetInfo(AssetInfoData {
+            path: request.path,
+            content_hash,
+        })
+    }
+
+    /// Drop the transfers of nodes which disconnected
+    pub fn retain_nodes(&mut self, connected_nodes: &[SocketAddr]) {
+        self.transfers
+            .retain(|transfer| connected_nodes.contains(&transfer.addr));
+    }
+
+    /// Take up to `max_chunks` chunks of the queued transfers, along with the node each chunk has to be sent to
+    pub fn next_chunks(&mut self, max_chunks: usize) -> Vec<(SocketAddr, HostToNodeMessage)> {
+        let mut chunks = vec![];
+        while chunks.len() < max_chunks {
+            let Some(transfer) = self.transfers.front_mut() else {
+                break;
+            };
+
+            let chunk_count = transfer.chunk_count();
+            let start = transfer.next_chunk as usize * ASSET_CHUNK_SIZE;
+            let end = (start + ASSET_CHUNK_SIZE).min(transfer.bytes.len());
+
+            chunks.push((
+                transfer.addr,
+                HostToNodeMessage::AssetChunk(AssetChunkData {
+                    content_hash: transfer.content_hash,
+                    chunk_idx: transfer.next_chunk,
+                    chunk_count,
+                    bytes: transfer.bytes[start.min(end)..end].to_vec(),
+                }),
+            ));
+
+            transfer.next_chunk += 1;
+            if transfer.next_chunk == chunk_count {
+                self.transfers.pop_front();
+            }
+        }
+
+        chunks
+    }
+}
+
+struct PendingAsset {
+    chunk_count: u32,
+    /// Chunks received so far by index, only chunks which arrived take up memory
+    chunks: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+}
+
+impl PendingAsset {
+    fn new(chunk_count: u32) -> Self {
+        Self {
+            chunk_count,
+            chunks: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+}
+
+/// Reassembles the chunks of assets streamed from the host, assets are identified by their content hash.
+/// Only chunks of expected content are kept, and only up to `MAX_PENDING_ASSETS` assets and `MAX_PENDING_ASSET_BYTES` bytes at once.
+#[derive(Default)]
+pub struct AssetStreamReceiver {
+    /// Content the host announced, along with its chunks once the first one arrived
+    expected: HashMap<u64, Option<PendingAsset>>,
+    /// Bytes of all chunks received for incomplete assets
+    pending_bytes: usize,
+}
+
+impl AssetStreamReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept the chunks of the content with this hash, chunks of any other content are dropped
+    pub fn expect(&mut self, content_hash: u64) {
+        self.expected.entry(content_hash).or_insert(None);
+    }
+
+    /// Forget all expected content along with the chunks received for it
+    pub fn clear(&mut self) {
+        self.expected.clear();
+        self.pending_bytes = 0;
+    }
+
+    fn pending_assets(&self) -> usize {
+        self.expected
+            .values()
+            .filter(|pending| pending.is_some())
+            .count()
+    }
+
+    fn discard(&mut self, content_hash: u64) {
+        if let Some(Some(pending)) = self.expected.remove(&content_hash) {
+            self.pending_bytes -= pending.bytes;
+        }
+    }
+
+    /// Store a chunk, returns the content of the asset once all of its chunks have been received.
+    /// Fails once the content doesn't match its hash or exceeds the limits, the asset is no longer expected afterwards.
+    pub fn receive(&mut self, chunk: &AssetChunkData) -> Option<Result<Vec<u8>>> {
+        let content_hash = chunk.content_hash;
+        let pending_assets = self.pending_assets();
+        let pending = self.expected.get_mut(&content_hash)?;
+
+        if pending.is_none() && pending_assets >= MAX_PENDING_ASSETS {
+            self.discard(content_hash);
+            return Some(Err(anyhow!(
+                "Too many assets are streamed at once, dropping asset {:016x}.",
+                content_hash
+            )));
+        }
+
+        let pending = pending.get_or_insert_with(|| PendingAsset::new(chunk.chunk_count));
+        if pending.chunk_count != chunk.chunk_count {
+            self.pending_bytes -= pending.bytes;
+            *pending = PendingAsset::new(chunk.chunk_count);
+        }
+
+        if chunk.chunk_idx >= pending.chunk_count || pending.chunks.contains_key(&chunk.chunk_idx) {
+            return None;
+        }
+
+        if self.pending_bytes + chunk.bytes.len() > MAX_PENDING_ASSET_BYTES {
+            self.discard(content_hash);
+            return Some(Err(anyhow!(
+                "Streamed assets exceed {} MiB, dropping asset {:016x}.",
+                MAX_PENDING_ASSET_BYTES >> 20,
+                content_hash
+            )));
+        }
+
+        pending.chunks.insert(chunk.chunk_idx, chunk.bytes.clone());
+        pending.bytes += chunk.bytes.len();
+        self.pending_bytes += chunk.bytes.len();
+
+        if pending.chunks.len() < pending.chunk_count as usize {
+            return None;
+        }
+
+        let Some(Some(pending)) = self.expected.remove(&content_hash) else {
+            return None;
+        };
+        self.pending_bytes -= pending.bytes;
+
+        let bytes: Vec<u8> = pending.chunks.into_values().flatten().collect();
+        if self::content_hash(&bytes) != content_hash {
+            return Some(Err(anyhow!(
+                "Streamed asset {:016x} doesn't match its content hash.",
+                content_hash
+            )));
+        }
+
+        Some(Ok(bytes))
+    }
+}
+
+/// Content addressed store of streamed assets on a render node, every asset is a file named after its content hash.
+/// An index maps asset paths to the hash of their latest content, so the node can tell the host which content it already has.
+pub struct AssetCache {
+    dir: PathBuf,
+    index: HashMap<String, u64>,
+}
+
+impl AssetCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+
+        // Lines of `[content hash as hex] [asset path]`, a damaged index only costs transfers
+        let mut index = HashMap::new();
+        if let Ok(index_text) = fs::read_to_string(dir.join(ASSET_CACHE_INDEX)) {
+            for line in index_text.lines() {
+                if let Some((content_hash, path)) = line.split_once(' ') {
+                    if let Ok(content_hash) = u64::from_str_radix(content_hash, 16) {
+                        index.insert(path.to_owned(), content_hash);
+                    }
+                }
+            }
+        }
+
+        Ok(Self { dir, index })
+    }
+
+    fn content_path(&self, content_hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", content_hash))
+    }
+
+    /// Hash of the content the node has for an asset, either streamed earlier or in its local asset directory. 0 when it has neither.
+    pub fn cached_hash(&self, path: &str) -> u64 {
+        if let Some(content_hash) = self.index.get(path) {
+            if self.content_path(*content_hash).exists() {
+                return *content_hash;
+            }
+        }
+
+        fs::read(path)
+            .map(|bytes| content_hash(&bytes))
+            .unwrap_or(0)
+    }
+
+    /// Content with the given hash, `None` if it isn't cached or got damaged on disk
+    pub fn load(&self, content_hash: u64) -> Option<Vec<u8>> {
+        let bytes = fs::read(self.content_path(content_hash)).ok()?;
+        (self::content_hash(&bytes) == content_hash).then_some(bytes)
+    }
+
+    /// Store content without associating it with a path yet, returns its hash
+    pub fn store(&self, bytes: &[u8]) -> Result<u64> {
+        let content_hash = content_hash(bytes);
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.content_path(content_hash), bytes)?;
+        Ok(content_hash)
+    }
+
+    /// Remember the content an asset path resolved to, so it doesn't have to be transferred again
+    pub fn set_path(&mut self, path: &str, content_hash: u64) -> Result<()> {
+        if self.index.insert(path.to_owned(), content_hash) == Some(content_hash) {
+            return Ok(());
+        }
+
+        let index_text: String = self
+            .index
+            .iter()
+            .map(|(path, content_hash)| format!("{:016x} {}\n", content_hash, path))
+            .collect();
+        fs::write(self.dir.join(ASSET_CACHE_INDEX), index_text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: SocketAddr =
+        SocketAddr::new(core::net::IpAddr::V4(core::net::Ipv4Addr::LOCALHOST), 34235);
+
+    fn asset_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    /// Chunks the host sends for `bytes`, in order
+    fn served_chunks(bytes: Vec<u8>) -> Vec<AssetChunkData> {
+        let mut server = AssetServer::new();
+        server.transfers.push_back(AssetTransfer {
+            addr: NODE,
+            content_hash: content_hash(&bytes),
+            bytes: Arc::new(bytes),
+            next_chunk: 0,
+        });
+
+        let mut chunks = vec![];
+        loop {
+            let next_chunks = server.next_chunks(2);
+            if next_chunks.is_empty() {
+                break;
+            }
+
+            for (addr, message) in next_chunks {
+                assert_eq!(addr, NODE);
+                // Decoding validates the chunk the same way the node does
+                match HostToNodeMessage::from_bytes(&message.to_bytes()).unwrap() {
+                    HostToNodeMessage::AssetChunk(chunk) => chunks.push(chunk),
+                    _ => panic!("Transfer produced a different message."),
+                }
+            }
+        }
+
+        chunks
+    }
+
+    fn temp_cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "appearance-asset-cache-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn chunks_reassemble_in_any_order() {
+        let bytes = asset_bytes(ASSET_CHUNK_SIZE * 2 + ASSET_CHUNK_SIZE / 2);
+        let chunks = served_chunks(bytes.clone());
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.chunk_count == 3));
+        assert_eq!(chunks[2].bytes.len(), ASSET_CHUNK_SIZE / 2);
+
+        let mut receiver = AssetStreamReceiver::new();
+        receiver.expect(chunks[0].content_hash);
+        assert!(receiver.receive(&chunks[2]).is_none());
+        assert!(receiver.receive(&chunks[0]).is_none());
+        // Chunks can arrive twice when a transfer is requested again
+        assert!(receiver.receive(&chunks[0]).is_none());
+        assert_eq!(receiver.receive(&chunks[1]).unwrap().unwrap(), bytes);
+        assert!(receiver.expected.is_empty());
+        assert_eq!(receiver.pending_bytes, 0);
+
+        // The content is no longer expected once it's complete
+        assert!(receiver.receive(&chunks[0]).is_none());
+        assert!(receiver.expected.is_empty());
+    }
+
+    #[test]
+    fn empty_asset_is_a_single_chunk() {
+        let chunks = served_chunks(vec![]);
+        assert_eq!(chunks.len(), 1);
+
+        let mut receiver = AssetStreamReceiver::new();
+        receiver.expect(chunks[0].content_hash);
+        assert!(receiver.receive(&chunks[0]).unwrap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let mut chunks = served_chunks(asset_bytes(ASSET_CHUNK_SIZE + 1));
+        chunks[1].bytes[0] ^= 1;
+
+        let mut receiver = AssetStreamReceiver::new();
+        receiver.expect(chunks[0].content_hash);
+        assert!(receiver.receive(&chunks[0]).is_none());
+        assert!(receiver.receive(&chunks[1]).unwrap().is_err());
+        assert!(receiver.expected.is_empty());
+        assert_eq!(receiver.pending_bytes, 0);
+    }
+
+    #[test]
+    fn unknown_content_is_dropped() {
+        let mut receiver = AssetStreamReceiver::new();
+        receiver.expect(1);
+
+        // A spoofed chunk claiming to be the first of the largest possible asset
+        let chunk = AssetChunkData {
+            content_hash: 2,
+            chunk_idx: 0,
+            chunk_count: MAX_ASSET_CHUNKS,
+            bytes: vec![0; ASSET_CHUNK_SIZE],
+        };
+        assert!(receiver.receive(&chunk).is_none());
+        assert_eq!(receiver.expected.len(), 1);
+        assert!(receiver.expected[&1].is_none());
+        assert_eq!(receiver.pending_bytes, 0);
+    }
+
+    #[test]
+    fn chunk_slots_are_allocated_lazily() {
+        let mut receiver = AssetStreamReceiver::new();
+        receiver.expect(1);
+
+        let chunk = AssetChunkData {
+            content_hash: 1,
+            chunk_idx: MAX_ASSET_CHUNKS - 1,
+            chunk_count: MAX_ASSET_CHUNKS,
+            bytes: vec![0; ASSET_CHUNK_SIZE],
+        };
+        assert!(receiver.receive(&chunk).is_none());
+        assert_eq!(receiver.pending_bytes, ASSET_CHUNK_SIZE);
+        assert_eq!(receiver.expected[&1].as_ref().unwrap().chunks.len(), 1);
+
+        receiver.clear();
+        assert!(receiver.expected.is_empty());
+        assert_eq!(receiver.pending_bytes, 0);
+    }
+
+    #[test]
+    fn pending_assets_are_limited() {
+        let chunk = |content_hash| AssetChunkData {
+            content_hash,
+            chunk_idx: 0,
+            chunk_count: 2,
+            bytes: vec![0; ASSET_CHUNK_SIZE],
+        };
+
+        let mut receiver = AssetStreamReceiver::new();
+        for content_hash in 1..=MAX_PENDING_ASSETS as u64 + 1 {
+            receiver.expect(content_hash);
+        }
+        for content_hash in 1..=MAX_PENDING_ASSETS as u64 {
+            assert!(receiver.receive(&chunk(content_hash)).is_none());
+        }
+
+        let content_hash = MAX_PENDING_ASSETS as u64 + 1;
+        assert!(receiver.receive(&chunk(content_hash)).unwrap().is_err());
+        assert!(!receiver.expected.contains_key(&content_hash));
+        assert_eq!(
+            receiver.pending_bytes,
+            MAX_PENDING_ASSETS * ASSET_CHUNK_SIZE
+        );
+    }
+
+    #[test]
+    fn cache_hit() {
+        let dir = temp_cache_dir("hit");
+        let bytes = asset_bytes(ASSET_CHUNK_SIZE * 3);
+
+        let mut cache = AssetCache::new(&dir).unwrap();
+        assert_eq!(cache.cached_hash("assets/models/missing.glb"), 0);
+
+        let content_hash = cache.store(&bytes).unwrap();
+        assert_eq!(content_hash, super::content_hash(&bytes));
+        cache
+            .set_path("assets/models/streamed.glb", content_hash)
+            .unwrap();
+
+        // The index survives restarting the node
+        let cache = AssetCache::new(&dir).unwrap();
+        assert_eq!(
+            cache.cached_hash("assets/models/streamed.glb"),
+            content_hash
+        );
+        assert_eq!(cache.load(content_hash), Some(bytes));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_cache_entry_is_a_miss() {
+        let dir = temp_cache_dir("damaged");
+        let cache = AssetCache::new(&dir).unwrap();
+
+        let content_hash = cache.store(&asset_bytes(16)).unwrap();
+        fs::write(cache.content_path(content_hash), b"damaged").unwrap();
+        assert_eq!(cache.load(content_hash), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn asset_paths_stay_inside_the_asset_directory() {
+        assert!(validate_asset_path("assets/models/duck.glb").is_ok());
+        assert!(validate_asset_path("assets/../secret.txt").is_err());
+        assert!(validate_asset_path("/etc/passwd").is_err());
+        assert!(validate_asset_path("src/main.rs").is_err());
+    }
+}
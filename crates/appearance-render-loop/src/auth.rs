@@ -0,0 +1,360 @@
+use anyhow::{anyhow, Result};
+use core::{fmt, net::SocketAddr, time::Duration};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Instant;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of the MAC appended to every message when a pre-shared key is used, a truncated HMAC-SHA256
+pub const MAC_SIZE: usize = 16;
+/// Bytes of the nonce the host challenges a node with, which identifies the session of their messages
+pub const SESSION_NONCE_SIZE: usize = 16;
+/// Bytes of the counter sealed into every message, each side counts the messages it seals in a session
+pub const COUNTER_SIZE: usize = 8;
+/// Messages sent unreliably can arrive out of order, so counters this far behind the highest opened one are still accepted once
+const REPLAY_WINDOW: u64 = 64;
+
+pub type SessionNonce = [u8; SESSION_NONCE_SIZE];
+
+/// Session of the challenge itself, messages sent in it are only accepted as a challenge
+pub const NO_SESSION: SessionNonce = [0; SESSION_NONCE_SIZE];
+
+/// Warnings about unauthenticated packets are logged at most this often
+const UNAUTHENTICATED_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Side of the connection a `MessageAuth` seals messages for. The side which sealed a message is part of its MAC,
+/// so a message can't be reflected back to the side which sealed it, even though both sides share the key, session and counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Node,
+}
+
+impl Role {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Host => 0,
+            Self::Node => 1,
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Self::Host => Self::Node,
+            Self::Node => Self::Host,
+        }
+    }
+}
+
+/// Key shared by the host and its nodes out of band, nodes which don't know it are never given the world or any work.
+/// Messages are authenticated with it, not encrypted.
+#[derive(Clone)]
+pub struct PreSharedKey(Vec<u8>);
+
+impl PreSharedKey {
+    /// Use any secret as key, such as a passphrase passed on the command line
+    pub fn new<K: AsRef<[u8]>>(key: K) -> Result<Self> {
+        if key.as_ref().is_empty() {
+            return Err(anyhow!("Pre-shared key can't be empty."));
+        }
+
+        Ok(Self(key.as_ref().to_vec()))
+    }
+
+    fn mac(
+        &self,
+        sender: Role,
+        session: &SessionNonce,
+        counter: &[u8],
+        payload: &[u8],
+    ) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).unwrap();
+        mac.update(&[sender.to_u8()]);
+        mac.update(session);
+        mac.update(counter);
+        mac.update(payload);
+        mac
+    }
+}
+
+// The key never ends up in logs
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PreSharedKey(..)")
+    }
+}
+
+/// Authenticates the messages exchanged with a single peer. Every message carries a counter and a MAC over the role of its sender, the session nonce, the counter and its bytes.
+/// Messages of an earlier session, another node or the own side fail the MAC, messages replayed within the session are rejected by their counter.
+#[derive(Debug, Clone)]
+pub struct MessageAuth {
+    pre_shared_key: PreSharedKey,
+    /// Side this seals messages for, only messages sealed by the other side are opened
+    role: Role,
+    session: SessionNonce,
+    /// Counter of the next message sealed in the session, starts at 1
+    next_counter: u64,
+    /// Highest counter opened in the session, 0 before any message was opened
+    highest_counter: u64,
+    /// Bit `n` is set once the counter `highest_counter - n` has been opened
+    replay_window: u64,
+}
+
+impl MessageAuth {
+    pub fn new(pre_shared_key: PreSharedKey, role: Role, session: SessionNonce) -> Self {
+        Self {
+            pre_shared_key,
+            role,
+            session,
+            next_counter: 1,
+            highest_counter: 0,
+            replay_window: 0,
+        }
+    }
+
+    /// Start a new session, counters start over in it. Setting the current session again, such as for a repeated challenge, keeps its counters.
+    pub fn set_session(&mut self, session: SessionNonce) {
+        if session != self.session {
+            *self = Self::new(self.pre_shared_key.clone(), self.role, session);
+        }
+    }
+
+    /// Append the counter and MAC of the message in the current session
+    pub fn seal(&mut self, mut message_bytes: Vec<u8>) -> Vec<u8> {
+        let counter = self.next_counter.to_le_bytes();
+        self.next_counter += 1;
+
+        let mac = self
+            .pre_shared_key
+            .mac(self.role, &self.session, &counter, &message_bytes)
+            .finalize()
+            .into_bytes();
+        message_bytes.extend_from_slice(&counter);
+        message_bytes.extend_from_slice(&mac[..MAC_SIZE]);
+        message_bytes
+    }
+
+    /// Verify and strip the counter and MAC of a message, returns `None` if it wasn't sealed with the same key in the current session
+    /// or if its counter has been opened before
+    pub fn open<'a>(&mut self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        let (counter, payload) = self.open_in_session(&self.session, bytes)?;
+        self.accept_counter(counter).then_some(payload)
+    }
+
+    /// Verify and strip the counter and MAC of a message sealed in `NO_SESSION`, which only a challenge of the host is.
+    /// Challenges aren't protected against replays, which is harmless: a replayed challenge carries a nonce the host no longer uses,
+    /// so the handshake answering it fails the MAC on the host and never registers the node. At most it delays the node until the host
+    /// challenges it again, which dropping packets achieves just as well. A node only accepts challenges until a message of its session arrived.
+    pub fn open_without_session<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        self.open_in_session(&NO_SESSION, bytes)
+            .map(|(_, payload)| payload)
+    }
+
+    fn open_in_session<'a>(
+        &self,
+        session: &SessionNonce,
+        bytes: &'a [u8],
+    ) -> Option<(u64, &'a [u8])> {
+        let payload_len = bytes.len().checked_sub(COUNTER_SIZE + MAC_SIZE)?;
+        let (payload, sealed) = bytes.split_at(payload_len);
+        let (counter, mac) = sealed.split_at(COUNTER_SIZE);
+
+        // Compared in constant time
+        self.pre_shared_key
+            .mac(self.role.peer(), session, counter, payload)
+            .verify_truncated_left(mac)
+            .ok()?;
+        Some((u64::from_le_bytes(counter.try_into().ok()?), payload))
+    }
+
+    /// Sliding window over the highest counters, each counter is accepted once
+    fn accept_counter(&mut self, counter: u64) -> bool {
+        if counter > self.highest_counter {
+            let shift = counter - self.highest_counter;
+            self.replay_window = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.replay_window << shift
+            };
+            self.replay_window |= 1;
+            self.highest_counter = counter;
+            return true;
+        }
+
+        let age = self.highest_counter - counter;
+        if counter == 0 || age >= REPLAY_WINDOW || self.replay_window & (1 << age) != 0 {
+            return false;
+        }
+        self.replay_window |= 1 << age;
+        true
+    }
+}
+
+/// Random nonce for the challenge of a newly connected node, never equal to `NO_SESSION`
+pub fn new_session_nonce() -> Result<SessionNonce> {
+    let mut session = NO_SESSION;
+    while session == NO_SESSION {
+        getrandom::getrandom(&mut session)
+            .map_err(|err| anyhow!("Failed to generate session nonce: {}", err))?;
+    }
+
+    Ok(session)
+}
+
+/// Logs dropped unauthenticated packets, rate limited so a flood of them doesn't flood the log as well
+#[derive(Debug, Default)]
+pub struct UnauthenticatedLog {
+    dropped_packets: u64,
+    last_warning: Option<Instant>,
+}
+
+impl UnauthenticatedLog {
+    pub fn drop_packet(&mut self, addr: &SocketAddr) {
+        self.dropped_packets += 1;
+
+        let warn = match self.last_warning {
+            Some(last_warning) => last_warning.elapsed() >= UNAUTHENTICATED_WARNING_INTERVAL,
+            None => true,
+        };
+        if warn {
+            log::warn!(
+                "Dropped {} unauthenticated packets, the latest from {}. Only peers with the same pre-shared key are accepted.",
+                self.dropped_packets,
+                addr
+            );
+            self.dropped_packets = 0;
+            self.last_warning = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: SessionNonce = [7; SESSION_NONCE_SIZE];
+
+    fn auth(key: &str, session: SessionNonce) -> MessageAuth {
+        MessageAuth::new(PreSharedKey::new(key).unwrap(), Role::Node, session)
+    }
+
+    fn host_auth(key: &str, session: SessionNonce) -> MessageAuth {
+        MessageAuth::new(PreSharedKey::new(key).unwrap(), Role::Host, session)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut sender = host_auth("venue", SESSION);
+        let mut receiver = auth("venue", SESSION);
+
+        for message in [&b"start render"[..], b"", b"tile"] {
+            let sealed = sender.seal(message.to_vec());
+            assert_eq!(sealed.len(), message.len() + COUNTER_SIZE + MAC_SIZE);
+            assert_eq!(receiver.open(&sealed), Some(message));
+        }
+    }
+
+    #[test]
+    fn wrong_key() {
+        let sealed = host_auth("venue", SESSION).seal(b"start render".to_vec());
+        assert_eq!(auth("not the venue", SESSION).open(&sealed), None);
+    }
+
+    #[test]
+    fn tampered_payload() {
+        let mut sealed = host_auth("venue", SESSION).seal(b"start render".to_vec());
+        sealed[0] ^= 1;
+        assert_eq!(auth("venue", SESSION).open(&sealed), None);
+    }
+
+    #[test]
+    fn tampered_counter() {
+        let mut sealed = host_auth("venue", SESSION).seal(b"start render".to_vec());
+        let counter = sealed.len() - MAC_SIZE - COUNTER_SIZE;
+        sealed[counter] ^= 2;
+        assert_eq!(auth("venue", SESSION).open(&sealed), None);
+    }
+
+    #[test]
+    fn truncated_mac() {
+        let sealed = host_auth("venue", SESSION).seal(b"start render".to_vec());
+        let mut receiver = auth("venue", SESSION);
+        assert_eq!(receiver.open(&sealed[..sealed.len() - 1]), None);
+        assert_eq!(receiver.open(&sealed[..COUNTER_SIZE + MAC_SIZE - 1]), None);
+        assert_eq!(receiver.open(&[]), None);
+    }
+
+    #[test]
+    fn wrong_session() {
+        let sealed = host_auth("venue", SESSION).seal(b"start render".to_vec());
+        assert_eq!(auth("venue", [8; SESSION_NONCE_SIZE]).open(&sealed), None);
+        assert_eq!(auth("venue", SESSION).open_without_session(&sealed), None);
+
+        let challenge = host_auth("venue", NO_SESSION).seal(b"challenge".to_vec());
+        assert_eq!(auth("venue", SESSION).open(&challenge), None);
+        assert_eq!(
+            auth("venue", SESSION).open_without_session(&challenge),
+            Some(&b"challenge"[..])
+        );
+    }
+
+    #[test]
+    fn replayed_counter() {
+        let mut sender = host_auth("venue", SESSION);
+        let mut receiver = auth("venue", SESSION);
+
+        let first = sender.seal(b"first".to_vec());
+        let second = sender.seal(b"second".to_vec());
+        assert!(receiver.open(&second).is_some());
+        assert!(receiver.open(&second).is_none());
+
+        // Reordered messages are accepted once within the window
+        assert!(receiver.open(&first).is_some());
+        assert!(receiver.open(&first).is_none());
+
+        // Messages which fell out of the window are rejected even if they never arrived
+        let stale = sender.seal(b"stale".to_vec());
+        for _ in 0..REPLAY_WINDOW {
+            let message = sender.seal(b"newer".to_vec());
+            assert!(receiver.open(&message).is_some());
+        }
+        assert!(receiver.open(&stale).is_none());
+    }
+
+    #[test]
+    fn reflected_message() {
+        let mut host = host_auth("venue", SESSION);
+        let mut node = auth("venue", SESSION);
+
+        // A ping reflected back to the host must not pass as the pong of the node
+        let ping = host.seal(b"ping".to_vec());
+        assert_eq!(host.open(&ping), None);
+        assert_eq!(node.open(&ping), Some(&b"ping"[..]));
+
+        let pong = node.seal(b"pong".to_vec());
+        assert_eq!(node.open(&pong), None);
+        assert_eq!(host.open(&pong), Some(&b"pong"[..]));
+
+        // The node can't forge a challenge the host accepts either
+        let challenge = auth("venue", NO_SESSION).seal(b"challenge".to_vec());
+        assert_eq!(node.open_without_session(&challenge), None);
+        assert_eq!(
+            host.open_without_session(&challenge),
+            Some(&b"challenge"[..])
+        );
+    }
+
+    #[test]
+    fn new_session_resets_counters() {
+        let mut sender = host_auth("venue", SESSION);
+        let mut receiver = auth("venue", SESSION);
+        assert!(receiver.open(&sender.seal(b"old".to_vec())).is_some());
+
+        let session = new_session_nonce().unwrap();
+        sender.set_session(session);
+        receiver.set_session(session);
+        let sealed = sender.seal(b"new".to_vec());
+        assert_eq!(receiver.open(&sealed), Some(&b"new"[..]));
+    }
+}
@@ -0,0 +1,55 @@
+use core::net::SocketAddr;
+
+/// Drops and reorders the unreliable packets a node sends, so the host can be exercised against a lossy network without needing one
+pub struct FaultInjection {
+    drop_rate: f32,
+    reorder_rate: f32,
+    rng_state: u64,
+    held_back: Option<(SocketAddr, Vec<u8>)>,
+}
+
+impl FaultInjection {
+    /// Drop `drop_rate` and swap `reorder_rate` of all packets with the packet after them, the same seed always affects the same packets
+    pub fn new(drop_rate: f32, reorder_rate: f32, seed: u64) -> Self {
+        Self {
+            drop_rate: drop_rate.clamp(0.0, 1.0),
+            reorder_rate: reorder_rate.clamp(0.0, 1.0),
+            rng_state: seed,
+            held_back: None,
+        }
+    }
+
+    /// Splitmix64, returns a value in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Packets to send in place of the given packet, in order
+    pub fn apply(&mut self, addr: SocketAddr, bytes: Vec<u8>) -> Vec<(SocketAddr, Vec<u8>)> {
+        if self.next_f32() < self.drop_rate {
+            return vec![];
+        }
+
+        if let Some(held_back) = self.held_back.take() {
+            return vec![(addr, bytes), held_back];
+        }
+
+        if self.next_f32() < self.reorder_rate {
+            self.held_back = Some((addr, bytes));
+            return vec![];
+        }
+
+        vec![(addr, bytes)]
+    }
+
+    /// Release the packet held back to be reordered, should be called before a node reports its work as finished
+    pub fn flush(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        self.held_back.take()
+    }
+}
@@ -1,19 +1,38 @@
-use anyhow::Result;
-use appearance_world::visible_world_action::VisibleWorldAction;
+use anyhow::{anyhow, Result};
+use appearance_packing::PackedRgb9e5;
+use appearance_wgpu::wgpu;
+use appearance_world::{
+    visible_world_action::{VisibleWorldAction, VisibleWorldActionType},
+    wire::{WireReader, WireWriter},
+};
 use core::{
     net::SocketAddr,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
 };
-use crossbeam::channel::Receiver;
+use crossbeam::channel::{Receiver, Sender};
+use glam::{UVec2, Vec3};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::{Arc, Mutex},
     thread,
+    time::Instant,
 };
 
 use unreliable::{Socket, SocketEvent};
 
-/// Size of each rendered block is a multiple of 8x8, as this is the minimum size jpeg is able to compress. This must also be a multiple of `PATH_TRACER_RAY_PACKET_SIZE`, which is 16.
+use crate::{
+    asset_stream::{AssetServer, ASSET_CHUNKS_PER_FRAME, ASSET_CHUNK_SIZE, MAX_ASSET_CHUNKS},
+    auth::{
+        new_session_nonce, MessageAuth, PreSharedKey, Role, SessionNonce, UnauthenticatedLog,
+        COUNTER_SIZE, MAC_SIZE, NO_SESSION,
+    },
+    load_balancer::{NodeRowSplit, RowLoadBalancer},
+    network_stats::NetworkStats,
+    tile_codec::{decode_tile, tonemap, DecodedTile, TileEncoding},
+};
+
+/// Size of each rendered block, this must be a multiple of `PATH_TRACER_RAY_PACKET_SIZE`, which is 16. Blocks on the edge of the frame are cropped to the resolution.
 pub const RENDER_BLOCK_SIZE: u32 = 64;
 pub const BYTES_PER_PIXEL: usize = 4;
 pub const NODE_BYTES_PER_PIXEL: usize = 4;
@@ -21,64 +40,299 @@ pub const NODE_BYTES_PER_PIXEL: usize = 4;
 pub const ENABLE_COMPRESSION: bool = true;
 pub const NODE_PIXEL_FORMAT: turbojpeg::PixelFormat = turbojpeg::PixelFormat::RGBX;
 
+const PROTOCOL_MAGIC: [u8; 2] = *b"AP";
+/// Bump whenever the encoding of any message changes, messages from a different version are rejected
+pub const PROTOCOL_VERSION: u16 = 16;
+/// Largest width and height a node accepts to render
+pub const MAX_RENDER_RESOLUTION: u32 = 16384;
+/// Largest packet sent between host and nodes, which keeps them below the common ethernet MTU of 1500 bytes including IP, UDP and socket headers
+pub const MAX_PACKET_SIZE: usize = 1200;
+/// Visible world actions are packed into messages up to this size, which leaves room for the counter and MAC appended when sealing a message
+pub const MAX_BATCH_SIZE: usize = MAX_PACKET_SIZE - COUNTER_SIZE - MAC_SIZE;
+/// Largest number of blocks in a single `RenderBlocks` request, which keeps the request within `MAX_BATCH_SIZE`
+pub const MAX_BLOCKS_PER_REQUEST: u32 = 256;
+/// Requests each node works on at once in tile scheduling, so a node never idles while waiting for its next request
+const BLOCK_REQUESTS_IN_FLIGHT: usize = 2;
+/// Time nodes get to deliver a frame by default, blocks which haven't arrived by then show the previous frame instead
+pub const DEFAULT_FRAME_DEADLINE: Duration = Duration::from_secs(1);
+/// Nodes which miss this many frame deadlines in a row are evicted
+pub const MAX_MISSED_DEADLINES: u32 = 3;
+/// Nodes which don't send an (authenticated) handshake within this time after connecting are refused, they're most likely running an outdated binary or lack the pre-shared key
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between pings the host sends every node to measure the round trip
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Every message starts with a magic, protocol version and message type
+fn write_header(writer: &mut WireWriter, ty: u8) {
+    writer.write_u8(PROTOCOL_MAGIC[0]);
+    writer.write_u8(PROTOCOL_MAGIC[1]);
+    writer.write_u16(PROTOCOL_VERSION);
+    writer.write_u8(ty);
+}
+
+fn read_header(reader: &mut WireReader) -> Result<u8> {
+    if [reader.read_u8()?, reader.read_u8()?] != PROTOCOL_MAGIC {
+        return Err(anyhow!("Message doesn't start with the protocol magic."));
+    }
+
+    let version = reader.read_u16()?;
+    if version != PROTOCOL_VERSION {
+        return Err(anyhow!(
+            "Unsupported protocol version {}, expected {}.",
+            version,
+            PROTOCOL_VERSION
+        ));
+    }
+
+    reader.read_u8()
+}
+
+/// Protocol version of a message without decoding the rest of it, this works for messages of any version
+pub fn peek_protocol_version(bytes: &[u8]) -> Result<u16> {
+    let mut reader = WireReader::new(bytes);
+    if [reader.read_u8()?, reader.read_u8()?] != PROTOCOL_MAGIC {
+        return Err(anyhow!("Message doesn't start with the protocol magic."));
+    }
+
+    reader.read_u16()
+}
+
 pub struct RenderPartialFinishedData {
     pub row: u32,
     pub column_block: u32,
     pub frame_idx: u32,
+    /// Sample index the block was rendered with when accumulating, 0 otherwise
+    pub sample_idx: u32,
+    pub encoding: TileEncoding,
     pub compressed_pixel_bytes: Vec<u8>,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct RenderFinishedData {
     pub frame_idx: u32,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct BlocksFinishedData {
+    pub frame_idx: u32,
+    pub request_idx: u32,
+}
+
+/// Sent by the host to measure the round trip to a node, the node answers with the same data
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PingData {
+    /// Microseconds since the host started when the ping was sent, wraps around
+    pub sent_micros: u32,
+    /// Microseconds the ping waited on the node before it was answered, only set in the answer
+    pub hold_micros: u32,
+    /// Round trip the host measured last so the node knows it as well, 0 until measured
+    pub round_trip_micros: u32,
+}
+
+impl PingData {
+    fn write(&self, writer: &mut WireWriter) {
+        writer.write_u32(self.sent_micros);
+        writer.write_u32(self.hold_micros);
+        writer.write_u32(self.round_trip_micros);
+    }
+
+    fn read(reader: &mut WireReader) -> Result<Self> {
+        Ok(Self {
+            sent_micros: reader.read_u32()?,
+            hold_micros: reader.read_u32()?,
+            round_trip_micros: reader.read_u32()?,
+        })
+    }
+}
+
+/// Sent by a host with a pre-shared key to every node which connects, the node seals its handshake and all later messages in the session of the nonce
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChallengeData {
+    pub nonce: SessionNonce,
+}
+
+/// Sent to a node evicted for missing too many frame deadlines, it has to handshake again before it's given any work.
+/// With a pre-shared key the host challenges it again, without one the node sends its handshake right away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvictData {
+    pub missed_deadlines: u32,
+}
+
+/// Sent by a node which needs an asset, `cached_hash` is the content hash of the version it already has or 0 if it has none
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetRequestData {
+    pub path: String,
+    pub cached_hash: u64,
+}
+
+/// Kind of renderer a node runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererKind {
+    /// Spectral path tracer running on the cpu
+    Cpu,
+    /// Hardware accelerated path tracer running on the gpu
+    Gpu,
+}
+
+impl RendererKind {
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Cpu),
+            1 => Ok(Self::Gpu),
+            value => Err(anyhow!("Unknown renderer kind {}.", value)),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Cpu => 0,
+            Self::Gpu => 1,
+        }
+    }
+}
+
+/// Sent by a node right after connecting, the host only gives work to nodes with a compatible handshake
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeCapabilities {
+    pub protocol_version: u16,
+    pub adapter_name: String,
+    pub backend: String,
+    /// Video memory of the adapter in bytes, 0 when unknown as wgpu doesn't expose it
+    pub vram_bytes: u64,
+    pub features: wgpu::Features,
+    pub renderer: RendererKind,
+}
+
+impl NodeCapabilities {
+    /// Capabilities of the current protocol version without any adapter information
+    pub fn new(renderer: RendererKind) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            adapter_name: String::new(),
+            backend: String::new(),
+            vram_bytes: 0,
+            features: wgpu::Features::empty(),
+            renderer,
+        }
+    }
+}
+
 pub enum NodeToHostMessage {
     RenderPartialFinished(RenderPartialFinishedData),
+    BlocksFinished(BlocksFinishedData),
+    RenderFinished(RenderFinishedData),
+    Handshake(NodeCapabilities),
+    AssetRequest(AssetRequestData),
+    Pong(PingData),
 }
 
 impl NodeToHostMessage {
     pub fn to_bytes(self) -> Vec<u8> {
-        match self {
-            NodeToHostMessage::RenderPartialFinished(mut data) => {
-                let mut bytes = bytemuck::bytes_of(&0u32).to_vec();
-                bytes.append(&mut bytemuck::bytes_of(&data.row).to_vec());
-                bytes.append(&mut bytemuck::bytes_of(&data.column_block).to_vec());
-                bytes.append(&mut bytemuck::bytes_of(&data.frame_idx).to_vec());
-                bytes.append(&mut data.compressed_pixel_bytes);
-
-                let padded_size = bytes.len().div_ceil(4) * 4;
-                bytes.resize(padded_size, 0u8);
+        let mut writer = WireWriter::new();
 
-                bytes
+        match self {
+            NodeToHostMessage::RenderPartialFinished(data) => {
+                write_header(&mut writer, 0);
+                writer.write_u32(data.row);
+                writer.write_u32(data.column_block);
+                writer.write_u32(data.frame_idx);
+                writer.write_u32(data.sample_idx);
+                writer.write_u8(data.encoding.to_u8());
+                writer.write_bytes(&data.compressed_pixel_bytes);
+            }
+            NodeToHostMessage::BlocksFinished(data) => {
+                write_header(&mut writer, 1);
+                writer.write_u32(data.frame_idx);
+                writer.write_u32(data.request_idx);
+            }
+            NodeToHostMessage::RenderFinished(data) => {
+                write_header(&mut writer, 2);
+                writer.write_u32(data.frame_idx);
+            }
+            NodeToHostMessage::Handshake(capabilities) => {
+                write_header(&mut writer, 3);
+                writer.write_u16(capabilities.protocol_version);
+                writer.write_string(&capabilities.adapter_name);
+                writer.write_string(&capabilities.backend);
+                writer.write_pod(&capabilities.vram_bytes);
+                // Features are sent by name, their bits differ between wgpu versions
+                let feature_names: Vec<&str> = capabilities
+                    .features
+                    .iter_names()
+                    .map(|(name, _)| name)
+                    .collect();
+                writer.write_u32(feature_names.len() as u32);
+                for name in feature_names {
+                    writer.write_string(name);
+                }
+                writer.write_u8(capabilities.renderer.to_u8());
+            }
+            NodeToHostMessage::AssetRequest(data) => {
+                write_header(&mut writer, 4);
+                writer.write_string(&data.path);
+                writer.write_pod(&data.cached_hash);
+            }
+            NodeToHostMessage::Pong(data) => {
+                write_header(&mut writer, 5);
+                data.write(&mut writer);
             }
         }
+
+        writer.into_bytes()
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 4 {
-            return Err(anyhow::Error::msg(
-                "Failed to convert bytes to node-to-host message. (Empty bytes)",
-            ));
-        }
+        let mut reader = WireReader::new(bytes);
+
+        let message = match read_header(&mut reader)? {
+            0 => Self::RenderPartialFinished(RenderPartialFinishedData {
+                row: reader.read_u32()?,
+                column_block: reader.read_u32()?,
+                frame_idx: reader.read_u32()?,
+                sample_idx: reader.read_u32()?,
+                encoding: TileEncoding::from_u8(reader.read_u8()?)?,
+                compressed_pixel_bytes: reader.read_bytes()?.to_vec(),
+            }),
+            1 => Self::BlocksFinished(BlocksFinishedData {
+                frame_idx: reader.read_u32()?,
+                request_idx: reader.read_u32()?,
+            }),
+            2 => Self::RenderFinished(RenderFinishedData {
+                frame_idx: reader.read_u32()?,
+            }),
+            3 => {
+                let protocol_version = reader.read_u16()?;
+                let adapter_name = reader.read_string()?;
+                let backend = reader.read_string()?;
+                let vram_bytes: u64 = reader.read_pod()?;
+
+                // Features unknown to this wgpu version can't be used by the host either, so they're skipped
+                let mut features = wgpu::Features::empty();
+                for _ in 0..reader.read_u32()? {
+                    if let Some(feature) = wgpu::Features::from_name(&reader.read_string()?) {
+                        features |= feature;
+                    }
+                }
 
-        let ty = *bytemuck::from_bytes::<u32>(&bytes[0..4]);
-        match ty {
-            0 => {
-                let row = *bytemuck::from_bytes::<u32>(&bytes[4..8]);
-                let column_block = *bytemuck::from_bytes::<u32>(&bytes[8..12]);
-                let frame_idx = *bytemuck::from_bytes::<u32>(&bytes[12..16]);
-                let compressed_pixel_bytes = bytes[16..bytes.len()].to_vec();
-                Ok(Self::RenderPartialFinished(RenderPartialFinishedData {
-                    row,
-                    column_block,
-                    frame_idx,
-                    compressed_pixel_bytes,
-                }))
+                Self::Handshake(NodeCapabilities {
+                    protocol_version,
+                    adapter_name,
+                    backend,
+                    vram_bytes,
+                    features,
+                    renderer: RendererKind::from_u8(reader.read_u8()?)?,
+                })
             }
-            _ => Err(anyhow::Error::msg(
-                "Failed to convert bytes to node-to-host message.",
-            )),
-        }
+            4 => Self::AssetRequest(AssetRequestData {
+                path: reader.read_string()?,
+                cached_hash: reader.read_pod()?,
+            }),
+            5 => Self::Pong(PingData::read(&mut reader)?),
+            ty => return Err(anyhow!("Unknown node-to-host message type {}.", ty)),
+        };
+
+        reader.finish()?;
+        Ok(message)
     }
 }
 
@@ -90,142 +344,730 @@ pub struct StartRenderData {
     pub row_start: u32,
     pub row_end: u32,
     pub frame_idx: u32,
+    /// `TileEncoding` the host would like to receive, nodes unable to render hdr fall back to jpeg
+    pub tile_encoding: u32,
+    /// Nonzero when accumulating a static view, every node renders the whole frame with a different sample index
+    pub sample_idx: u32,
+}
+
+/// Blocks covering the resolution, blocks on the right and bottom edge are cropped when the resolution isn't a multiple of `RENDER_BLOCK_SIZE`
+pub fn block_count(resolution: UVec2) -> UVec2 {
+    UVec2::new(
+        resolution.x.div_ceil(RENDER_BLOCK_SIZE),
+        resolution.y.div_ceil(RENDER_BLOCK_SIZE),
+    )
+}
+
+/// Resolution rounded up to whole blocks. Pixels are stored block after block, so buffers of pixels are this large including the padding of edge blocks.
+pub fn padded_resolution(resolution: UVec2) -> UVec2 {
+    block_count(resolution) * RENDER_BLOCK_SIZE
+}
+
+/// Pixels of the block within the resolution, only these are sent to the host. Empty for blocks outside the resolution.
+pub fn cropped_block_size(resolution: UVec2, block: UVec2) -> UVec2 {
+    resolution
+        .saturating_sub(block.saturating_mul(UVec2::splat(RENDER_BLOCK_SIZE)))
+        .min(UVec2::splat(RENDER_BLOCK_SIZE))
+}
+
+fn validate_render_resolution(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 || width > MAX_RENDER_RESOLUTION || height > MAX_RENDER_RESOLUTION
+    {
+        return Err(anyhow!("Invalid render resolution {}x{}.", width, height));
+    }
+
+    Ok(())
+}
+
+impl StartRenderData {
+    pub fn tile_encoding(&self) -> TileEncoding {
+        TileEncoding::from_u8(self.tile_encoding as u8).unwrap_or_default()
+    }
+
+    /// Sample index to render with, `None` for regular real-time rendering
+    pub fn sample_index(&self) -> Option<u32> {
+        (self.sample_idx > 0).then_some(self.sample_idx)
+    }
+
+    /// Reject render requests a node isn't able to fulfill
+    fn validate(&self) -> Result<()> {
+        validate_render_resolution(self.width, self.height)?;
+
+        if self.tile_encoding > u8::MAX as u32 {
+            return Err(anyhow!("Unknown tile encoding {}.", self.tile_encoding));
+        }
+        TileEncoding::from_u8(self.tile_encoding as u8)?;
+
+        if self.row_start >= self.row_end || self.row_end > self.height {
+            return Err(anyhow!(
+                "Invalid render rows {}..{} for height {}.",
+                self.row_start,
+                self.row_end,
+                self.height
+            ));
+        }
+
+        // Only the last block row can be cropped
+        if self.row_start % RENDER_BLOCK_SIZE != 0
+            || (self.row_end % RENDER_BLOCK_SIZE != 0 && self.row_end != self.height)
+        {
+            return Err(anyhow!(
+                "Render region isn't aligned to blocks of {} pixels.",
+                RENDER_BLOCK_SIZE
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A list of blocks to render, used by `RenderScheduling::Tiles`
+#[derive(Debug, Clone, Default)]
+pub struct RenderBlocksData {
+    pub width: u32,
+    pub height: u32,
+    pub frame_idx: u32,
+    /// Identifies the request in the `BlocksFinished` reply
+    pub request_idx: u32,
+    pub tile_encoding: TileEncoding,
+    pub blocks: Vec<UVec2>,
+}
+
+impl RenderBlocksData {
+    /// Reject render requests a node isn't able to fulfill
+    fn validate(&self) -> Result<()> {
+        validate_render_resolution(self.width, self.height)?;
+
+        if self.blocks.is_empty() || self.blocks.len() > MAX_BLOCKS_PER_REQUEST as usize {
+            return Err(anyhow!(
+                "Invalid number of blocks {} in render request.",
+                self.blocks.len()
+            ));
+        }
+
+        let num_blocks = block_count(UVec2::new(self.width, self.height));
+        if let Some(block) = self
+            .blocks
+            .iter()
+            .find(|block| block.x >= num_blocks.x || block.y >= num_blocks.y)
+        {
+            return Err(anyhow!(
+                "Block {} is outside of the render resolution {}x{}.",
+                block,
+                self.width,
+                self.height
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Bytes `HostToNodeMessage::VisibleWorldActions` needs per action
+fn visible_world_action_size(action: &VisibleWorldAction) -> usize {
+    4 + 1 + 4 + action.data.len()
+}
+
+/// Split actions into batches of at most `MAX_BATCH_SIZE` bytes, keeping their order. Actions exceeding the size by themselves get a batch of their own.
+pub fn batch_visible_world_actions(
+    visible_world_actions: Vec<VisibleWorldAction>,
+) -> Vec<Vec<VisibleWorldAction>> {
+    // Message header and action count
+    const BATCH_HEADER_SIZE: usize = 5 + 4;
+
+    let mut batches = vec![];
+    let mut batch = vec![];
+    let mut batch_size = BATCH_HEADER_SIZE;
+    for visible_world_action in visible_world_actions {
+        let size = visible_world_action_size(&visible_world_action);
+        if !batch.is_empty() && batch_size + size > MAX_BATCH_SIZE {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = BATCH_HEADER_SIZE;
+        }
+
+        batch.push(visible_world_action);
+        batch_size += size;
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// Reply to an `AssetRequest`, a `content_hash` of 0 means the host can't serve the asset.
+/// When the hash differs from the one the node reported, the content follows in `AssetChunk` messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetInfoData {
+    pub path: String,
+    pub content_hash: u64,
+}
+
+/// Part of the content of a streamed asset, the asset is complete once all chunks with its content hash arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetChunkData {
+    pub content_hash: u64,
+    pub chunk_idx: u32,
+    pub chunk_count: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl AssetChunkData {
+    /// Reject chunks which can't be part of a valid transfer
+    fn validate(&self) -> Result<()> {
+        if self.chunk_count == 0
+            || self.chunk_count > MAX_ASSET_CHUNKS
+            || self.chunk_idx >= self.chunk_count
+        {
+            return Err(anyhow!(
+                "Invalid asset chunk {} of {}.",
+                self.chunk_idx,
+                self.chunk_count
+            ));
+        }
+
+        if self.bytes.len() > ASSET_CHUNK_SIZE {
+            return Err(anyhow!(
+                "Asset chunk of {} bytes exceeds the chunk size.",
+                self.bytes.len()
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub enum HostToNodeMessage {
     StartRender(StartRenderData),
-    VisibleWorldAction(VisibleWorldAction),
+    VisibleWorldActions(Vec<VisibleWorldAction>),
+    RenderBlocks(RenderBlocksData),
+    AssetInfo(AssetInfoData),
+    AssetChunk(AssetChunkData),
+    Ping(PingData),
+    Challenge(ChallengeData),
+    Evict(EvictData),
 }
 
 impl HostToNodeMessage {
     pub fn to_bytes(self) -> Vec<u8> {
+        let mut writer = WireWriter::new();
+
         match self {
             HostToNodeMessage::StartRender(data) => {
-                let mut bytes = bytemuck::bytes_of(&0u32).to_vec();
-                bytes.append(&mut bytemuck::bytes_of(&data).to_vec());
-                bytes
+                write_header(&mut writer, 0);
+                writer.write_pod(&data);
+            }
+            HostToNodeMessage::VisibleWorldActions(actions) => {
+                write_header(&mut writer, 1);
+                writer.write_u32(actions.len() as u32);
+                for action in actions {
+                    writer.write_u32(action.ty);
+                    writer.write_u8(action.must_sync as u8);
+                    writer.write_bytes(&action.data);
+                }
+            }
+            HostToNodeMessage::RenderBlocks(data) => {
+                write_header(&mut writer, 2);
+                writer.write_u32(data.width);
+                writer.write_u32(data.height);
+                writer.write_u32(data.frame_idx);
+                writer.write_u32(data.request_idx);
+                writer.write_u8(data.tile_encoding.to_u8());
+                // Block coordinates never exceed `MAX_RENDER_RESOLUTION / RENDER_BLOCK_SIZE`
+                writer.write_u32(data.blocks.len() as u32);
+                for block in data.blocks {
+                    writer.write_u16(block.x as u16);
+                    writer.write_u16(block.y as u16);
+                }
+            }
+            HostToNodeMessage::AssetInfo(data) => {
+                write_header(&mut writer, 3);
+                writer.write_string(&data.path);
+                writer.write_pod(&data.content_hash);
+            }
+            HostToNodeMessage::AssetChunk(data) => {
+                write_header(&mut writer, 4);
+                writer.write_pod(&data.content_hash);
+                writer.write_u32(data.chunk_idx);
+                writer.write_u32(data.chunk_count);
+                writer.write_bytes(&data.bytes);
             }
-            HostToNodeMessage::VisibleWorldAction(mut data) => {
-                let mut bytes = bytemuck::bytes_of(&1u32).to_vec();
-                bytes.append(&mut bytemuck::bytes_of(&data.ty).to_vec());
-                bytes.append(&mut bytemuck::bytes_of(&(data.must_sync as u32)).to_vec());
-                bytes.append(&mut data.data);
-                bytes
+            HostToNodeMessage::Ping(data) => {
+                write_header(&mut writer, 5);
+                data.write(&mut writer);
+            }
+            HostToNodeMessage::Challenge(data) => {
+                write_header(&mut writer, 6);
+                writer.write_pod(&data.nonce);
+            }
+            HostToNodeMessage::Evict(data) => {
+                write_header(&mut writer, 7);
+                writer.write_u32(data.missed_deadlines);
             }
         }
+
+        writer.into_bytes()
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 4 {
-            return Err(anyhow::Error::msg(
-                "Failed to convert bytes to host-to-node message. (Empty bytes)",
-            ));
-        }
+        let mut reader = WireReader::new(bytes);
 
-        let ty = *bytemuck::from_bytes::<u32>(&bytes[0..4]);
-        match ty {
-            0 => Ok(Self::StartRender(*bytemuck::from_bytes::<StartRenderData>(
-                &bytes[4..bytes.len()],
-            ))),
+        let message = match read_header(&mut reader)? {
+            0 => {
+                let data: StartRenderData = reader.read_pod()?;
+                data.validate()?;
+                Self::StartRender(data)
+            }
             1 => {
-                let ty = *bytemuck::from_bytes::<u32>(&bytes[4..8]);
-                let must_sync = (*bytemuck::from_bytes::<u32>(&bytes[8..12])) != 0;
-                let data = bytes[12..bytes.len()].to_vec();
-                Ok(Self::VisibleWorldAction(VisibleWorldAction {
-                    ty,
-                    data,
-                    must_sync,
-                }))
+                // Don't preallocate using the count, it hasn't been validated yet
+                let count = reader.read_u32()?;
+                let mut actions = vec![];
+                for _ in 0..count {
+                    let ty = reader.read_u32()?;
+                    let must_sync = match reader.read_u8()? {
+                        0 => false,
+                        1 => true,
+                        value => return Err(anyhow!("Invalid must sync value {}.", value)),
+                    };
+                    let data = reader.read_bytes()?.to_vec();
+
+                    actions.push(VisibleWorldAction {
+                        ty,
+                        data,
+                        must_sync,
+                    });
+                }
+
+                Self::VisibleWorldActions(actions)
             }
-            _ => Err(anyhow::Error::msg(
-                "Failed to convert bytes to host-to-node message.",
-            )),
-        }
+            2 => {
+                let mut data = RenderBlocksData {
+                    width: reader.read_u32()?,
+                    height: reader.read_u32()?,
+                    frame_idx: reader.read_u32()?,
+                    request_idx: reader.read_u32()?,
+                    tile_encoding: TileEncoding::from_u8(reader.read_u8()?)?,
+                    blocks: vec![],
+                };
+
+                let count = reader.read_u32()?;
+                if count > MAX_BLOCKS_PER_REQUEST {
+                    return Err(anyhow!("Too many blocks {} in render request.", count));
+                }
+                for _ in 0..count {
+                    data.blocks.push(UVec2::new(
+                        reader.read_u16()? as u32,
+                        reader.read_u16()? as u32,
+                    ));
+                }
+
+                data.validate()?;
+                Self::RenderBlocks(data)
+            }
+            3 => Self::AssetInfo(AssetInfoData {
+                path: reader.read_string()?,
+                content_hash: reader.read_pod()?,
+            }),
+            4 => {
+                let data = AssetChunkData {
+                    content_hash: reader.read_pod()?,
+                    chunk_idx: reader.read_u32()?,
+                    chunk_count: reader.read_u32()?,
+                    bytes: reader.read_bytes()?.to_vec(),
+                };
+                data.validate()?;
+                Self::AssetChunk(data)
+            }
+            5 => Self::Ping(PingData::read(&mut reader)?),
+            6 => Self::Challenge(ChallengeData {
+                nonce: reader.read_pod()?,
+            }),
+            7 => Self::Evict(EvictData {
+                missed_deadlines: reader.read_u32()?,
+            }),
+            ty => return Err(anyhow!("Unknown host-to-node message type {}.", ty)),
+        };
+
+        reader.finish()?;
+        Ok(message)
     }
 }
 
 pub const BUFFERED_PIXEL_COUNT: usize = 2;
+/// Samples this far behind the newest sample which arrived are rejected, every node renders a single sample at a time
+/// so this only drops samples which were lost or took far too long
+const ACCUMULATION_SAMPLE_WINDOW: u32 = 64;
+
+/// Pixels which arrived for the frame currently being rendered
+#[derive(Default)]
+struct FrameArrivals {
+    frame_idx: u32,
+    /// Last time pixels arrived for each block row
+    block_rows: Vec<Option<Instant>>,
+    /// Whether each block arrived, in the same order as the blocks in the pixel buffers
+    blocks: Vec<bool>,
+}
+
+/// Radiance of all samples nodes rendered of a static view
+#[derive(Default)]
+struct Accumulation {
+    /// Samples with a lower index were rendered before the view last changed
+    first_sample_idx: u32,
+    radiance_sum: Vec<Vec3>,
+    block_samples: Vec<u32>,
+    /// Blocks received of the latest `ACCUMULATION_SAMPLE_WINDOW` samples, older samples are rejected
+    received: BTreeMap<u32, Vec<bool>>,
+}
+
+/// What became of pixels received from a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WrittenPixels {
+    Written,
+    /// The block already arrived for the same frame
+    Duplicate,
+    /// The pixels were stale or didn't fit the render target
+    Discarded,
+}
 
+/// Pixels of the frames being rendered, stored block after block like the pixels handed out by a `NodeRenderer`.
+/// Edge blocks are stored whole, the pixels outside of the resolution are padding.
 struct BufferedPixelData {
     width: u32,
     height: u32,
+    num_blocks: UVec2,
     pixels: [Mutex<Vec<u8>>; BUFFERED_PIXEL_COUNT],
+    /// Linear radiance of every pixel, the source of `pixels` when nodes send hdr tiles. Only hdr tiles are written to it,
+    /// the radiance of blocks sent as ldr tiles is unknown and left black.
+    hdr_pixels: [Mutex<Vec<PackedRgb9e5>>; BUFFERED_PIXEL_COUNT],
+    /// Exposure applied before tonemapping hdr tiles, stored as f32 bits
+    exposure: AtomicU32,
     duplicate_map: [Mutex<HashMap<u32, bool>>; BUFFERED_PIXEL_COUNT],
     frame_idx: AtomicU32,
     received_packet_count: [AtomicU32; BUFFERED_PIXEL_COUNT],
+    frame_arrivals: Mutex<FrameArrivals>,
+    accumulation: Mutex<Accumulation>,
 }
 
 impl BufferedPixelData {
     fn new(width: u32, height: u32) -> Self {
-        let pixel_bytes = (width * height) as usize * BYTES_PER_PIXEL;
+        let num_blocks = block_count(UVec2::new(width, height));
+        let pixel_count = padded_resolution(UVec2::new(width, height)).element_product() as usize;
 
-        let pixels = std::array::from_fn(|_| Mutex::new(vec![0; pixel_bytes]));
+        let pixels = std::array::from_fn(|_| Mutex::new(vec![0; pixel_count * BYTES_PER_PIXEL]));
+        let hdr_pixels =
+            std::array::from_fn(|_| Mutex::new(vec![PackedRgb9e5::new(Vec3::ZERO); pixel_count]));
         let duplicate_map = std::array::from_fn(|_| Mutex::new(HashMap::new()));
         let received_packet_count = std::array::from_fn(|_| AtomicU32::new(0));
 
         Self {
             width,
             height,
+            num_blocks,
             pixels,
+            hdr_pixels,
+            exposure: AtomicU32::new(1.0f32.to_bits()),
             duplicate_map,
             frame_idx: AtomicU32::new(0),
             received_packet_count,
+            frame_arrivals: Mutex::new(FrameArrivals::default()),
+            accumulation: Mutex::new(Accumulation::default()),
+        }
+    }
+
+    /// Discard all accumulated samples, samples below `first_sample_idx` which still arrive are rejected
+    fn reset_accumulation(&self, first_sample_idx: u32) {
+        if let Ok(mut accumulation) = self.accumulation.lock() {
+            *accumulation = Accumulation {
+                first_sample_idx,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Add a sample of a block to the accumulated radiance, returns the average of all samples of the block.
+    /// Returns `None` for samples which were received before or belong to an earlier view.
+    fn accumulate(
+        &self,
+        block_idx: usize,
+        sample_idx: u32,
+        hdr_render_pixels: &[PackedRgb9e5],
+    ) -> Option<Vec<PackedRgb9e5>> {
+        let mut accumulation = self.accumulation.lock().ok()?;
+        let accumulation = &mut *accumulation;
+
+        let newest_sample_idx = accumulation
+            .received
+            .last_key_value()
+            .map_or(sample_idx, |(newest_sample_idx, _)| {
+                sample_idx.max(*newest_sample_idx)
+            });
+        let oldest_sample_idx = accumulation
+            .first_sample_idx
+            .max(newest_sample_idx.saturating_sub(ACCUMULATION_SAMPLE_WINDOW - 1));
+        if sample_idx < oldest_sample_idx {
+            return None;
+        }
+
+        let block_count = self.num_blocks.element_product() as usize;
+        accumulation.received = accumulation.received.split_off(&oldest_sample_idx);
+        let received = accumulation
+            .received
+            .entry(sample_idx)
+            .or_insert_with(|| vec![false; block_count]);
+        if std::mem::replace(&mut received[block_idx], true) {
+            return None;
+        }
+
+        let pixels_per_block = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize;
+        if accumulation.radiance_sum.is_empty() {
+            accumulation.radiance_sum = vec![Vec3::ZERO; block_count * pixels_per_block];
+            accumulation.block_samples = vec![0; block_count];
+        }
+
+        let block_samples = &mut accumulation.block_samples[block_idx];
+        *block_samples += 1;
+        let weight = 1.0 / *block_samples as f32;
+
+        Some(
+            accumulation.radiance_sum
+                [(block_idx * pixels_per_block)..((block_idx + 1) * pixels_per_block)]
+                .iter_mut()
+                .zip(hdr_render_pixels)
+                .map(|(radiance_sum, pixel)| {
+                    *radiance_sum += pixel.unpack();
+                    PackedRgb9e5::new(*radiance_sum * weight)
+                })
+                .collect(),
+        )
+    }
+
+    /// Samples per pixel accumulated in every block, 0 when not accumulating
+    fn accumulated_samples(&self) -> u32 {
+        self.accumulation
+            .lock()
+            .ok()
+            .and_then(|accumulation| accumulation.block_samples.iter().min().copied())
+            .unwrap_or(0)
+    }
+
+    /// Start tracking the arrival of pixels of a frame, only pixels of this frame are tracked.
+    /// Pixels of frames before the previous frame are rejected from now on, they would overwrite this frame.
+    fn begin_frame_arrivals(&self, frame_idx: u32) {
+        if let Ok(mut frame_arrivals) = self.frame_arrivals.lock() {
+            frame_arrivals.frame_idx = frame_idx;
+            frame_arrivals.block_rows = vec![None; self.num_blocks.y as usize];
+            frame_arrivals.blocks = vec![false; self.num_blocks.element_product() as usize];
+        }
+    }
+
+    /// Last time any pixels of the block rows arrived, `None` if none did
+    fn last_block_row_arrival(&self, block_start: u32, block_end: u32) -> Option<Instant> {
+        let frame_arrivals = self.frame_arrivals.lock().ok()?;
+        frame_arrivals
+            .block_rows
+            .get(block_start as usize..block_end as usize)?
+            .iter()
+            .flatten()
+            .max()
+            .copied()
+    }
+
+    /// Number of block rows of which any pixels arrived
+    fn arrived_block_rows(&self, block_start: u32, block_end: u32) -> u32 {
+        self.frame_arrivals
+            .lock()
+            .ok()
+            .and_then(|frame_arrivals| {
+                frame_arrivals
+                    .block_rows
+                    .get(block_start as usize..block_end as usize)
+                    .map(|block_rows| block_rows.iter().flatten().count() as u32)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Copy the blocks of the previous frame into every block which didn't arrive for this frame, returns the number of blocks copied.
+    /// Pixels which still arrive afterwards overwrite the copied blocks.
+    fn fill_missing_blocks(&self, frame_idx: u32) -> u32 {
+        let missing_blocks: Vec<usize> = match self.frame_arrivals.lock() {
+            Ok(frame_arrivals) if frame_arrivals.frame_idx == frame_idx => frame_arrivals
+                .blocks
+                .iter()
+                .enumerate()
+                .filter(|(_, arrived)| !**arrived)
+                .map(|(block_idx, _)| block_idx)
+                .collect(),
+            _ => return 0,
+        };
+
+        let idx = frame_idx as usize % BUFFERED_PIXEL_COUNT;
+        let previous_idx = (frame_idx as usize + BUFFERED_PIXEL_COUNT - 1) % BUFFERED_PIXEL_COUNT;
+        let pixels_per_block = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize;
+
+        if let (Ok(mut pixels), Ok(previous_pixels)) =
+            (self.pixels[idx].lock(), self.pixels[previous_idx].lock())
+        {
+            let block_bytes = pixels_per_block * BYTES_PER_PIXEL;
+            for block_idx in &missing_blocks {
+                let range = (block_idx * block_bytes)..((block_idx + 1) * block_bytes);
+                pixels[range.clone()].copy_from_slice(&previous_pixels[range]);
+            }
         }
+
+        if let (Ok(mut hdr_pixels), Ok(previous_hdr_pixels)) = (
+            self.hdr_pixels[idx].lock(),
+            self.hdr_pixels[previous_idx].lock(),
+        ) {
+            for block_idx in &missing_blocks {
+                let range = (block_idx * pixels_per_block)..((block_idx + 1) * pixels_per_block);
+                hdr_pixels[range.clone()].copy_from_slice(&previous_hdr_pixels[range]);
+            }
+        }
+
+        missing_blocks.len() as u32
     }
 
     fn read_pixels_idx(&self) -> usize {
         (self.frame_idx.load(Ordering::SeqCst) + 1) as usize % BUFFERED_PIXEL_COUNT
     }
 
+    fn exposure(&self) -> f32 {
+        f32::from_bits(self.exposure.load(Ordering::SeqCst))
+    }
+
+    fn set_exposure(&self, exposure: f32) {
+        self.exposure.store(exposure.to_bits(), Ordering::SeqCst);
+    }
+
     fn write_render_finished_pixels(
         &self,
-        render_pixels: &[u8],
+        tile: DecodedTile,
         render_partial_finished_data: RenderPartialFinishedData,
-    ) {
-        let idx = render_partial_finished_data.frame_idx as usize % BUFFERED_PIXEL_COUNT;
+    ) -> WrittenPixels {
+        // Samples can take a node several frames, they're averaged into whichever frame is being rendered when they arrive
+        let is_sample = render_partial_finished_data.sample_idx > 0;
+        let frame_idx = if is_sample {
+            self.frame_arrivals
+                .lock()
+                .map(|frame_arrivals| frame_arrivals.frame_idx)
+                .unwrap_or(render_partial_finished_data.frame_idx)
+        } else {
+            render_partial_finished_data.frame_idx
+        };
+        let idx = frame_idx as usize % BUFFERED_PIXEL_COUNT;
+
+        if render_partial_finished_data.column_block >= self.num_blocks.x
+            || render_partial_finished_data.row >= self.height
+            || render_partial_finished_data.row % RENDER_BLOCK_SIZE != 0
+        {
+            log::warn!("Received pixels outside of the render target, ignoring them.");
+            return WrittenPixels::Discarded;
+        }
 
-        if let Ok(mut duplicate_map) = self.duplicate_map[idx].lock() {
-            assert!(render_partial_finished_data.column_block < u16::MAX as u32);
-            assert!(render_partial_finished_data.row < u16::MAX as u32);
+        if !is_sample
+            && self.frame_arrivals.lock().is_ok_and(|frame_arrivals| {
+                render_partial_finished_data.frame_idx + 1 < frame_arrivals.frame_idx
+            })
+        {
+            return WrittenPixels::Discarded;
+        }
 
-            let key = render_partial_finished_data.column_block
-                | (render_partial_finished_data.row << 16);
-            if duplicate_map.insert(key, true).is_some() {
-                return;
+        let pixels_per_block = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize;
+        let block_idx = (render_partial_finished_data.column_block
+            + (render_partial_finished_data.row / RENDER_BLOCK_SIZE) * self.num_blocks.x)
+            as usize;
+
+        let tile = if is_sample {
+            // Every node sends the same blocks when accumulating, their samples are averaged instead of replacing each other
+            let DecodedTile::Hdr(hdr_render_pixels) = tile else {
+                log::debug!("Tonemapped tiles can't be accumulated, ignoring them.");
+                return WrittenPixels::Discarded;
+            };
+
+            match self.accumulate(
+                block_idx,
+                render_partial_finished_data.sample_idx,
+                &hdr_render_pixels,
+            ) {
+                Some(hdr_render_pixels) => DecodedTile::Hdr(hdr_render_pixels),
+                None => return WrittenPixels::Discarded,
+            }
+        } else {
+            if let Ok(mut duplicate_map) = self.duplicate_map[idx].lock() {
+                let key = render_partial_finished_data.column_block
+                    | (render_partial_finished_data.row << 16);
+                if duplicate_map.insert(key, true).is_some() {
+                    return WrittenPixels::Duplicate;
+                }
             }
-        }
+
+            tile
+        };
+
+        // Hdr tiles are resolved to display pixels here, ldr tiles are already tonemapped by the node.
+        // Tonemapping can't be undone, so ldr tiles don't provide any radiance.
+        let (render_pixels, hdr_render_pixels) = match tile {
+            DecodedTile::Ldr(render_pixels) => (
+                render_pixels,
+                vec![PackedRgb9e5::new(Vec3::ZERO); pixels_per_block],
+            ),
+            DecodedTile::Hdr(hdr_render_pixels) => {
+                let exposure = self.exposure();
+                let render_pixels: Vec<u8> = hdr_render_pixels
+                    .iter()
+                    .flat_map(|pixel| {
+                        let sdr = tonemap(pixel.unpack() * exposure) * 255.0;
+                        [sdr.x as u8, sdr.y as u8, sdr.z as u8, 255]
+                    })
+                    .collect();
+                (render_pixels, hdr_render_pixels)
+            }
+        };
+
+        let pixel_offset = block_idx * pixels_per_block;
 
         if let Ok(mut pixels) = self.pixels[idx].lock() {
-            unsafe {
-                let bytes_per_block = RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE * 4;
-                let blocks_per_row = self.width / RENDER_BLOCK_SIZE;
-                let offset = render_partial_finished_data.column_block * bytes_per_block
-                    + (render_partial_finished_data.row / RENDER_BLOCK_SIZE)
-                        * blocks_per_row
-                        * bytes_per_block;
+            pixels[(pixel_offset * BYTES_PER_PIXEL)
+                ..((pixel_offset + pixels_per_block) * BYTES_PER_PIXEL)]
+                .copy_from_slice(&render_pixels);
+        }
+
+        if let Ok(mut hdr_pixels) = self.hdr_pixels[idx].lock() {
+            hdr_pixels[pixel_offset..(pixel_offset + pixels_per_block)]
+                .copy_from_slice(&hdr_render_pixels);
+        }
 
-                let dst_ptr = &mut pixels[offset as usize] as *mut u8;
-                let src_ptr = &render_pixels[0] as *const u8;
-                std::ptr::copy_nonoverlapping(src_ptr, dst_ptr, bytes_per_block as usize);
+        if let Ok(mut frame_arrivals) = self.frame_arrivals.lock() {
+            if frame_arrivals.frame_idx == frame_idx {
+                let block_row = (render_partial_finished_data.row / RENDER_BLOCK_SIZE) as usize;
+                if let Some(arrival) = frame_arrivals.block_rows.get_mut(block_row) {
+                    *arrival = Some(Instant::now());
+                }
+                if let Some(arrived) = frame_arrivals.blocks.get_mut(block_idx) {
+                    *arrived = true;
+                }
             }
         }
 
         self.received_packet_count[idx].fetch_add(1, Ordering::SeqCst);
+        WrittenPixels::Written
     }
 
     fn set_pixels_pink(&self) {
         for pixels in &self.pixels {
             if let Ok(mut pixels) = pixels.lock() {
-                for x in 0..self.width {
-                    for y in 0..self.height {
-                        pixels[(y * self.width + x) as usize * BYTES_PER_PIXEL] = 255;
-                        pixels[(y * self.width + x) as usize * BYTES_PER_PIXEL + 1] = 0;
-                        pixels[(y * self.width + x) as usize * BYTES_PER_PIXEL + 2] = 255;
-                        pixels[(y * self.width + x) as usize * BYTES_PER_PIXEL + 3] = 255;
-                    }
+                for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+                    pixel.copy_from_slice(&[255, 0, 255, 255]);
                 }
             }
         }
+
+        for hdr_pixels in &self.hdr_pixels {
+            if let Ok(mut hdr_pixels) = hdr_pixels.lock() {
+                hdr_pixels.fill(PackedRgb9e5::new(Vec3::new(1.0, 0.0, 1.0)));
+            }
+        }
     }
 
     fn read_pixels<F: Fn(&[u8])>(&self, callback: F) {
@@ -234,6 +1076,12 @@ impl BufferedPixelData {
         }
     }
 
+    fn read_hdr_pixels<F: Fn(&[PackedRgb9e5])>(&self, callback: F) {
+        if let Ok(hdr_pixels) = self.hdr_pixels[self.read_pixels_idx()].lock() {
+            callback(hdr_pixels.as_ref());
+        }
+    }
+
     pub fn next_frame(&self) {
         self.duplicate_map[self.read_pixels_idx()]
             .lock()
@@ -247,41 +1095,384 @@ impl BufferedPixelData {
     }
 }
 
-pub struct Host {
-    connected_nodes: Arc<Mutex<Vec<SocketAddr>>>,
-    has_received_new_connections: Arc<AtomicBool>,
-    socket: Socket,
-    node_port: u16,
+/// How the blocks of a frame are divided across nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderScheduling {
+    /// Every node renders one contiguous range of rows, sized by how fast the node rendered previous frames
+    #[default]
+    Rows,
+    /// Nodes pull requests of `blocks_per_request` blocks from a shared queue until all blocks of the frame have been handed out
+    Tiles { blocks_per_request: u32 },
+}
 
-    receive_events_thread: Option<thread::JoinHandle<()>>,
-    receive_events_running: Arc<AtomicBool>,
+/// Blocks requested from a node in tile scheduling
+struct BlockRequest {
+    addr: SocketAddr,
+    blocks: Vec<UVec2>,
+    sent: Instant,
+    /// Request for the same blocks sent to another node, whichever finishes first completes both
+    twin: Option<u32>,
+}
 
-    width: u32,
-    height: u32,
-    pixels: Arc<BufferedPixelData>,
-    frame_idx: u32,
+/// Blocks of a frame which still have to be handed out in tile scheduling, and the requests nodes are working on
+struct TileQueue {
+    pending: VecDeque<UVec2>,
+    in_flight: HashMap<u32, BlockRequest>,
+    next_request_idx: u32,
 }
 
-impl Host {
-    pub fn new(host_port: u16, node_port: u16, width: u32, height: u32) -> Result<Self> {
-        let connected_nodes = Arc::new(Mutex::new(Vec::new()));
-        let has_received_new_connections = Arc::new(AtomicBool::new(false));
-        let pixels = Arc::new(BufferedPixelData::new(width, height));
-        let socket = Socket::new(None, host_port)?;
+impl TileQueue {
+    fn new(num_blocks_x: u32, num_blocks_y: u32) -> Self {
+        Self {
+            pending: (0..num_blocks_y)
+                .flat_map(|y| (0..num_blocks_x).map(move |x| UVec2::new(x, y)))
+                .collect(),
+            in_flight: HashMap::new(),
+            next_request_idx: 0,
+        }
+    }
 
-        let mut host = Self {
-            connected_nodes,
-            has_received_new_connections,
-            socket,
-            node_port,
+    fn insert_request(&mut self, addr: SocketAddr, blocks: Vec<UVec2>, twin: Option<u32>) -> u32 {
+        let request_idx = self.next_request_idx;
+        self.next_request_idx += 1;
+        self.in_flight.insert(
+            request_idx,
+            BlockRequest {
+                addr,
+                blocks,
+                sent: Instant::now(),
+                twin,
+            },
+        );
+
+        request_idx
+    }
 
-            receive_events_thread: None,
-            receive_events_running: Arc::new(AtomicBool::new(false)),
+    /// Take the next blocks for a node, `None` once all blocks have been handed out
+    fn next_request(
+        &mut self,
+        addr: SocketAddr,
+        blocks_per_request: u32,
+    ) -> Option<(u32, Vec<UVec2>)> {
+        if self.pending.is_empty() {
+            return None;
+        }
 
-            width,
-            height,
-            pixels,
-            frame_idx: 0,
+        let count = (blocks_per_request as usize).min(self.pending.len());
+        let blocks: Vec<UVec2> = self.pending.drain(..count).collect();
+        let request_idx = self.insert_request(addr, blocks.clone(), None);
+
+        Some((request_idx, blocks))
+    }
+
+    /// Once all blocks have been handed out, give an idle node the blocks of the oldest request another node has been working on for longer than `min_age`
+    fn reissue_straggler(
+        &mut self,
+        addr: SocketAddr,
+        min_age: Duration,
+    ) -> Option<(u32, Vec<UVec2>)> {
+        if !self.pending.is_empty() {
+            return None;
+        }
+
+        let (straggler_idx, blocks) = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| {
+                request.addr != addr && request.twin.is_none() && request.sent.elapsed() > min_age
+            })
+            .min_by_key(|(_, request)| request.sent)
+            .map(|(request_idx, request)| (*request_idx, request.blocks.clone()))?;
+
+        let request_idx = self.insert_request(addr, blocks.clone(), Some(straggler_idx));
+        self.in_flight.get_mut(&straggler_idx).unwrap().twin = Some(request_idx);
+
+        Some((request_idx, blocks))
+    }
+
+    /// Mark a request as finished, returns the node which finished it and the node still working on its twin
+    fn finish(&mut self, request_idx: u32) -> Option<(SocketAddr, Option<SocketAddr>)> {
+        let request = self.in_flight.remove(&request_idx)?;
+        let twin_addr = request
+            .twin
+            .and_then(|twin| self.in_flight.remove(&twin))
+            .map(|twin| twin.addr);
+
+        Some((request.addr, twin_addr))
+    }
+
+    fn in_flight_count(&self, addr: SocketAddr) -> usize {
+        self.in_flight
+            .values()
+            .filter(|request| request.addr == addr)
+            .count()
+    }
+
+    /// Put the blocks of nodes which disconnected back at the front of the queue, unless another node is already working on them
+    fn requeue_lost(&mut self, connected_nodes: &[SocketAddr]) {
+        let lost_requests: Vec<u32> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| !connected_nodes.contains(&request.addr))
+            .map(|(request_idx, _)| *request_idx)
+            .collect();
+
+        for request_idx in lost_requests {
+            let Some(request) = self.in_flight.remove(&request_idx) else {
+                continue;
+            };
+
+            if let Some(twin) = request.twin.and_then(|twin| self.in_flight.get_mut(&twin)) {
+                twin.twin = None;
+            } else {
+                for block in request.blocks.into_iter().rev() {
+                    self.pending.push_front(block);
+                }
+            }
+        }
+    }
+
+    /// Give up on all requests still in flight, returns the node of each abandoned request
+    fn abandon(&mut self) -> Vec<SocketAddr> {
+        self.in_flight
+            .drain()
+            .map(|(_, request)| request.addr)
+            .collect()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.in_flight.is_empty()
+    }
+}
+
+/// A node connected to the host, nodes only receive incremental world actions and render work once they've been sent a snapshot of the world
+struct ConnectedNode {
+    addr: SocketAddr,
+    connected_at: Instant,
+    status: NodeStatus,
+    capabilities: Option<NodeCapabilities>,
+    synced: bool,
+    /// Render requests of earlier frames the node didn't finish before their deadline and is still working on
+    late_requests: u32,
+    /// Frame deadlines missed in a row, a node is unhealthy while this is nonzero
+    missed_deadlines: u32,
+    stats: NetworkStats,
+    /// Seals and opens the messages of the node once it has been challenged, always `None` without a pre-shared key
+    auth: Option<MessageAuth>,
+}
+
+impl ConnectedNode {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            connected_at: Instant::now(),
+            status: NodeStatus::AwaitingHandshake,
+            capabilities: None,
+            synced: false,
+            late_requests: 0,
+            missed_deadlines: 0,
+            stats: NetworkStats::default(),
+            auth: None,
+        }
+    }
+
+    /// Prepare a message for sending to the node, counting it in the stats
+    fn seal(&mut self, message_bytes: Vec<u8>) -> Vec<u8> {
+        let message_bytes = match &mut self.auth {
+            Some(auth) => auth.seal(message_bytes),
+            None => message_bytes,
+        };
+        self.stats.count_sent(message_bytes.len());
+        message_bytes
+    }
+
+    fn handshake(&mut self, capabilities: NodeCapabilities) {
+        if capabilities.protocol_version != PROTOCOL_VERSION {
+            self.refuse(format!(
+                "it speaks protocol version {}, the host speaks {}",
+                capabilities.protocol_version, PROTOCOL_VERSION
+            ));
+            return;
+        }
+
+        log::info!(
+            "Node {:?} is ready: {} ({}, {:?} renderer, {} MiB vram)",
+            self.addr,
+            capabilities.adapter_name,
+            capabilities.backend,
+            capabilities.renderer,
+            capabilities.vram_bytes >> 20
+        );
+
+        // A node only handshakes again after losing its state, so it needs a new snapshot
+        self.status = NodeStatus::Ready;
+        self.capabilities = Some(capabilities);
+        self.synced = false;
+    }
+
+    /// Forget the session and progress of the node, it's registered again by its next handshake
+    fn evict(&mut self) {
+        *self = Self {
+            stats: self.stats,
+            ..Self::new(self.addr)
+        };
+    }
+
+    /// Stop giving the node any work, the reason is logged once
+    fn refuse(&mut self, reason: String) {
+        if !matches!(&self.status, NodeStatus::Refused(refused_reason) if *refused_reason == reason)
+        {
+            log::warn!("Refusing node {:?}: {}.", self.addr, reason);
+        }
+
+        self.status = NodeStatus::Refused(reason);
+        self.synced = false;
+    }
+
+    fn info(&self) -> NodeInfo {
+        NodeInfo {
+            addr: self.addr,
+            status: self.status.clone(),
+            capabilities: self.capabilities.clone(),
+            synced: self.synced,
+            missed_deadlines: self.missed_deadlines,
+            stats: self.stats,
+        }
+    }
+}
+
+/// Whether a connected node can be given work
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeStatus {
+    AwaitingHandshake,
+    /// The node is incompatible with the host, for the given reason
+    Refused(String),
+    Ready,
+}
+
+/// Entry of a node in the registry of the host
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub addr: SocketAddr,
+    pub status: NodeStatus,
+    /// Capabilities sent in the handshake of the node, `None` until received
+    pub capabilities: Option<NodeCapabilities>,
+    /// Whether the node has been sent a snapshot of the world and receives render work
+    pub synced: bool,
+    pub missed_deadlines: u32,
+    pub stats: NetworkStats,
+}
+
+/// Render work a node reported as finished, `request_idx` is only set for block requests
+#[derive(Debug, Clone, Copy)]
+struct RenderProgress {
+    addr: SocketAddr,
+    frame_idx: u32,
+    request_idx: Option<u32>,
+}
+
+/// How much of a frame arrived before its deadline, returned by `Host::render`
+#[derive(Debug, Clone, Default)]
+pub struct FrameCompleteness {
+    pub frame_idx: u32,
+    pub total_blocks: u32,
+    /// Blocks which didn't arrive before the deadline, these show the previous frame instead
+    pub missing_blocks: u32,
+    /// Nodes still working on requests of this or earlier frames when the deadline expired
+    pub late_nodes: Vec<SocketAddr>,
+    /// Nodes evicted this frame after missing `MAX_MISSED_DEADLINES` deadlines in a row
+    pub evicted_nodes: Vec<SocketAddr>,
+}
+
+impl FrameCompleteness {
+    pub fn is_complete(&self) -> bool {
+        self.missing_blocks == 0
+    }
+}
+
+pub struct Host {
+    connected_nodes: Arc<Mutex<Vec<ConnectedNode>>>,
+    socket: Socket,
+    node_port: u16,
+
+    receive_events_thread: Option<thread::JoinHandle<()>>,
+    receive_events_running: Arc<AtomicBool>,
+
+    width: u32,
+    height: u32,
+    pixels: Arc<BufferedPixelData>,
+    frame_idx: u32,
+    load_balancer: RowLoadBalancer,
+    scheduling: RenderScheduling,
+    tile_encoding: TileEncoding,
+    frame_deadline: Duration,
+    progress_sender: Sender<RenderProgress>,
+    progress_receiver: Receiver<RenderProgress>,
+    asset_server: AssetServer,
+    asset_request_sender: Sender<(SocketAddr, AssetRequestData)>,
+    asset_request_receiver: Receiver<(SocketAddr, AssetRequestData)>,
+    /// Static frames after which all nodes render the whole frame and their samples are averaged, `None` disables accumulation
+    accumulation_delay: Option<u32>,
+    /// Frames rendered since the view last changed
+    static_frames: u32,
+    last_camera_update: Option<Vec<u8>>,
+    /// Sample index of the next whole frame handed out while accumulating, never 0
+    next_sample_idx: u32,
+    /// Nodes working on a sample, these aren't bound to the frame deadline
+    accumulating_nodes: Vec<SocketAddr>,
+    accumulating: bool,
+    /// Pings carry the time since this instant
+    epoch: Instant,
+    last_ping: Option<Instant>,
+    /// Nodes have to prove they know this key before they're given the world, `None` accepts any node
+    pre_shared_key: Option<PreSharedKey>,
+}
+
+impl Host {
+    /// Start listening for nodes, with a pre-shared key only nodes knowing the same key are accepted and all unauthenticated traffic is dropped
+    pub fn new(
+        host_port: u16,
+        node_port: u16,
+        width: u32,
+        height: u32,
+        pre_shared_key: Option<PreSharedKey>,
+    ) -> Result<Self> {
+        let connected_nodes = Arc::new(Mutex::new(Vec::new()));
+        let pixels = Arc::new(BufferedPixelData::new(width, height));
+        let socket = Socket::new(None, host_port)?;
+        let (progress_sender, progress_receiver) = crossbeam::channel::unbounded();
+        let (asset_request_sender, asset_request_receiver) = crossbeam::channel::unbounded();
+
+        let mut host = Self {
+            connected_nodes,
+            socket,
+            node_port,
+
+            receive_events_thread: None,
+            receive_events_running: Arc::new(AtomicBool::new(false)),
+
+            width,
+            height,
+            pixels,
+            frame_idx: 0,
+            load_balancer: RowLoadBalancer::new(),
+            scheduling: RenderScheduling::default(),
+            tile_encoding: TileEncoding::default(),
+            frame_deadline: DEFAULT_FRAME_DEADLINE,
+            progress_sender,
+            progress_receiver,
+            asset_server: AssetServer::new(),
+            asset_request_sender,
+            asset_request_receiver,
+            accumulation_delay: None,
+            static_frames: 0,
+            last_camera_update: None,
+            next_sample_idx: 1,
+            accumulating_nodes: vec![],
+            accumulating: false,
+            epoch: Instant::now(),
+            last_ping: None,
+            pre_shared_key,
         };
 
         host.respawn_recieve_events();
@@ -290,69 +1481,139 @@ impl Host {
     }
 
     fn respawn_recieve_events(&mut self) {
-        println!("Respawn recieve events...");
+        log::debug!("Restarting the receive events thread.");
 
         if let Some(receive_events_thread) = self.receive_events_thread.take() {
-            println!("Trying to kill current thread");
+            log::debug!("Stopping the current receive events thread.");
             self.receive_events_running.store(false, Ordering::SeqCst);
             let _ = receive_events_thread.join();
-            println!("Killed current thread!");
+            log::debug!("Stopped the current receive events thread.");
         }
 
         self.receive_events_running.store(true, Ordering::SeqCst);
 
         let receive_events_event_receiver = self.socket.event_receiver().clone();
         let recieve_events_connected_nodes = self.connected_nodes.clone();
-        let recieve_events_has_received_new_connections = self.has_received_new_connections.clone();
         let recieve_events_receive_events_running = self.receive_events_running.clone();
         let recieve_events_pixels = self.pixels.clone();
+        let recieve_events_progress_sender = self.progress_sender.clone();
+        let recieve_events_asset_request_sender = self.asset_request_sender.clone();
+        let recieve_events_epoch = self.epoch;
+        let recieve_events_authenticate = self.pre_shared_key.is_some();
         self.receive_events_thread = Some(thread::spawn(move || {
             Self::receive_events(
                 receive_events_event_receiver,
                 recieve_events_connected_nodes,
-                recieve_events_has_received_new_connections,
                 recieve_events_receive_events_running,
                 recieve_events_pixels,
+                recieve_events_progress_sender,
+                recieve_events_asset_request_sender,
+                recieve_events_epoch,
+                recieve_events_authenticate,
             )
         }));
-        println!("Spawned new thread!");
+        log::debug!("Started a new receive events thread.");
     }
 
     fn receive_events(
         event_receiver: Receiver<SocketEvent>,
-        connected_nodes: Arc<Mutex<Vec<SocketAddr>>>,
-        has_received_new_connections: Arc<AtomicBool>,
+        connected_nodes: Arc<Mutex<Vec<ConnectedNode>>>,
         receive_events_running: Arc<AtomicBool>,
         pixels: Arc<BufferedPixelData>,
+        progress_sender: Sender<RenderProgress>,
+        asset_request_sender: Sender<(SocketAddr, AssetRequestData)>,
+        epoch: Instant,
+        authenticate: bool,
     ) {
+        let mut unauthenticated_log = UnauthenticatedLog::default();
+
         while receive_events_running.load(Ordering::SeqCst) {
             if let Ok(socket_event) = event_receiver.try_recv() {
                 match socket_event {
                     SocketEvent::Packet(packet, delay) => {
-                        if delay > 1 {
-                            println!("REJECT!");
+                        let rejected = delay > 1;
+                        Self::update_stats(&connected_nodes, *packet.addr(), |stats| {
+                            stats.count_received(packet.payload().len());
+                            if rejected {
+                                stats.packets_rejected += 1;
+                            }
+                        });
+                        if rejected {
+                            log::debug!(
+                                "Rejected packet from {} which arrived {} barriers late.",
+                                packet.addr(),
+                                delay
+                            );
                             continue;
                         }
 
-                        if !packet.is_barrier() {
-                            if let Ok(message) = NodeToHostMessage::from_bytes(packet.payload()) {
+                        // Only messages sealed in the session of a challenged node are accepted, this includes its handshake
+                        let payload = if authenticate {
+                            let payload =
+                                connected_nodes.lock().ok().and_then(|mut connected_nodes| {
+                                    connected_nodes
+                                        .iter_mut()
+                                        .find(|node| node.addr == *packet.addr())
+                                        .and_then(|node| node.auth.as_mut())
+                                        .and_then(|auth| auth.open(packet.payload()))
+                                });
+
+                            match payload {
+                                Some(payload) => payload,
+                                None => {
+                                    Self::update_stats(&connected_nodes, *packet.addr(), |stats| {
+                                        stats.packets_unauthenticated += 1
+                                    });
+                                    unauthenticated_log.drop_packet(packet.addr());
+                                    continue;
+                                }
+                            }
+                        } else {
+                            packet.payload()
+                        };
+
+                        if !payload.is_empty() {
+                            if let Ok(message) = NodeToHostMessage::from_bytes(payload) {
                                 match message {
                                     NodeToHostMessage::RenderPartialFinished(data) => {
-                                        let uncompressed_pixels = if ENABLE_COMPRESSION {
-                                            turbojpeg::decompress(
-                                                &data.compressed_pixel_bytes,
-                                                NODE_PIXEL_FORMAT,
-                                            )
-                                            .unwrap()
-                                            .pixels
-                                        } else {
-                                            data.compressed_pixel_bytes.clone()
+                                        // Edge blocks are sent cropped to the resolution
+                                        let tile_size = cropped_block_size(
+                                            UVec2::new(pixels.width, pixels.height),
+                                            UVec2::new(
+                                                data.column_block,
+                                                data.row / RENDER_BLOCK_SIZE,
+                                            ),
+                                        );
+                                        let tile = match decode_tile(
+                                            data.encoding,
+                                            &data.compressed_pixel_bytes,
+                                            tile_size,
+                                        ) {
+                                            Ok(tile) => tile,
+                                            Err(err) => {
+                                                log::warn!(
+                                                    "Failed to decompress pixels from {}: {}",
+                                                    packet.addr(),
+                                                    err
+                                                );
+                                                Self::update_stats(
+                                                    &connected_nodes,
+                                                    *packet.addr(),
+                                                    |stats| stats.packets_dropped += 1,
+                                                );
+                                                continue;
+                                            }
                                         };
 
-                                        pixels.write_render_finished_pixels(
-                                            &uncompressed_pixels,
-                                            data,
-                                        );
+                                        if pixels.write_render_finished_pixels(tile, data)
+                                            == WrittenPixels::Duplicate
+                                        {
+                                            Self::update_stats(
+                                                &connected_nodes,
+                                                *packet.addr(),
+                                                |stats| stats.duplicate_blocks += 1,
+                                            );
+                                        }
 
                                         // TODO: in the future the 8x8 blocks can be memcpied, however this will require a more advanced blit pass to display correctly
                                         // let first_dst_pixel = (data.row * width) + data.row_start;
@@ -370,30 +1631,95 @@ impl Host {
                                         //     );
                                         // }
                                     }
+                                    NodeToHostMessage::BlocksFinished(data) => {
+                                        let _ = progress_sender.send(RenderProgress {
+                                            addr: *packet.addr(),
+                                            frame_idx: data.frame_idx,
+                                            request_idx: Some(data.request_idx),
+                                        });
+                                    }
+                                    NodeToHostMessage::RenderFinished(data) => {
+                                        let _ = progress_sender.send(RenderProgress {
+                                            addr: *packet.addr(),
+                                            frame_idx: data.frame_idx,
+                                            request_idx: None,
+                                        });
+                                    }
+                                    NodeToHostMessage::Handshake(capabilities) => {
+                                        if let Ok(mut connected_nodes) = connected_nodes.lock() {
+                                            // The handshake can overtake the connect event
+                                            let addr = *packet.addr();
+                                            if !connected_nodes.iter().any(|node| node.addr == addr)
+                                            {
+                                                connected_nodes.push(ConnectedNode::new(addr));
+                                            }
+
+                                            if let Some(node) = connected_nodes
+                                                .iter_mut()
+                                                .find(|node| node.addr == addr)
+                                            {
+                                                node.handshake(capabilities);
+                                            }
+                                        }
+                                    }
+                                    NodeToHostMessage::AssetRequest(data) => {
+                                        let _ = asset_request_sender.send((*packet.addr(), data));
+                                    }
+                                    NodeToHostMessage::Pong(data) => {
+                                        // The time the ping waited for the node to finish rendering isn't part of the round trip
+                                        let round_trip_micros = (epoch.elapsed().as_micros()
+                                            as u32)
+                                            .wrapping_sub(data.sent_micros)
+                                            .saturating_sub(data.hold_micros);
+                                        Self::update_stats(
+                                            &connected_nodes,
+                                            *packet.addr(),
+                                            |stats| {
+                                                stats.round_trip = Some(Duration::from_micros(
+                                                    round_trip_micros as u64,
+                                                ))
+                                            },
+                                        );
+                                    }
                                 }
                             } else {
-                                log::warn!("Failed to read message from {}.", packet.addr());
+                                Self::update_stats(&connected_nodes, *packet.addr(), |stats| {
+                                    stats.packets_dropped += 1
+                                });
+
+                                match peek_protocol_version(payload) {
+                                    Ok(version) if version != PROTOCOL_VERSION => {
+                                        if let Ok(mut connected_nodes) = connected_nodes.lock() {
+                                            if let Some(node) = connected_nodes
+                                                .iter_mut()
+                                                .find(|node| node.addr == *packet.addr())
+                                            {
+                                                node.refuse(format!(
+                                                    "it speaks protocol version {}, the host speaks {}",
+                                                    version, PROTOCOL_VERSION
+                                                ));
+                                            }
+                                        }
+                                    }
+                                    _ => {
+                                        log::warn!("Failed to read message from {}.", packet.addr())
+                                    }
+                                }
                             }
                         }
                     }
                     SocketEvent::Connect(addr) => {
                         log::info!("Node connected at {:?}", addr);
                         if let Ok(mut connected_nodes) = connected_nodes.lock() {
-                            connected_nodes.push(addr);
-                            has_received_new_connections.store(true, Ordering::SeqCst);
+                            if !connected_nodes.iter().any(|node| node.addr == addr) {
+                                connected_nodes.push(ConnectedNode::new(addr));
+                            }
                         }
                     }
                     SocketEvent::Disconnect(addr) => {
                         log::info!("Node disconnected at {:?}...", addr);
                         if let Ok(mut connected_nodes) = connected_nodes.lock() {
-                            let mut node_idx = 0;
-                            for (i, node) in connected_nodes.iter().enumerate() {
-                                if *node == addr {
-                                    node_idx = i;
-                                    break;
-                                }
-                            }
-                            connected_nodes.remove(node_idx);
+                            connected_nodes.retain(|node| node.addr != addr);
                         }
                     }
                 }
@@ -403,103 +1729,1043 @@ impl Host {
         }
     }
 
+    /// Update the network stats of a node, packets of nodes which aren't connected aren't counted
+    fn update_stats<F: FnOnce(&mut NetworkStats)>(
+        connected_nodes: &Mutex<Vec<ConnectedNode>>,
+        addr: SocketAddr,
+        update: F,
+    ) {
+        if let Ok(mut connected_nodes) = connected_nodes.lock() {
+            if let Some(node) = connected_nodes.iter_mut().find(|node| node.addr == addr) {
+                update(&mut node.stats);
+            }
+        }
+    }
+
+    /// Send a message to a node reliably, sealed in its session and counted in its stats
+    fn send_barrier(&self, addr: SocketAddr, message_bytes: Vec<u8>) {
+        let message_bytes = match self.connected_nodes.lock() {
+            Ok(mut connected_nodes) => {
+                match connected_nodes.iter_mut().find(|node| node.addr == addr) {
+                    Some(node) => node.seal(message_bytes),
+                    None => message_bytes,
+                }
+            }
+            Err(_) => message_bytes,
+        };
+        self.socket
+            .packet_sender()
+            .send_barrier(addr, message_bytes)
+            .unwrap();
+    }
+
+    fn set_barrier_wait(&self, addr: SocketAddr, barrier_wait: Duration) {
+        Self::update_stats(&self.connected_nodes, addr, |stats| {
+            stats.barrier_wait = barrier_wait
+        });
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
 
         // Invalidate any current incoming pixels by skipping a few frames ahead
         self.frame_idx += 3;
+        let exposure = self.pixels.exposure();
         self.pixels = Arc::new(BufferedPixelData::new(width, height));
+        self.pixels.set_exposure(exposure);
+        self.reset_accumulation();
 
         self.respawn_recieve_events();
     }
 
+    /// Send the actions of a frame to all synced nodes. Redundant actions are coalesced first and the remaining ones are packed into batched messages.
+    /// A batch containing any action that must sync is sent as a barrier, so it costs a single round-trip.
     pub fn send_visible_world_actions(&mut self, visible_world_actions: Vec<VisibleWorldAction>) {
         let packet_sender = self.socket.packet_sender();
 
-        for visible_world_action in visible_world_actions {
-            let must_sync = visible_world_action.must_sync;
+        let visible_world_actions = VisibleWorldAction::coalesce(visible_world_actions);
+
+        // Applications tend to send the camera every frame, only a camera that actually moved changes the view
+        let mut view_changed = false;
+        for action in &visible_world_actions {
+            if let Ok(VisibleWorldActionType::CameraUpdate(_)) =
+                VisibleWorldActionType::from_ty_and_bytes(action.ty, &action.data)
+            {
+                if self.last_camera_update.as_ref() != Some(&action.data) {
+                    self.last_camera_update = Some(action.data.clone());
+                    view_changed = true;
+                }
+            } else {
+                view_changed = true;
+            }
+        }
+        if view_changed {
+            self.reset_accumulation();
+        }
+
+        for batch in batch_visible_world_actions(visible_world_actions) {
+            let must_sync = batch.iter().any(|action| action.must_sync);
 
-            let message = HostToNodeMessage::VisibleWorldAction(visible_world_action);
+            let message = HostToNodeMessage::VisibleWorldActions(batch);
             let message_bytes = message.to_bytes();
 
-            if let Ok(connected_nodes) = self.connected_nodes.lock() {
-                for node in connected_nodes.iter() {
+            if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+                for node in connected_nodes.iter_mut().filter(|node| node.synced) {
+                    let message_bytes = node.seal(message_bytes.clone());
                     if must_sync {
                         packet_sender
-                            .send_barrier(*node, message_bytes.clone())
+                            .send_barrier(node.addr, message_bytes)
                             .unwrap();
                     } else {
                         // Incoming connection addresses can provide a different port than the port they actively listen on
                         // This doesn't matter for tcp as it works with handshakes, but for udp it does
-                        let mut addr = *node;
+                        let mut addr = node.addr;
                         addr.set_port(self.node_port);
-                        packet_sender
-                            .send_unreliable(addr, message_bytes.clone())
-                            .unwrap();
+                        packet_sender.send_unreliable(addr, message_bytes).unwrap();
                     }
                 }
             }
         }
     }
 
-    /// Returns if there were any new connections since the last time this function was called
-    pub fn handle_new_connections(&mut self) -> bool {
-        let has_received_new_connections = self.has_received_new_connections.load(Ordering::SeqCst);
-        self.has_received_new_connections
-            .store(false, Ordering::SeqCst);
-        has_received_new_connections
+    /// Returns if any connected node still has to be sent a snapshot of the world
+    pub fn has_unsynced_nodes(&self) -> bool {
+        self.connected_nodes.lock().is_ok_and(|connected_nodes| {
+            connected_nodes
+                .iter()
+                .any(|node| !node.synced && node.status == NodeStatus::Ready)
+        })
     }
 
-    pub fn render<F: Fn(&[u8])>(&mut self, result_callback: F) {
-        if let Ok(connected_nodes) = self.connected_nodes.lock() {
-            // Return pink when no nodes connected, this should be a visual warning to the host
-            if connected_nodes.is_empty() {
-                self.pixels.set_pixels_pink();
-            } else {
-                let barrier = self.socket.barrier().fetch_add(1, Ordering::SeqCst) + 1;
+    /// Send a snapshot of the entire world, such as `World::snapshot_visible_world_actions`, to the nodes which aren't synced yet.
+    /// Nodes which are already in sync aren't affected. Every batch is sent reliably, as the snapshot is the only state the node will ever receive.
+    pub fn send_snapshot(&mut self, snapshot: Vec<VisibleWorldAction>) {
+        self.challenge_nodes();
 
-                let num_nodes = connected_nodes.len() as u32;
-                let rows_per_node = self.height / num_nodes;
+        let packet_sender = self.socket.packet_sender();
 
-                let packet_sender = self.socket.packet_sender();
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            let unsynced_nodes: Vec<&mut ConnectedNode> = connected_nodes
+                .iter_mut()
+                .filter(|node| !node.synced && node.status == NodeStatus::Ready)
+                .collect();
+            if unsynced_nodes.is_empty() {
+                return;
+            }
 
-                // Notify all connected nodes to start rendering their assigned part of the screen
-                for (i, node) in connected_nodes.iter().enumerate() {
-                    let row_start = rows_per_node * i as u32;
-                    let row_end = if i as u32 == num_nodes - 1 {
-                        self.height
-                    } else {
-                        rows_per_node * (i as u32 + 1)
-                    };
+            let messages_bytes: Vec<Vec<u8>> = batch_visible_world_actions(snapshot)
+                .into_iter()
+                .map(|batch| HostToNodeMessage::VisibleWorldActions(batch).to_bytes())
+                .collect();
 
-                    assert!((row_end - row_start) % RENDER_BLOCK_SIZE == 0);
+            for node in unsynced_nodes {
+                log::info!(
+                    "Sending a snapshot of {} messages to {:?}.",
+                    messages_bytes.len(),
+                    node.addr
+                );
 
-                    let message = HostToNodeMessage::StartRender(StartRenderData {
-                        width: self.width,
-                        height: self.height,
-                        row_start,
-                        row_end,
-                        frame_idx: self.frame_idx,
-                    });
+                for message_bytes in &messages_bytes {
+                    let message_bytes = node.seal(message_bytes.clone());
                     packet_sender
-                        .send_barrier(*node, message.to_bytes())
+                        .send_barrier(node.addr, message_bytes)
                         .unwrap();
                 }
+                node.synced = true;
+            }
+        }
+    }
 
-                // Wait for all nodes to finish rendering
-                loop {
-                    if self.socket.barrier().load(Ordering::SeqCst) == barrier + 1 {
-                        break;
+    /// Rows currently assigned to each node and how long each node took to render them last frame
+    pub fn row_splits(&self) -> Vec<NodeRowSplit> {
+        self.load_balancer
+            .row_splits(RENDER_BLOCK_SIZE, self.height)
+    }
+
+    pub fn scheduling(&self) -> RenderScheduling {
+        self.scheduling
+    }
+
+    /// Switch how work is divided across nodes, takes effect from the next frame
+    pub fn set_scheduling(&mut self, scheduling: RenderScheduling) {
+        self.scheduling = match scheduling {
+            RenderScheduling::Tiles { blocks_per_request } => RenderScheduling::Tiles {
+                blocks_per_request: blocks_per_request.clamp(1, MAX_BLOCKS_PER_REQUEST),
+            },
+            RenderScheduling::Rows => RenderScheduling::Rows,
+        };
+    }
+
+    pub fn tile_encoding(&self) -> TileEncoding {
+        self.tile_encoding
+    }
+
+    /// Request nodes to send their pixels with this encoding from the next frame on, nodes unable to render hdr keep sending jpeg
+    pub fn set_tile_encoding(&mut self, tile_encoding: TileEncoding) {
+        if self.tile_encoding != tile_encoding {
+            self.tile_encoding = tile_encoding;
+            self.reset_accumulation();
+        }
+    }
+
+    pub fn accumulation_delay(&self) -> Option<u32> {
+        self.accumulation_delay
+    }
+
+    /// Once the view has been static for this many frames, every node renders the whole frame with its own sample index and the host averages them into a converging image.
+    /// Only hdr tiles can be averaged, so accumulation requires an hdr `TileEncoding`. `None` disables accumulation.
+    pub fn set_accumulation_delay(&mut self, accumulation_delay: Option<u32>) {
+        self.accumulation_delay = accumulation_delay;
+        self.reset_accumulation();
+    }
+
+    /// Whether nodes are accumulating samples of a static view
+    pub fn is_accumulating(&self) -> bool {
+        self.accumulating
+    }
+
+    /// Samples per pixel of the accumulated image, 0 when not accumulating
+    pub fn accumulated_samples(&self) -> u32 {
+        if self.accumulating {
+            self.pixels.accumulated_samples()
+        } else {
+            0
+        }
+    }
+
+    /// Start over from a single sample, samples which are still being rendered are rejected once they arrive
+    fn reset_accumulation(&mut self) {
+        self.static_frames = 0;
+        self.accumulating = false;
+        self.pixels.reset_accumulation(self.next_sample_idx);
+    }
+
+    /// Nodes still rendering a sample of a view which changed are busy, they're given work again once they finish like any late node
+    fn stop_accumulating_nodes(&mut self) {
+        for node in std::mem::take(&mut self.accumulating_nodes) {
+            self.add_late_requests(node, 1);
+        }
+    }
+
+    /// A node finished rendering a whole frame, returns false if it wasn't working on a sample
+    fn finish_accumulation_sample(&mut self, progress: RenderProgress) -> bool {
+        if progress.request_idx.is_some() || !self.accumulating_nodes.contains(&progress.addr) {
+            return false;
+        }
+
+        self.accumulating_nodes
+            .retain(|node| *node != progress.addr);
+        true
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.pixels.exposure()
+    }
+
+    /// Exposure applied to hdr tiles before tonemapping them for display
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.pixels.set_exposure(exposure.max(0.0));
+    }
+
+    /// Linear radiance of the last rendered frame, laid out in blocks like the pixels passed to `render`.
+    /// Only hdr `TileEncoding`s provide radiance, blocks of nodes sending jpeg tiles are black.
+    pub fn read_hdr_pixels<F: Fn(&[PackedRgb9e5])>(&self, callback: F) {
+        self.pixels.read_hdr_pixels(callback);
+    }
+
+    /// Registry entries of all connected nodes, including nodes which were refused
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.connected_nodes
+            .lock()
+            .map(|connected_nodes| connected_nodes.iter().map(ConnectedNode::info).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn node(&self, addr: SocketAddr) -> Option<NodeInfo> {
+        self.connected_nodes
+            .lock()
+            .ok()
+            .and_then(|connected_nodes| {
+                connected_nodes
+                    .iter()
+                    .find(|node| node.addr == addr)
+                    .map(ConnectedNode::info)
+            })
+    }
+
+    /// Challenge nodes which connected since the last call to prove they know the pre-shared key, their handshake answers the challenge
+    fn challenge_nodes(&self) {
+        let Some(pre_shared_key) = &self.pre_shared_key else {
+            return;
+        };
+
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            for node in connected_nodes
+                .iter_mut()
+                .filter(|node| node.status == NodeStatus::AwaitingHandshake && node.auth.is_none())
+            {
+                let nonce = match new_session_nonce() {
+                    Ok(nonce) => nonce,
+                    Err(err) => {
+                        log::warn!("Failed to challenge node {:?}: {}", node.addr, err);
+                        continue;
                     }
-                    thread::yield_now();
+                };
+
+                // The challenge itself can't be sealed in the session it starts
+                let message = HostToNodeMessage::Challenge(ChallengeData { nonce });
+                let message_bytes =
+                    MessageAuth::new(pre_shared_key.clone(), Role::Host, NO_SESSION)
+                        .seal(message.to_bytes());
+                node.stats.count_sent(message_bytes.len());
+                node.auth = Some(MessageAuth::new(pre_shared_key.clone(), Role::Host, nonce));
+
+                self.socket
+                    .packet_sender()
+                    .send_barrier(node.addr, message_bytes)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Refuse nodes which connected without sending a handshake in time
+    fn refuse_silent_nodes(&self) {
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            for node in connected_nodes.iter_mut() {
+                if node.status == NodeStatus::AwaitingHandshake
+                    && node.connected_at.elapsed() > HANDSHAKE_TIMEOUT
+                {
+                    node.refuse(format!(
+                        "no handshake within {:?}, it's most likely running an outdated binary or lacks the pre-shared key",
+                        HANDSHAKE_TIMEOUT
+                    ));
                 }
             }
         }
+    }
+
+    pub fn frame_deadline(&self) -> Duration {
+        self.frame_deadline
+    }
+
+    /// Time nodes get to deliver a frame, `render` never blocks much longer than this even when nodes stop responding
+    pub fn set_frame_deadline(&mut self, frame_deadline: Duration) {
+        self.frame_deadline = frame_deadline;
+    }
+
+    /// Nodes which have been sent a snapshot of the world and can be given render work
+    fn synced_nodes(&self) -> Vec<SocketAddr> {
+        self.connected_nodes
+            .lock()
+            .map(|connected_nodes| {
+                connected_nodes
+                    .iter()
+                    .filter(|node| node.synced)
+                    .map(|node| node.addr)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn late_requests(&self, addr: SocketAddr) -> u32 {
+        self.connected_nodes
+            .lock()
+            .ok()
+            .and_then(|connected_nodes| {
+                connected_nodes
+                    .iter()
+                    .find(|node| node.addr == addr)
+                    .map(|node| node.late_requests)
+            })
+            .unwrap_or(0)
+    }
+
+    fn add_late_requests(&self, addr: SocketAddr, count: u32) {
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            if let Some(node) = connected_nodes.iter_mut().find(|node| node.addr == addr) {
+                node.late_requests += count;
+            }
+        }
+    }
+
+    /// A node finished a request after its deadline, or one that was completed by another node in the meantime
+    fn finish_late_request(&self, addr: SocketAddr) {
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            if let Some(node) = connected_nodes.iter_mut().find(|node| node.addr == addr) {
+                node.late_requests = node.late_requests.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Count a missed deadline for every node still working on late requests, evicting nodes which missed too many in a row.
+    /// Evicted nodes aren't given work until they handshake again. Nodes which finished their work of this frame in time are healthy again.
+    fn update_node_health(
+        &mut self,
+        working_nodes: &[SocketAddr],
+        frame_completeness: &mut FrameCompleteness,
+    ) {
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            for node in connected_nodes.iter_mut() {
+                if node.late_requests > 0 {
+                    node.missed_deadlines += 1;
+                    frame_completeness.late_nodes.push(node.addr);
+                } else if working_nodes.contains(&node.addr) {
+                    node.missed_deadlines = 0;
+                }
+            }
+
+            for node in connected_nodes
+                .iter_mut()
+                .filter(|node| node.missed_deadlines >= MAX_MISSED_DEADLINES)
+            {
+                log::warn!(
+                    "Evicting node {:?} after missing {} frame deadlines in a row.",
+                    node.addr,
+                    node.missed_deadlines
+                );
+                frame_completeness.evicted_nodes.push(node.addr);
+
+                // Sealed in the session it's evicted from, the node doesn't trust messages outside of it
+                let message = HostToNodeMessage::Evict(EvictData {
+                    missed_deadlines: node.missed_deadlines,
+                });
+                let message_bytes = node.seal(message.to_bytes());
+                self.socket
+                    .packet_sender()
+                    .send_barrier(node.addr, message_bytes)
+                    .unwrap();
+
+                node.evict();
+            }
+        }
+    }
+
+    /// Answer the asset requests of nodes and send the next chunks of the assets being streamed to them
+    fn serve_assets(&mut self) {
+        while let Ok((addr, request)) = self.asset_request_receiver.try_recv() {
+            let message = self.asset_server.request(addr, request);
+            self.send_barrier(addr, message.to_bytes());
+        }
+
+        let connected_nodes: Vec<SocketAddr> =
+            self.nodes().into_iter().map(|node| node.addr).collect();
+        self.asset_server.retain_nodes(&connected_nodes);
+
+        for (addr, message) in self.asset_server.next_chunks(ASSET_CHUNKS_PER_FRAME) {
+            self.send_barrier(addr, message.to_bytes());
+        }
+    }
+
+    /// Ping every node once per `PING_INTERVAL`, the answers are handled as they arrive by the receive thread
+    fn ping_nodes(&mut self) {
+        if self
+            .last_ping
+            .is_some_and(|last_ping| last_ping.elapsed() < PING_INTERVAL)
+        {
+            return;
+        }
+        self.last_ping = Some(Instant::now());
+
+        let packet_sender = self.socket.packet_sender();
+        let sent_micros = self.epoch.elapsed().as_micros() as u32;
+
+        if let Ok(mut connected_nodes) = self.connected_nodes.lock() {
+            for node in connected_nodes
+                .iter_mut()
+                .filter(|node| node.status == NodeStatus::Ready)
+            {
+                let message = HostToNodeMessage::Ping(PingData {
+                    sent_micros,
+                    hold_micros: 0,
+                    round_trip_micros: node
+                        .stats
+                        .round_trip
+                        .map_or(0, |round_trip| round_trip.as_micros() as u32),
+                });
+                let message_bytes = node.seal(message.to_bytes());
+
+                packet_sender
+                    .send_barrier(node.addr, message_bytes)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Render a frame across all nodes and pass the pixels to `result_callback`, returns how much of the frame arrived in time.
+    /// Blocks which didn't arrive before the frame deadline show the previous frame instead.
+    /// Pixels are laid out block after block, as `padded_resolution` pixels of which the padding of edge blocks isn't part of the frame.
+    pub fn render<F: Fn(&[u8])>(&mut self, result_callback: F) -> FrameCompleteness {
+        let deadline = Instant::now() + self.frame_deadline;
+
+        let mut frame_completeness = FrameCompleteness {
+            frame_idx: self.frame_idx,
+            total_blocks: block_count(UVec2::new(self.width, self.height)).element_product(),
+            ..Default::default()
+        };
+
+        self.challenge_nodes();
+        self.refuse_silent_nodes();
+        self.serve_assets();
+        self.ping_nodes();
+
+        // All progress still queued belongs to samples or requests of earlier frames which missed their deadline
+        while let Ok(progress) = self.progress_receiver.try_recv() {
+            if !self.finish_accumulation_sample(progress) {
+                self.finish_late_request(progress.addr);
+            }
+        }
+
+        let accumulate = self.tile_encoding.is_hdr()
+            && self
+                .accumulation_delay
+                .is_some_and(|accumulation_delay| self.static_frames >= accumulation_delay);
+        if accumulate && !self.accumulating {
+            log::info!(
+                "View has been static for {} frames, accumulating samples.",
+                self.static_frames
+            );
+            self.pixels.reset_accumulation(self.next_sample_idx);
+        }
+        if !accumulate {
+            self.stop_accumulating_nodes();
+        }
+        self.accumulating = accumulate;
+        self.static_frames = self.static_frames.saturating_add(1);
+
+        // Nodes which haven't received a snapshot yet don't know what the world looks like
+        let connected_nodes = self.synced_nodes();
+        self.accumulating_nodes
+            .retain(|node| connected_nodes.contains(node));
+
+        // Return pink when no nodes connected, this should be a visual warning to the host
+        if connected_nodes.is_empty() {
+            self.pixels.set_pixels_pink();
+            frame_completeness.missing_blocks = frame_completeness.total_blocks;
+        } else {
+            self.pixels.begin_frame_arrivals(self.frame_idx);
+
+            let working_nodes = if self.accumulating {
+                self.render_accumulation(&connected_nodes, deadline)
+            } else {
+                match self.scheduling {
+                    RenderScheduling::Rows => self.render_rows(&connected_nodes, deadline),
+                    RenderScheduling::Tiles { blocks_per_request } => {
+                        self.render_tiles(&connected_nodes, blocks_per_request, deadline)
+                    }
+                }
+            };
+
+            frame_completeness.missing_blocks = self.pixels.fill_missing_blocks(self.frame_idx);
+            self.update_node_health(&working_nodes, &mut frame_completeness);
+        }
 
         self.pixels.read_pixels(result_callback);
 
         self.pixels.next_frame();
         self.frame_idx += 1;
+
+        frame_completeness
+    }
+
+    /// Returns the nodes which were given work
+    fn render_rows(
+        &mut self,
+        connected_nodes: &[SocketAddr],
+        deadline: Instant,
+    ) -> Vec<SocketAddr> {
+        // Nodes still working on an earlier frame would only fall further behind, their rows go to the other nodes instead
+        let available_nodes: Vec<SocketAddr> = connected_nodes
+            .iter()
+            .copied()
+            .filter(|node| self.late_requests(*node) == 0)
+            .collect();
+        if available_nodes.is_empty() {
+            return vec![];
+        }
+
+        let block_splits = self
+            .load_balancer
+            .split(&available_nodes, self.height.div_ceil(RENDER_BLOCK_SIZE));
+
+        let render_start = Instant::now();
+        let mut working_nodes = vec![];
+
+        // Notify all available nodes to start rendering their assigned part of the screen
+        for (node, (block_start, block_end)) in available_nodes.iter().zip(&block_splits) {
+            if block_start == block_end {
+                continue;
+            }
+
+            // The last block row is cropped when the height isn't a multiple of the block size
+            let row_start = block_start * RENDER_BLOCK_SIZE;
+            let row_end = (block_end * RENDER_BLOCK_SIZE).min(self.height);
+
+            let message = HostToNodeMessage::StartRender(StartRenderData {
+                width: self.width,
+                height: self.height,
+                row_start,
+                row_end,
+                frame_idx: self.frame_idx,
+                tile_encoding: self.tile_encoding.to_u8() as u32,
+                sample_idx: 0,
+            });
+            self.send_barrier(*node, message.to_bytes());
+            working_nodes.push(*node);
+        }
+
+        // Wait for all nodes to finish rendering, or the deadline to expire
+        let mut rendering_nodes = working_nodes.clone();
+        while !rendering_nodes.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match self
+                .progress_receiver
+                .recv_timeout((deadline - now).min(Duration::from_millis(100)))
+            {
+                Ok(progress) => {
+                    if progress.frame_idx == self.frame_idx && progress.request_idx.is_none() {
+                        rendering_nodes.retain(|node| *node != progress.addr);
+                        self.set_barrier_wait(progress.addr, render_start.elapsed());
+                    } else {
+                        self.finish_late_request(progress.addr);
+                    }
+                }
+                Err(_) => {
+                    // Nodes which disconnected mid-frame won't finish anymore
+                    let connected_nodes = self.synced_nodes();
+                    rendering_nodes.retain(|node| connected_nodes.contains(node));
+                }
+            }
+        }
+
+        for node in &rendering_nodes {
+            log::warn!(
+                "Node {:?} missed the deadline of frame {}.",
+                node,
+                self.frame_idx
+            );
+            self.add_late_requests(*node, 1);
+            self.set_barrier_wait(*node, render_start.elapsed());
+        }
+
+        // Move rows from slow to fast nodes based on when their last rows arrived
+        let elapsed = render_start.elapsed();
+        let frame_times: Vec<Option<Duration>> = available_nodes
+            .iter()
+            .zip(&block_splits)
+            .map(|(node, (block_start, block_end))| {
+                if rendering_nodes.contains(node) {
+                    // Extrapolate how long the node would have taken from the rows it did deliver in time
+                    let arrived_block_rows = self
+                        .pixels
+                        .arrived_block_rows(*block_start, *block_end)
+                        .max(1);
+                    Some(
+                        elapsed
+                            .mul_f32((block_end - block_start) as f32 / arrived_block_rows as f32),
+                    )
+                } else {
+                    self.pixels
+                        .last_block_row_arrival(*block_start, *block_end)
+                        .map(|arrival| arrival.duration_since(render_start))
+                }
+            })
+            .collect();
+        self.load_balancer.report(&frame_times);
+
+        working_nodes
+    }
+
+    /// Give every idle node the whole frame to render with its own sample index, then wait for samples to arrive until the deadline.
+    /// Rendering a whole frame can take a node longer than the deadline, nodes working on a sample are only given a new one once they finish.
+    /// Returns the nodes which were given work or finished a sample.
+    fn render_accumulation(
+        &mut self,
+        connected_nodes: &[SocketAddr],
+        deadline: Instant,
+    ) -> Vec<SocketAddr> {
+        let render_start = Instant::now();
+
+        let idle_nodes: Vec<SocketAddr> = connected_nodes
+            .iter()
+            .copied()
+            .filter(|node| {
+                !self.accumulating_nodes.contains(node) && self.late_requests(*node) == 0
+            })
+            .collect();
+        for node in &idle_nodes {
+            let message = HostToNodeMessage::StartRender(StartRenderData {
+                width: self.width,
+                height: self.height,
+                row_start: 0,
+                row_end: self.height,
+                frame_idx: self.frame_idx,
+                tile_encoding: self.tile_encoding.to_u8() as u32,
+                sample_idx: self.next_sample_idx,
+            });
+            self.send_barrier(*node, message.to_bytes());
+
+            self.next_sample_idx = self.next_sample_idx.checked_add(1).unwrap_or(1);
+            self.accumulating_nodes.push(*node);
+        }
+
+        let mut working_nodes = idle_nodes;
+        while !self.accumulating_nodes.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match self
+                .progress_receiver
+                .recv_timeout((deadline - now).min(Duration::from_millis(100)))
+            {
+                Ok(progress) => {
+                    if self.finish_accumulation_sample(progress) {
+                        self.set_barrier_wait(progress.addr, render_start.elapsed());
+                        if !working_nodes.contains(&progress.addr) {
+                            working_nodes.push(progress.addr);
+                        }
+                    } else {
+                        self.finish_late_request(progress.addr);
+                    }
+                }
+                Err(_) => {
+                    // Nodes which disconnected mid-sample won't finish anymore
+                    let connected_nodes = self.synced_nodes();
+                    self.accumulating_nodes
+                        .retain(|node| connected_nodes.contains(node));
+                }
+            }
+        }
+
+        working_nodes
+    }
+
+    /// Returns the nodes which were given work
+    fn render_tiles(
+        &mut self,
+        connected_nodes: &[SocketAddr],
+        blocks_per_request: u32,
+        deadline: Instant,
+    ) -> Vec<SocketAddr> {
+        let render_start = Instant::now();
+        let num_blocks = block_count(UVec2::new(self.width, self.height));
+        let mut tile_queue = TileQueue::new(num_blocks.x, num_blocks.y);
+        let mut connected_nodes = connected_nodes.to_vec();
+
+        // Requests which are taking this long are rendered by idle nodes as well
+        let straggler_age = self.frame_deadline / 2;
+
+        // Late requests of earlier frames count towards the requests a node is working on
+        for node in &connected_nodes {
+            let late_requests = self.late_requests(*node) as usize;
+            for _ in late_requests..BLOCK_REQUESTS_IN_FLIGHT {
+                self.send_block_request(&mut tile_queue, *node, blocks_per_request);
+            }
+        }
+        let mut working_nodes: Vec<SocketAddr> = connected_nodes
+            .iter()
+            .copied()
+            .filter(|node| tile_queue.in_flight_count(*node) > 0)
+            .collect();
+
+        // Hand out the remaining blocks to whichever node finishes a request first
+        while !tile_queue.is_finished() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match self
+                .progress_receiver
+                .recv_timeout((deadline - now).min(Duration::from_millis(100)))
+            {
+                Ok(progress) => {
+                    let finished = match progress.request_idx {
+                        Some(request_idx) if progress.frame_idx == self.frame_idx => {
+                            tile_queue.finish(request_idx)
+                        }
+                        _ => None,
+                    };
+
+                    let Some((node, twin_node)) = finished else {
+                        self.finish_late_request(progress.addr);
+                        continue;
+                    };
+                    self.set_barrier_wait(node, render_start.elapsed());
+
+                    // The node working on the twin is busy until it finishes it as well
+                    if let Some(twin_node) = twin_node {
+                        self.add_late_requests(twin_node, 1);
+                    }
+
+                    if !self.send_block_request(&mut tile_queue, node, blocks_per_request) {
+                        self.send_straggler_request(&mut tile_queue, node, straggler_age);
+                    }
+                }
+                Err(_) => {
+                    // Blocks of nodes which disconnected mid-frame are given to the remaining nodes
+                    connected_nodes = self.synced_nodes();
+                    if connected_nodes.is_empty() {
+                        log::warn!("All nodes disconnected before finishing the frame.");
+                        break;
+                    }
+
+                    tile_queue.requeue_lost(&connected_nodes);
+                    for node in &connected_nodes {
+                        let late_requests = self.late_requests(*node) as usize;
+                        while tile_queue.in_flight_count(*node) + late_requests
+                            < BLOCK_REQUESTS_IN_FLIGHT
+                        {
+                            if !self.send_block_request(&mut tile_queue, *node, blocks_per_request)
+                                && !self.send_straggler_request(
+                                    &mut tile_queue,
+                                    *node,
+                                    straggler_age,
+                                )
+                            {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            for node in &connected_nodes {
+                if !working_nodes.contains(node) && tile_queue.in_flight_count(*node) > 0 {
+                    working_nodes.push(*node);
+                }
+            }
+        }
+
+        for node in tile_queue.abandon() {
+            log::warn!(
+                "Node {:?} missed the deadline of frame {}.",
+                node,
+                self.frame_idx
+            );
+            self.add_late_requests(node, 1);
+            self.set_barrier_wait(node, render_start.elapsed());
+        }
+
+        working_nodes
+    }
+
+    /// Send the next blocks in the queue to a node, returns false when there are no blocks left
+    fn send_block_request(
+        &self,
+        tile_queue: &mut TileQueue,
+        node: SocketAddr,
+        blocks_per_request: u32,
+    ) -> bool {
+        let Some((request_idx, blocks)) = tile_queue.next_request(node, blocks_per_request) else {
+            return false;
+        };
+
+        self.send_render_blocks(node, request_idx, blocks);
+        true
+    }
+
+    /// Let an idle node render the blocks of a request which another node is taking too long for, returns false when there is no such request
+    fn send_straggler_request(
+        &self,
+        tile_queue: &mut TileQueue,
+        node: SocketAddr,
+        straggler_age: Duration,
+    ) -> bool {
+        let Some((request_idx, blocks)) = tile_queue.reissue_straggler(node, straggler_age) else {
+            return false;
+        };
+
+        self.send_render_blocks(node, request_idx, blocks);
+        true
+    }
+
+    fn send_render_blocks(&self, node: SocketAddr, request_idx: u32, blocks: Vec<UVec2>) {
+        let message = HostToNodeMessage::RenderBlocks(RenderBlocksData {
+            width: self.width,
+            height: self.height,
+            frame_idx: self.frame_idx,
+            request_idx,
+            tile_encoding: self.tile_encoding,
+            blocks,
+        });
+        self.send_barrier(node, message.to_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(idx: u8, len: usize) -> VisibleWorldAction {
+        VisibleWorldAction {
+            ty: 2,
+            data: vec![idx; len],
+            must_sync: idx % 2 == 0,
+        }
+    }
+
+    fn decode_batch(batch: Vec<VisibleWorldAction>) -> (usize, Vec<VisibleWorldAction>) {
+        let message_bytes = HostToNodeMessage::VisibleWorldActions(batch).to_bytes();
+        match HostToNodeMessage::from_bytes(&message_bytes).unwrap() {
+            HostToNodeMessage::VisibleWorldActions(actions) => (message_bytes.len(), actions),
+            _ => panic!("Batch decoded to a different message."),
+        }
+    }
+
+    fn evict_message() -> Vec<u8> {
+        HostToNodeMessage::Evict(EvictData {
+            missed_deadlines: MAX_MISSED_DEADLINES,
+        })
+        .to_bytes()
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let message_bytes = evict_message();
+        assert_eq!(
+            peek_protocol_version(&message_bytes).unwrap(),
+            PROTOCOL_VERSION
+        );
+        match HostToNodeMessage::from_bytes(&message_bytes).unwrap() {
+            HostToNodeMessage::Evict(data) => {
+                assert_eq!(data.missed_deadlines, MAX_MISSED_DEADLINES)
+            }
+            _ => panic!("Evict decoded to a different message."),
+        }
+    }
+
+    #[test]
+    fn truncated_message() {
+        let message_bytes = evict_message();
+        for len in 0..message_bytes.len() {
+            assert!(HostToNodeMessage::from_bytes(&message_bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut message_bytes = evict_message();
+        message_bytes[0] = b'X';
+        assert!(HostToNodeMessage::from_bytes(&message_bytes).is_err());
+        assert!(NodeToHostMessage::from_bytes(&message_bytes).is_err());
+        assert!(peek_protocol_version(&message_bytes).is_err());
+    }
+
+    #[test]
+    fn wrong_protocol_version() {
+        let mut message_bytes = evict_message();
+        message_bytes[2..4].copy_from_slice(&(PROTOCOL_VERSION - 1).to_le_bytes());
+        assert!(HostToNodeMessage::from_bytes(&message_bytes).is_err());
+        assert_eq!(
+            peek_protocol_version(&message_bytes).unwrap(),
+            PROTOCOL_VERSION - 1
+        );
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut message_bytes = evict_message();
+        message_bytes.push(0);
+        assert!(HostToNodeMessage::from_bytes(&message_bytes).is_err());
+    }
+
+    #[test]
+    fn sealed_messages_fit_max_packet_size() {
+        let pre_shared_key = PreSharedKey::new("venue").unwrap();
+        let mut auth = MessageAuth::new(pre_shared_key, Role::Host, [7; 16]);
+
+        let actions: Vec<_> = (0..100).map(|idx| action(idx, 80)).collect();
+        let mut messages: Vec<_> = batch_visible_world_actions(actions)
+            .into_iter()
+            .map(HostToNodeMessage::VisibleWorldActions)
+            .collect();
+        messages.push(HostToNodeMessage::AssetChunk(AssetChunkData {
+            content_hash: 1,
+            chunk_idx: 0,
+            chunk_count: 1,
+            bytes: vec![0; ASSET_CHUNK_SIZE],
+        }));
+        messages.push(HostToNodeMessage::RenderBlocks(RenderBlocksData {
+            width: MAX_RENDER_RESOLUTION,
+            height: MAX_RENDER_RESOLUTION,
+            frame_idx: 0,
+            request_idx: 0,
+            tile_encoding: TileEncoding::Rgb9e5Lz4,
+            blocks: vec![UVec2::ZERO; MAX_BLOCKS_PER_REQUEST as usize],
+        }));
+
+        for message in messages {
+            let message_bytes = message.to_bytes();
+            assert!(message_bytes.len() <= MAX_BATCH_SIZE);
+            assert!(auth.seal(message_bytes).len() <= MAX_PACKET_SIZE);
+        }
+    }
+
+    #[test]
+    fn no_actions_no_batches() {
+        assert!(batch_visible_world_actions(vec![]).is_empty());
+    }
+
+    #[test]
+    fn batches_fit_max_batch_size_in_order() {
+        // Transform actions, which are sent for every moving entity each frame
+        let actions: Vec<_> = (0..100).map(|idx| action(idx, 80)).collect();
+
+        let batches = batch_visible_world_actions(actions);
+        assert!(batches.len() > 1);
+
+        let mut decoded = vec![];
+        for batch in batches {
+            let (message_size, actions) = decode_batch(batch);
+            assert!(message_size <= MAX_BATCH_SIZE);
+            decoded.extend(actions);
+        }
+
+        assert_eq!(decoded.len(), 100);
+        for (idx, action) in decoded.iter().enumerate() {
+            assert_eq!(action.ty, 2);
+            assert_eq!(action.data, vec![idx as u8; 80]);
+            assert_eq!(action.must_sync, idx % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn oversized_action_gets_own_batch() {
+        let actions = vec![action(0, 16), action(1, MAX_BATCH_SIZE + 1), action(2, 16)];
+
+        let batches = batch_visible_world_actions(actions);
+        assert_eq!(batches.len(), 3);
+
+        let sizes: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                let (message_size, actions) = decode_batch(batch);
+                assert_eq!(actions.len(), 1);
+                message_size
+            })
+            .collect();
+        assert!(sizes[0] <= MAX_BATCH_SIZE);
+        assert!(sizes[1] > MAX_BATCH_SIZE);
+        assert!(sizes[2] <= MAX_BATCH_SIZE);
+    }
+
+    #[test]
+    fn batch_exactly_at_max_batch_size() {
+        // Header, action count and a single action header take 18 bytes
+        let actions = vec![action(0, MAX_BATCH_SIZE - 18), action(1, 0)];
+
+        let batches = batch_visible_world_actions(actions);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            decode_batch(batches.into_iter().next().unwrap()).0,
+            MAX_BATCH_SIZE
+        );
     }
 }
@@ -11,9 +11,16 @@ use winit::{
     window::{Window, WindowId},
 };
 
+pub mod asset_stream;
+pub mod auth;
 pub mod block_to_linear_pass;
+pub mod fault_injection;
 pub mod host;
+pub mod load_balancer;
+pub mod network_stats;
 pub mod node;
+pub mod recording;
+pub mod tile_codec;
 
 pub use winit;
 
@@ -0,0 +1,312 @@
+use core::{net::SocketAddr, time::Duration};
+
+/// Weight of the latest measurement in the smoothed speed of a node
+const SPEED_SMOOTHING: f32 = 0.3;
+/// A new split is only applied when it's predicted to shorten the frame by at least this fraction, which prevents rows from flip-flopping between nodes
+const REBALANCE_HYSTERESIS: f32 = 0.05;
+
+/// Rows of the frame assigned to a node, in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeRowSplit {
+    pub addr: SocketAddr,
+    pub row_start: u32,
+    pub row_end: u32,
+    /// Time between sending the render request and receiving the last rows of the node, `None` until measured
+    pub frame_time: Option<Duration>,
+}
+
+struct NodeLoad {
+    addr: SocketAddr,
+    block_rows: u32,
+    /// Smoothed block rows rendered per second, `None` until measured
+    speed: Option<f32>,
+    frame_time: Option<Duration>,
+}
+
+/// Splits the rows of a frame across nodes in proportion to how fast each node rendered its previous rows.
+/// Rows are assigned in whole blocks, so every split stays aligned to `RENDER_BLOCK_SIZE`.
+#[derive(Default)]
+pub struct RowLoadBalancer {
+    nodes: Vec<NodeLoad>,
+    block_rows: u32,
+}
+
+impl RowLoadBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block row ranges `start..end` for each node in order, nodes beyond the number of block rows are left without work.
+    /// Nodes keep their measured speed as long as they stay connected, a change in nodes or resolution redistributes all rows.
+    pub fn split(&mut self, addrs: &[SocketAddr], block_rows: u32) -> Vec<(u32, u32)> {
+        let nodes_changed = self.nodes.len() != addrs.len()
+            || self
+                .nodes
+                .iter()
+                .zip(addrs)
+                .any(|(node, addr)| node.addr != *addr);
+
+        if nodes_changed || self.block_rows != block_rows {
+            let mut nodes: Vec<NodeLoad> = addrs
+                .iter()
+                .map(|addr| {
+                    let speed = self
+                        .nodes
+                        .iter()
+                        .find(|node| node.addr == *addr)
+                        .and_then(|node| node.speed);
+
+                    NodeLoad {
+                        addr: *addr,
+                        block_rows: 0,
+                        speed,
+                        frame_time: None,
+                    }
+                })
+                .collect();
+
+            let block_counts = Self::proportional_block_rows(&Self::weights(&nodes), block_rows);
+            for (node, block_count) in nodes.iter_mut().zip(block_counts) {
+                node.block_rows = block_count;
+            }
+
+            self.nodes = nodes;
+            self.block_rows = block_rows;
+        }
+
+        let mut block_start = 0;
+        self.nodes
+            .iter()
+            .map(|node| {
+                let range = (block_start, block_start + node.block_rows);
+                block_start += node.block_rows;
+                range
+            })
+            .collect()
+    }
+
+    /// Report how long each node of the last `split` took, in the same order. Nodes without a measurement keep their rows.
+    pub fn report(&mut self, frame_times: &[Option<Duration>]) {
+        for (node, frame_time) in self.nodes.iter_mut().zip(frame_times) {
+            node.frame_time = *frame_time;
+
+            if let Some(frame_time) = frame_time {
+                if node.block_rows == 0 || frame_time.is_zero() {
+                    continue;
+                }
+
+                let speed = node.block_rows as f32 / frame_time.as_secs_f32();
+                node.speed = Some(match node.speed {
+                    Some(previous) => previous + (speed - previous) * SPEED_SMOOTHING,
+                    None => speed,
+                });
+            }
+        }
+
+        self.rebalance();
+    }
+
+    /// The current split in pixel rows, the last block row is cropped to `height` when it isn't a multiple of `block_size`
+    pub fn row_splits(&self, block_size: u32, height: u32) -> Vec<NodeRowSplit> {
+        let mut block_start = 0;
+        self.nodes
+            .iter()
+            .map(|node| {
+                let row_start = (block_start * block_size).min(height);
+                block_start += node.block_rows;
+                let row_end = (block_start * block_size).min(height);
+
+                NodeRowSplit {
+                    addr: node.addr,
+                    row_start,
+                    row_end,
+                    frame_time: node.frame_time,
+                }
+            })
+            .collect()
+    }
+
+    fn rebalance(&mut self) {
+        // Only rebalance once the speed of every node is known
+        let Some(speeds) = self
+            .nodes
+            .iter()
+            .map(|node| node.speed)
+            .collect::<Option<Vec<f32>>>()
+        else {
+            return;
+        };
+
+        let block_counts = Self::proportional_block_rows(&speeds, self.block_rows);
+
+        let predicted_frame_time = |block_counts: &mut dyn Iterator<Item = u32>| {
+            block_counts
+                .zip(&speeds)
+                .map(|(block_count, speed)| block_count as f32 / speed)
+                .fold(0.0, f32::max)
+        };
+        let current = predicted_frame_time(&mut self.nodes.iter().map(|node| node.block_rows));
+        let proposed = predicted_frame_time(&mut block_counts.iter().copied());
+
+        if proposed < current * (1.0 - REBALANCE_HYSTERESIS) {
+            for (node, block_count) in self.nodes.iter_mut().zip(block_counts) {
+                node.block_rows = block_count;
+            }
+        }
+    }
+
+    /// Relative speed of each node, nodes which haven't been measured yet are assumed to be as fast as the average node
+    fn weights(nodes: &[NodeLoad]) -> Vec<f32> {
+        let measured: Vec<f32> = nodes.iter().filter_map(|node| node.speed).collect();
+        let average = if measured.is_empty() {
+            1.0
+        } else {
+            measured.iter().sum::<f32>() / measured.len() as f32
+        };
+
+        nodes
+            .iter()
+            .map(|node| node.speed.unwrap_or(average))
+            .collect()
+    }
+
+    /// Distribute block rows proportional to the weights using the largest remainder, giving every node at least one block row while there are enough
+    fn proportional_block_rows(weights: &[f32], block_rows: u32) -> Vec<u32> {
+        let mut block_counts = vec![0; weights.len()];
+        if weights.is_empty() {
+            return block_counts;
+        }
+
+        let total_weight: f32 = weights.iter().map(|weight| weight.max(0.0)).sum();
+        let shares: Vec<f32> = weights
+            .iter()
+            .map(|weight| {
+                if total_weight > 0.0 {
+                    weight.max(0.0) / total_weight * block_rows as f32
+                } else {
+                    block_rows as f32 / weights.len() as f32
+                }
+            })
+            .collect();
+
+        let mut assigned = 0;
+        for (block_count, share) in block_counts.iter_mut().zip(&shares) {
+            *block_count = (share.floor() as u32).min(block_rows - assigned);
+            assigned += *block_count;
+        }
+
+        let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+        by_remainder.sort_by(|a, b| {
+            let a = shares[*a] - shares[*a].floor();
+            let b = shares[*b] - shares[*b].floor();
+            b.total_cmp(&a)
+        });
+        for i in by_remainder
+            .into_iter()
+            .cycle()
+            .take((block_rows - assigned) as usize)
+        {
+            block_counts[i] += 1;
+        }
+
+        // Nodes without rows can't be measured, so they would never get any rows back
+        for i in 0..block_counts.len().min(block_rows as usize) {
+            if block_counts[i] == 0 {
+                let largest = (0..block_counts.len())
+                    .max_by_key(|j| block_counts[*j])
+                    .unwrap();
+                block_counts[largest] -= 1;
+                block_counts[i] += 1;
+            }
+        }
+
+        block_counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(count: u16) -> Vec<SocketAddr> {
+        (0..count)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], 4000 + i)))
+            .collect()
+    }
+
+    fn secs(secs: f32) -> Option<Duration> {
+        Some(Duration::from_secs_f32(secs))
+    }
+
+    #[test]
+    fn even_split_without_measurements() {
+        let mut load_balancer = RowLoadBalancer::new();
+        assert_eq!(
+            load_balancer.split(&addrs(3), 10),
+            vec![(0, 4), (4, 7), (7, 10)]
+        );
+    }
+
+    #[test]
+    fn rows_follow_measured_throughput() {
+        let addrs = addrs(2);
+        let mut load_balancer = RowLoadBalancer::new();
+        assert_eq!(load_balancer.split(&addrs, 12), vec![(0, 6), (6, 12)]);
+
+        // The second node renders three times slower
+        load_balancer.report(&[secs(1.0), secs(3.0)]);
+        assert_eq!(load_balancer.split(&addrs, 12), vec![(0, 9), (9, 12)]);
+
+        let row_splits = load_balancer.row_splits(64, 12 * 64 - 10);
+        assert_eq!(row_splits[0].row_start, 0);
+        assert_eq!(row_splits[0].row_end, 9 * 64);
+        assert_eq!(row_splits[0].frame_time, secs(1.0));
+        assert_eq!(row_splits[1].row_start, 9 * 64);
+        assert_eq!(row_splits[1].row_end, 12 * 64 - 10);
+    }
+
+    #[test]
+    fn small_differences_keep_the_split() {
+        let addrs = addrs(2);
+        let mut load_balancer = RowLoadBalancer::new();
+        load_balancer.split(&addrs, 100);
+
+        load_balancer.report(&[secs(1.0), secs(1.06)]);
+        assert_eq!(load_balancer.split(&addrs, 100), vec![(0, 50), (50, 100)]);
+    }
+
+    #[test]
+    fn unmeasured_nodes_keep_the_split() {
+        let addrs = addrs(2);
+        let mut load_balancer = RowLoadBalancer::new();
+        load_balancer.split(&addrs, 12);
+
+        load_balancer.report(&[secs(1.0), None]);
+        assert_eq!(load_balancer.split(&addrs, 12), vec![(0, 6), (6, 12)]);
+    }
+
+    #[test]
+    fn joining_node_gets_average_speed() {
+        let addrs = addrs(3);
+        let mut load_balancer = RowLoadBalancer::new();
+        load_balancer.split(&addrs[..2], 12);
+        load_balancer.report(&[secs(1.0), secs(3.0)]);
+
+        assert_eq!(
+            load_balancer.split(&addrs, 12),
+            vec![(0, 6), (6, 8), (8, 12)]
+        );
+    }
+
+    #[test]
+    fn every_node_keeps_a_row() {
+        assert_eq!(
+            RowLoadBalancer::proportional_block_rows(&[100.0, 1.0], 10),
+            vec![9, 1]
+        );
+        assert_eq!(
+            RowLoadBalancer::proportional_block_rows(&[1.0, 1.0, 1.0], 2),
+            vec![1, 1, 0]
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use core::time::Duration;
+
+/// Counters of the connection between the host and a single node, kept by both sides.
+/// Together with the render times of a node these tell whether a stutter comes from the network or from rendering.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Packets discarded because they arrived too far behind a barrier
+    pub packets_rejected: u64,
+    /// Packets discarded because they couldn't be read or decoded
+    pub packets_dropped: u64,
+    /// Packets discarded because they weren't sealed with the pre-shared key, only counted for peers which are connected
+    pub packets_unauthenticated: u64,
+    /// Blocks which arrived more than once for the same frame, only counted by the host
+    pub duplicate_blocks: u64,
+    /// Time the host waited for the node to finish its work of the last frame, only measured by the host
+    pub barrier_wait: Duration,
+    /// Latest round trip of a ping, excluding the time the ping waited for the node to finish rendering. `None` until measured.
+    pub round_trip: Option<Duration>,
+}
+
+impl NetworkStats {
+    pub fn count_sent(&mut self, bytes: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+    }
+
+    pub fn count_received(&mut self, bytes: usize) {
+        self.packets_received += 1;
+        self.bytes_received += bytes as u64;
+    }
+}
@@ -1,20 +1,54 @@
-use core::{net::SocketAddr, ops::FnMut, sync::atomic::Ordering};
+use core::{net::SocketAddr, ops::FnMut, time::Duration};
 use glam::UVec2;
-use std::thread;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
 
 use anyhow::Result;
+use appearance_asset_database::asset_paths::resolve_asset_path;
 use appearance_world::visible_world_action::VisibleWorldActionType;
 use unreliable::{Socket, SocketEvent};
 
-use crate::host::{
-    HostToNodeMessage, NodeToHostMessage, RenderPartialFinishedData, StartRenderData,
-    ENABLE_COMPRESSION, NODE_BYTES_PER_PIXEL, NODE_PIXEL_FORMAT, RENDER_BLOCK_SIZE,
+use crate::{
+    asset_stream::{validate_asset_path, AssetCache, AssetStreamReceiver, DEFAULT_ASSET_CACHE_DIR},
+    auth::{MessageAuth, PreSharedKey, Role, UnauthenticatedLog, NO_SESSION},
+    fault_injection::FaultInjection,
+    host::{
+        block_count, cropped_block_size, AssetChunkData, AssetInfoData, AssetRequestData,
+        BlocksFinishedData, ChallengeData, EvictData, HostToNodeMessage, NodeCapabilities,
+        NodeToHostMessage, PingData, RenderBlocksData, RenderFinishedData,
+        RenderPartialFinishedData, StartRenderData, NODE_BYTES_PER_PIXEL, RENDER_BLOCK_SIZE,
+    },
+    network_stats::NetworkStats,
+    recording::Recorder,
+    tile_codec::{encode_tile, TileEncoding},
 };
 
 pub trait NodeRenderer {
     // TODO: world manipulation
     fn visible_world_action(&mut self, action: &VisibleWorldActionType);
 
+    /// Load an asset streamed from the host, `path` is the resolved asset path models spawned afterwards refer to
+    fn provide_asset(&mut self, path: &str, bytes: &[u8]);
+
+    /// Describes the renderer to the host in the handshake, use `NodeCapabilities::new` to fill in the protocol version
+    fn capabilities(&self) -> NodeCapabilities;
+
+    /// Switch the pixels passed to render callbacks between tonemapped 8-bit RGBX and linear radiance packed as `PackedRgb9e5`.
+    /// Returns if the renderer outputs hdr pixels now, renderers without hdr support always return false.
+    fn set_hdr_output(&mut self, _hdr_output: bool) -> bool {
+        false
+    }
+
+    /// Render with the given sample index instead of one derived from the frame, so samples of different nodes are independent.
+    /// `None` returns to regular real-time rendering, renderers without control over their seeds can ignore this.
+    fn set_sample_index(&mut self, _sample_index: Option<u32>) {}
+
+    /// Render the rows `start_row..end_row`, `result_callback` is called with the pixels of all blocks covering the rows one block after the other.
+    /// `start_row` is aligned to blocks, blocks crossing the right or bottom edge of the resolution are padded to whole blocks.
     fn render<F: FnMut(&[u8])>(
         &mut self,
         resolution: UVec2,
@@ -22,122 +56,639 @@ pub trait NodeRenderer {
         end_row: u32,
         result_callback: F,
     );
+
+    /// Render an arbitrary list of blocks, `result_callback` is called with the position and pixels of each block in the given order.
+    /// By default the rows covering all blocks are rendered at once and the blocks are cut out of them, renderers able to render individual blocks should override this.
+    fn render_blocks<F: FnMut(UVec2, &[u8])>(
+        &mut self,
+        resolution: UVec2,
+        blocks: &[UVec2],
+        mut result_callback: F,
+    ) {
+        let (Some(min_block_y), Some(max_block_y)) = (
+            blocks.iter().map(|block| block.y).min(),
+            blocks.iter().map(|block| block.y).max(),
+        ) else {
+            return;
+        };
+
+        let num_blocks_x = block_count(resolution).x;
+        let block_bytes = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize * NODE_BYTES_PER_PIXEL;
+
+        self.render(
+            resolution,
+            min_block_y * RENDER_BLOCK_SIZE,
+            ((max_block_y + 1) * RENDER_BLOCK_SIZE).min(resolution.y),
+            |pixels| {
+                for block in blocks {
+                    let block_idx = ((block.y - min_block_y) * num_blocks_x + block.x) as usize;
+                    result_callback(
+                        *block,
+                        &pixels[(block_idx * block_bytes)..((block_idx + 1) * block_bytes)],
+                    );
+                }
+            },
+        );
+    }
 }
 
 pub struct Node<T: NodeRenderer> {
     socket: Socket,
-    host_port: u16,
+    host_addr: SocketAddr,
     renderer: T,
+    recorder: Option<Recorder>,
+    fault_injection: Option<FaultInjection>,
+    asset_cache: AssetCache,
+    asset_stream_receiver: AssetStreamReceiver,
+    /// Assets requested from the host, with the content hash the node reported having
+    requested_assets: HashMap<String, u64>,
+    /// Paths of assets waiting for their content to be streamed, by content hash
+    awaited_assets: HashMap<u64, Vec<String>>,
+    /// Assets the host has been asked about, these can be spawned right away
+    resolved_assets: HashSet<String>,
+    /// Actions held back until the assets they spawn have been resolved, applied in order
+    pending_actions: VecDeque<VisibleWorldActionType>,
+    stats: Arc<Mutex<NetworkStats>>,
+    /// Seals and opens messages in the session of the latest challenge of the host, `None` without a pre-shared key
+    auth: Option<MessageAuth>,
+    /// Whether a message sealed in the session of the challenge arrived, only then the host is known to have started it
+    session_established: bool,
+    unauthenticated_log: UnauthenticatedLog,
 }
 
 impl<T: NodeRenderer + 'static> Node<T> {
-    pub fn new(renderer: T, host_addr: SocketAddr, receiving_port: u16) -> Result<Self> {
+    /// Connect to the host, with a pre-shared key the node only handshakes once challenged and drops all unauthenticated traffic
+    pub fn new(
+        renderer: T,
+        host_addr: SocketAddr,
+        receiving_port: u16,
+        pre_shared_key: Option<PreSharedKey>,
+    ) -> Result<Self> {
         let socket = Socket::new(Some(host_addr), receiving_port)?;
 
         Ok(Self {
             socket,
-            host_port: host_addr.port(),
+            host_addr,
             renderer,
+            recorder: None,
+            fault_injection: None,
+            asset_cache: AssetCache::new(DEFAULT_ASSET_CACHE_DIR)?,
+            asset_stream_receiver: AssetStreamReceiver::new(),
+            requested_assets: HashMap::new(),
+            awaited_assets: HashMap::new(),
+            resolved_assets: HashSet::new(),
+            pending_actions: VecDeque::new(),
+            stats: Arc::new(Mutex::new(NetworkStats::default())),
+            auth: pre_shared_key
+                .map(|pre_shared_key| MessageAuth::new(pre_shared_key, Role::Node, NO_SESSION)),
+            session_established: false,
+            unauthenticated_log: UnauthenticatedLog::default(),
         })
     }
 
+    /// Stats of the connection to the host, shared with the node so they can be read while it runs
+    pub fn network_stats(&self) -> Arc<Mutex<NetworkStats>> {
+        self.stats.clone()
+    }
+
+    fn update_stats<F: FnOnce(&mut NetworkStats)>(&self, update: F) {
+        if let Ok(mut stats) = self.stats.lock() {
+            update(&mut stats);
+        }
+    }
+
+    fn drop_unauthenticated(&mut self, addr: &SocketAddr) {
+        self.update_stats(|stats| stats.packets_unauthenticated += 1);
+        self.unauthenticated_log.drop_packet(addr);
+    }
+
+    /// Seal a message in the current session, messages are sent as is without a pre-shared key
+    fn seal(auth: Option<&mut MessageAuth>, message_bytes: Vec<u8>) -> Vec<u8> {
+        match auth {
+            Some(auth) => auth.seal(message_bytes),
+            None => message_bytes,
+        }
+    }
+
+    /// Send a message to the host reliably, counting it in the stats
+    fn send_barrier(&mut self, addr: &SocketAddr, message_bytes: Vec<u8>) {
+        let message_bytes = Self::seal(self.auth.as_mut(), message_bytes);
+        self.update_stats(|stats| stats.count_sent(message_bytes.len()));
+        self.socket
+            .packet_sender()
+            .send_barrier(*addr, message_bytes)
+            .unwrap();
+    }
+
+    /// Record every message received from the host, so it can be replayed later using `Replay`
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Drop and reorder the pixel packets sent to the host, for testing how the host copes with a lossy network
+    pub fn with_fault_injection(mut self, fault_injection: FaultInjection) -> Self {
+        self.fault_injection = Some(fault_injection);
+        self
+    }
+
+    /// Keep assets streamed from the host in this cache instead of `DEFAULT_ASSET_CACHE_DIR`
+    pub fn with_asset_cache(mut self, asset_cache: AssetCache) -> Self {
+        self.asset_cache = asset_cache;
+        self
+    }
+
+    /// Apply a visible world action once every asset it and the actions before it spawn has been resolved with the host
+    fn visible_world_action(&mut self, action: VisibleWorldActionType, addr: &SocketAddr) {
+        if let VisibleWorldActionType::SpawnModel(data) = &action {
+            self.request_asset(resolve_asset_path(data.asset_path(), ""), addr);
+        }
+
+        self.pending_actions.push_back(action);
+        self.apply_pending_actions();
+    }
+
+    fn apply_pending_actions(&mut self) {
+        while let Some(action) = self.pending_actions.front() {
+            if let VisibleWorldActionType::SpawnModel(data) = action {
+                if !self
+                    .resolved_assets
+                    .contains(&resolve_asset_path(data.asset_path(), ""))
+                {
+                    break;
+                }
+            }
+
+            let action = self.pending_actions.pop_front().unwrap();
+            self.renderer.visible_world_action(&action);
+        }
+    }
+
+    /// Ask the host for the content of an asset, unless it has been asked already
+    fn request_asset(&mut self, path: String, addr: &SocketAddr) {
+        if self.resolved_assets.contains(&path) || self.requested_assets.contains_key(&path) {
+            return;
+        }
+
+        // Assets outside of the asset directory can't be streamed, the renderer loads them locally
+        if validate_asset_path(&path).is_err() {
+            self.resolved_assets.insert(path);
+            return;
+        }
+
+        let cached_hash = self.asset_cache.cached_hash(&path);
+        let message = NodeToHostMessage::AssetRequest(AssetRequestData {
+            path: path.clone(),
+            cached_hash,
+        });
+        self.send_barrier(addr, message.to_bytes());
+
+        self.requested_assets.insert(path, cached_hash);
+    }
+
+    fn resolve_asset(&mut self, path: String) {
+        self.requested_assets.remove(&path);
+        self.resolved_assets.insert(path);
+        self.apply_pending_actions();
+    }
+
+    /// Hand cached content to the renderer and remember which content the path resolved to
+    fn provide_asset(&mut self, path: String, content_hash: u64, bytes: &[u8]) {
+        if let Err(err) = self.asset_cache.set_path(&path, content_hash) {
+            log::warn!("Failed to update the asset cache index: {}", err);
+        }
+
+        self.renderer.provide_asset(&path, bytes);
+        self.resolve_asset(path);
+    }
+
+    fn asset_info(&mut self, data: AssetInfoData) {
+        let Some(cached_hash) = self.requested_assets.get(&data.path).copied() else {
+            return;
+        };
+
+        if data.content_hash == 0 {
+            log::warn!(
+                "Host can't serve asset {}, loading it locally instead.",
+                data.path
+            );
+        } else if let Some(bytes) = self.asset_cache.load(data.content_hash) {
+            self.provide_asset(data.path, data.content_hash, &bytes);
+            return;
+        } else if data.content_hash != cached_hash {
+            // The content follows in chunks
+            self.asset_stream_receiver.expect(data.content_hash);
+            self.awaited_assets
+                .entry(data.content_hash)
+                .or_default()
+                .push(data.path);
+            return;
+        }
+
+        // Either unavailable or identical to the local asset directory, so the renderer loads it from there
+        self.resolve_asset(data.path);
+    }
+
+    /// Chunks of content the host didn't announce in an `AssetInfo` are dropped
+    fn asset_chunk(&mut self, data: AssetChunkData) {
+        match self.asset_stream_receiver.receive(&data) {
+            Some(Ok(bytes)) => {
+                // Cached by content, so it isn't streamed again for other paths with the same content
+                if let Err(err) = self.asset_cache.store(&bytes) {
+                    log::warn!("Failed to cache streamed asset: {}", err);
+                }
+
+                for path in self
+                    .awaited_assets
+                    .remove(&data.content_hash)
+                    .unwrap_or_default()
+                {
+                    log::info!("Received asset {} from the host.", path);
+                    self.provide_asset(path, data.content_hash, &bytes);
+                }
+            }
+            Some(Err(err)) => {
+                log::warn!("{}", err);
+
+                // Don't hold back the world forever, the renderer attempts to load the assets locally
+                for path in self
+                    .awaited_assets
+                    .remove(&data.content_hash)
+                    .unwrap_or_default()
+                {
+                    self.resolve_asset(path);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Resolve all outstanding asset requests, the host won't answer them anymore
+    fn abandon_asset_requests(&mut self) {
+        self.awaited_assets.clear();
+        self.asset_stream_receiver.clear();
+        let requested_assets: Vec<String> = self.requested_assets.keys().cloned().collect();
+        for path in requested_assets {
+            self.resolve_asset(path);
+        }
+    }
+
+    /// Prepare the renderer for the encoding requested by the host, returns the encoding the renderer is able to provide
+    fn prepare_tile_encoding(&mut self, tile_encoding: TileEncoding) -> TileEncoding {
+        if self.renderer.set_hdr_output(tile_encoding.is_hdr()) {
+            tile_encoding
+        } else {
+            TileEncoding::Jpeg
+        }
+    }
+
+    /// Compress and send the pixels of a single block to the host, edge blocks are cropped to the resolution
+    #[allow(clippy::too_many_arguments)]
+    fn send_block(
+        socket: &Socket,
+        stats: &Mutex<NetworkStats>,
+        auth: Option<&mut MessageAuth>,
+        fault_injection: &mut Option<FaultInjection>,
+        host_port: u16,
+        addr: &SocketAddr,
+        frame_idx: u32,
+        sample_idx: u32,
+        encoding: TileEncoding,
+        resolution: UVec2,
+        block: UVec2,
+        block_pixels: &[u8],
+    ) {
+        let message = NodeToHostMessage::RenderPartialFinished(RenderPartialFinishedData {
+            row: block.y * RENDER_BLOCK_SIZE,
+            column_block: block.x,
+            frame_idx,
+            sample_idx,
+            encoding,
+            compressed_pixel_bytes: encode_tile(
+                encoding,
+                block_pixels,
+                cropped_block_size(resolution, block),
+            ),
+        });
+
+        let message_bytes = Self::seal(auth, message.to_bytes());
+
+        let mut addr = *addr;
+        addr.set_port(host_port);
+        let packets = match fault_injection {
+            Some(fault_injection) => fault_injection.apply(addr, message_bytes),
+            None => vec![(addr, message_bytes)],
+        };
+        for (addr, bytes) in packets {
+            if let Ok(mut stats) = stats.lock() {
+                stats.count_sent(bytes.len());
+            }
+            socket.packet_sender().send_unreliable(addr, bytes).unwrap();
+        }
+    }
+
+    /// Send the pixel packet held back by fault injection, so it arrives before the work is reported as finished
+    fn flush_fault_injection(&mut self) {
+        if let Some((addr, bytes)) = self
+            .fault_injection
+            .as_mut()
+            .and_then(|fault_injection| fault_injection.flush())
+        {
+            self.update_stats(|stats| stats.count_sent(bytes.len()));
+            self.socket
+                .packet_sender()
+                .send_unreliable(addr, bytes)
+                .unwrap();
+        }
+    }
+
     fn start_render(&mut self, data: StartRenderData, addr: &SocketAddr) {
         log::info!("start render: {:?}", data);
 
+        let encoding = self.prepare_tile_encoding(data.tile_encoding());
+        self.renderer.set_sample_index(data.sample_index());
         self.renderer.render(
             UVec2::new(data.width, data.height),
             data.row_start,
             data.row_end,
             |pixels| {
-                let num_blocks_x = data.width / RENDER_BLOCK_SIZE;
-                let num_blocks_y = (data.row_end - data.row_start) / RENDER_BLOCK_SIZE;
+                let num_blocks_x = data.width.div_ceil(RENDER_BLOCK_SIZE);
+                let num_blocks_y = (data.row_end - data.row_start).div_ceil(RENDER_BLOCK_SIZE);
+                let block_bytes =
+                    (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize * NODE_BYTES_PER_PIXEL;
 
                 for local_block_y in 0..num_blocks_y {
                     for local_block_x in 0..num_blocks_x {
-                        let block_size = RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE;
-                        let start_pixel = (local_block_y * block_size * num_blocks_x)
-                            + local_block_x * block_size;
-                        let end_pixel = start_pixel + block_size;
-
-                        let block_pixels = &pixels[(start_pixel as usize * NODE_BYTES_PER_PIXEL)
-                            ..(end_pixel as usize * NODE_BYTES_PER_PIXEL)];
-
-                        let image = turbojpeg::Image {
-                            pixels: block_pixels,
-                            width: RENDER_BLOCK_SIZE as usize,
-                            height: RENDER_BLOCK_SIZE as usize,
-                            pitch: RENDER_BLOCK_SIZE as usize * NODE_BYTES_PER_PIXEL,
-                            format: NODE_PIXEL_FORMAT,
-                        };
+                        let block_idx = (local_block_y * num_blocks_x + local_block_x) as usize;
 
-                        let compressed_pixel_bytes = if ENABLE_COMPRESSION {
-                            turbojpeg::compress(image, 95, turbojpeg::Subsamp::Sub2x2)
-                                .unwrap()
-                                .to_vec()
-                        } else {
-                            block_pixels.to_vec()
-                        };
-
-                        let message =
-                            NodeToHostMessage::RenderPartialFinished(RenderPartialFinishedData {
-                                row: (local_block_y * RENDER_BLOCK_SIZE) + data.row_start,
-                                column_block: local_block_x,
-                                frame_idx: data.frame_idx,
-                                compressed_pixel_bytes,
-                            });
-
-                        let mut addr = *addr;
-                        addr.set_port(self.host_port);
-                        self.socket
-                            .packet_sender()
-                            .send_unreliable(addr, message.to_bytes())
-                            .unwrap();
+                        Self::send_block(
+                            &self.socket,
+                            &self.stats,
+                            self.auth.as_mut(),
+                            &mut self.fault_injection,
+                            self.host_addr.port(),
+                            addr,
+                            data.frame_idx,
+                            data.sample_idx,
+                            encoding,
+                            UVec2::new(data.width, data.height),
+                            UVec2::new(
+                                local_block_x,
+                                local_block_y + data.row_start / RENDER_BLOCK_SIZE,
+                            ),
+                            &pixels[(block_idx * block_bytes)..((block_idx + 1) * block_bytes)],
+                        );
                     }
                 }
+            },
+        );
 
-                self.socket.barrier().fetch_add(1, Ordering::SeqCst);
-                self.socket
-                    .packet_sender()
-                    .send_barrier(*addr, vec![])
-                    .unwrap();
+        self.flush_fault_injection();
+
+        // Sent reliably, the host waits for this until the frame deadline
+        let message = NodeToHostMessage::RenderFinished(RenderFinishedData {
+            frame_idx: data.frame_idx,
+        });
+        self.send_barrier(addr, message.to_bytes());
+    }
+
+    fn render_blocks(&mut self, data: RenderBlocksData, addr: &SocketAddr) {
+        log::debug!(
+            "render {} blocks for frame {}",
+            data.blocks.len(),
+            data.frame_idx
+        );
+
+        let encoding = self.prepare_tile_encoding(data.tile_encoding);
+        self.renderer.set_sample_index(None);
+        self.renderer.render_blocks(
+            UVec2::new(data.width, data.height),
+            &data.blocks,
+            |block, block_pixels| {
+                Self::send_block(
+                    &self.socket,
+                    &self.stats,
+                    self.auth.as_mut(),
+                    &mut self.fault_injection,
+                    self.host_addr.port(),
+                    addr,
+                    data.frame_idx,
+                    0,
+                    encoding,
+                    UVec2::new(data.width, data.height),
+                    block,
+                    block_pixels,
+                );
             },
         );
+
+        self.flush_fault_injection();
+
+        // Sent reliably, the host hands out the next request once this arrives
+        let message = NodeToHostMessage::BlocksFinished(BlocksFinishedData {
+            frame_idx: data.frame_idx,
+            request_idx: data.request_idx,
+        });
+        self.send_barrier(addr, message.to_bytes());
+    }
+
+    /// Answer a ping of the host, excluding the time it waited since `arrival` from the round trip
+    fn ping(&mut self, data: PingData, arrival: Instant, addr: &SocketAddr) {
+        if data.round_trip_micros > 0 {
+            self.update_stats(|stats| {
+                stats.round_trip = Some(Duration::from_micros(data.round_trip_micros as u64))
+            });
+        }
+
+        let message = NodeToHostMessage::Pong(PingData {
+            hold_micros: arrival.elapsed().as_micros() as u32,
+            ..data
+        });
+        let message_bytes = Self::seal(self.auth.as_mut(), message.to_bytes());
+        self.update_stats(|stats| stats.count_sent(message_bytes.len()));
+
+        let mut addr = *addr;
+        addr.set_port(self.host_addr.port());
+        self.socket
+            .packet_sender()
+            .send_unreliable(addr, message_bytes)
+            .unwrap();
+    }
+
+    /// Answer a challenge of the host with a handshake sealed in the session it started.
+    /// Challenges aren't sealed in a session, so they're only accepted from the host until a message of the session arrived.
+    fn challenge(&mut self, data: ChallengeData, addr: &SocketAddr) {
+        // The port of the host can differ from the port its packets arrive from
+        if addr.ip() != self.host_addr.ip() {
+            log::warn!(
+                "Ignoring challenge from {}, the host is at {}.",
+                addr,
+                self.host_addr
+            );
+            return;
+        }
+
+        match &mut self.auth {
+            Some(auth) => auth.set_session(data.nonce),
+            None => {
+                log::warn!(
+                    "Ignoring challenge of the host at {}, the node has no pre-shared key.",
+                    addr
+                );
+                return;
+            }
+        }
+
+        let host_addr = self.host_addr;
+        self.send_handshake(&host_addr);
+    }
+
+    /// Register with the host again after it evicted the node, with a pre-shared key in answer to the challenge that follows
+    fn evict(&mut self, data: EvictData, addr: &SocketAddr) {
+        log::warn!(
+            "Evicted by the host after missing {} frame deadlines in a row, registering again.",
+            data.missed_deadlines
+        );
+
+        if self.auth.is_some() {
+            self.session_established = false;
+        } else {
+            self.send_handshake(addr);
+        }
+    }
+
+    fn send_handshake(&mut self, addr: &SocketAddr) {
+        let message = NodeToHostMessage::Handshake(self.renderer.capabilities());
+        self.send_barrier(addr, message.to_bytes());
     }
 
     pub fn run(mut self) {
+        // Events are timestamped as they arrive, so the time they queue up while rendering can be excluded from the round trip
+        let (event_sender, event_receiver) = crossbeam::channel::unbounded();
+        let socket_event_receiver = self.socket.event_receiver().clone();
+        thread::spawn(move || {
+            while let Ok(socket_event) = socket_event_receiver.recv() {
+                if event_sender.send((Instant::now(), socket_event)).is_err() {
+                    break;
+                }
+            }
+        });
+
         loop {
             #[allow(clippy::collapsible_match)]
-            if let Ok(socket_event) = self.socket.event_receiver().try_recv() {
+            if let Ok((arrival, socket_event)) = event_receiver.try_recv() {
                 match socket_event {
                     SocketEvent::Packet(packet, delay) => {
+                        self.update_stats(|stats| {
+                            stats.count_received(packet.payload().len());
+                            if delay > 0 {
+                                stats.packets_rejected += 1;
+                            }
+                        });
                         if delay > 0 {
                             continue;
                         }
 
-                        if let Ok(message) = HostToNodeMessage::from_bytes(packet.payload()) {
-                            match message {
-                                HostToNodeMessage::StartRender(data) => {
-                                    self.start_render(data, packet.addr());
+                        // With a pre-shared key only messages sealed in the current session are accepted,
+                        // or a challenge starting a new one as long as the current one isn't established
+                        let (payload, challenge_only) = match &mut self.auth {
+                            Some(auth) => match auth.open(packet.payload()) {
+                                Some(payload) => {
+                                    self.session_established = true;
+                                    (payload, false)
+                                }
+                                None => match auth
+                                    .open_without_session(packet.payload())
+                                    .filter(|_| !self.session_established)
+                                {
+                                    Some(payload) => (payload, true),
+                                    None => {
+                                        self.drop_unauthenticated(packet.addr());
+                                        continue;
+                                    }
+                                },
+                            },
+                            None => (packet.payload(), false),
+                        };
+
+                        match HostToNodeMessage::from_bytes(payload) {
+                            Ok(message) => {
+                                if challenge_only
+                                    && !matches!(message, HostToNodeMessage::Challenge(_))
+                                {
+                                    self.drop_unauthenticated(packet.addr());
+                                    continue;
+                                }
+
+                                if let Some(recorder) = &mut self.recorder {
+                                    if let Err(err) = recorder.record(payload) {
+                                        log::warn!("Failed to record message: {}", err);
+                                    }
                                 }
-                                HostToNodeMessage::VisibleWorldAction(data) => {
-                                    let visible_world_action =
-                                        VisibleWorldActionType::from_ty_and_bytes(
-                                            data.ty,
-                                            data.data.as_ref(),
-                                        );
-
-                                    self.renderer.visible_world_action(&visible_world_action);
+
+                                match message {
+                                    HostToNodeMessage::StartRender(data) => {
+                                        self.start_render(data, packet.addr());
+                                    }
+                                    HostToNodeMessage::RenderBlocks(data) => {
+                                        self.render_blocks(data, packet.addr());
+                                    }
+                                    HostToNodeMessage::VisibleWorldActions(actions) => {
+                                        for data in actions {
+                                            match VisibleWorldActionType::from_ty_and_bytes(
+                                                data.ty,
+                                                data.data.as_ref(),
+                                            ) {
+                                                Ok(visible_world_action) => self
+                                                    .visible_world_action(
+                                                        visible_world_action,
+                                                        packet.addr(),
+                                                    ),
+                                                Err(err) => log::warn!(
+                                                    "Failed to read visible world action from {}: {}",
+                                                    packet.addr(),
+                                                    err
+                                                ),
+                                            }
+                                        }
+                                    }
+                                    HostToNodeMessage::AssetInfo(data) => {
+                                        self.asset_info(data);
+                                    }
+                                    HostToNodeMessage::AssetChunk(data) => {
+                                        self.asset_chunk(data);
+                                    }
+                                    HostToNodeMessage::Ping(data) => {
+                                        self.ping(data, arrival, packet.addr());
+                                    }
+                                    HostToNodeMessage::Challenge(data) => {
+                                        self.challenge(data, packet.addr());
+                                    }
+                                    HostToNodeMessage::Evict(data) => {
+                                        self.evict(data, packet.addr());
+                                    }
                                 }
                             }
-                        } else {
-                            log::warn!("Failed to read message from {}.", packet.addr());
+                            Err(err) => {
+                                self.update_stats(|stats| stats.packets_dropped += 1);
+                                log::warn!("Failed to read message from {}: {}", packet.addr(), err)
+                            }
                         }
                     }
                     SocketEvent::Connect(addr) => {
                         log::info!("Node connected at {:?}", addr);
+
+                        // The host doesn't give any work to nodes until it received their handshake, with a pre-shared key it's sent in answer to the challenge of the host
+                        if self.auth.is_none() {
+                            self.send_handshake(&addr);
+                        }
                     }
                     SocketEvent::Disconnect(addr) => {
                         log::info!("Node disconnected at {:?}...", addr);
+                        self.abandon_asset_requests();
+
+                        // A host which restarts challenges the node again
+                        self.session_established = false;
                     }
                 }
             }
@@ -0,0 +1,470 @@
+use anyhow::{anyhow, Result};
+use appearance_world::visible_world_action::VisibleWorldActionType;
+use core::time::Duration;
+use glam::UVec2;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use crate::{
+    asset_stream::AssetStreamReceiver,
+    host::{HostToNodeMessage, StartRenderData},
+    node::NodeRenderer,
+};
+
+const RECORDING_MAGIC: [u8; 4] = *b"APRC";
+const RECORDING_VERSION: u32 = 13;
+
+/// Writes every host-to-node message to a timestamped binary log.
+/// The log starts with a magic and version, followed by entries of `[timestamp micros: u64][length: u32][message bytes]`, all little endian.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record a serialized `HostToNodeMessage`
+    pub fn record(&mut self, message_bytes: &[u8]) -> Result<()> {
+        let timestamp = self.start.elapsed().as_micros() as u64;
+
+        self.writer.write_all(&timestamp.to_le_bytes())?;
+        self.writer
+            .write_all(&(message_bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(message_bytes)?;
+
+        // Flush on every frame, a recording is most valuable right before a crash
+        if let Ok(HostToNodeMessage::StartRender(_) | HostToNodeMessage::RenderBlocks(_)) =
+            HostToNodeMessage::from_bytes(message_bytes)
+        {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+pub struct RecordedMessage {
+    pub timestamp: Duration,
+    pub message: HostToNodeMessage,
+}
+
+/// Feeds a log written by a `Recorder` into a `NodeRenderer`, frame by frame.
+/// Assets streamed from the host during the recording are replayed as well, other assets are loaded from the local asset directory.
+pub struct Replay {
+    messages: Vec<RecordedMessage>,
+    cursor: usize,
+    asset_stream_receiver: AssetStreamReceiver,
+    /// Content of streamed assets by content hash, and the paths which resolved to each content hash
+    streamed_assets: HashMap<u64, Vec<u8>>,
+    streamed_asset_paths: HashMap<u64, Vec<String>>,
+}
+
+impl Replay {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut bytes = vec![];
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decode a recording, fails on recordings of another version as well as on truncated or corrupt ones
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 || bytes[0..4] != RECORDING_MAGIC {
+            return Err(anyhow!("Not a valid recording."));
+        }
+
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != RECORDING_VERSION {
+            return Err(anyhow!(
+                "Unsupported recording version {}, expected {}.",
+                version,
+                RECORDING_VERSION
+            ));
+        }
+
+        let mut messages = vec![];
+        let mut offset = 8;
+        while offset < bytes.len() {
+            if offset + 12 > bytes.len() {
+                return Err(anyhow!(
+                    "Recording ends with a truncated entry at byte {}.",
+                    offset
+                ));
+            }
+
+            let timestamp = u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+            let length =
+                u32::from_le_bytes(bytes[offset + 8..offset + 12].try_into().unwrap()) as usize;
+            offset += 12;
+
+            if length > bytes.len() - offset {
+                return Err(anyhow!(
+                    "Recording ends with a truncated entry at byte {}.",
+                    offset - 12
+                ));
+            }
+
+            let message = HostToNodeMessage::from_bytes(&bytes[offset..offset + length])
+                .map_err(|err| anyhow!("Corrupt recorded message at byte {}: {}", offset, err))?;
+            messages.push(RecordedMessage {
+                timestamp: Duration::from_micros(timestamp),
+                message,
+            });
+            offset += length;
+        }
+
+        Ok(Self {
+            messages,
+            cursor: 0,
+            asset_stream_receiver: AssetStreamReceiver::new(),
+            streamed_assets: HashMap::new(),
+            streamed_asset_paths: HashMap::new(),
+        })
+    }
+
+    pub fn messages(&self) -> &[RecordedMessage] {
+        &self.messages
+    }
+
+    /// Number of frames, a run of block requests of the same frame counts as a single frame
+    pub fn frame_count(&self) -> usize {
+        let mut frame_count = 0;
+        let mut blocks_frame_idx = None;
+        for message in &self.messages {
+            match &message.message {
+                HostToNodeMessage::StartRender(_) => frame_count += 1,
+                HostToNodeMessage::RenderBlocks(data) => {
+                    if blocks_frame_idx != Some(data.frame_idx) {
+                        frame_count += 1;
+                        blocks_frame_idx = Some(data.frame_idx);
+                    }
+                }
+                HostToNodeMessage::VisibleWorldActions(_)
+                | HostToNodeMessage::AssetInfo(_)
+                | HostToNodeMessage::AssetChunk(_)
+                | HostToNodeMessage::Ping(_)
+                | HostToNodeMessage::Challenge(_)
+                | HostToNodeMessage::Evict(_) => {}
+            }
+        }
+        frame_count
+    }
+
+    /// Restart the replay from the first recorded message
+    pub fn rewind(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Apply all visible world actions up to the next recorded render and render it.
+    /// Block requests of the same frame are rendered together, `result_callback` is then called for every block.
+    /// Returns the render data of the replayed frame, or `None` when the end of the recording has been reached.
+    pub fn replay_frame<T: NodeRenderer, F: FnMut(&[u8])>(
+        &mut self,
+        renderer: &mut T,
+        mut result_callback: F,
+    ) -> Option<StartRenderData> {
+        while let Some(recorded_message) = self.messages.get(self.cursor) {
+            self.cursor += 1;
+
+            match &recorded_message.message {
+                HostToNodeMessage::VisibleWorldActions(actions) => {
+                    for data in actions {
+                        match VisibleWorldActionType::from_ty_and_bytes(data.ty, data.data.as_ref())
+                        {
+                            Ok(visible_world_action) => {
+                                renderer.visible_world_action(&visible_world_action)
+                            }
+                            Err(err) => {
+                                log::warn!("Skipping recorded visible world action: {}", err)
+                            }
+                        }
+                    }
+                }
+                HostToNodeMessage::AssetInfo(data) => {
+                    match self.streamed_assets.get(&data.content_hash) {
+                        Some(bytes) => renderer.provide_asset(&data.path, bytes),
+                        None => self.asset_stream_receiver.expect(data.content_hash),
+                    }
+                    self.streamed_asset_paths
+                        .entry(data.content_hash)
+                        .or_default()
+                        .push(data.path.clone());
+                }
+                HostToNodeMessage::AssetChunk(data) => {
+                    match self.asset_stream_receiver.receive(data) {
+                        Some(Ok(bytes)) => {
+                            for path in self
+                                .streamed_asset_paths
+                                .get(&data.content_hash)
+                                .into_iter()
+                                .flatten()
+                            {
+                                renderer.provide_asset(path, &bytes);
+                            }
+                            self.streamed_assets.insert(data.content_hash, bytes);
+                        }
+                        Some(Err(err)) => log::warn!("Skipping recorded asset: {}", err),
+                        None => {}
+                    }
+                }
+                HostToNodeMessage::Ping(_)
+                | HostToNodeMessage::Challenge(_)
+                | HostToNodeMessage::Evict(_) => {}
+                HostToNodeMessage::StartRender(data) => {
+                    renderer.set_hdr_output(data.tile_encoding().is_hdr());
+                    renderer.set_sample_index(data.sample_index());
+                    renderer.render(
+                        UVec2::new(data.width, data.height),
+                        data.row_start,
+                        data.row_end,
+                        result_callback,
+                    );
+                    return Some(*data);
+                }
+                HostToNodeMessage::RenderBlocks(data) => {
+                    let resolution = UVec2::new(data.width, data.height);
+                    let frame_idx = data.frame_idx;
+                    let tile_encoding = data.tile_encoding;
+                    renderer.set_hdr_output(tile_encoding.is_hdr());
+                    renderer.set_sample_index(None);
+                    renderer.render_blocks(resolution, &data.blocks, |_, pixels| {
+                        result_callback(pixels)
+                    });
+
+                    while let Some(RecordedMessage {
+                        message: HostToNodeMessage::RenderBlocks(data),
+                        ..
+                    }) = self.messages.get(self.cursor)
+                    {
+                        if data.frame_idx != frame_idx {
+                            break;
+                        }
+
+                        self.cursor += 1;
+                        renderer.render_blocks(resolution, &data.blocks, |_, pixels| {
+                            result_callback(pixels)
+                        });
+                    }
+
+                    return Some(StartRenderData {
+                        width: resolution.x,
+                        height: resolution.y,
+                        row_start: 0,
+                        row_end: resolution.y,
+                        frame_idx,
+                        tile_encoding: tile_encoding.to_u8() as u32,
+                        sample_idx: 0,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use appearance_world::visible_world_action::VisibleWorldAction;
+    use std::path::PathBuf;
+
+    use crate::host::{NodeCapabilities, PingData, RendererKind};
+
+    /// Renderer which only keeps track of the calls made to it
+    #[derive(Default)]
+    struct CallLog {
+        calls: Vec<String>,
+    }
+
+    impl NodeRenderer for CallLog {
+        fn visible_world_action(&mut self, action: &VisibleWorldActionType) {
+            self.calls.push(format!("{:?}", action));
+        }
+
+        fn provide_asset(&mut self, path: &str, _bytes: &[u8]) {
+            self.calls.push(format!("Asset {}", path));
+        }
+
+        fn capabilities(&self) -> NodeCapabilities {
+            NodeCapabilities::new(RendererKind::Cpu)
+        }
+
+        fn render<F: FnMut(&[u8])>(
+            &mut self,
+            resolution: UVec2,
+            start_row: u32,
+            end_row: u32,
+            mut result_callback: F,
+        ) {
+            self.calls
+                .push(format!("Render {} {}..{}", resolution, start_row, end_row));
+            result_callback(&[]);
+        }
+    }
+
+    fn start_render(frame_idx: u32) -> HostToNodeMessage {
+        HostToNodeMessage::StartRender(StartRenderData {
+            width: 128,
+            height: 100,
+            row_start: 0,
+            row_end: 100,
+            frame_idx,
+            tile_encoding: 0,
+            sample_idx: 0,
+        })
+    }
+
+    fn clear(clear_idx: u32) -> HostToNodeMessage {
+        HostToNodeMessage::VisibleWorldActions(vec![VisibleWorldAction::new(
+            VisibleWorldActionType::Clear(clear_idx),
+        )])
+    }
+
+    /// Encoded messages of a short session of two frames
+    fn session() -> Vec<Vec<u8>> {
+        vec![
+            clear(0),
+            HostToNodeMessage::Ping(PingData {
+                sent_micros: 1,
+                hold_micros: 0,
+                round_trip_micros: 0,
+            }),
+            start_render(0),
+            clear(1),
+            start_render(1),
+        ]
+        .into_iter()
+        .map(HostToNodeMessage::to_bytes)
+        .collect()
+    }
+
+    fn record(name: &str, messages: &[Vec<u8>]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "appearance-recording-{}-{}",
+            std::process::id(),
+            name
+        ));
+
+        let mut recorder = Recorder::new(&path).unwrap();
+        for message_bytes in messages {
+            recorder.record(message_bytes).unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let session = session();
+        let path = record("round-trip", &session);
+        let mut replay = Replay::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.frame_count(), 2);
+        assert!(replay
+            .messages()
+            .windows(2)
+            .all(|pair| pair[0].timestamp < pair[1].timestamp));
+
+        let expected_calls = [
+            vec!["Clear(0)", "Render [128, 100] 0..100"],
+            vec!["Clear(1)", "Render [128, 100] 0..100"],
+        ];
+        for _ in 0..2 {
+            for (frame_idx, expected_calls) in expected_calls.iter().enumerate() {
+                let mut renderer = CallLog::default();
+                let data = replay.replay_frame(&mut renderer, |_| {}).unwrap();
+                assert_eq!(data.frame_idx, frame_idx as u32);
+                assert_eq!(renderer.calls, *expected_calls);
+            }
+            assert!(replay
+                .replay_frame(&mut CallLog::default(), |_| {})
+                .is_none());
+            replay.rewind();
+        }
+
+        let replayed: Vec<Vec<u8>> = std::mem::take(&mut replay.messages)
+            .into_iter()
+            .map(|recorded_message| recorded_message.message.to_bytes())
+            .collect();
+        assert_eq!(replayed, session);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = RECORDING_MAGIC.to_vec();
+        bytes.extend_from_slice(&(RECORDING_VERSION + 1).to_le_bytes());
+        assert!(Replay::from_bytes(&bytes).is_err());
+
+        let mut bytes = b"APRX".to_vec();
+        bytes.extend_from_slice(&RECORDING_VERSION.to_le_bytes());
+        assert!(Replay::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_recording() {
+        let session = session();
+        let path = record("truncated", &session);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Only cutting the recording right after an entry results in a valid recording
+        let mut entry_ends = vec![8];
+        for message_bytes in &session {
+            entry_ends.push(entry_ends.last().unwrap() + 12 + message_bytes.len());
+        }
+        assert_eq!(*entry_ends.last().unwrap(), bytes.len());
+
+        for len in 0..=bytes.len() {
+            let replay = Replay::from_bytes(&bytes[..len]);
+            match entry_ends.iter().position(|end| *end == len) {
+                Some(message_count) => assert_eq!(replay.unwrap().messages().len(), message_count),
+                None => assert!(replay.is_err(), "Truncated to {} bytes", len),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_recording() {
+        let session = session();
+        let path = record("corrupt", &session);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Protocol magic of the first message
+        let mut corrupt = bytes.clone();
+        corrupt[8 + 12] ^= 0xff;
+        assert!(Replay::from_bytes(&corrupt).is_err());
+
+        // Length of the first message pointing past the end
+        let mut corrupt = bytes.clone();
+        corrupt[8 + 8..8 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Replay::from_bytes(&corrupt).is_err());
+
+        // Length of the first message cutting it short
+        let mut corrupt = bytes;
+        corrupt[8 + 8..8 + 12].copy_from_slice(&4u32.to_le_bytes());
+        assert!(Replay::from_bytes(&corrupt).is_err());
+    }
+}
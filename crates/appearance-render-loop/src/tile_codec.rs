@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use appearance_packing::PackedRgb9e5;
+use glam::{UVec2, Vec3};
+
+use crate::host::{ENABLE_COMPRESSION, NODE_BYTES_PER_PIXEL, NODE_PIXEL_FORMAT, RENDER_BLOCK_SIZE};
+
+const PIXELS_PER_BLOCK: usize = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize;
+const BYTES_PER_BLOCK: usize = PIXELS_PER_BLOCK * NODE_BYTES_PER_PIXEL;
+
+/// How the pixels of a block are encoded when sent from a node to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileEncoding {
+    /// Tonemapped 8-bit RGBX, lossy jpeg compressed when `ENABLE_COMPRESSION` is set. Only displayed, it can't be accumulated or read as radiance.
+    #[default]
+    Jpeg,
+    /// Linear radiance packed as `PackedRgb9e5`, losslessly lz4 compressed. The only encoding feeding the hdr pixels and accumulation of the host.
+    Rgb9e5Lz4,
+}
+
+impl TileEncoding {
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Jpeg),
+            1 => Ok(Self::Rgb9e5Lz4),
+            value => Err(anyhow!("Unknown tile encoding {}.", value)),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Jpeg => 0,
+            Self::Rgb9e5Lz4 => 1,
+        }
+    }
+
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Self::Rgb9e5Lz4)
+    }
+}
+
+/// Pixels of a single block received by the host, always a whole block with cropped edge blocks padded
+pub enum DecodedTile {
+    /// Tonemapped 8-bit RGBX
+    Ldr(Vec<u8>),
+    Hdr(Vec<PackedRgb9e5>),
+}
+
+/// Encode the pixels of a single block as handed out by a `NodeRenderer`, cropped to `tile_size` for blocks on the edge of the frame
+pub fn encode_tile(encoding: TileEncoding, block_pixels: &[u8], tile_size: UVec2) -> Vec<u8> {
+    match encoding {
+        TileEncoding::Jpeg => {
+            if ENABLE_COMPRESSION {
+                // Cropped by the pitch, the padding is skipped without copying
+                let image = turbojpeg::Image {
+                    pixels: block_pixels,
+                    width: tile_size.x as usize,
+                    height: tile_size.y as usize,
+                    pitch: RENDER_BLOCK_SIZE as usize * NODE_BYTES_PER_PIXEL,
+                    format: NODE_PIXEL_FORMAT,
+                };
+
+                turbojpeg::compress(image, 95, turbojpeg::Subsamp::Sub2x2)
+                    .unwrap()
+                    .to_vec()
+            } else {
+                crop_block(block_pixels, tile_size)
+            }
+        }
+        TileEncoding::Rgb9e5Lz4 => {
+            lz4_flex::compress(&shuffle_bytes(&crop_block(block_pixels, tile_size)))
+        }
+    }
+}
+
+/// Decode the pixels of a single block cropped to `tile_size`, fails on anything but exactly that many pixels
+pub fn decode_tile(encoding: TileEncoding, bytes: &[u8], tile_size: UVec2) -> Result<DecodedTile> {
+    let tile_bytes = tile_size.element_product() as usize * NODE_BYTES_PER_PIXEL;
+    if tile_bytes == 0 {
+        return Err(anyhow!(
+            "Tile of {}x{} pixels is empty.",
+            tile_size.x,
+            tile_size.y
+        ));
+    }
+
+    match encoding {
+        TileEncoding::Jpeg => {
+            let pixels = if ENABLE_COMPRESSION {
+                turbojpeg::decompress(bytes, NODE_PIXEL_FORMAT)?.pixels
+            } else {
+                bytes.to_vec()
+            };
+
+            if pixels.len() != tile_bytes {
+                return Err(anyhow!("Tile has {} bytes of pixels.", pixels.len()));
+            }
+            Ok(DecodedTile::Ldr(pad_block(&pixels, tile_size)))
+        }
+        TileEncoding::Rgb9e5Lz4 => {
+            // The output size is known up front, so a malicious tile can't make it allocate more
+            let shuffled = lz4_flex::decompress(bytes, tile_bytes)?;
+            if shuffled.len() != tile_bytes {
+                return Err(anyhow!("Tile has {} bytes of pixels.", shuffled.len()));
+            }
+
+            let pixels = pad_block(&unshuffle_bytes(&shuffled), tile_size);
+            Ok(DecodedTile::Hdr(bytemuck::pod_collect_to_vec(&pixels[..])))
+        }
+    }
+}
+
+/// Copy the top left `tile_size` pixels out of a whole block
+fn crop_block(block_pixels: &[u8], tile_size: UVec2) -> Vec<u8> {
+    let block_row_bytes = RENDER_BLOCK_SIZE as usize * NODE_BYTES_PER_PIXEL;
+    let tile_row_bytes = tile_size.x as usize * NODE_BYTES_PER_PIXEL;
+
+    block_pixels
+        .chunks_exact(block_row_bytes)
+        .take(tile_size.y as usize)
+        .flat_map(|row| &row[..tile_row_bytes])
+        .copied()
+        .collect()
+}
+
+/// Place the pixels of a cropped tile in the top left of a whole block, the padding is zeroed
+fn pad_block(tile_pixels: &[u8], tile_size: UVec2) -> Vec<u8> {
+    let block_row_bytes = RENDER_BLOCK_SIZE as usize * NODE_BYTES_PER_PIXEL;
+    let tile_row_bytes = tile_size.x as usize * NODE_BYTES_PER_PIXEL;
+
+    let mut block_pixels = vec![0; BYTES_PER_BLOCK];
+    for (block_row, tile_row) in block_pixels
+        .chunks_exact_mut(block_row_bytes)
+        .zip(tile_pixels.chunks_exact(tile_row_bytes))
+    {
+        block_row[..tile_row_bytes].copy_from_slice(tile_row);
+    }
+    block_pixels
+}
+
+/// Filmic tonemapping curve, matches the curve nodes apply to ldr tiles
+pub fn tonemap(hdr: Vec3) -> Vec3 {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+
+    ((hdr * (a * hdr + b)) / (hdr * (c * hdr + d) + e)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+/// Group the n-th byte of every pixel together, neighbouring pixels share most of their exponent and high bits which lz4 compresses far better
+fn shuffle_bytes(pixels: &[u8]) -> Vec<u8> {
+    let pixel_count = pixels.len() / NODE_BYTES_PER_PIXEL;
+    let mut shuffled = vec![0; pixels.len()];
+    for (i, pixel) in pixels.chunks_exact(NODE_BYTES_PER_PIXEL).enumerate() {
+        for (byte_idx, byte) in pixel.iter().enumerate() {
+            shuffled[byte_idx * pixel_count + i] = *byte;
+        }
+    }
+    shuffled
+}
+
+fn unshuffle_bytes(shuffled: &[u8]) -> Vec<u8> {
+    let pixel_count = shuffled.len() / NODE_BYTES_PER_PIXEL;
+    let mut pixels = vec![0; shuffled.len()];
+    for (i, pixel) in pixels.chunks_exact_mut(NODE_BYTES_PER_PIXEL).enumerate() {
+        for (byte_idx, byte) in pixel.iter_mut().enumerate() {
+            *byte = shuffled[byte_idx * pixel_count + i];
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{block_count, cropped_block_size};
+
+    /// Largest value `PackedRgb9e5` can represent
+    const MAX_RGB9E5: f32 = 65408.0;
+
+    /// Radiance of every pixel in a block, with the extreme values spread over the block so cropping keeps some of each
+    fn block_radiance() -> Vec<Vec3> {
+        let special = [
+            Vec3::ZERO,
+            Vec3::new(1e10, f32::INFINITY, MAX_RGB9E5),
+            Vec3::new(-1.0, f32::NEG_INFINITY, -1e-3),
+            Vec3::new(f32::NAN, 0.5, f32::NAN),
+        ];
+
+        (0..PIXELS_PER_BLOCK)
+            .map(|i| match special.get(i % 7) {
+                Some(radiance) => *radiance,
+                None => Vec3::new(i as f32 * 0.01, 1.0 / (i + 1) as f32, 3.0),
+            })
+            .collect()
+    }
+
+    fn encode_hdr_block(radiance: &[Vec3]) -> Vec<u8> {
+        let packed: Vec<PackedRgb9e5> = radiance
+            .iter()
+            .map(|radiance| PackedRgb9e5::new(*radiance))
+            .collect();
+        bytemuck::cast_slice(&packed).to_vec()
+    }
+
+    fn round_trip_hdr(tile_size: UVec2) {
+        let block_pixels = encode_hdr_block(&block_radiance());
+        let expected: &[PackedRgb9e5] = bytemuck::cast_slice(&block_pixels);
+
+        let bytes = encode_tile(TileEncoding::Rgb9e5Lz4, &block_pixels, tile_size);
+        let Ok(DecodedTile::Hdr(pixels)) = decode_tile(TileEncoding::Rgb9e5Lz4, &bytes, tile_size)
+        else {
+            panic!("Failed to decode a {} tile.", tile_size);
+        };
+
+        assert_eq!(pixels.len(), PIXELS_PER_BLOCK);
+        for (i, (pixel, expected)) in pixels.iter().zip(expected).enumerate() {
+            let x = i as u32 % RENDER_BLOCK_SIZE;
+            let y = i as u32 / RENDER_BLOCK_SIZE;
+            if x < tile_size.x && y < tile_size.y {
+                assert_eq!(pixel.unpack(), expected.unpack(), "Pixel {}x{}", x, y);
+            } else {
+                assert_eq!(pixel.unpack(), Vec3::ZERO, "Padding {}x{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn rgb9e5_clamps_out_of_range_values() {
+        let unpacked: Vec<Vec3> = block_radiance()[..4]
+            .iter()
+            .map(|radiance| PackedRgb9e5::new(*radiance).unpack())
+            .collect();
+
+        assert_eq!(unpacked[0], Vec3::ZERO);
+        assert_eq!(unpacked[1], Vec3::splat(MAX_RGB9E5));
+        assert_eq!(unpacked[2], Vec3::ZERO);
+        assert_eq!(unpacked[3].x, 0.0);
+        assert_eq!(unpacked[3].z, 0.0);
+        assert!(unpacked[3].is_finite());
+    }
+
+    #[test]
+    fn rgb9e5_lz4_round_trip() {
+        round_trip_hdr(UVec2::splat(RENDER_BLOCK_SIZE));
+    }
+
+    #[test]
+    fn rgb9e5_lz4_round_trip_edge_blocks() {
+        for resolution in [UVec2::new(1000, 700), UVec2::new(65, 1), UVec2::new(1, 127)] {
+            let last_block = block_count(resolution) - 1;
+            for block in [
+                UVec2::new(last_block.x, 0),
+                UVec2::new(0, last_block.y),
+                last_block,
+            ] {
+                round_trip_hdr(cropped_block_size(resolution, block));
+            }
+        }
+    }
+
+    #[test]
+    fn rgb9e5_lz4_rejects_wrong_tile_size() {
+        let block_pixels = encode_hdr_block(&block_radiance());
+        let bytes = encode_tile(TileEncoding::Rgb9e5Lz4, &block_pixels, UVec2::new(40, 60));
+
+        assert!(decode_tile(TileEncoding::Rgb9e5Lz4, &bytes, UVec2::new(40, 61)).is_err());
+        assert!(decode_tile(TileEncoding::Rgb9e5Lz4, &bytes, UVec2::new(40, 59)).is_err());
+        assert!(decode_tile(TileEncoding::Rgb9e5Lz4, &bytes, UVec2::new(0, 60)).is_err());
+        assert!(decode_tile(
+            TileEncoding::Rgb9e5Lz4,
+            &bytes[..bytes.len() / 2],
+            UVec2::new(40, 60)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,549 @@
+//! Runs a `Host` and `Node`s in a single process over localhost. Nodes run a mock renderer painting a deterministic pattern per block,
+//! which the frames assembled by the host have to match byte for byte, also when pixel packets are dropped and reordered.
+
+use appearance_packing::PackedRgb9e5;
+use appearance_render_loop::{
+    auth::PreSharedKey,
+    fault_injection::FaultInjection,
+    host::{
+        block_count, Host, NodeCapabilities, NodeStatus, RenderScheduling, RendererKind,
+        MAX_MISSED_DEADLINES, RENDER_BLOCK_SIZE,
+    },
+    node::{Node, NodeRenderer},
+    tile_codec::{tonemap, TileEncoding},
+};
+use appearance_world::visible_world_action::VisibleWorldActionType;
+use core::{
+    cell::RefCell,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use glam::{UVec2, Vec3};
+use std::{sync::Arc, thread, time::Instant};
+
+const WIDTH: u32 = 4 * RENDER_BLOCK_SIZE;
+const HEIGHT: u32 = 3 * RENDER_BLOCK_SIZE;
+const PIXELS_PER_BLOCK: usize = (RENDER_BLOCK_SIZE * RENDER_BLOCK_SIZE) as usize;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Radiance of a pixel, which only depends on its position so every frame looks the same
+fn pattern(block: UVec2, pixel: UVec2) -> PackedRgb9e5 {
+    PackedRgb9e5::new(Vec3::new(
+        (block.x + 1) as f32 / 8.0,
+        (block.y + 1) as f32 / 8.0,
+        (pixel.y * RENDER_BLOCK_SIZE + pixel.x) as f32 / PIXELS_PER_BLOCK as f32,
+    ))
+}
+
+fn block_pattern(block: UVec2) -> Vec<PackedRgb9e5> {
+    (0..RENDER_BLOCK_SIZE)
+        .flat_map(|y| (0..RENDER_BLOCK_SIZE).map(move |x| pattern(block, UVec2::new(x, y))))
+        .collect()
+}
+
+/// Pattern of the blocks in rows `block_start..block_end`, laid out one block after the other like the pixels of a `NodeRenderer`.
+/// Blocks crossing the right edge of `width` are painted whole.
+fn rows_pattern(width: u32, block_start: u32, block_end: u32) -> Vec<PackedRgb9e5> {
+    (block_start..block_end)
+        .flat_map(|y| {
+            (0..width.div_ceil(RENDER_BLOCK_SIZE))
+                .flat_map(move |x| block_pattern(UVec2::new(x, y)))
+        })
+        .collect()
+}
+
+/// Pixels of a single block which lie within `resolution`, the padding of blocks crossing its edge isn't sent by nodes
+fn visible_pixels<T: Copy>(block_pixels: &[T], block: UVec2, resolution: UVec2) -> Vec<T> {
+    let visible = (resolution - block * RENDER_BLOCK_SIZE).min(UVec2::splat(RENDER_BLOCK_SIZE));
+    block_pixels
+        .chunks_exact(RENDER_BLOCK_SIZE as usize)
+        .take(visible.y as usize)
+        .flat_map(|row| &row[..visible.x as usize])
+        .copied()
+        .collect()
+}
+
+/// Pattern of the whole frame at `resolution`
+fn frame_pattern(resolution: UVec2) -> Vec<PackedRgb9e5> {
+    rows_pattern(resolution.x, 0, resolution.y.div_ceil(RENDER_BLOCK_SIZE))
+}
+
+/// Tonemapped frame the host should pass to the render callback, matching how it resolves hdr tiles at an exposure of 1
+fn expected_ldr_frame(resolution: UVec2) -> Vec<[u8; 4]> {
+    frame_pattern(resolution)
+        .iter()
+        .map(|pixel| {
+            let sdr = tonemap(pixel.unpack()) * 255.0;
+            [sdr.x as u8, sdr.y as u8, sdr.z as u8, 255]
+        })
+        .collect()
+}
+
+fn expected_hdr_frame(resolution: UVec2) -> Vec<u32> {
+    bytemuck::cast_vec(frame_pattern(resolution))
+}
+
+/// Renders the pattern as hdr tiles, only hdr tiles are lossless so ldr output isn't supported
+#[derive(Default)]
+struct MockRenderer {
+    /// Rendering blocks while set, like a node which stopped responding
+    stall: Arc<AtomicBool>,
+}
+
+impl NodeRenderer for MockRenderer {
+    fn visible_world_action(&mut self, _action: &VisibleWorldActionType) {}
+
+    fn provide_asset(&mut self, _path: &str, _bytes: &[u8]) {}
+
+    fn capabilities(&self) -> NodeCapabilities {
+        NodeCapabilities {
+            adapter_name: String::from("Mock"),
+            backend: String::from("Mock"),
+            ..NodeCapabilities::new(RendererKind::Cpu)
+        }
+    }
+
+    fn set_hdr_output(&mut self, hdr_output: bool) -> bool {
+        hdr_output
+    }
+
+    fn render<F: FnMut(&[u8])>(
+        &mut self,
+        resolution: UVec2,
+        start_row: u32,
+        end_row: u32,
+        mut result_callback: F,
+    ) {
+        while self.stall.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let pixels = rows_pattern(
+            resolution.x,
+            start_row / RENDER_BLOCK_SIZE,
+            end_row.div_ceil(RENDER_BLOCK_SIZE),
+        );
+        result_callback(bytemuck::cast_slice(&pixels));
+    }
+}
+
+/// Faults injected into the pixel packets of every node, each node uses its own seed
+#[derive(Debug, Clone, Copy)]
+struct Faults {
+    drop_rate: f32,
+    reorder_rate: f32,
+}
+
+/// Start the `i`th node on the ports after the host on `base_port`, it keeps running until the test ends.
+/// Returns the flag which stalls its renderer while set.
+fn spawn_node(
+    base_port: u16,
+    i: u16,
+    faults: Option<Faults>,
+    pre_shared_key: Option<&'static str>,
+) -> Arc<AtomicBool> {
+    let node_port = base_port + 1 + i;
+    let renderer = MockRenderer::default();
+    let stall = renderer.stall.clone();
+    thread::spawn(move || {
+        let host_addr = SocketAddr::from(([127, 0, 0, 1], base_port));
+        let pre_shared_key = pre_shared_key.map(|key| PreSharedKey::new(key).unwrap());
+        let mut node = Node::new(renderer, host_addr, node_port, pre_shared_key).unwrap();
+        if let Some(faults) = faults {
+            node = node.with_fault_injection(FaultInjection::new(
+                faults.drop_rate,
+                faults.reorder_rate,
+                i as u64,
+            ));
+        }
+        node.run();
+    });
+    stall
+}
+
+struct Loopback {
+    host: Host,
+    resolution: UVec2,
+    /// Stall flag of every node, in the order they were spawned
+    stalls: Vec<Arc<AtomicBool>>,
+}
+
+impl Loopback {
+    /// Start a host on `base_port` and `node_count` nodes on the ports after it, then wait until all nodes can be given work
+    fn new(base_port: u16, node_count: u16, faults: Option<Faults>) -> Self {
+        Self::with_pre_shared_key(base_port, node_count, faults, None)
+    }
+
+    /// Same as `new`, with the host and all nodes using the given pre-shared key
+    fn with_pre_shared_key(
+        base_port: u16,
+        node_count: u16,
+        faults: Option<Faults>,
+        pre_shared_key: Option<&'static str>,
+    ) -> Self {
+        Self::start(
+            base_port,
+            node_count,
+            faults,
+            pre_shared_key,
+            UVec2::new(WIDTH, HEIGHT),
+        )
+    }
+
+    /// Same as `new`, rendering at the given resolution
+    fn with_resolution(base_port: u16, node_count: u16, resolution: UVec2) -> Self {
+        Self::start(base_port, node_count, None, None, resolution)
+    }
+
+    fn start(
+        base_port: u16,
+        node_count: u16,
+        faults: Option<Faults>,
+        pre_shared_key: Option<&'static str>,
+        resolution: UVec2,
+    ) -> Self {
+        let mut host = Host::new(
+            base_port,
+            base_port + 1,
+            resolution.x,
+            resolution.y,
+            pre_shared_key.map(|key| PreSharedKey::new(key).unwrap()),
+        )
+        .unwrap();
+        host.set_tile_encoding(TileEncoding::Rgb9e5Lz4);
+
+        let stalls = (0..node_count)
+            .map(|i| spawn_node(base_port, i, faults, pre_shared_key))
+            .collect();
+
+        // Nodes only receive work once they sent their handshake and were sent a snapshot of the (empty) world
+        let start = Instant::now();
+        while host.nodes().iter().filter(|node| node.synced).count() < node_count as usize {
+            assert!(
+                start.elapsed() < CONNECT_TIMEOUT,
+                "Nodes didn't connect within {:?}.",
+                CONNECT_TIMEOUT
+            );
+
+            host.send_snapshot(vec![]);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        Self {
+            host,
+            resolution,
+            stalls,
+        }
+    }
+
+    /// Keep sending the world to nodes which aren't synced until every node can be given work again, like an application does every frame
+    fn wait_until_synced(&mut self) {
+        let start = Instant::now();
+        while !self
+            .host
+            .nodes()
+            .iter()
+            .all(|node| node.synced && node.status == NodeStatus::Ready)
+        {
+            assert!(
+                start.elapsed() < CONNECT_TIMEOUT,
+                "Nodes didn't sync within {:?}.",
+                CONNECT_TIMEOUT
+            );
+
+            self.host.send_snapshot(vec![]);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn resize(&mut self, resolution: UVec2) {
+        self.host.resize(resolution.x, resolution.y);
+        self.resolution = resolution;
+    }
+
+    /// Render frames until the host assembled the entire pattern, returns the number of frames it took.
+    /// Until then every block must either match the pattern or never have arrived at all.
+    /// Only pixels within the resolution are compared, the host keeps whole blocks.
+    fn render_until_complete(&mut self, max_frames: u32) -> u32 {
+        let expected_ldr = expected_ldr_frame(self.resolution);
+        let expected_hdr = expected_hdr_frame(self.resolution);
+        let num_blocks = block_count(self.resolution);
+        // Pixel buffers of the host start out black, blocks which never arrived keep that
+        let empty_hdr: u32 = bytemuck::cast(PackedRgb9e5::new(Vec3::ZERO));
+
+        for frame in 1..=max_frames {
+            let ldr = RefCell::new(vec![]);
+            self.host
+                .render(|pixels| *ldr.borrow_mut() = pixels.to_vec());
+            let ldr: Vec<[u8; 4]> = bytemuck::cast_vec(ldr.into_inner());
+
+            let hdr = RefCell::new(vec![]);
+            self.host.read_hdr_pixels(|pixels| {
+                *hdr.borrow_mut() = bytemuck::cast_slice(pixels).to_vec()
+            });
+            let hdr: Vec<u32> = hdr.into_inner();
+
+            assert_eq!(ldr.len(), expected_ldr.len());
+            assert_eq!(hdr.len(), expected_hdr.len());
+
+            let mut complete = true;
+            for block_idx in 0..num_blocks.element_product() as usize {
+                let block = UVec2::new(
+                    block_idx as u32 % num_blocks.x,
+                    block_idx as u32 / num_blocks.x,
+                );
+                let pixels = block_idx * PIXELS_PER_BLOCK..(block_idx + 1) * PIXELS_PER_BLOCK;
+
+                let ldr_block = visible_pixels(&ldr[pixels.clone()], block, self.resolution);
+                let expected_ldr_block =
+                    visible_pixels(&expected_ldr[pixels.clone()], block, self.resolution);
+                assert!(
+                    ldr_block == expected_ldr_block
+                        || ldr_block.iter().all(|pixel| *pixel == [0; 4]),
+                    "Block {} of frame {} doesn't match the pattern.",
+                    block_idx,
+                    frame
+                );
+
+                let hdr_block = visible_pixels(&hdr[pixels.clone()], block, self.resolution);
+                let expected_hdr_block =
+                    visible_pixels(&expected_hdr[pixels], block, self.resolution);
+                assert!(
+                    hdr_block == expected_hdr_block
+                        || hdr_block.iter().all(|pixel| *pixel == empty_hdr),
+                    "Hdr block {} of frame {} doesn't match the pattern.",
+                    block_idx,
+                    frame
+                );
+
+                complete &= ldr_block == expected_ldr_block && hdr_block == expected_hdr_block;
+            }
+
+            if complete {
+                return frame;
+            }
+        }
+
+        panic!(
+            "The host didn't assemble the complete frame within {} frames.",
+            max_frames
+        );
+    }
+}
+
+#[test]
+fn single_node_rows() {
+    let mut loopback = Loopback::new(41000, 1, None);
+    loopback.render_until_complete(8);
+}
+
+#[test]
+fn multiple_nodes_rows() {
+    let mut loopback = Loopback::new(41010, 3, None);
+    loopback.render_until_complete(8);
+}
+
+#[test]
+fn multiple_nodes_tiles() {
+    let mut loopback = Loopback::new(41020, 3, None);
+    loopback.host.set_scheduling(RenderScheduling::Tiles {
+        blocks_per_request: 2,
+    });
+    loopback.render_until_complete(8);
+}
+
+#[test]
+fn packet_loss_and_reordering_rows() {
+    let mut loopback = Loopback::new(
+        41030,
+        2,
+        Some(Faults {
+            drop_rate: 0.2,
+            reorder_rate: 0.2,
+        }),
+    );
+    loopback.render_until_complete(64);
+}
+
+#[test]
+fn packet_loss_and_reordering_tiles() {
+    let mut loopback = Loopback::new(
+        41040,
+        2,
+        Some(Faults {
+            drop_rate: 0.2,
+            reorder_rate: 0.2,
+        }),
+    );
+    loopback.host.set_scheduling(RenderScheduling::Tiles {
+        blocks_per_request: 3,
+    });
+    loopback.render_until_complete(64);
+}
+
+#[test]
+fn accumulation_of_static_view() {
+    let mut loopback = Loopback::new(41050, 3, None);
+    loopback.host.set_accumulation_delay(Some(2));
+    loopback.render_until_complete(8);
+
+    // Nodes render identical samples, so the averaged image has to keep matching the pattern while samples add up
+    let start = Instant::now();
+    while loopback.host.accumulated_samples() < 6 {
+        assert!(
+            start.elapsed() < CONNECT_TIMEOUT,
+            "Nodes didn't accumulate enough samples within {:?}.",
+            CONNECT_TIMEOUT
+        );
+
+        loopback.render_until_complete(8);
+    }
+    assert!(loopback.host.is_accumulating());
+}
+
+#[test]
+fn network_stats() {
+    let mut loopback = Loopback::new(41060, 2, None);
+    loopback.render_until_complete(8);
+
+    // Pongs are answered between frames, so keep rendering until every node's round trip has been measured
+    let start = Instant::now();
+    while loopback
+        .host
+        .nodes()
+        .iter()
+        .any(|node| node.stats.round_trip.is_none())
+    {
+        assert!(
+            start.elapsed() < CONNECT_TIMEOUT,
+            "Round trips weren't measured within {:?}.",
+            CONNECT_TIMEOUT
+        );
+
+        loopback.render_until_complete(8);
+    }
+
+    for node in loopback.host.nodes() {
+        assert!(node.stats.packets_sent > 0 && node.stats.bytes_sent > 0);
+        assert!(node.stats.packets_received > 0 && node.stats.bytes_received > 0);
+        assert_eq!(node.stats.packets_dropped, 0);
+    }
+}
+
+#[test]
+fn authenticated_nodes() {
+    let mut loopback = Loopback::with_pre_shared_key(
+        41070,
+        2,
+        Some(Faults {
+            drop_rate: 0.2,
+            reorder_rate: 0.2,
+        }),
+        Some("venue"),
+    );
+    loopback.render_until_complete(64);
+
+    for node in loopback.host.nodes() {
+        assert_eq!(node.stats.packets_unauthenticated, 0);
+    }
+}
+
+#[test]
+fn unauthenticated_nodes() {
+    let mut host = Host::new(
+        41080,
+        41081,
+        WIDTH,
+        HEIGHT,
+        Some(PreSharedKey::new("venue").unwrap()),
+    )
+    .unwrap();
+    spawn_node(41080, 0, None, None);
+    spawn_node(41080, 1, None, Some("not the venue"));
+
+    // The node with the wrong key answers the challenge with a handshake the host has to drop
+    let start = Instant::now();
+    while !host
+        .nodes()
+        .iter()
+        .any(|node| node.stats.packets_unauthenticated > 0)
+    {
+        assert!(
+            start.elapsed() < CONNECT_TIMEOUT,
+            "No unauthenticated packets were dropped within {:?}.",
+            CONNECT_TIMEOUT
+        );
+
+        host.send_snapshot(vec![]);
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // Neither node is ever sent the world or given any work
+    for _ in 0..50 {
+        host.send_snapshot(vec![]);
+        thread::sleep(Duration::from_millis(10));
+    }
+    for node in host.nodes() {
+        assert!(!node.synced);
+        assert_ne!(node.status, NodeStatus::Ready);
+    }
+}
+
+#[test]
+fn resolution_not_multiple_of_block_size_rows() {
+    let mut loopback = Loopback::with_resolution(41090, 2, UVec2::new(WIDTH - 17, HEIGHT + 5));
+    loopback.render_until_complete(8);
+
+    // Windows are resized freely, the nodes have to follow along
+    loopback.resize(UVec2::new(WIDTH + 31, HEIGHT - 40));
+    loopback.render_until_complete(8);
+}
+
+#[test]
+fn resolution_not_multiple_of_block_size_tiles() {
+    let mut loopback = Loopback::with_resolution(41100, 3, UVec2::new(WIDTH + 1, HEIGHT - 1));
+    loopback.host.set_scheduling(RenderScheduling::Tiles {
+        blocks_per_request: 2,
+    });
+    loopback.render_until_complete(8);
+
+    loopback.resize(UVec2::new(RENDER_BLOCK_SIZE / 2, RENDER_BLOCK_SIZE / 3));
+    loopback.render_until_complete(8);
+}
+
+fn stalled_node_is_evicted_and_recovers(base_port: u16, pre_shared_key: Option<&'static str>) {
+    let mut loopback = Loopback::with_pre_shared_key(base_port, 2, None, pre_shared_key);
+    loopback.host.set_frame_deadline(Duration::from_millis(50));
+    loopback.render_until_complete(8);
+
+    // The other node takes over the work of the stalled node, so frames keep completing
+    loopback.stalls[0].store(true, Ordering::SeqCst);
+    let mut evicted = vec![];
+    for _ in 0..MAX_MISSED_DEADLINES * 4 {
+        evicted.extend(loopback.host.render(|_| {}).evicted_nodes);
+        if !evicted.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(evicted.len(), 1, "The stalled node wasn't evicted.");
+    let node = loopback.host.node(evicted[0]).unwrap();
+    assert!(!node.synced);
+    assert_eq!(node.status, NodeStatus::AwaitingHandshake);
+
+    // Once it responds again it registers with a new handshake and is given work again
+    loopback.stalls[0].store(false, Ordering::SeqCst);
+    loopback.wait_until_synced();
+    assert_eq!(loopback.host.nodes().len(), 2);
+    loopback.render_until_complete(8);
+}
+
+#[test]
+fn stalled_node_recovers() {
+    stalled_node_is_evicted_and_recovers(41110, None);
+}
+
+#[test]
+fn stalled_authenticated_node_recovers() {
+    stalled_node_is_evicted_and_recovers(41120, Some("venue"));
+}
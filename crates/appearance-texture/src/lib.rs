@@ -124,8 +124,15 @@ impl Texture {
         let pixel_id = (id.y * self.width + id.x) as usize;
 
         let mut result = Vec4::ONE;
-        for i in 0..self.format.num_channels() {
-            result[i] = self.data[pixel_id * self.format.num_channels() + i] as f32 / 255.0;
+        if self.format == TextureFormat::Rgba32Float {
+            for i in 0..self.format.num_channels() {
+                let offset = (pixel_id * self.format.num_channels() + i) * size_of::<f32>();
+                result[i] = bytemuck::pod_read_unaligned(&self.data[offset..offset + 4]);
+            }
+        } else {
+            for i in 0..self.format.num_channels() {
+                result[i] = self.data[pixel_id * self.format.num_channels() + i] as f32 / 255.0;
+            }
         }
         result
     }
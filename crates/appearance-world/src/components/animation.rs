@@ -0,0 +1,74 @@
+use appearance_transform::Transform;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::visible_world_action::{AnimationData, VisibleWorldAction, VisibleWorldActionType};
+
+use super::Component;
+
+/// Plays an animation clip of the entity's model, render nodes sample the clip at `time` to pose the model's nodes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationComponent {
+    /// Index of the clip in the animations of the model
+    pub clip: u32,
+    /// Playback position in seconds
+    pub time: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub playing: bool,
+}
+
+impl Default for AnimationComponent {
+    fn default() -> Self {
+        Self {
+            clip: 0,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+        }
+    }
+}
+
+impl AnimationComponent {
+    pub fn new(clip: u32) -> Self {
+        Self {
+            clip,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Advance the playback position when playing
+    pub fn advance(&mut self, delta_time: f32) {
+        if self.playing {
+            self.time += delta_time * self.speed;
+        }
+    }
+}
+
+impl Component for AnimationComponent {
+    fn visible_world_actions(
+        &self,
+        _transform: &Transform,
+        entity_uuid: Uuid,
+        visible_world_actions: &mut Vec<VisibleWorldAction>,
+    ) {
+        visible_world_actions.push(VisibleWorldAction::new(
+            VisibleWorldActionType::AnimateModel(AnimationData::new(entity_uuid, self)),
+        ));
+    }
+}
+
+impl specs::Component for AnimationComponent {
+    type Storage = specs::VecStorage<Self>;
+}
@@ -0,0 +1,84 @@
+use appearance_transform::Transform;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::visible_world_action::{LightData, VisibleWorldAction, VisibleWorldActionType};
+
+use super::Component;
+
+/// Shape of a light, oriented along the forward, right and up vectors of the entity transform
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum LightType {
+    Point,
+    /// Angles in radians, measured from the forward vector to the edge of the cone
+    Spot {
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    Directional,
+    /// One sided rectangle facing forward, extents are in world units
+    Rect {
+        width: f32,
+        height: f32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightComponent {
+    #[serde(flatten)]
+    pub ty: LightType,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl LightComponent {
+    pub fn new(ty: LightType, color: Vec3, intensity: f32) -> Self {
+        Self {
+            ty,
+            color,
+            intensity,
+        }
+    }
+
+    pub fn point(color: Vec3, intensity: f32) -> Self {
+        Self::new(LightType::Point, color, intensity)
+    }
+
+    pub fn spot(color: Vec3, intensity: f32, inner_angle: f32, outer_angle: f32) -> Self {
+        Self::new(
+            LightType::Spot {
+                inner_angle,
+                outer_angle,
+            },
+            color,
+            intensity,
+        )
+    }
+
+    pub fn directional(color: Vec3, intensity: f32) -> Self {
+        Self::new(LightType::Directional, color, intensity)
+    }
+
+    pub fn rect(color: Vec3, intensity: f32, width: f32, height: f32) -> Self {
+        Self::new(LightType::Rect { width, height }, color, intensity)
+    }
+}
+
+impl Component for LightComponent {
+    fn visible_world_actions(
+        &self,
+        transform: &Transform,
+        entity_uuid: Uuid,
+        visible_world_actions: &mut Vec<VisibleWorldAction>,
+    ) {
+        visible_world_actions.push(VisibleWorldAction::new(VisibleWorldActionType::SpawnLight(
+            LightData::new(transform.get_matrix(), entity_uuid, self),
+        )));
+    }
+}
+
+impl specs::Component for LightComponent {
+    type Storage = specs::VecStorage<Self>;
+}
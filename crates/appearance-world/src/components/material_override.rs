@@ -0,0 +1,74 @@
+use appearance_transform::Transform;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::visible_world_action::{
+    MaterialOverrideData, VisibleWorldAction, VisibleWorldActionType,
+};
+
+use super::Component;
+
+/// Replaces material properties of all materials of an entity's model, fields left at `None` keep the value from the model.
+/// Textures are kept, so an overridden color or emission acts as a tint on top of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaterialOverrideComponent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roughness: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metallic: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmission: Option<f32>,
+}
+
+impl MaterialOverrideComponent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_color(mut self, color: Vec3) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = Some(roughness);
+        self
+    }
+
+    pub fn with_metallic(mut self, metallic: f32) -> Self {
+        self.metallic = Some(metallic);
+        self
+    }
+
+    pub fn with_emission(mut self, emission: Vec3) -> Self {
+        self.emission = Some(emission);
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: f32) -> Self {
+        self.transmission = Some(transmission);
+        self
+    }
+}
+
+impl Component for MaterialOverrideComponent {
+    fn visible_world_actions(
+        &self,
+        _transform: &Transform,
+        entity_uuid: Uuid,
+        visible_world_actions: &mut Vec<VisibleWorldAction>,
+    ) {
+        visible_world_actions.push(VisibleWorldAction::new(
+            VisibleWorldActionType::MaterialOverride(MaterialOverrideData::new(entity_uuid, self)),
+        ));
+    }
+}
+
+impl specs::Component for MaterialOverrideComponent {
+    type Storage = specs::VecStorage<Self>;
+}
@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Result};
+use appearance_model::{
+    material::Material,
+    mesh::{generate_normals, generate_tangents, Mesh},
+    Model,
+};
+use appearance_transform::Transform;
+use glam::{Vec2, Vec3};
+use uuid::Uuid;
+
+use crate::{
+    mesh_stream::split_into_mesh_chunks,
+    visible_world_action::{SpawnMeshData, VisibleWorldAction, VisibleWorldActionType},
+    wire::{WireReader, WireWriter},
+};
+
+use super::Component;
+
+/// Untextured material of a procedural mesh
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct MeshMaterial {
+    pub color: Vec3,
+    pub roughness: f32,
+    pub emission: Vec3,
+    pub metallic: f32,
+    pub transmission: f32,
+}
+
+impl Default for MeshMaterial {
+    fn default() -> Self {
+        let material = Material::default();
+
+        Self {
+            color: material.color,
+            roughness: material.roughness,
+            emission: material.emission,
+            metallic: material.metallic,
+            transmission: material.transmission,
+        }
+    }
+}
+
+/// In-memory geometry rendered like a model, streamed to render nodes instead of being loaded from an asset.
+/// Normals and texture coordinates may be left empty, normals are then generated by the nodes.
+#[derive(Debug, Clone, Default)]
+pub struct MeshComponent {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub tex_coords: Vec<Vec2>,
+    pub indices: Vec<u32>,
+    pub material: MeshMaterial,
+    revision: u32,
+}
+
+impl MeshComponent {
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        tex_coords: Vec<Vec2>,
+        indices: Vec<u32>,
+    ) -> Self {
+        Self {
+            positions,
+            normals,
+            tex_coords,
+            indices,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_material(mut self, material: MeshMaterial) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Incremented every time the geometry is changed through `World::mesh_mut`
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub(crate) fn set_revision(&mut self, revision: u32) {
+        self.revision = revision;
+    }
+
+    /// Make sure all attributes match the vertex count and all indices form triangles within the vertices
+    pub fn validate(&self) -> Result<()> {
+        let vertex_count = self.positions.len();
+
+        if !self.normals.is_empty() && self.normals.len() != vertex_count {
+            return Err(anyhow!(
+                "Mesh has {} normals for {} vertices.",
+                self.normals.len(),
+                vertex_count
+            ));
+        }
+
+        if !self.tex_coords.is_empty() && self.tex_coords.len() != vertex_count {
+            return Err(anyhow!(
+                "Mesh has {} texture coordinates for {} vertices.",
+                self.tex_coords.len(),
+                vertex_count
+            ));
+        }
+
+        if self.indices.len() % 3 != 0 {
+            return Err(anyhow!(
+                "Mesh index count {} isn't a multiple of 3.",
+                self.indices.len()
+            ));
+        }
+
+        if let Some(index) = self
+            .indices
+            .iter()
+            .find(|index| **index as usize >= vertex_count)
+        {
+            return Err(anyhow!(
+                "Mesh index {} is out of range of {} vertices.",
+                index,
+                vertex_count
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Encode the geometry and material, the revision is sent alongside the chunks instead
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = WireWriter::new();
+        writer.write_pod_slice(&self.positions);
+        writer.write_pod_slice(&self.normals);
+        writer.write_pod_slice(&self.tex_coords);
+        writer.write_pod_slice(&self.indices);
+        writer.write_pod(&self.material);
+        writer.into_bytes()
+    }
+
+    /// Decode a mesh encoded by `to_bytes`, fails on malformed data and invalid geometry
+    pub fn from_bytes(bytes: &[u8], revision: u32) -> Result<Self> {
+        let mut reader = WireReader::new(bytes);
+
+        let mesh = Self {
+            positions: reader.read_pod_vec()?,
+            normals: reader.read_pod_vec()?,
+            tex_coords: reader.read_pod_vec()?,
+            indices: reader.read_pod_vec()?,
+            material: reader.read_pod()?,
+            revision,
+        };
+
+        reader.finish()?;
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Build a model with a single node holding this mesh, the mesh must be valid
+    pub fn to_model(&self) -> Model {
+        let normals = if self.normals.is_empty() {
+            generate_normals(&self.positions, &self.indices)
+        } else {
+            self.normals.clone()
+        };
+        let tex_coords = if self.tex_coords.is_empty() {
+            vec![Vec2::ZERO; self.positions.len()]
+        } else {
+            self.tex_coords.clone()
+        };
+        let tangents = generate_tangents(&self.positions, &normals, &tex_coords, &self.indices);
+
+        let material = Material {
+            color: self.material.color,
+            roughness: self.material.roughness,
+            metallic: self.material.metallic,
+            emission: self.material.emission,
+            transmission: self.material.transmission,
+            ..Default::default()
+        };
+
+        let mesh = Mesh::new(
+            self.positions.clone(),
+            normals,
+            tangents,
+            tex_coords,
+            vec![0; self.indices.len() / 3],
+            self.indices.clone(),
+            material.is_opaque,
+            material.is_emissive(),
+        );
+
+        Model::from_mesh(mesh, material)
+    }
+
+    /// Queue the chunks carrying the current geometry to the render nodes
+    pub(crate) fn geometry_visible_world_actions(
+        &self,
+        entity_uuid: Uuid,
+        visible_world_actions: &mut Vec<VisibleWorldAction>,
+    ) {
+        visible_world_actions.extend(
+            split_into_mesh_chunks(entity_uuid, self.revision, &self.to_bytes())
+                .into_iter()
+                .map(|chunk| VisibleWorldAction::new(VisibleWorldActionType::MeshChunk(chunk))),
+        );
+    }
+}
+
+impl Component for MeshComponent {
+    fn visible_world_actions(
+        &self,
+        transform: &Transform,
+        entity_uuid: Uuid,
+        visible_world_actions: &mut Vec<VisibleWorldAction>,
+    ) {
+        visible_world_actions.push(VisibleWorldAction::new(VisibleWorldActionType::SpawnMesh(
+            SpawnMeshData {
+                transform_matrix: transform.get_matrix(),
+                entity_uuid,
+            },
+        )));
+        self.geometry_visible_world_actions(entity_uuid, visible_world_actions);
+    }
+}
+
+impl specs::Component for MeshComponent {
+    type Storage = specs::DenseVecStorage<Self>;
+}
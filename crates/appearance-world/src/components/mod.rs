@@ -1,3 +1,11 @@
+pub mod animation;
+pub use animation::*;
+pub mod light;
+pub use light::*;
+pub mod material_override;
+pub use material_override::*;
+pub mod mesh;
+pub use mesh::*;
 pub mod model;
 use appearance_transform::Transform;
 pub use model::*;
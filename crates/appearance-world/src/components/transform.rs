@@ -7,6 +7,9 @@ pub struct TransformComponent {
     uuid: Uuid,
     pub(crate) marked_for_destroy: bool,
     pub(crate) entity: Option<specs::Entity>,
+    pub(crate) parent: Option<specs::Entity>,
+    pub(crate) children: Vec<specs::Entity>,
+    pub(crate) hierarchy_changed: bool,
 }
 
 impl TransformComponent {
@@ -17,6 +20,9 @@ impl TransformComponent {
             uuid: Uuid::new_v4(),
             marked_for_destroy: false,
             entity: None,
+            parent: None,
+            children: Vec::new(),
+            hierarchy_changed: false,
         }
     }
 
@@ -31,6 +37,14 @@ impl TransformComponent {
     pub fn marked_for_destroy(&self) -> bool {
         self.marked_for_destroy
     }
+
+    pub fn parent(&self) -> Option<specs::Entity> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[specs::Entity] {
+        &self.children
+    }
 }
 
 impl specs::Component for TransformComponent {
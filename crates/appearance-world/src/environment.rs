@@ -0,0 +1,32 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// Sky and sun lighting the world
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+    /// Asset path of the equirectangular sky texture
+    pub sky_texture: String,
+    pub sky_intensity: f32,
+    /// Rotation of the sky texture around the up axis, in radians
+    pub sky_rotation: f32,
+    /// Normalized direction the sun is shining towards
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    /// Angular radius in radians scaled by a magnitude of 10
+    pub sun_size: f32,
+    pub sun_intensity: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            sky_texture: "::evening_road_01_puresky_4k.hdr".to_owned(),
+            sky_intensity: 1.0,
+            sky_rotation: 0.0,
+            sun_direction: Vec3::new(-0.2, -1.0, 0.3).normalize(),
+            sun_color: Vec3::ONE,
+            sun_size: 0.5,
+            sun_intensity: 100.0,
+        }
+    }
+}
@@ -1,18 +1,33 @@
+use std::{path::Path, sync::Mutex};
+
+use anyhow::Result;
 use appearance_camera::Camera;
 use appearance_transform::Transform;
-use components::{Component, ModelComponent, TransformComponent};
-use glam::Vec3;
-use specs::{Builder, Join, WorldExt};
+use components::{
+    AnimationComponent, Component, LightComponent, MaterialOverrideComponent, MeshComponent,
+    ModelComponent, TransformComponent,
+};
+use environment::Environment;
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use raycast::{RaycastHit, RaycastInstanceDesc, RaycastScene};
+use scene::{Scene, SceneCamera, SceneEntity, SceneTransform, SCENE_FORMAT_VERSION};
+use specs::{Builder, Join, LendJoin, WorldExt};
 use uuid::Uuid;
 use visible_world_action::{
-    CameraUpdateData, DestroyModelData, SpawnModelData, TransformModelData, VisibleWorldAction,
-    VisibleWorldActionType,
+    AnimationData, CameraUpdateData, DestroyLightData, DestroyModelData, EnvironmentData,
+    LightData, MaterialOverrideData, SpawnMeshData, SpawnModelData, TransformModelData,
+    VisibleWorldAction, VisibleWorldActionType,
 };
 
 pub use specs;
 
 pub mod components;
+pub mod environment;
+pub mod mesh_stream;
+pub mod raycast;
+pub mod scene;
 pub mod visible_world_action;
+pub mod wire;
 
 pub struct EntityBuilder<'a> {
     visible_world_actions: &'a mut Vec<VisibleWorldAction>,
@@ -63,6 +78,8 @@ pub struct World {
     ecs: specs::World,
     entities_marked_for_destroy: Vec<specs::Entity>,
     camera: Camera,
+    environment: Environment,
+    raycast_scene: Mutex<RaycastScene>,
 
     visible_world_actions: Option<Vec<VisibleWorldAction>>,
 }
@@ -76,6 +93,10 @@ impl Default for World {
 impl World {
     pub fn new() -> Self {
         let mut ecs = specs::World::new();
+        ecs.register::<AnimationComponent>();
+        ecs.register::<LightComponent>();
+        ecs.register::<MaterialOverrideComponent>();
+        ecs.register::<MeshComponent>();
         ecs.register::<ModelComponent>();
         ecs.register::<TransformComponent>();
 
@@ -89,6 +110,8 @@ impl World {
                 100.0,
                 1.0,
             ),
+            environment: Environment::default(),
+            raycast_scene: Mutex::new(RaycastScene::new()),
             visible_world_actions: Some(Vec::new()),
         }
     }
@@ -122,14 +145,133 @@ impl World {
         entity
     }
 
+    /// Destroy an entity along with all of its descendants
     pub fn destroy_entity(&mut self, entity: specs::Entity) {
         appearance_profiling::profile_function!();
 
-        self.entities_mut::<TransformComponent>()
-            .get_mut(entity)
-            .unwrap()
-            .marked_for_destroy = true;
-        self.entities_marked_for_destroy.push(entity);
+        let mut transform = self.ecs.write_storage::<TransformComponent>();
+
+        let Some(transform_component) = transform.get(entity) else {
+            log::warn!("Can't destroy non existing entity {:?}.", entity);
+            return;
+        };
+
+        if let Some(parent) = transform_component.parent {
+            if let Some(parent_component) = transform.get_mut(parent) {
+                parent_component.children.retain(|child| *child != entity);
+            }
+        }
+
+        let mut stack = vec![entity];
+        while let Some(entity) = stack.pop() {
+            let Some(transform_component) = transform.get_mut(entity) else {
+                continue;
+            };
+            if transform_component.marked_for_destroy {
+                continue;
+            }
+
+            transform_component.marked_for_destroy = true;
+            stack.extend_from_slice(&transform_component.children);
+            self.entities_marked_for_destroy.push(entity);
+        }
+    }
+
+    /// Attach `child` to `parent`, or detach it when `parent` is `None`.
+    /// The transform of the child is kept as is and becomes relative to the new parent.
+    pub fn set_parent(&mut self, child: specs::Entity, parent: Option<specs::Entity>) {
+        appearance_profiling::profile_function!();
+
+        let mut transform = self.ecs.write_storage::<TransformComponent>();
+
+        if let Some(parent) = parent {
+            if transform.get(parent).is_none() {
+                log::warn!(
+                    "Can't parent {:?} to non existing entity {:?}.",
+                    child,
+                    parent
+                );
+                return;
+            }
+
+            let mut ancestor = Some(parent);
+            while let Some(entity) = ancestor {
+                if entity == child {
+                    log::warn!(
+                        "Can't parent {:?} to its own descendant {:?}.",
+                        child,
+                        parent
+                    );
+                    return;
+                }
+                ancestor = transform.get(entity).and_then(|component| component.parent);
+            }
+        }
+
+        let Some(child_component) = transform.get_mut(child) else {
+            log::warn!("Can't parent non existing entity {:?}.", child);
+            return;
+        };
+        let prev_parent = child_component.parent;
+        child_component.parent = parent;
+        child_component.hierarchy_changed = true;
+
+        if let Some(prev_parent) =
+            prev_parent.and_then(|prev_parent| transform.get_mut(prev_parent))
+        {
+            prev_parent.children.retain(|entity| *entity != child);
+        }
+
+        if let Some(parent) = parent.and_then(|parent| transform.get_mut(parent)) {
+            parent.children.push(child);
+        }
+    }
+
+    pub fn parent(&self, entity: specs::Entity) -> Option<specs::Entity> {
+        self.entities::<TransformComponent>()
+            .get(entity)
+            .and_then(|transform_component| transform_component.parent)
+    }
+
+    pub fn children(&self, entity: specs::Entity) -> Vec<specs::Entity> {
+        self.entities::<TransformComponent>()
+            .get(entity)
+            .map(|transform_component| transform_component.children.clone())
+            .unwrap_or_default()
+    }
+
+    /// World space matrix of an entity, combining its transform with the transforms of all its ancestors
+    pub fn world_matrix(&self, entity: specs::Entity) -> Mat4 {
+        Self::world_matrix_from_storage(&self.entities::<TransformComponent>(), entity)
+    }
+
+    fn world_matrix_from_storage(
+        transform: &specs::ReadStorage<'_, TransformComponent>,
+        entity: specs::Entity,
+    ) -> Mat4 {
+        let mut matrix = Mat4::IDENTITY;
+
+        let mut current = Some(entity);
+        while let Some(entity) = current {
+            let Some(transform_component) = transform.get(entity) else {
+                break;
+            };
+
+            matrix = transform_component.transform.get_matrix() * matrix;
+            current = transform_component.parent;
+        }
+
+        matrix
+    }
+
+    /// Find the first entity with the given name which isn't marked for destruction
+    pub fn find_entity(&self, name: &str) -> Option<specs::Entity> {
+        self.entities::<TransformComponent>()
+            .join()
+            .find(|transform_component| {
+                transform_component.entity_name == name && !transform_component.marked_for_destroy
+            })
+            .map(|transform_component| transform_component.entity())
     }
 
     pub fn entities<T: specs::Component>(&self) -> specs::ReadStorage<T> {
@@ -147,82 +289,629 @@ impl World {
     pub fn camera_mut<F: FnMut(&mut Camera)>(&mut self, mut callback: F) {
         callback(&mut self.camera);
 
+        let camera_update_data = self.camera_update_data();
         self.visible_world_actions
             .as_mut()
             .unwrap()
             .push(VisibleWorldAction::new(
-                VisibleWorldActionType::CameraUpdate(CameraUpdateData {
-                    fov: self.camera.get_fov(),
-                    near: self.camera.get_near(),
-                    far: self.camera.get_far(),
-                    transform_matrix_bytes: self.camera.transform.get_matrix(),
-                    _padding: 0,
-                }),
+                VisibleWorldActionType::CameraUpdate(camera_update_data),
             ));
     }
 
-    /// WARNING - This is very expensive!
-    /// Add the current state of all visible elements of the world to the visible world actions.
-    pub fn resync_all_visible_world_actions(&mut self) {
+    fn camera_update_data(&self) -> CameraUpdateData {
+        CameraUpdateData {
+            fov: self.camera.get_fov(),
+            near: self.camera.get_near(),
+            far: self.camera.get_far(),
+            aperture: self.camera.get_aperture(),
+            focus_distance: self.camera.get_focus_distance(),
+            sensor_size: self.camera.get_sensor_size(),
+            blade_count: self.camera.get_blade_count(),
+            transform_matrix_bytes: self.camera.transform.get_matrix(),
+            _padding: 0,
+        }
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Replace the sky and sun, render nodes are notified of the changes
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.visible_world_actions
+            .as_mut()
+            .unwrap()
+            .push(VisibleWorldAction::new(
+                VisibleWorldActionType::SetEnvironment(EnvironmentData::new(&environment)),
+            ));
+
+        self.environment = environment;
+    }
+
+    /// Modify the light of an entity, render nodes are notified of the changes
+    pub fn light_mut<F: FnMut(&mut LightComponent)>(
+        &mut self,
+        entity: specs::Entity,
+        mut callback: F,
+    ) {
+        let (transform, mut light): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::WriteStorage<'_, LightComponent>,
+        ) = self.ecs.system_data();
+
+        let (Some(transform_component), Some(light_component)) =
+            (transform.get(entity), light.get_mut(entity))
+        else {
+            log::warn!("Entity {:?} doesn't have a light.", entity);
+            return;
+        };
+
+        callback(light_component);
+
+        self.visible_world_actions
+            .as_mut()
+            .unwrap()
+            .push(VisibleWorldAction::new(
+                VisibleWorldActionType::UpdateLight(LightData::new(
+                    Self::world_matrix_from_storage(&transform, entity),
+                    *transform_component.uuid(),
+                    light_component,
+                )),
+            ));
+    }
+
+    /// Set or remove the material override of an entity, render nodes are notified of the changes
+    pub fn set_material_override(
+        &mut self,
+        entity: specs::Entity,
+        material_override: Option<MaterialOverrideComponent>,
+    ) {
+        let (transform, mut material_overrides): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::WriteStorage<'_, MaterialOverrideComponent>,
+        ) = self.ecs.system_data();
+
+        let Some(transform_component) = transform.get(entity) else {
+            log::warn!(
+                "Can't override materials of non existing entity {:?}.",
+                entity
+            );
+            return;
+        };
+        let entity_uuid = *transform_component.uuid();
+
+        let data = if let Some(material_override) = material_override {
+            let data = MaterialOverrideData::new(entity_uuid, &material_override);
+            if let Err(err) = material_overrides.insert(entity, material_override) {
+                log::warn!("Can't override materials of entity {:?}: {}", entity, err);
+                return;
+            }
+            data
+        } else {
+            if material_overrides.remove(entity).is_none() {
+                return;
+            }
+            MaterialOverrideData::cleared(entity_uuid)
+        };
+
+        self.visible_world_actions
+            .as_mut()
+            .unwrap()
+            .push(VisibleWorldAction::new(
+                VisibleWorldActionType::MaterialOverride(data),
+            ));
+    }
+
+    /// Modify the animation of an entity, render nodes are notified of the changes
+    pub fn animation_mut<F: FnMut(&mut AnimationComponent)>(
+        &mut self,
+        entity: specs::Entity,
+        mut callback: F,
+    ) {
+        let (transform, mut animation): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::WriteStorage<'_, AnimationComponent>,
+        ) = self.ecs.system_data();
+
+        let (Some(transform_component), Some(animation_component)) =
+            (transform.get(entity), animation.get_mut(entity))
+        else {
+            log::warn!("Entity {:?} doesn't have an animation.", entity);
+            return;
+        };
+
+        callback(animation_component);
+
+        self.visible_world_actions
+            .as_mut()
+            .unwrap()
+            .push(VisibleWorldAction::new(
+                VisibleWorldActionType::AnimateModel(AnimationData::new(
+                    *transform_component.uuid(),
+                    animation_component,
+                )),
+            ));
+    }
+
+    /// Change the geometry of a procedural mesh, render nodes receive the new geometry as a new revision
+    pub fn mesh_mut<F: FnMut(&mut MeshComponent)>(
+        &mut self,
+        entity: specs::Entity,
+        mut callback: F,
+    ) {
+        let (transform, mut mesh): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::WriteStorage<'_, MeshComponent>,
+        ) = self.ecs.system_data();
+
+        let (Some(transform_component), Some(mesh_component)) =
+            (transform.get(entity), mesh.get_mut(entity))
+        else {
+            log::warn!("Entity {:?} doesn't have a mesh.", entity);
+            return;
+        };
+
+        // The callback is free to replace the entire component, the revision has to keep increasing regardless
+        let revision = mesh_component.revision();
+        callback(mesh_component);
+        mesh_component.set_revision(revision.wrapping_add(1));
+
+        mesh_component.geometry_visible_world_actions(
+            *transform_component.uuid(),
+            self.visible_world_actions.as_mut().unwrap(),
+        );
+    }
+
+    /// Advance all playing animations by `delta_time` seconds, render nodes are notified of the new playback positions
+    pub fn advance_animations(&mut self, delta_time: f32) {
         appearance_profiling::profile_function!();
 
-        self.visible_world_actions = Some(vec![VisibleWorldAction::new(
-            VisibleWorldActionType::Clear(0),
-        )]);
+        let (transform, mut animation): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::WriteStorage<'_, AnimationComponent>,
+        ) = self.ecs.system_data();
+
+        let visible_world_actions = self.visible_world_actions.as_mut().unwrap();
 
-        let (transform, model): (
+        for (transform_component, animation_component) in (&transform, &mut animation).join() {
+            if !animation_component.playing || transform_component.marked_for_destroy {
+                continue;
+            }
+
+            animation_component.advance(delta_time);
+
+            visible_world_actions.push(VisibleWorldAction::new(
+                VisibleWorldActionType::AnimateModel(AnimationData::new(
+                    *transform_component.uuid(),
+                    animation_component,
+                )),
+            ));
+        }
+    }
+
+    /// Find the closest model hit by a ray, hits further than `max_t` along the normalized direction are ignored.
+    /// Models are loaded on first use, the acceleration structure is updated whenever any model entity changed.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<RaycastHit> {
+        appearance_profiling::profile_function!();
+
+        let instance_descs = {
+            let (transform, model, animation): (
+                specs::ReadStorage<'_, TransformComponent>,
+                specs::ReadStorage<'_, ModelComponent>,
+                specs::ReadStorage<'_, AnimationComponent>,
+            ) = self.ecs.system_data();
+
+            (&transform, &model, animation.maybe())
+                .join()
+                .filter(|(transform_component, _, _)| !transform_component.marked_for_destroy)
+                .map(
+                    |(transform_component, model_component, animation_component)| {
+                        RaycastInstanceDesc {
+                            entity: transform_component.entity(),
+                            asset_path: model_component.model.clone(),
+                            transform: Self::world_matrix_from_storage(
+                                &transform,
+                                transform_component.entity(),
+                            ),
+                            animation: animation_component.cloned(),
+                        }
+                    },
+                )
+                .collect()
+        };
+
+        let mut raycast_scene = self.raycast_scene.lock().unwrap();
+        raycast_scene.update(instance_descs);
+        raycast_scene.raycast(origin, direction, max_t)
+    }
+
+    /// Raycast from the camera through a point on screen, `screen_uv` goes from (0, 0) at the top left to (1, 1) at the bottom right.
+    /// The aspect ratio of the camera must match the one of the rendered image.
+    pub fn pick(&self, screen_uv: Vec2) -> Option<RaycastHit> {
+        let inv_view = self.camera.transform.get_matrix();
+        let inv_proj = self.camera.get_matrix().inverse();
+
+        let ndc = Vec2::new(screen_uv.x * 2.0 - 1.0, 1.0 - screen_uv.y * 2.0);
+        let origin = inv_view * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let target = inv_proj * Vec4::from((ndc, 1.0, 1.0));
+        let direction = inv_view * Vec4::from((target.xyz().normalize(), 0.0));
+
+        self.raycast(origin.xyz(), direction.xyz(), f32::MAX)
+    }
+
+    /// Capture all entities and the camera in a serializable scene
+    #[allow(clippy::type_complexity)]
+    pub fn scene(&self) -> Scene {
+        let (transform, model, light, material_override, animation): (
             specs::ReadStorage<'_, TransformComponent>,
             specs::ReadStorage<'_, ModelComponent>,
+            specs::ReadStorage<'_, LightComponent>,
+            specs::ReadStorage<'_, MaterialOverrideComponent>,
+            specs::ReadStorage<'_, AnimationComponent>,
         ) = self.ecs.system_data();
 
-        for (transform_component, model_component) in (&transform, &model).join() {
-            self.visible_world_actions
-                .as_mut()
-                .unwrap()
-                .push(VisibleWorldAction::new(VisibleWorldActionType::SpawnModel(
-                    SpawnModelData::new(
-                        transform_component.transform.get_matrix(),
-                        *transform_component.uuid(),
+        let entities = (&transform)
+            .join()
+            .filter(|transform_component| {
+                transform_component.parent.is_none() && !transform_component.marked_for_destroy
+            })
+            .map(|transform_component| {
+                Self::scene_entity(
+                    transform_component.entity(),
+                    &transform,
+                    &model,
+                    &light,
+                    &material_override,
+                    &animation,
+                )
+            })
+            .collect();
+
+        Scene {
+            version: SCENE_FORMAT_VERSION,
+            camera: SceneCamera {
+                transform: SceneTransform::from(&self.camera.transform),
+                fov: self.camera.get_fov(),
+                near: self.camera.get_near(),
+                far: self.camera.get_far(),
+                aperture: self.camera.get_aperture(),
+                focus_distance: self.camera.get_focus_distance(),
+                sensor_size: self.camera.get_sensor_size(),
+                blade_count: self.camera.get_blade_count(),
+            },
+            environment: self.environment.clone(),
+            entities,
+        }
+    }
+
+    fn scene_entity(
+        entity: specs::Entity,
+        transform: &specs::ReadStorage<'_, TransformComponent>,
+        model: &specs::ReadStorage<'_, ModelComponent>,
+        light: &specs::ReadStorage<'_, LightComponent>,
+        material_override: &specs::ReadStorage<'_, MaterialOverrideComponent>,
+        animation: &specs::ReadStorage<'_, AnimationComponent>,
+    ) -> SceneEntity {
+        let transform_component = transform.get(entity).unwrap();
+
+        SceneEntity {
+            name: transform_component.entity_name.clone(),
+            transform: SceneTransform::from(&transform_component.transform),
+            model: model
+                .get(entity)
+                .map(|model_component| model_component.model.clone()),
+            light: light.get(entity).cloned(),
+            material_override: material_override.get(entity).cloned(),
+            animation: animation.get(entity).cloned(),
+            children: transform_component
+                .children
+                .iter()
+                .filter(|child| !transform.get(**child).unwrap().marked_for_destroy)
+                .map(|child| {
+                    Self::scene_entity(
+                        *child,
+                        transform,
+                        model,
+                        light,
+                        material_override,
+                        animation,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Destroy all current entities and replace them with the entities and camera described by the scene
+    pub fn set_scene(&mut self, scene: &Scene) {
+        appearance_profiling::profile_function!();
+
+        let entities: Vec<specs::Entity> = self
+            .entities::<TransformComponent>()
+            .join()
+            .filter(|transform_component| !transform_component.marked_for_destroy)
+            .map(|transform_component| transform_component.entity())
+            .collect();
+        for entity in entities {
+            self.destroy_entity(entity);
+        }
+
+        for entity in &scene.entities {
+            self.create_scene_entity(entity, None);
+        }
+
+        self.camera_mut(|camera| {
+            camera.transform = Transform::from(scene.camera.transform);
+            camera.set_fov(scene.camera.fov);
+            camera.set_near(scene.camera.near);
+            camera.set_far(scene.camera.far);
+            camera.set_aperture(scene.camera.aperture);
+            camera.set_focus_distance(scene.camera.focus_distance);
+            camera.set_sensor_size(scene.camera.sensor_size);
+            camera.set_blade_count(scene.camera.blade_count);
+        });
+        self.set_environment(scene.environment.clone());
+    }
+
+    fn create_scene_entity(&mut self, entity: &SceneEntity, parent: Option<specs::Entity>) {
+        let transform = Transform::from(entity.transform);
+
+        let created_entity = self.create_entity(&entity.name, transform, |mut builder| {
+            if let Some(model) = &entity.model {
+                builder = builder.with(ModelComponent::new(model));
+            }
+            if let Some(light) = &entity.light {
+                builder = builder.with(light.clone());
+            }
+            if let Some(material_override) = &entity.material_override {
+                builder = builder.with(material_override.clone());
+            }
+            if let Some(animation) = &entity.animation {
+                builder = builder.with(animation.clone());
+            }
+            builder
+        });
+
+        if parent.is_some() {
+            self.set_parent(created_entity, parent);
+        }
+
+        for child in &entity.children {
+            self.create_scene_entity(child, Some(created_entity));
+        }
+    }
+
+    pub fn save_scene<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.scene().to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load_scene<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let scene = Scene::from_json(&std::fs::read_to_string(path)?)?;
+        self.set_scene(&scene);
+        Ok(())
+    }
+
+    /// Add the current state of all visible elements of the world to the visible world actions, replacing the actions of this frame.
+    /// Prefer sending `snapshot_visible_world_actions` to just the nodes which need it, as this resyncs every node.
+    pub fn resync_all_visible_world_actions(&mut self) {
+        self.visible_world_actions = Some(self.snapshot_visible_world_actions());
+    }
+
+    /// Actions recreating the current state of all visible elements of the world from scratch, starting with a `Clear`.
+    /// The actions of the current frame are left untouched, so a snapshot can be sent to a late joining node while all other nodes keep receiving incremental actions.
+    /// Call this after `finalize_visible_world_actions`, entities destroyed this frame are left out.
+    #[allow(clippy::type_complexity)]
+    pub fn snapshot_visible_world_actions(&self) -> Vec<VisibleWorldAction> {
+        appearance_profiling::profile_function!();
+
+        let mut visible_world_actions = vec![
+            VisibleWorldAction::new(VisibleWorldActionType::Clear(0)),
+            VisibleWorldAction::new(VisibleWorldActionType::SetEnvironment(
+                EnvironmentData::new(&self.environment),
+            )),
+            VisibleWorldAction::new(VisibleWorldActionType::CameraUpdate(
+                self.camera_update_data(),
+            )),
+        ];
+
+        let (transform, model, mesh, light, material_override, animation): (
+            specs::ReadStorage<'_, TransformComponent>,
+            specs::ReadStorage<'_, ModelComponent>,
+            specs::ReadStorage<'_, MeshComponent>,
+            specs::ReadStorage<'_, LightComponent>,
+            specs::ReadStorage<'_, MaterialOverrideComponent>,
+            specs::ReadStorage<'_, AnimationComponent>,
+        ) = self.ecs.system_data();
+
+        for (
+            transform_component,
+            model_component,
+            mesh_component,
+            light_component,
+            material_override_component,
+            animation_component,
+        ) in (
+            &transform,
+            model.maybe(),
+            mesh.maybe(),
+            light.maybe(),
+            material_override.maybe(),
+            animation.maybe(),
+        )
+            .join()
+        {
+            if transform_component.marked_for_destroy {
+                continue;
+            }
+
+            let entity_uuid = *transform_component.uuid();
+            let matrix = Self::world_matrix_from_storage(&transform, transform_component.entity());
+
+            if let Some(model_component) = model_component {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::SpawnModel(SpawnModelData::new(
+                        matrix,
+                        entity_uuid,
                         &model_component.model,
-                    ),
-                )));
+                    )),
+                ));
+            }
+
+            if let Some(mesh_component) = mesh_component {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::SpawnMesh(SpawnMeshData {
+                        transform_matrix: matrix,
+                        entity_uuid,
+                    }),
+                ));
+                mesh_component
+                    .geometry_visible_world_actions(entity_uuid, &mut visible_world_actions);
+            }
+
+            if let Some(light_component) = light_component {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::SpawnLight(LightData::new(
+                        matrix,
+                        entity_uuid,
+                        light_component,
+                    )),
+                ));
+            }
+
+            if let Some(material_override_component) = material_override_component {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::MaterialOverride(MaterialOverrideData::new(
+                        entity_uuid,
+                        material_override_component,
+                    )),
+                ));
+            }
+
+            if let Some(animation_component) = animation_component {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::AnimateModel(AnimationData::new(
+                        entity_uuid,
+                        animation_component,
+                    )),
+                ));
+            }
         }
+
+        visible_world_actions
     }
 
     /// Record the final visible world actions which happened somewhere along the current frame. Call this before `get_visible_world_actions` to make sure no actions are missed.
     /// Finalization is not required when doing a resync during the same frame.
+    #[allow(clippy::type_complexity)]
     pub fn finalize_visible_world_actions(&mut self) {
         appearance_profiling::profile_function!();
 
-        let (transform, model): (
-            specs::ReadStorage<'_, TransformComponent>,
+        let (mut transform, model, mesh, light, material_override): (
+            specs::WriteStorage<'_, TransformComponent>,
             specs::ReadStorage<'_, ModelComponent>,
+            specs::ReadStorage<'_, MeshComponent>,
+            specs::ReadStorage<'_, LightComponent>,
+            specs::ReadStorage<'_, MaterialOverrideComponent>,
         ) = self.ecs.system_data();
 
         let visible_world_actions = self.visible_world_actions.as_mut().unwrap();
 
-        for (transform_component, _model_component) in (&transform, &model).join() {
-            if transform_component.marked_for_destroy {
+        for (
+            transform_component,
+            model_component,
+            mesh_component,
+            light_component,
+            material_override_component,
+        ) in (
+            &transform,
+            model.maybe(),
+            mesh.maybe(),
+            light.maybe(),
+            material_override.maybe(),
+        )
+            .join()
+        {
+            if !transform_component.marked_for_destroy {
+                continue;
+            }
+
+            // Procedural meshes are rendered as models by the nodes
+            if model_component.is_some() || mesh_component.is_some() {
                 visible_world_actions.push(VisibleWorldAction::new(
                     VisibleWorldActionType::DestroyModel(DestroyModelData {
                         entity_uuid: *transform_component.uuid(),
                     }),
                 ));
-
-                continue;
             }
 
-            if transform_component
-                .transform
-                .handle_has_changed_this_frame()
-            {
+            if light_component.is_some() {
                 visible_world_actions.push(VisibleWorldAction::new(
-                    VisibleWorldActionType::TransformModel(TransformModelData {
-                        transform_matrix: transform_component.transform.get_matrix(),
+                    VisibleWorldActionType::DestroyLight(DestroyLightData {
                         entity_uuid: *transform_component.uuid(),
                     }),
                 ));
             }
+
+            if material_override_component.is_some() {
+                visible_world_actions.push(VisibleWorldAction::new(
+                    VisibleWorldActionType::MaterialOverride(MaterialOverrideData::cleared(
+                        *transform_component.uuid(),
+                    )),
+                ));
+            }
+        }
+
+        // Walk the hierarchy from the roots down, any change to an ancestor moves the entire subtree
+        let mut stack: Vec<(specs::Entity, Mat4, bool)> = (&transform)
+            .join()
+            .filter(|transform_component| {
+                transform_component.parent.is_none() && !transform_component.marked_for_destroy
+            })
+            .map(|transform_component| (transform_component.entity(), Mat4::IDENTITY, false))
+            .collect();
+
+        while let Some((entity, parent_matrix, parent_changed)) = stack.pop() {
+            let transform_component = transform.get_mut(entity).unwrap();
+            if transform_component.marked_for_destroy {
+                continue;
+            }
+
+            let has_changed = transform_component
+                .transform
+                .handle_has_changed_this_frame()
+                || transform_component.hierarchy_changed
+                || parent_changed;
+            transform_component.hierarchy_changed = false;
+
+            let matrix = parent_matrix * transform_component.transform.get_matrix();
+
+            if has_changed {
+                if model.get(entity).is_some() || mesh.get(entity).is_some() {
+                    visible_world_actions.push(VisibleWorldAction::new(
+                        VisibleWorldActionType::TransformModel(TransformModelData {
+                            transform_matrix: matrix,
+                            entity_uuid: *transform_component.uuid(),
+                        }),
+                    ));
+                }
+
+                if let Some(light_component) = light.get(entity) {
+                    visible_world_actions.push(VisibleWorldAction::new(
+                        VisibleWorldActionType::UpdateLight(LightData::new(
+                            matrix,
+                            *transform_component.uuid(),
+                            light_component,
+                        )),
+                    ));
+                }
+            }
+
+            stack.extend(
+                transform_component
+                    .children
+                    .iter()
+                    .map(|child| (*child, matrix, has_changed)),
+            );
         }
     }
 
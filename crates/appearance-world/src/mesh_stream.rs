@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{components::MeshComponent, visible_world_action::MeshChunkData};
+
+/// Bytes of encoded geometry per chunk, small enough for a chunk to fit a single batch of `MAX_BATCH_SIZE`
+pub const MESH_CHUNK_SIZE: usize = 1024;
+/// Largest number of chunks a mesh may be split into, limits meshes to 256MiB of encoded geometry
+pub const MAX_MESH_CHUNKS: u32 = 256 * 1024;
+
+/// Key of a procedural mesh in the model maps of the renderers, can't collide with asset paths
+pub fn mesh_model_key(entity_uuid: &Uuid) -> String {
+    format!("mesh://{}", entity_uuid)
+}
+
+/// Split an encoded mesh into chunks of at most `MESH_CHUNK_SIZE` bytes, an empty mesh still results in a single chunk
+pub fn split_into_mesh_chunks(
+    entity_uuid: Uuid,
+    revision: u32,
+    bytes: &[u8],
+) -> Vec<MeshChunkData> {
+    let chunk_count = bytes.len().div_ceil(MESH_CHUNK_SIZE).max(1) as u32;
+
+    (0..chunk_count)
+        .map(|chunk_idx| {
+            let start = chunk_idx as usize * MESH_CHUNK_SIZE;
+            let end = (start + MESH_CHUNK_SIZE).min(bytes.len());
+
+            MeshChunkData::new(
+                entity_uuid,
+                revision,
+                chunk_idx,
+                chunk_count,
+                &bytes[start.min(end)..end],
+            )
+        })
+        .collect()
+}
+
+struct PendingMesh {
+    revision: u32,
+    chunks: Vec<Option<Vec<u8>>>,
+    missing_chunks: u32,
+}
+
+impl PendingMesh {
+    fn new(revision: u32, chunk_count: u32) -> Self {
+        Self {
+            revision,
+            chunks: vec![None; chunk_count as usize],
+            missing_chunks: chunk_count,
+        }
+    }
+}
+
+/// Reassembles the chunks of procedural meshes on the render nodes.
+/// Chunks of a newer revision discard an incomplete older revision, chunks of older revisions are ignored.
+#[derive(Default)]
+pub struct MeshStreamReceiver {
+    pending: HashMap<Uuid, PendingMesh>,
+    completed_revisions: HashMap<Uuid, u32>,
+}
+
+impl MeshStreamReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a chunk, returns the decoded mesh once all chunks of its revision have been received
+    pub fn receive(&mut self, chunk: &MeshChunkData) -> Option<Result<MeshComponent>> {
+        if self
+            .completed_revisions
+            .get(&chunk.entity_uuid)
+            .is_some_and(|revision| chunk.revision <= *revision)
+        {
+            return None;
+        }
+
+        let pending = self
+            .pending
+            .entry(chunk.entity_uuid)
+            .or_insert_with(|| PendingMesh::new(chunk.revision, chunk.chunk_count));
+        if chunk.revision < pending.revision {
+            return None;
+        }
+        if chunk.revision > pending.revision || pending.chunks.len() != chunk.chunk_count as usize {
+            *pending = PendingMesh::new(chunk.revision, chunk.chunk_count);
+        }
+
+        let slot = &mut pending.chunks[chunk.chunk_idx as usize];
+        if slot.is_none() {
+            *slot = Some(chunk.bytes().to_vec());
+            pending.missing_chunks -= 1;
+        }
+
+        if pending.missing_chunks > 0 {
+            return None;
+        }
+
+        let pending = self.pending.remove(&chunk.entity_uuid).unwrap();
+        self.completed_revisions
+            .insert(chunk.entity_uuid, pending.revision);
+
+        let bytes: Vec<u8> = pending.chunks.into_iter().flatten().flatten().collect();
+        Some(
+            MeshComponent::from_bytes(&bytes, pending.revision)
+                .map_err(|err| anyhow!("Failed to decode mesh {}: {}", chunk.entity_uuid, err)),
+        )
+    }
+
+    /// Forget a destroyed mesh, including any chunks still in flight
+    pub fn remove(&mut self, entity_uuid: &Uuid) {
+        self.pending.remove(entity_uuid);
+        self.completed_revisions.remove(entity_uuid);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.completed_revisions.clear();
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use appearance_asset_database::{asset_paths::resolve_asset_path, AssetDatabase};
+use appearance_model::{mesh::Mesh, Model};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use tinybvh::{BlasInstance, Bvh, BvhBase, Ray};
+
+use crate::components::AnimationComponent;
+
+/// Closest intersection of a ray with the models in the world
+#[derive(Debug, Clone, Copy)]
+pub struct RaycastHit {
+    pub entity: specs::Entity,
+    /// Distance along the normalized ray direction
+    pub distance: f32,
+    pub position: Vec3,
+    /// Interpolated vertex normal in world space
+    pub normal: Vec3,
+    /// Index into the materials of the entity's model
+    pub material_idx: u32,
+}
+
+/// A model entity as placed in the world, the tlas is only updated when any of these change
+#[derive(Clone, PartialEq)]
+pub(crate) struct RaycastInstanceDesc {
+    pub entity: specs::Entity,
+    pub asset_path: String,
+    pub transform: Mat4,
+    pub animation: Option<AnimationComponent>,
+}
+
+struct RaycastModel {
+    model: Arc<Model>,
+    blasses: Vec<Arc<Bvh>>,
+    // Triangle soup referenced by the blasses, must outlive them
+    _blas_vertices: Vec<Vec<Vec4>>,
+}
+
+impl RaycastModel {
+    fn new(model: Arc<Model>) -> Self {
+        let mut blasses = Vec::with_capacity(model.meshes.len());
+        let mut blas_vertices = Vec::with_capacity(model.meshes.len());
+
+        for mesh in &model.meshes {
+            let vertices = Self::triangle_soup(mesh);
+
+            let mut blas = Bvh::new();
+            blas.build(&vertices);
+
+            blasses.push(Arc::new(blas));
+            blas_vertices.push(vertices);
+        }
+
+        Self {
+            model,
+            blasses,
+            _blas_vertices: blas_vertices,
+        }
+    }
+
+    fn triangle_soup(mesh: &Mesh) -> Vec<Vec4> {
+        mesh.indices
+            .iter()
+            .map(|i| Vec4::from((mesh.packed_vertices[*i as usize].position, 0.0)))
+            .collect()
+    }
+}
+
+/// Blas instance in the tlas, pointing back at the entity and model node it was created for
+struct RaycastInstance {
+    entity: specs::Entity,
+    asset_path: String,
+    node: u32,
+    blas: Arc<Bvh>,
+    transform: Mat4,
+    inv_trans_transform: Mat4,
+}
+
+/// Host-side copy of the world geometry, built the same way as the geometry of the cpu path tracer
+pub(crate) struct RaycastScene {
+    model_assets: AssetDatabase<Model>,
+    models: HashMap<String, RaycastModel>,
+    tlas: Bvh,
+    instance_descs: Vec<RaycastInstanceDesc>,
+    /// Range of `instances` created for each instance desc, empty when its model failed to load
+    instance_ranges: Vec<Range<usize>>,
+    instances: Vec<RaycastInstance>,
+}
+
+impl RaycastScene {
+    pub fn new() -> Self {
+        Self {
+            model_assets: AssetDatabase::new(),
+            models: HashMap::new(),
+            tlas: Bvh::new(),
+            instance_descs: Vec::new(),
+            instance_ranges: Vec::new(),
+            instances: Vec::new(),
+        }
+    }
+
+    /// Update the tlas when the model entities changed since the last update.
+    /// As long as no entities are added, removed or swap their model only the transforms of the changed entities are recomputed,
+    /// the blasses and instances are reused and only the top level over the instance bounds is built again.
+    pub fn update(&mut self, instance_descs: Vec<RaycastInstanceDesc>) {
+        if instance_descs == self.instance_descs {
+            return;
+        }
+
+        appearance_profiling::profile_function!();
+
+        let same_instances = instance_descs.len() == self.instance_descs.len()
+            && instance_descs.iter().zip(&self.instance_descs).all(
+                |(instance_desc, old_instance_desc)| {
+                    instance_desc.entity == old_instance_desc.entity
+                        && instance_desc.asset_path == old_instance_desc.asset_path
+                },
+            );
+
+        if same_instances {
+            for (i, instance_desc) in instance_descs.iter().enumerate() {
+                let range = self.instance_ranges[i].clone();
+                if range.is_empty() || *instance_desc == self.instance_descs[i] {
+                    continue;
+                }
+
+                let model = &self.models[&self.instances[range.start].asset_path];
+                for (instance, (_, transform)) in self.instances[range]
+                    .iter_mut()
+                    .zip(Self::mesh_node_transforms(model, instance_desc))
+                {
+                    instance.transform = transform;
+                    instance.inv_trans_transform = transform.inverse().transpose();
+                }
+            }
+        } else {
+            self.rebuild_instances(&instance_descs);
+        }
+
+        let blas_instances = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(i, instance)| BlasInstance::new(instance.transform, i as u32))
+            .collect::<Vec<_>>();
+        let blasses = self
+            .instances
+            .iter()
+            .map(|instance| instance.blas.clone() as Arc<dyn BvhBase>)
+            .collect();
+
+        self.tlas = Bvh::new();
+        if !blas_instances.is_empty() {
+            self.tlas.build_with_blas_instances(blas_instances, blasses);
+        }
+        self.instance_descs = instance_descs;
+    }
+
+    /// Create the instances of all model nodes with a mesh, loading models on first use
+    fn rebuild_instances(&mut self, instance_descs: &[RaycastInstanceDesc]) {
+        self.instances.clear();
+        self.instance_ranges.clear();
+
+        for instance_desc in instance_descs {
+            let resolved_asset_path = resolve_asset_path(&instance_desc.asset_path, "");
+            let first_instance = self.instances.len();
+
+            if !self.models.contains_key(&resolved_asset_path) {
+                match self.model_assets.get(&resolved_asset_path) {
+                    Ok(model) => {
+                        self.models
+                            .insert(resolved_asset_path.clone(), RaycastModel::new(model));
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            "Failed to load {} for raycasting: {}.",
+                            resolved_asset_path,
+                            err
+                        );
+                        self.instance_ranges.push(first_instance..first_instance);
+                        continue;
+                    }
+                }
+            }
+            let model = &self.models[&resolved_asset_path];
+
+            for (node, transform) in Self::mesh_node_transforms(model, instance_desc) {
+                let mesh_idx = model.model.nodes[node as usize].mesh.unwrap();
+
+                self.instances.push(RaycastInstance {
+                    entity: instance_desc.entity,
+                    asset_path: resolved_asset_path.clone(),
+                    node,
+                    blas: model.blasses[mesh_idx as usize].clone(),
+                    transform,
+                    inv_trans_transform: transform.inverse().transpose(),
+                });
+            }
+            self.instance_ranges
+                .push(first_instance..self.instances.len());
+        }
+    }
+
+    /// World transforms of all nodes with a mesh, always in the same order for the same model
+    fn mesh_node_transforms(
+        model: &RaycastModel,
+        instance_desc: &RaycastInstanceDesc,
+    ) -> Vec<(u32, Mat4)> {
+        let animated_node_transforms = instance_desc.animation.as_ref().and_then(|animation| {
+            model
+                .model
+                .animations
+                .get(animation.clip as usize)
+                .map(|clip| clip.sample(animation.time, animation.looping, &model.model.nodes))
+        });
+
+        let mut mesh_node_transforms = vec![];
+        let mut stack: Vec<(u32, Mat4)> = model
+            .model
+            .root_nodes
+            .iter()
+            .map(|root_node| (*root_node, instance_desc.transform))
+            .collect();
+        while let Some((node, parent_transform)) = stack.pop() {
+            let local_transform = animated_node_transforms.as_ref().map_or_else(
+                || model.model.nodes[node as usize].transform.get_matrix(),
+                |node_transforms| node_transforms[node as usize],
+            );
+            let transform = parent_transform * local_transform;
+
+            if model.model.nodes[node as usize].mesh.is_some() {
+                mesh_node_transforms.push((node, transform));
+            }
+
+            stack.extend(
+                model.model.nodes[node as usize]
+                    .children
+                    .iter()
+                    .map(|child| (*child, transform)),
+            );
+        }
+
+        mesh_node_transforms
+    }
+
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<RaycastHit> {
+        if self.instances.is_empty() {
+            return None;
+        }
+
+        let direction = direction.normalize();
+        let mut ray = Ray::new(origin, direction);
+        self.tlas.intersect(&mut ray);
+
+        let hit = ray.hit;
+        if hit.t == 1e30 || hit.t > max_t {
+            return None;
+        }
+
+        let instance = &self.instances[hit.inst as usize];
+        let model = &self.models[&instance.asset_path].model;
+        let mesh_idx = model.nodes[instance.node as usize].mesh.unwrap();
+        let mesh = &model.meshes[mesh_idx as usize];
+
+        let barycentrics = Vec3::new(1.0 - hit.u - hit.v, hit.u, hit.v);
+
+        let v0 = &mesh.packed_vertices[mesh.indices[(hit.prim * 3) as usize] as usize];
+        let v1 = &mesh.packed_vertices[mesh.indices[(hit.prim * 3 + 1) as usize] as usize];
+        let v2 = &mesh.packed_vertices[mesh.indices[(hit.prim * 3 + 2) as usize] as usize];
+
+        let normal = v0.normal.unpack() * barycentrics.x
+            + v1.normal.unpack() * barycentrics.y
+            + v2.normal.unpack() * barycentrics.z;
+        let normal = (instance.inv_trans_transform * Vec4::from((normal, 0.0)))
+            .xyz()
+            .normalize();
+
+        Some(RaycastHit {
+            entity: instance.entity,
+            distance: hit.t,
+            position: origin + direction * hit.t,
+            normal,
+            material_idx: mesh.triangle_material_indices[hit.prim as usize],
+        })
+    }
+}
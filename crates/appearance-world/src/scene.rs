@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use appearance_camera::Camera;
+use appearance_transform::Transform;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    components::{AnimationComponent, LightComponent, MaterialOverrideComponent},
+    environment::Environment,
+};
+
+/// Version written to newly saved scenes, bump whenever the format changes in a non backwards compatible way
+pub const SCENE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SceneTransform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl From<&Transform> for SceneTransform {
+    fn from(transform: &Transform) -> Self {
+        Self {
+            translation: transform.get_translation(),
+            rotation: transform.get_rotation(),
+            scale: transform.get_scale(),
+        }
+    }
+}
+
+impl From<SceneTransform> for Transform {
+    fn from(transform: SceneTransform) -> Self {
+        Transform::new(transform.translation, transform.rotation, transform.scale)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub transform: SceneTransform,
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    #[serde(default = "SceneCamera::default_focus_distance")]
+    pub focus_distance: f32,
+    #[serde(default = "SceneCamera::default_sensor_size")]
+    pub sensor_size: f32,
+    #[serde(default)]
+    pub blade_count: u32,
+}
+
+impl SceneCamera {
+    fn default_focus_distance() -> f32 {
+        Camera::default().get_focus_distance()
+    }
+
+    fn default_sensor_size() -> f32 {
+        Camera::default().get_sensor_size()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneEntity {
+    pub name: String,
+    pub transform: SceneTransform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<LightComponent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material_override: Option<MaterialOverrideComponent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<AnimationComponent>,
+    /// Entities attached to this entity, their transforms are relative to this entity
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<SceneEntity>,
+}
+
+/// Serializable description of a world, stored as json on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub camera: SceneCamera,
+    #[serde(default)]
+    pub environment: Environment,
+    pub entities: Vec<SceneEntity>,
+}
+
+impl Scene {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let scene: Scene = serde_json::from_str(json)?;
+
+        if scene.version == 0 || scene.version > SCENE_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Unsupported scene version {}, expected at most {}.",
+                scene.version,
+                SCENE_FORMAT_VERSION
+            ));
+        }
+
+        Ok(scene)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
@@ -1,9 +1,16 @@
-use core::str;
-use std::io::Write;
-
-use glam::Mat4;
+use anyhow::{anyhow, Result};
+use appearance_transform::{FORWARD, RIGHT, UP};
+use glam::{Mat4, Vec2, Vec3};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
+use crate::{
+    components::{AnimationComponent, LightComponent, LightType, MaterialOverrideComponent},
+    environment::Environment,
+    mesh_stream::{MAX_MESH_CHUNKS, MESH_CHUNK_SIZE},
+    wire::{WireReader, WireWriter},
+};
+
 #[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
 #[repr(C)]
 pub struct CameraUpdateData {
@@ -11,40 +18,126 @@ pub struct CameraUpdateData {
     pub fov: f32,
     pub near: f32,
     pub far: f32,
+    /// Aperture as f-number, zero for a pinhole camera
+    pub aperture: f32,
+    pub focus_distance: f32,
+    /// Vertical sensor size in millimeters
+    pub sensor_size: f32,
+    pub blade_count: u32,
     pub _padding: u32,
 }
 
-#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
-#[repr(C)]
+#[derive(Debug, Clone)]
 pub struct SpawnModelData {
     pub transform_matrix: Mat4,
     pub entity_uuid: Uuid,
-    asset_path_bytes: [u8; 256],
+    asset_path: String,
 }
 
 impl SpawnModelData {
-    pub fn new(transform_matrix: Mat4, entity_uuid: Uuid, asset_path: &String) -> Self {
-        let mut asset_path_bytes = [0u8; 256];
-        {
-            let mut asset_path_bytes = &mut asset_path_bytes[..];
-            let _ = asset_path_bytes.write(asset_path.as_bytes()).unwrap();
-        }
-
+    pub fn new(transform_matrix: Mat4, entity_uuid: Uuid, asset_path: &str) -> Self {
         Self {
             transform_matrix,
             entity_uuid,
-            asset_path_bytes,
+            asset_path: asset_path.to_owned(),
         }
     }
 
     pub fn asset_path(&self) -> &str {
-        let nul_range_end = self
-            .asset_path_bytes
-            .iter()
-            .position(|&c| c == b'\0')
-            .unwrap_or(self.asset_path_bytes.len());
+        &self.asset_path
+    }
 
-        str::from_utf8(&self.asset_path_bytes[0..nul_range_end]).unwrap()
+    fn write(&self, writer: &mut WireWriter) {
+        writer.write_pod(&self.transform_matrix);
+        writer.write_pod(&self.entity_uuid);
+        writer.write_string(&self.asset_path);
+    }
+
+    fn read(reader: &mut WireReader) -> Result<Self> {
+        Ok(Self {
+            transform_matrix: reader.read_pod()?,
+            entity_uuid: reader.read_pod()?,
+            asset_path: reader.read_string()?,
+        })
+    }
+}
+
+/// Spawn an entity rendering a procedural mesh, its geometry follows in `MeshChunkData` chunks
+#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct SpawnMeshData {
+    pub transform_matrix: Mat4,
+    pub entity_uuid: Uuid,
+}
+
+/// Part of the encoded geometry of a procedural mesh, a new revision replaces the geometry once all of its chunks arrived
+#[derive(Debug, Clone)]
+pub struct MeshChunkData {
+    pub entity_uuid: Uuid,
+    pub revision: u32,
+    pub chunk_idx: u32,
+    pub chunk_count: u32,
+    bytes: Vec<u8>,
+}
+
+impl MeshChunkData {
+    pub fn new(
+        entity_uuid: Uuid,
+        revision: u32,
+        chunk_idx: u32,
+        chunk_count: u32,
+        bytes: &[u8],
+    ) -> Self {
+        Self {
+            entity_uuid,
+            revision,
+            chunk_idx,
+            chunk_count,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn write(&self, writer: &mut WireWriter) {
+        writer.write_pod(&self.entity_uuid);
+        writer.write_u32(self.revision);
+        writer.write_u32(self.chunk_idx);
+        writer.write_u32(self.chunk_count);
+        writer.write_bytes(&self.bytes);
+    }
+
+    fn read(reader: &mut WireReader) -> Result<Self> {
+        let chunk = Self {
+            entity_uuid: reader.read_pod()?,
+            revision: reader.read_u32()?,
+            chunk_idx: reader.read_u32()?,
+            chunk_count: reader.read_u32()?,
+            bytes: reader.read_bytes()?.to_vec(),
+        };
+
+        if chunk.chunk_count == 0
+            || chunk.chunk_count > MAX_MESH_CHUNKS
+            || chunk.chunk_idx >= chunk.chunk_count
+        {
+            return Err(anyhow!(
+                "Invalid mesh chunk {} of {}.",
+                chunk.chunk_idx,
+                chunk.chunk_count
+            ));
+        }
+
+        if chunk.bytes.len() > MESH_CHUNK_SIZE {
+            return Err(anyhow!(
+                "Mesh chunk of {} bytes exceeds the maximum of {} bytes.",
+                chunk.bytes.len(),
+                MESH_CHUNK_SIZE
+            ));
+        }
+
+        Ok(chunk)
     }
 }
 
@@ -61,59 +154,328 @@ pub struct DestroyModelData {
     pub entity_uuid: Uuid,
 }
 
+#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct LightData {
+    pub transform_matrix: Mat4,
+    pub entity_uuid: Uuid,
+    pub color: Vec3,
+    pub intensity: f32,
+    ty: u32,
+    params: Vec2,
+    _padding: u32,
+}
+
+impl LightData {
+    pub fn new(transform_matrix: Mat4, entity_uuid: Uuid, light: &LightComponent) -> Self {
+        let (ty, params) = match light.ty {
+            LightType::Point => (0, Vec2::ZERO),
+            LightType::Spot {
+                inner_angle,
+                outer_angle,
+            } => (1, Vec2::new(inner_angle, outer_angle)),
+            LightType::Directional => (2, Vec2::ZERO),
+            LightType::Rect { width, height } => (3, Vec2::new(width, height)),
+        };
+
+        Self {
+            transform_matrix,
+            entity_uuid,
+            color: light.color,
+            intensity: light.intensity,
+            ty,
+            params,
+            _padding: 0,
+        }
+    }
+
+    pub fn light_type(&self) -> Result<LightType> {
+        Ok(match self.ty {
+            0 => LightType::Point,
+            1 => LightType::Spot {
+                inner_angle: self.params.x,
+                outer_angle: self.params.y,
+            },
+            2 => LightType::Directional,
+            3 => LightType::Rect {
+                width: self.params.x,
+                height: self.params.y,
+            },
+            _ => return Err(anyhow!("Unknown light type {}.", self.ty)),
+        })
+    }
+
+    fn read(reader: &mut WireReader) -> Result<Self> {
+        let light: Self = reader.read_pod()?;
+        light.light_type()?;
+
+        Ok(light)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.transform_matrix.transform_point3(Vec3::ZERO)
+    }
+
+    /// Normalized direction the light is emitting towards
+    pub fn direction(&self) -> Vec3 {
+        self.transform_matrix.transform_vector3(FORWARD).normalize()
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.transform_matrix.transform_vector3(RIGHT).normalize()
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.transform_matrix.transform_vector3(UP).normalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct DestroyLightData {
+    pub entity_uuid: Uuid,
+}
+
+const MATERIAL_OVERRIDE_COLOR: u32 = 1 << 0;
+const MATERIAL_OVERRIDE_ROUGHNESS: u32 = 1 << 1;
+const MATERIAL_OVERRIDE_METALLIC: u32 = 1 << 2;
+const MATERIAL_OVERRIDE_EMISSION: u32 = 1 << 3;
+const MATERIAL_OVERRIDE_TRANSMISSION: u32 = 1 << 4;
+
+/// Material override of an entity, an override without any fields set removes the override
+#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct MaterialOverrideData {
+    pub entity_uuid: Uuid,
+    color: Vec3,
+    roughness: f32,
+    emission: Vec3,
+    metallic: f32,
+    transmission: f32,
+    flags: u32,
+    _padding0: u32,
+    _padding1: u32,
+}
+
+impl MaterialOverrideData {
+    pub fn new(entity_uuid: Uuid, material_override: &MaterialOverrideComponent) -> Self {
+        let flags = [
+            (material_override.color.is_some(), MATERIAL_OVERRIDE_COLOR),
+            (
+                material_override.roughness.is_some(),
+                MATERIAL_OVERRIDE_ROUGHNESS,
+            ),
+            (
+                material_override.metallic.is_some(),
+                MATERIAL_OVERRIDE_METALLIC,
+            ),
+            (
+                material_override.emission.is_some(),
+                MATERIAL_OVERRIDE_EMISSION,
+            ),
+            (
+                material_override.transmission.is_some(),
+                MATERIAL_OVERRIDE_TRANSMISSION,
+            ),
+        ]
+        .into_iter()
+        .filter(|(is_set, _)| *is_set)
+        .fold(0, |flags, (_, bit)| flags | bit);
+
+        Self {
+            entity_uuid,
+            color: material_override.color.unwrap_or_default(),
+            roughness: material_override.roughness.unwrap_or_default(),
+            emission: material_override.emission.unwrap_or_default(),
+            metallic: material_override.metallic.unwrap_or_default(),
+            transmission: material_override.transmission.unwrap_or_default(),
+            flags,
+            _padding0: 0,
+            _padding1: 0,
+        }
+    }
+
+    pub fn cleared(entity_uuid: Uuid) -> Self {
+        Self::new(entity_uuid, &MaterialOverrideComponent::default())
+    }
+
+    pub fn is_cleared(&self) -> bool {
+        self.flags == 0
+    }
+
+    pub fn material_override(&self) -> MaterialOverrideComponent {
+        let field = |bit: u32| self.flags & bit != 0;
+
+        MaterialOverrideComponent {
+            color: field(MATERIAL_OVERRIDE_COLOR).then_some(self.color),
+            roughness: field(MATERIAL_OVERRIDE_ROUGHNESS).then_some(self.roughness),
+            metallic: field(MATERIAL_OVERRIDE_METALLIC).then_some(self.metallic),
+            emission: field(MATERIAL_OVERRIDE_EMISSION).then_some(self.emission),
+            transmission: field(MATERIAL_OVERRIDE_TRANSMISSION).then_some(self.transmission),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvironmentData {
+    sky_texture: String,
+    pub sky_intensity: f32,
+    pub sky_rotation: f32,
+    pub sun_direction: Vec3,
+    pub sun_size: f32,
+    pub sun_color: Vec3,
+    pub sun_intensity: f32,
+}
+
+impl EnvironmentData {
+    pub fn new(environment: &Environment) -> Self {
+        Self {
+            sky_texture: environment.sky_texture.clone(),
+            sky_intensity: environment.sky_intensity,
+            sky_rotation: environment.sky_rotation,
+            sun_direction: environment.sun_direction,
+            sun_size: environment.sun_size,
+            sun_color: environment.sun_color,
+            sun_intensity: environment.sun_intensity,
+        }
+    }
+
+    pub fn sky_texture(&self) -> &str {
+        &self.sky_texture
+    }
+
+    fn write(&self, writer: &mut WireWriter) {
+        writer.write_string(&self.sky_texture);
+        writer.write_pod(&self.sky_intensity);
+        writer.write_pod(&self.sky_rotation);
+        writer.write_pod(&self.sun_direction);
+        writer.write_pod(&self.sun_size);
+        writer.write_pod(&self.sun_color);
+        writer.write_pod(&self.sun_intensity);
+    }
+
+    fn read(reader: &mut WireReader) -> Result<Self> {
+        Ok(Self {
+            sky_texture: reader.read_string()?,
+            sky_intensity: reader.read_pod()?,
+            sky_rotation: reader.read_pod()?,
+            sun_direction: reader.read_pod()?,
+            sun_size: reader.read_pod()?,
+            sun_color: reader.read_pod()?,
+            sun_intensity: reader.read_pod()?,
+        })
+    }
+}
+
+/// Playback state of the animation of a model, render nodes sample the clip themselves
+#[derive(Debug, Clone, Copy, bytemuck::NoUninit, bytemuck::AnyBitPattern)]
+#[repr(C)]
+pub struct AnimationData {
+    pub entity_uuid: Uuid,
+    pub clip: u32,
+    pub time: f32,
+    looping: u32,
+}
+
+impl AnimationData {
+    pub fn new(entity_uuid: Uuid, animation: &AnimationComponent) -> Self {
+        Self {
+            entity_uuid,
+            clip: animation.clip,
+            time: animation.time,
+            looping: animation.looping as u32,
+        }
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping != 0
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum VisibleWorldActionType {
     CameraUpdate(CameraUpdateData),
     SpawnModel(SpawnModelData),
     TransformModel(TransformModelData),
     DestroyModel(DestroyModelData),
     Clear(u32),
+    SpawnLight(LightData),
+    UpdateLight(LightData),
+    DestroyLight(DestroyLightData),
+    MaterialOverride(MaterialOverrideData),
+    SetEnvironment(EnvironmentData),
+    AnimateModel(AnimationData),
+    SpawnMesh(SpawnMeshData),
+    MeshChunk(MeshChunkData),
 }
 
-impl From<VisibleWorldActionType> for u32 {
-    fn from(val: VisibleWorldActionType) -> Self {
+impl From<&VisibleWorldActionType> for u32 {
+    fn from(val: &VisibleWorldActionType) -> Self {
         match val {
             VisibleWorldActionType::CameraUpdate(_) => 0,
             VisibleWorldActionType::SpawnModel(_) => 1,
             VisibleWorldActionType::TransformModel(_) => 2,
             VisibleWorldActionType::DestroyModel(_) => 3,
             VisibleWorldActionType::Clear(_) => 4,
+            VisibleWorldActionType::SpawnLight(_) => 5,
+            VisibleWorldActionType::UpdateLight(_) => 6,
+            VisibleWorldActionType::DestroyLight(_) => 7,
+            VisibleWorldActionType::MaterialOverride(_) => 8,
+            VisibleWorldActionType::SetEnvironment(_) => 9,
+            VisibleWorldActionType::AnimateModel(_) => 10,
+            VisibleWorldActionType::SpawnMesh(_) => 11,
+            VisibleWorldActionType::MeshChunk(_) => 12,
         }
     }
 }
 
 impl VisibleWorldActionType {
-    pub fn from_ty_and_bytes(ty: u32, bytes: &[u8]) -> Self {
-        match ty {
-            0 => Self::CameraUpdate(*bytemuck::from_bytes::<CameraUpdateData>(bytes)),
-            1 => Self::SpawnModel(*bytemuck::from_bytes::<SpawnModelData>(bytes)),
-            2 => Self::TransformModel(*bytemuck::from_bytes::<TransformModelData>(bytes)),
-            3 => Self::DestroyModel(*bytemuck::from_bytes::<DestroyModelData>(bytes)),
-            4 => Self::Clear(*bytemuck::from_bytes::<u32>(bytes)),
-            _ => panic!(),
-        }
-    }
+    /// Decode an action encoded by `to_bytes`, fails on unknown types and malformed data
+    pub fn from_ty_and_bytes(ty: u32, bytes: &[u8]) -> Result<Self> {
+        let mut reader = WireReader::new(bytes);
 
-    pub fn data_size_from_ty(ty: u32) -> usize {
-        match ty {
-            0 => std::mem::size_of::<CameraUpdateData>(),
-            1 => std::mem::size_of::<SpawnModelData>(),
-            2 => std::mem::size_of::<TransformModelData>(),
-            3 => std::mem::size_of::<DestroyModelData>(),
-            4 => std::mem::size_of::<u32>(),
-            _ => panic!(),
-        }
+        let action = match ty {
+            0 => Self::CameraUpdate(reader.read_pod()?),
+            1 => Self::SpawnModel(SpawnModelData::read(&mut reader)?),
+            2 => Self::TransformModel(reader.read_pod()?),
+            3 => Self::DestroyModel(reader.read_pod()?),
+            4 => Self::Clear(reader.read_pod()?),
+            5 => Self::SpawnLight(LightData::read(&mut reader)?),
+            6 => Self::UpdateLight(LightData::read(&mut reader)?),
+            7 => Self::DestroyLight(reader.read_pod()?),
+            8 => Self::MaterialOverride(reader.read_pod()?),
+            9 => Self::SetEnvironment(EnvironmentData::read(&mut reader)?),
+            10 => Self::AnimateModel(reader.read_pod()?),
+            11 => Self::SpawnMesh(reader.read_pod()?),
+            12 => Self::MeshChunk(MeshChunkData::read(&mut reader)?),
+            _ => return Err(anyhow!("Unknown visible world action type {}.", ty)),
+        };
+
+        reader.finish()?;
+        Ok(action)
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = WireWriter::new();
+
         match &self {
-            Self::CameraUpdate(data) => bytemuck::bytes_of(data),
-            Self::SpawnModel(data) => bytemuck::bytes_of(data),
-            Self::TransformModel(data) => bytemuck::bytes_of(data),
-            Self::DestroyModel(data) => bytemuck::bytes_of(data),
-            Self::Clear(data) => bytemuck::bytes_of(data),
+            Self::CameraUpdate(data) => writer.write_pod(data),
+            Self::SpawnModel(data) => data.write(&mut writer),
+            Self::TransformModel(data) => writer.write_pod(data),
+            Self::DestroyModel(data) => writer.write_pod(data),
+            Self::Clear(data) => writer.write_pod(data),
+            Self::SpawnLight(data) => writer.write_pod(data),
+            Self::UpdateLight(data) => writer.write_pod(data),
+            Self::DestroyLight(data) => writer.write_pod(data),
+            Self::MaterialOverride(data) => writer.write_pod(data),
+            Self::SetEnvironment(data) => data.write(&mut writer),
+            Self::AnimateModel(data) => writer.write_pod(data),
+            Self::SpawnMesh(data) => writer.write_pod(data),
+            Self::MeshChunk(data) => data.write(&mut writer),
         }
+
+        writer.into_bytes()
     }
 
     pub fn must_sync(&self) -> bool {
@@ -123,8 +485,53 @@ impl VisibleWorldActionType {
             Self::TransformModel(_) => false,
             Self::DestroyModel(_) => true,
             Self::Clear(_) => true,
+            Self::SpawnLight(_) => true,
+            Self::UpdateLight(_) => false,
+            Self::DestroyLight(_) => true,
+            Self::MaterialOverride(_) => true,
+            Self::SetEnvironment(_) => true,
+            Self::AnimateModel(_) => false,
+            Self::SpawnMesh(_) => true,
+            Self::MeshChunk(_) => true,
+        }
+    }
+
+    /// Uuid of the entity this action applies to, `None` for world wide actions
+    pub fn entity_uuid(&self) -> Option<Uuid> {
+        match self {
+            Self::CameraUpdate(_) => None,
+            Self::SpawnModel(data) => Some(data.entity_uuid),
+            Self::TransformModel(data) => Some(data.entity_uuid),
+            Self::DestroyModel(data) => Some(data.entity_uuid),
+            Self::Clear(_) => None,
+            Self::SpawnLight(data) => Some(data.entity_uuid),
+            Self::UpdateLight(data) => Some(data.entity_uuid),
+            Self::DestroyLight(data) => Some(data.entity_uuid),
+            Self::MaterialOverride(data) => Some(data.entity_uuid),
+            Self::SetEnvironment(_) => None,
+            Self::AnimateModel(data) => Some(data.entity_uuid),
+            Self::SpawnMesh(data) => Some(data.entity_uuid),
+            Self::MeshChunk(data) => Some(data.entity_uuid),
         }
     }
+
+    fn is_light(&self) -> bool {
+        matches!(
+            self,
+            Self::SpawnLight(_) | Self::UpdateLight(_) | Self::DestroyLight(_)
+        )
+    }
+}
+
+/// State which is fully replaced by every action of the same key, so only the last action per key matters
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum CoalesceKey {
+    Camera,
+    Environment,
+    Transform(Uuid),
+    Light(Uuid),
+    MaterialOverride(Uuid),
+    Animation(Uuid),
 }
 
 /// A visible action in the world, used to notify render nodes how the world changes
@@ -136,8 +543,8 @@ pub struct VisibleWorldAction {
 
 impl VisibleWorldAction {
     pub fn new(action: VisibleWorldActionType) -> Self {
-        let ty = action.into();
-        let data = action.as_bytes().to_vec();
+        let ty = (&action).into();
+        let data = action.to_bytes();
 
         Self {
             ty,
@@ -145,4 +552,268 @@ impl VisibleWorldAction {
             must_sync: action.must_sync(),
         }
     }
+
+    /// Remove actions made redundant by later actions in the same list, without changing the resulting world.
+    /// Only the last camera, environment, transform, light update, material override, animation and mesh revision are kept,
+    /// entities spawned and destroyed within the list are dropped entirely. Actions which fail to decode are kept as is.
+    pub fn coalesce(actions: Vec<Self>) -> Vec<Self> {
+        let decoded: Vec<Option<VisibleWorldActionType>> = actions
+            .iter()
+            .map(|action| VisibleWorldActionType::from_ty_and_bytes(action.ty, &action.data).ok())
+            .collect();
+        let mut keep = vec![true; actions.len()];
+
+        let mut spawned_models = HashMap::new();
+        let mut spawned_lights = HashMap::new();
+        for (i, action) in decoded.iter().enumerate() {
+            let cancelled = match action {
+                Some(VisibleWorldActionType::SpawnModel(SpawnModelData {
+                    entity_uuid, ..
+                }))
+                | Some(VisibleWorldActionType::SpawnMesh(SpawnMeshData { entity_uuid, .. })) => {
+                    spawned_models.insert(*entity_uuid, i);
+                    None
+                }
+                Some(VisibleWorldActionType::DestroyModel(data)) => spawned_models
+                    .remove(&data.entity_uuid)
+                    .map(|start| (start, data.entity_uuid, false)),
+                Some(VisibleWorldActionType::SpawnLight(data)) => {
+                    spawned_lights.insert(data.entity_uuid, i);
+                    None
+                }
+                Some(VisibleWorldActionType::DestroyLight(data)) => spawned_lights
+                    .remove(&data.entity_uuid)
+                    .map(|start| (start, data.entity_uuid, true)),
+                Some(VisibleWorldActionType::Clear(_)) => {
+                    spawned_models.clear();
+                    spawned_lights.clear();
+                    None
+                }
+                _ => None,
+            };
+
+            // Everything which happened to the entity between its spawn and destroy is invisible to the nodes
+            if let Some((start, entity_uuid, is_light)) = cancelled {
+                for (keep, action) in keep[start..=i].iter_mut().zip(&decoded[start..=i]) {
+                    if let Some(action) = action {
+                        if action.entity_uuid() == Some(entity_uuid)
+                            && action.is_light() == is_light
+                        {
+                            *keep = false;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Walk backwards, so the first action seen for a key is the one that sticks
+        let mut superseded = HashSet::new();
+        let mut latest_mesh_revisions = HashMap::new();
+        for (i, action) in decoded.iter().enumerate().rev() {
+            if !keep[i] {
+                continue;
+            }
+
+            let key = match action {
+                Some(VisibleWorldActionType::CameraUpdate(_)) => CoalesceKey::Camera,
+                Some(VisibleWorldActionType::SetEnvironment(_)) => CoalesceKey::Environment,
+                Some(VisibleWorldActionType::TransformModel(data)) => {
+                    CoalesceKey::Transform(data.entity_uuid)
+                }
+                Some(VisibleWorldActionType::UpdateLight(data)) => {
+                    CoalesceKey::Light(data.entity_uuid)
+                }
+                Some(VisibleWorldActionType::MaterialOverride(data)) => {
+                    CoalesceKey::MaterialOverride(data.entity_uuid)
+                }
+                Some(VisibleWorldActionType::AnimateModel(data)) => {
+                    CoalesceKey::Animation(data.entity_uuid)
+                }
+                Some(VisibleWorldActionType::MeshChunk(data)) => {
+                    // Chunks of a revision replaced later on would be discarded by the nodes anyway
+                    let latest_revision = *latest_mesh_revisions
+                        .entry(data.entity_uuid)
+                        .or_insert(data.revision);
+                    if data.revision != latest_revision {
+                        keep[i] = false;
+                    }
+                    continue;
+                }
+                Some(VisibleWorldActionType::SpawnModel(SpawnModelData {
+                    entity_uuid, ..
+                }))
+                | Some(VisibleWorldActionType::SpawnMesh(SpawnMeshData { entity_uuid, .. }))
+                | Some(VisibleWorldActionType::DestroyModel(DestroyModelData { entity_uuid })) => {
+                    latest_mesh_revisions.remove(entity_uuid);
+                    superseded.remove(&CoalesceKey::Transform(*entity_uuid));
+                    superseded.remove(&CoalesceKey::MaterialOverride(*entity_uuid));
+                    superseded.remove(&CoalesceKey::Animation(*entity_uuid));
+                    continue;
+                }
+                Some(VisibleWorldActionType::SpawnLight(LightData { entity_uuid, .. }))
+                | Some(VisibleWorldActionType::DestroyLight(DestroyLightData { entity_uuid })) => {
+                    superseded.remove(&CoalesceKey::Light(*entity_uuid));
+                    continue;
+                }
+                Some(VisibleWorldActionType::Clear(_)) => {
+                    superseded.clear();
+                    latest_mesh_revisions.clear();
+                    continue;
+                }
+                None => continue,
+            };
+
+            if !superseded.insert(key) {
+                keep[i] = false;
+            }
+        }
+
+        actions
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(action, keep)| keep.then_some(action))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: Uuid = Uuid::from_u128(1);
+    const OTHER_ENTITY: Uuid = Uuid::from_u128(2);
+
+    fn camera(fov: f32) -> VisibleWorldAction {
+        VisibleWorldAction::new(VisibleWorldActionType::CameraUpdate(CameraUpdateData {
+            transform_matrix_bytes: Mat4::IDENTITY,
+            fov,
+            ..bytemuck::Zeroable::zeroed()
+        }))
+    }
+
+    fn spawn_model(entity_uuid: Uuid) -> VisibleWorldAction {
+        VisibleWorldAction::new(VisibleWorldActionType::SpawnModel(SpawnModelData::new(
+            Mat4::IDENTITY,
+            entity_uuid,
+            "::models/duck.glb",
+        )))
+    }
+
+    fn transform_model(entity_uuid: Uuid, x: f32) -> VisibleWorldAction {
+        VisibleWorldAction::new(VisibleWorldActionType::TransformModel(TransformModelData {
+            transform_matrix: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            entity_uuid,
+        }))
+    }
+
+    fn destroy_model(entity_uuid: Uuid) -> VisibleWorldAction {
+        VisibleWorldAction::new(VisibleWorldActionType::DestroyModel(DestroyModelData {
+            entity_uuid,
+        }))
+    }
+
+    fn light(entity_uuid: Uuid, intensity: f32) -> LightData {
+        LightData::new(
+            Mat4::IDENTITY,
+            entity_uuid,
+            &LightComponent::point(Vec3::ONE, intensity),
+        )
+    }
+
+    fn encoded(actions: &[VisibleWorldAction]) -> Vec<(u32, Vec<u8>)> {
+        actions
+            .iter()
+            .map(|action| (action.ty, action.data.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn spawn_update_destroy_in_one_frame() {
+        let actions = vec![
+            camera(1.0),
+            spawn_model(ENTITY),
+            transform_model(ENTITY, 1.0),
+            transform_model(OTHER_ENTITY, 1.0),
+            transform_model(ENTITY, 2.0),
+            destroy_model(ENTITY),
+            VisibleWorldAction::new(VisibleWorldActionType::SpawnLight(light(ENTITY, 1.0))),
+            VisibleWorldAction::new(VisibleWorldActionType::UpdateLight(light(ENTITY, 2.0))),
+            VisibleWorldAction::new(VisibleWorldActionType::DestroyLight(DestroyLightData {
+                entity_uuid: ENTITY,
+            })),
+        ];
+
+        assert_eq!(
+            encoded(&VisibleWorldAction::coalesce(actions)),
+            encoded(&[camera(1.0), transform_model(OTHER_ENTITY, 1.0)])
+        );
+    }
+
+    #[test]
+    fn last_write_wins_per_key() {
+        let actions = vec![
+            camera(1.0),
+            transform_model(ENTITY, 1.0),
+            transform_model(OTHER_ENTITY, 1.0),
+            VisibleWorldAction::new(VisibleWorldActionType::UpdateLight(light(ENTITY, 1.0))),
+            camera(2.0),
+            transform_model(ENTITY, 2.0),
+            VisibleWorldAction::new(VisibleWorldActionType::UpdateLight(light(ENTITY, 2.0))),
+        ];
+
+        assert_eq!(
+            encoded(&VisibleWorldAction::coalesce(actions)),
+            encoded(&[
+                transform_model(OTHER_ENTITY, 1.0),
+                camera(2.0),
+                transform_model(ENTITY, 2.0),
+                VisibleWorldAction::new(VisibleWorldActionType::UpdateLight(light(ENTITY, 2.0))),
+            ])
+        );
+    }
+
+    #[test]
+    fn respawned_entity_keeps_its_new_state() {
+        let actions = vec![
+            spawn_model(ENTITY),
+            transform_model(ENTITY, 1.0),
+            destroy_model(ENTITY),
+            spawn_model(ENTITY),
+            transform_model(ENTITY, 2.0),
+        ];
+
+        assert_eq!(
+            encoded(&VisibleWorldAction::coalesce(actions)),
+            encoded(&[spawn_model(ENTITY), transform_model(ENTITY, 2.0)])
+        );
+    }
+
+    #[test]
+    fn updates_around_a_destroy_or_clear_are_kept() {
+        let actions = vec![
+            transform_model(ENTITY, 1.0),
+            destroy_model(ENTITY),
+            transform_model(OTHER_ENTITY, 1.0),
+            VisibleWorldAction::new(VisibleWorldActionType::Clear(0)),
+            transform_model(OTHER_ENTITY, 2.0),
+        ];
+        let expected = encoded(&actions);
+
+        assert_eq!(encoded(&VisibleWorldAction::coalesce(actions)), expected);
+    }
+
+    #[test]
+    fn undecodable_actions_are_kept() {
+        let unknown = || VisibleWorldAction {
+            ty: 99,
+            data: vec![1, 2, 3],
+            must_sync: true,
+        };
+        let actions = vec![unknown(), camera(1.0), unknown(), camera(2.0)];
+
+        assert_eq!(
+            encoded(&VisibleWorldAction::coalesce(actions)),
+            encoded(&[unknown(), unknown(), camera(2.0)])
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, Result};
+use bytemuck::{AnyBitPattern, NoUninit};
+
+/// Upper bound for decoded strings, protects against allocating huge buffers from corrupt lengths
+pub const MAX_WIRE_STRING_LEN: usize = 4096;
+
+/// Little endian binary encoding shared by all messages sent between host and nodes
+#[derive(Default)]
+pub struct WireWriter {
+    bytes: Vec<u8>,
+}
+
+impl WireWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_pod<T: NoUninit>(&mut self, value: &T) {
+        self.bytes.extend_from_slice(bytemuck::bytes_of(value));
+    }
+
+    /// Write a length prefixed utf-8 string, strings longer than `MAX_WIRE_STRING_LEN` are rejected when reading
+    pub fn write_string(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Write a count prefixed slice of plain old data
+    pub fn write_pod_slice<T: NoUninit>(&mut self, values: &[T]) {
+        self.write_u32(values.len() as u32);
+        self.bytes.extend_from_slice(bytemuck::cast_slice(values));
+    }
+
+    /// Write a length prefixed byte slice
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Decodes data written by a `WireWriter`, malformed input always results in an error instead of a panic
+pub struct WireReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unexpected end of data, needed {} bytes at offset {} of {}.",
+                    len,
+                    self.offset,
+                    self.bytes.len()
+                )
+            })?;
+
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Read a plain old data struct, no alignment is required
+    pub fn read_pod<T: AnyBitPattern>(&mut self) -> Result<T> {
+        bytemuck::try_pod_read_unaligned(self.take(std::mem::size_of::<T>())?)
+            .map_err(|err| anyhow!("Failed to read {}: {}.", std::any::type_name::<T>(), err))
+    }
+
+    /// Read a slice written by `write_pod_slice`, the count is validated against the remaining data before allocating
+    pub fn read_pod_vec<T: AnyBitPattern>(&mut self) -> Result<Vec<T>> {
+        let count = self.read_u32()? as usize;
+        let len = count
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or_else(|| anyhow!("Slice of {} elements is too large.", count))?;
+
+        self.take(len)?
+            .chunks_exact(std::mem::size_of::<T>())
+            .map(|bytes| {
+                bytemuck::try_pod_read_unaligned(bytes).map_err(|err| {
+                    anyhow!("Failed to read {}: {}.", std::any::type_name::<T>(), err)
+                })
+            })
+            .collect()
+    }
+
+    pub fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u32()? as usize;
+        if len > MAX_WIRE_STRING_LEN {
+            return Err(anyhow!(
+                "String of {} bytes exceeds the maximum of {} bytes.",
+                len,
+                MAX_WIRE_STRING_LEN
+            ));
+        }
+
+        Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Make sure all data has been consumed, trailing bytes mean the data wasn't what it was expected to be
+    pub fn finish(self) -> Result<()> {
+        if self.offset != self.bytes.len() {
+            return Err(anyhow!(
+                "Unexpected {} trailing bytes.",
+                self.bytes.len() - self.offset
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = WireWriter::new();
+        writer.write_u8(7);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xdeadbeef);
+        writer.write_pod(&1.5f32);
+        writer.write_string("::models/duck.glb");
+        writer.write_pod_slice(&[1u32, 2, 3]);
+        writer.write_bytes(&[4, 5]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = WireReader::new(&bytes);
+        assert_eq!(reader.read_u8().unwrap(), 7);
+        assert_eq!(reader.read_u16().unwrap(), 0x1234);
+        assert_eq!(reader.read_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(reader.read_pod::<f32>().unwrap(), 1.5);
+        assert_eq!(reader.read_string().unwrap(), "::models/duck.glb");
+        assert_eq!(reader.read_pod_vec::<u32>().unwrap(), vec![1, 2, 3]);
+        assert_eq!(reader.read_bytes().unwrap(), &[4, 5]);
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn truncated_input() {
+        fn read(bytes: &[u8]) -> Result<()> {
+            let mut reader = WireReader::new(bytes);
+            reader.read_u32()?;
+            reader.read_string()?;
+            reader.read_bytes()?;
+            reader.finish()
+        }
+
+        let mut writer = WireWriter::new();
+        writer.write_u32(1);
+        writer.write_string("asset");
+        writer.write_bytes(&[1, 2, 3]);
+        let bytes = writer.into_bytes();
+
+        read(&bytes).unwrap();
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err(), "Truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn oversized_string() {
+        let mut writer = WireWriter::new();
+        writer.write_string(&"a".repeat(MAX_WIRE_STRING_LEN));
+        writer.write_string(&"a".repeat(MAX_WIRE_STRING_LEN + 1));
+        let bytes = writer.into_bytes();
+
+        let mut reader = WireReader::new(&bytes);
+        assert_eq!(reader.read_string().unwrap().len(), MAX_WIRE_STRING_LEN);
+        assert!(reader.read_string().is_err());
+    }
+
+    #[test]
+    fn oversized_length_prefix() {
+        let mut writer = WireWriter::new();
+        writer.write_u32(u32::MAX);
+        let bytes = writer.into_bytes();
+
+        assert!(WireReader::new(&bytes).read_bytes().is_err());
+        assert!(WireReader::new(&bytes).read_pod_vec::<[u64; 4]>().is_err());
+    }
+
+    #[test]
+    fn invalid_utf8_string() {
+        let mut writer = WireWriter::new();
+        writer.write_bytes(&[0xff, 0xfe]);
+        let bytes = writer.into_bytes();
+
+        assert!(WireReader::new(&bytes).read_string().is_err());
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut writer = WireWriter::new();
+        writer.write_u32(1);
+        writer.write_u8(2);
+        let bytes = writer.into_bytes();
+
+        let mut reader = WireReader::new(&bytes);
+        reader.read_u32().unwrap();
+        assert!(reader.finish().is_err());
+
+        let mut reader = WireReader::new(&bytes);
+        reader.read_u32().unwrap();
+        reader.read_u8().unwrap();
+        reader.finish().unwrap();
+    }
+}
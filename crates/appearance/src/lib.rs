@@ -9,6 +9,7 @@ pub use appearance_camera;
 pub use appearance_distributed_renderer;
 pub use appearance_input;
 pub use appearance_model;
+pub use appearance_path_tracer;
 pub use appearance_path_tracer_gpu;
 pub use appearance_profiling;
 pub use appearance_render_loop;